@@ -0,0 +1,76 @@
+use std::{
+    env,
+    path::{Path, PathBuf},
+    process::Command,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Runs git in the crate directory, if git is installed.
+fn git(dir: &Path, args: &[&str]) -> Option<String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .output()
+        .ok()?;
+    let stdout = String::from_utf8(output.stdout).ok()?;
+    (output.status.success() && !stdout.trim().is_empty()).then(|| stdout.trim().to_string())
+}
+
+/// Whether the crate directory is the top of its own git checkout. A crate that is vendored
+/// into another repository, or unpacked from a published .crate, would otherwise report the
+/// commit of whatever repository it sits in.
+fn is_checkout(dir: &Path) -> bool {
+    let top = git(dir, &["rev-parse", "--show-toplevel"]).map(PathBuf::from);
+    match (
+        top.and_then(|top| top.canonicalize().ok()),
+        dir.canonicalize(),
+    ) {
+        (Some(top), Ok(dir)) => top == dir,
+        _ => false,
+    }
+}
+
+fn main() {
+    let dir = PathBuf::from(env::var_os("CARGO_MANIFEST_DIR").unwrap_or_else(|| ".".into()));
+    // Build the commit and time into the binary for --version-path.
+    let git_dir = if is_checkout(&dir) {
+        git(&dir, &["rev-parse", "--absolute-git-dir"])
+    } else {
+        None
+    };
+    if let Some(git_dir) = git_dir {
+        if let Some(commit) = git(&dir, &["rev-parse", "--short=12", "HEAD"]) {
+            println!("cargo:rustc-env=SERVE_GIT_COMMIT={}", commit);
+        }
+        // A new commit changes the branch the HEAD points to, not the files of the crate.
+        // Worktrees have their HEAD outside of .git.
+        let git_dir = Path::new(&git_dir);
+        if dir.join(".git/HEAD").is_file() {
+            println!("cargo:rerun-if-changed=.git/HEAD");
+        }
+        println!("cargo:rerun-if-changed={}", git_dir.join("HEAD").display());
+        if let Some(branch) = git(&dir, &["symbolic-ref", "-q", "HEAD"]) {
+            println!("cargo:rerun-if-changed={}", git_dir.join(branch).display());
+        }
+        println!(
+            "cargo:rerun-if-changed={}",
+            git_dir.join("packed-refs").display()
+        );
+        println!("cargo:rerun-if-changed=src");
+        println!("cargo:rerun-if-changed=Cargo.toml");
+        println!("cargo:rerun-if-changed=build.rs");
+    }
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+    // SOURCE_DATE_EPOCH keeps reproducible builds reproducible.
+    let built = env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|epoch| epoch.parse::<u64>().ok())
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0)
+        });
+    println!("cargo:rustc-env=SERVE_BUILD_TIME={}", built);
+}
@@ -0,0 +1,383 @@
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, Request, State},
+    http::{header, HeaderMap},
+    middleware::Next,
+    response::Response,
+};
+use futures_util::StreamExt;
+use ipnet::IpNet;
+use std::{
+    net::SocketAddr,
+    sync::{mpsc, Arc},
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{forwarded, path, rolling_file::RollingFile};
+
+const COMMON: &str = "{remote_addr} - - [{time}] \"{method} {uri} {version}\" {status} {bytes}";
+const COMBINED: &str = "{remote_addr} - - [{time}] \"{method} {uri} {version}\" {status} {bytes} \
+                        \"{referer}\" \"{user_agent}\"";
+
+#[derive(Copy, Clone, Debug)]
+enum Field {
+    RemoteAddr,
+    Time,
+    Method,
+    Uri,
+    Path,
+    Version,
+    Status,
+    Bytes,
+    Latency,
+    Referer,
+    UserAgent,
+}
+
+impl Field {
+    fn parse(name: &str) -> Option<Self> {
+        Some(match name {
+            "remote_addr" => Field::RemoteAddr,
+            "time" => Field::Time,
+            "method" => Field::Method,
+            "uri" => Field::Uri,
+            "path" => Field::Path,
+            "version" => Field::Version,
+            "status" => Field::Status,
+            "bytes" => Field::Bytes,
+            "latency" => Field::Latency,
+            "referer" => Field::Referer,
+            "user_agent" => Field::UserAgent,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Segment {
+    Literal(String),
+    Field(Field),
+}
+
+/// The format of access log lines: a list of literal text and `{field}` placeholders.
+#[derive(Clone, Debug)]
+pub struct Format(Vec<Segment>);
+
+/// Parses `common`, `combined` or a template like `{method} {path} {status} {latency}`.
+pub fn parse_format(s: &str) -> Result<Format, String> {
+    let template = match s {
+        "common" => COMMON,
+        "combined" => COMBINED,
+        _ => s,
+    };
+    let mut segments = Vec::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        if start > 0 {
+            segments.push(Segment::Literal(rest[..start].to_string()));
+        }
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| format!("{} has a {{ without a closing }}", s))?;
+        let name = &rest[start + 1..start + end];
+        let field = Field::parse(name).ok_or_else(|| {
+            format!(
+                "{{{}}} is not a field, use remote_addr, time, method, uri, path, version, \
+                 status, bytes, latency, referer or user_agent",
+                name
+            )
+        })?;
+        segments.push(Segment::Field(field));
+        rest = &rest[start + end + 1..];
+    }
+    if !rest.is_empty() {
+        segments.push(Segment::Literal(rest.to_string()));
+    }
+    Ok(Format(segments))
+}
+
+pub struct AccessLog {
+    format: Format,
+    trusted_proxies: Arc<[IpNet]>,
+    /// Requests below these paths are not logged.
+    exclude: Arc<[String]>,
+    lines: mpsc::Sender<String>,
+}
+
+impl AccessLog {
+    /// Lines are written to `file` by a thread of its own, so requests never wait for the disk.
+    pub fn new(
+        file: RollingFile,
+        format: Format,
+        trusted_proxies: Arc<[IpNet]>,
+        exclude: Arc<[String]>,
+    ) -> Self {
+        let (lines, rx) = mpsc::channel();
+        std::thread::spawn(move || write_lines(file, rx));
+        AccessLog {
+            format,
+            trusted_proxies,
+            exclude,
+            lines,
+        }
+    }
+}
+
+fn write_lines(mut file: RollingFile, rx: mpsc::Receiver<String>) {
+    while let Ok(line) = rx.recv() {
+        let mut result = file.write_line(&line);
+        while let Ok(line) = rx.try_recv() {
+            result = result.and_then(|_| file.write_line(&line));
+        }
+        if let Err(e) = result.and_then(|_| file.flush()) {
+            tracing::error!("unable to write the access log: {}", e);
+        }
+    }
+}
+
+/// What is known about a request before its response has been sent.
+struct Entry {
+    log: Arc<AccessLog>,
+    start: Instant,
+    time: SystemTime,
+    remote_addr: Option<String>,
+    method: String,
+    uri: String,
+    path: String,
+    version: String,
+    referer: Option<String>,
+    user_agent: Option<String>,
+    status: u16,
+    bytes: usize,
+}
+
+impl Entry {
+    fn line(&self) -> String {
+        let mut line = String::new();
+        for segment in &self.log.format.0 {
+            match segment {
+                Segment::Literal(text) => line.push_str(text),
+                Segment::Field(field) => {
+                    let value = match field {
+                        Field::RemoteAddr => self.remote_addr.clone(),
+                        Field::Time => Some(common_log_time(self.time)),
+                        Field::Method => Some(self.method.clone()),
+                        Field::Uri => Some(self.uri.clone()),
+                        Field::Path => Some(self.path.clone()),
+                        Field::Version => Some(self.version.clone()),
+                        Field::Status => Some(self.status.to_string()),
+                        Field::Bytes => Some(self.bytes.to_string()),
+                        Field::Latency => Some(format!("{}ms", self.start.elapsed().as_millis())),
+                        Field::Referer => self.referer.clone(),
+                        Field::UserAgent => self.user_agent.clone(),
+                    };
+                    line.push_str(value.as_deref().unwrap_or("-"));
+                }
+            }
+        }
+        line
+    }
+}
+
+/// The line is written once the body has been sent, or the client went away.
+impl Drop for Entry {
+    fn drop(&mut self) {
+        let _ = self.log.lines.send(self.line());
+    }
+}
+
+fn header_value(headers: &HeaderMap, name: header::HeaderName) -> Option<String> {
+    headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.replace('"', "\\\""))
+}
+
+/// Middleware that writes a line to the access log for every request, with the number of body
+/// bytes that were sent.
+pub async fn apply(State(log): State<Arc<AccessLog>>, request: Request, next: Next) -> Response {
+    let path = request.uri().path();
+    if log
+        .exclude
+        .iter()
+        .any(|prefix| path::has_prefix(path, prefix))
+    {
+        return next.run(request).await;
+    }
+    let remote_addr =
+        request
+            .extensions()
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(peer)| {
+                forwarded::resolve(&log.trusted_proxies, peer.ip(), request.headers()).to_string()
+            });
+    let mut entry = Entry {
+        log: log.clone(),
+        start: Instant::now(),
+        time: SystemTime::now(),
+        remote_addr,
+        method: request.method().to_string(),
+        uri: request.uri().to_string(),
+        path: request.uri().path().to_string(),
+        version: format!("{:?}", request.version()),
+        referer: header_value(request.headers(), header::REFERER),
+        user_agent: header_value(request.headers(), header::USER_AGENT),
+        status: 0,
+        bytes: 0,
+    };
+    let response = next.run(request).await;
+    entry.status = response.status().as_u16();
+    let (parts, body) = response.into_parts();
+    let body = body.into_data_stream().map(move |chunk| {
+        // Borrowing all of it moves the entry into the closure, so it is dropped with the body.
+        let entry = &mut entry;
+        if let Ok(bytes) = &chunk {
+            entry.bytes += bytes.len();
+        }
+        chunk
+    });
+    Response::from_parts(parts, Body::from_stream(body))
+}
+
+/// The UTC `(year, month, day)` of a number of days since 1970-01-01, see
+/// https://howardhinnant.github.io/date_algorithms.html#civil_from_days
+pub fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    (yoe + era * 400 + i64::from(month <= 2), month, day)
+}
+
+/// Formats a time like `10/Oct/2000:13:55:36 +0000`, in UTC.
+fn common_log_time(time: SystemTime) -> String {
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let (days, secs_of_day) = ((secs / 86400) as i64, secs % 86400);
+    let (year, month, day) = civil_from_days(days);
+    format!(
+        "{:02}/{}/{}:{:02}:{:02}:{:02} +0000",
+        day,
+        MONTHS[(month - 1) as usize],
+        year,
+        secs_of_day / 3600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        rolling_file::Rotation,
+        test_util::{body_string, TempDir},
+    };
+    use axum::{middleware, routing::get, Router};
+    use std::{path::Path, time::Duration};
+    use tower::ServiceExt;
+
+    /// Serves `/` and `/healthz`, logging to `access.log` in `dir`.
+    fn app(dir: &TempDir, format: &str, exclude: &[&str]) -> Router {
+        let file =
+            RollingFile::open(&dir.path().join("access.log"), Rotation::Never, 0, 0, None).unwrap();
+        let exclude = exclude.iter().map(|prefix| prefix.to_string()).collect();
+        let log = AccessLog::new(file, parse_format(format).unwrap(), Arc::from([]), exclude);
+        Router::new()
+            .route("/", get(|| async { "hello" }))
+            .route("/healthz", get(|| async { "ok" }))
+            .layer(middleware::from_fn_with_state(Arc::new(log), apply))
+    }
+
+    /// Sends a GET request for `uri` from 192.0.2.1 and reads the whole response.
+    async fn send(app: &Router, uri: &str) {
+        let mut request = Request::builder()
+            .uri(uri)
+            .header(header::USER_AGENT, "curl/8.0 \"test\"")
+            .body(Body::empty())
+            .unwrap();
+        let peer = SocketAddr::from(([192, 0, 2, 1], 4711));
+        request.extensions_mut().insert(ConnectInfo(peer));
+        body_string(app.clone().oneshot(request).await.unwrap()).await;
+    }
+
+    /// Waits for `count` lines in the log and returns them.
+    async fn lines(dir: &Path, count: usize) -> Vec<String> {
+        let path = dir.join("access.log");
+        for _ in 0..250 {
+            let content = std::fs::read_to_string(&path).unwrap_or_default();
+            if content.lines().count() >= count {
+                return content.lines().map(str::to_string).collect();
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        panic!("fewer than {} lines in {}", count, path.display());
+    }
+
+    #[tokio::test]
+    async fn line_per_request() {
+        let dir = TempDir::new("access-log-lines");
+        let app = app(&dir, "combined", &[]);
+        send(&app, "/?a=1").await;
+        send(&app, "/missing").await;
+        let lines = lines(dir.path(), 2).await;
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("192.0.2.1 - - ["), "{}", lines[0]);
+        assert!(
+            lines[0].ends_with("] \"GET /?a=1 HTTP/1.1\" 200 5 \"-\" \"curl/8.0 \\\"test\\\"\""),
+            "{}",
+            lines[0]
+        );
+        assert!(
+            lines[1].contains("\"GET /missing HTTP/1.1\" 404 0 "),
+            "{}",
+            lines[1]
+        );
+    }
+
+    #[tokio::test]
+    async fn custom_format() {
+        let dir = TempDir::new("access-log-format");
+        let app = app(&dir, "{method} {path} {status} {bytes} {latency}", &[]);
+        send(&app, "/?a=1").await;
+        let line = &lines(dir.path(), 1).await[0];
+        let (start, latency) = line.rsplit_once(' ').unwrap();
+        assert_eq!(start, "GET / 200 5");
+        assert!(latency.ends_with("ms"), "{}", line);
+    }
+
+    #[tokio::test]
+    async fn excluded_paths_are_not_logged() {
+        let dir = TempDir::new("access-log-exclude");
+        let app = app(&dir, "{path} {status}", &["/healthz"]);
+        send(&app, "/healthz").await;
+        send(&app, "/").await;
+        send(&app, "/healthz?full").await;
+        send(&app, "/healthzz").await;
+        assert_eq!(lines(dir.path(), 2).await, ["/ 200", "/healthzz 404"]);
+    }
+
+    #[test]
+    fn invalid_formats() {
+        assert!(parse_format("{method").is_err());
+        assert!(parse_format("{host}").is_err());
+        assert!(parse_format("no fields").is_ok());
+    }
+
+    #[test]
+    fn time_in_common_log_format() {
+        let time = UNIX_EPOCH + Duration::from_secs(971_186_136);
+        assert_eq!(common_log_time(time), "10/Oct/2000:13:55:36 +0000");
+        let time = UNIX_EPOCH + Duration::from_secs(1_709_164_800);
+        assert_eq!(common_log_time(time), "29/Feb/2024:00:00:00 +0000");
+    }
+}
@@ -0,0 +1,288 @@
+use axum::{
+    body::{Body, Bytes},
+    extract::{OriginalUri, Request},
+    http::{header, HeaderValue, Method, StatusCode},
+    response::{IntoResponse, Redirect, Response},
+};
+use flate2::read::GzDecoder;
+use percent_encoding::percent_decode_str;
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::Read,
+    path::{Component, Path},
+    sync::Arc,
+};
+use tower::{service_fn, ServiceExt};
+
+use crate::{
+    accepts_html, boxed,
+    error::ServeError,
+    ranges::{self, Ranges},
+    FileService,
+};
+
+/// The files of a zip or tar archive, read into memory at startup.
+pub struct Archive {
+    files: HashMap<String, Bytes>,
+}
+
+impl Archive {
+    /// Reads a `.zip`, `.tar`, `.tar.gz` or `.tgz` file.
+    pub fn open(path: &Path) -> Result<Self, ServeError> {
+        let invalid = |e: &dyn std::fmt::Display| {
+            ServeError::InvalidArchive(format!("{}: {}", path.display(), e))
+        };
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        let file = File::open(path).map_err(|e| invalid(&e))?;
+        let mut files = HashMap::new();
+        if name.ends_with(".zip") {
+            let mut zip = zip::ZipArchive::new(file).map_err(|e| invalid(&e))?;
+            for i in 0..zip.len() {
+                let mut entry = zip.by_index(i).map_err(|e| invalid(&e))?;
+                let Some(key) = entry.is_file().then(|| key(entry.name())).flatten() else {
+                    continue;
+                };
+                let mut content = Vec::with_capacity(entry.size() as usize);
+                entry.read_to_end(&mut content).map_err(|e| invalid(&e))?;
+                files.insert(key, Bytes::from(content));
+            }
+        } else if name.ends_with(".tar") || name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            let reader: Box<dyn Read> = if name.ends_with(".tar") {
+                Box::new(file)
+            } else {
+                Box::new(GzDecoder::new(file))
+            };
+            let mut tar = tar::Archive::new(reader);
+            for entry in tar.entries().map_err(|e| invalid(&e))? {
+                let mut entry = entry.map_err(|e| invalid(&e))?;
+                if !entry.header().entry_type().is_file() {
+                    continue;
+                }
+                let name = entry.path().map_err(|e| invalid(&e))?;
+                let Some(key) = key(&name.to_string_lossy()) else {
+                    continue;
+                };
+                let mut content = Vec::with_capacity(entry.size() as usize);
+                entry.read_to_end(&mut content).map_err(|e| invalid(&e))?;
+                files.insert(key, Bytes::from(content));
+            }
+        } else {
+            return Err(invalid(&"use a .zip, .tar, .tar.gz or .tgz file"));
+        }
+        Ok(Archive { files })
+    }
+
+    pub fn file_count(&self) -> usize {
+        self.files.len()
+    }
+
+    /// Looks up a request path. Directories are served from their index.html.
+    fn get(&self, request_path: &str) -> Option<(&String, &Bytes)> {
+        let decoded = percent_decode_str(request_path).decode_utf8().ok()?;
+        let mut key = key(&decoded)?;
+        if key.is_empty() || decoded.ends_with('/') {
+            if !key.is_empty() {
+                key.push('/');
+            }
+            key.push_str("index.html");
+        }
+        self.files.get_key_value(&key)
+    }
+
+    fn is_dir(&self, request_path: &str) -> bool {
+        let key = percent_decode_str(request_path)
+            .decode_utf8()
+            .ok()
+            .and_then(|decoded| key(&decoded));
+        key.is_some_and(|key| self.files.contains_key(&format!("{}/index.html", key)))
+    }
+}
+
+/// Normalizes an entry name or request path to `dir/file`. Names that try to leave the root
+/// are skipped.
+fn key(name: &str) -> Option<String> {
+    let mut segments = Vec::new();
+    for component in Path::new(name.trim_start_matches('/')).components() {
+        match component {
+            Component::Normal(segment) => segments.push(segment.to_str()?),
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+    Some(segments.join("/"))
+}
+
+fn file_response(request: &Request, key: &str, content: &Bytes) -> Response {
+    let content_type = mime_guess::from_path(key)
+        .first_raw()
+        .unwrap_or("application/octet-stream");
+    let headers = [
+        (header::CONTENT_TYPE, HeaderValue::from_static(content_type)),
+        (header::ACCEPT_RANGES, HeaderValue::from_static("bytes")),
+    ];
+    let response = (headers, Body::from(content.clone())).into_response();
+    let len = content.len() as u64;
+    let range = request
+        .headers()
+        .get(header::RANGE)
+        .and_then(|value| value.to_str().ok());
+    match range.map(|value| ranges::parse(value, len)) {
+        Some(Ranges::Partial(ranges)) => ranges::partial(response, ranges, len),
+        Some(Ranges::Unsatisfiable) => ranges::unsatisfiable(len),
+        Some(Ranges::Full) | None => response,
+    }
+}
+
+/// Serves files from `archive` like `ServeDir` serves a directory. Requests for missing files
+/// go to `fallback`, or get the archive's index.html when `spa` is set and they want HTML.
+pub fn service(archive: Arc<Archive>, spa: bool, fallback: FileService) -> FileService {
+    boxed(service_fn(move |request: Request| {
+        let archive = archive.clone();
+        let fallback = fallback.clone();
+        async move {
+            if request.method() != Method::GET && request.method() != Method::HEAD {
+                return Ok((
+                    StatusCode::METHOD_NOT_ALLOWED,
+                    [(header::ALLOW, "GET,HEAD")],
+                )
+                    .into_response());
+            }
+            let request_path = request.uri().path().to_string();
+            if let Some((key, content)) = archive.get(&request_path) {
+                return Ok(file_response(&request, key, content));
+            }
+            if !request_path.ends_with('/') && archive.is_dir(&request_path) {
+                let uri = request
+                    .extensions()
+                    .get::<OriginalUri>()
+                    .map(|original| &original.0)
+                    .unwrap_or(request.uri());
+                let mut location = format!("/{}/", uri.path().trim_matches('/'));
+                if let Some(query) = uri.query() {
+                    location.push('?');
+                    location.push_str(query);
+                }
+                return Ok(Redirect::temporary(&location).into_response());
+            }
+            if spa && accepts_html(&request) {
+                if let Some((key, index)) = archive.get("/") {
+                    return Ok(file_response(&request, key, index));
+                }
+            }
+            fallback.oneshot(request).await
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{body_string, get, TempDir};
+    use std::io::Write;
+    use zip::write::SimpleFileOptions;
+
+    const FILES: [(&str, &str); 3] = [
+        ("index.html", "home"),
+        ("assets/css/site.css", "body {}"),
+        ("docs/index.html", "docs"),
+    ];
+
+    fn zip(root: &TempDir) -> Archive {
+        let path = root.path().join("site.zip");
+        let mut zip = zip::ZipWriter::new(File::create(&path).unwrap());
+        zip.add_directory("assets/", SimpleFileOptions::default())
+            .unwrap();
+        for (name, content) in FILES {
+            zip.start_file(name, SimpleFileOptions::default()).unwrap();
+            zip.write_all(content.as_bytes()).unwrap();
+        }
+        zip.finish().unwrap();
+        Archive::open(&path).unwrap()
+    }
+
+    async fn send(archive: Archive, request: Request) -> Response {
+        let not_found = boxed(service_fn(|_: Request| async {
+            Ok(StatusCode::NOT_FOUND.into_response())
+        }));
+        service(Arc::new(archive), false, not_found)
+            .oneshot(request)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn index_is_served_from_a_zip() {
+        let root = TempDir::new("archive-index");
+        let response = send(zip(&root), get("/")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "text/html");
+        assert_eq!(body_string(response).await, "home");
+        let response = send(zip(&root), get("/docs/")).await;
+        assert_eq!(body_string(response).await, "docs");
+        let response = send(zip(&root), get("/docs?a=1")).await;
+        assert_eq!(response.headers()[header::LOCATION], "/docs/?a=1");
+    }
+
+    #[tokio::test]
+    async fn nested_asset_is_served_from_a_zip() {
+        let root = TempDir::new("archive-asset");
+        assert_eq!(zip(&root).file_count(), 3);
+        let response = send(zip(&root), get("/assets/css/site.css")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "text/css");
+        assert_eq!(body_string(response).await, "body {}");
+
+        let mut request = get("/assets/css/site.css");
+        let range = HeaderValue::from_static("bytes=0-3");
+        request.headers_mut().insert(header::RANGE, range);
+        let response = send(zip(&root), request).await;
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(body_string(response).await, "body");
+
+        let response = send(zip(&root), get("/assets/missing.css")).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn tar_gz_is_read() {
+        let root = TempDir::new("archive-tar");
+        let path = root.path().join("site.tar.gz");
+        let gz = flate2::write::GzEncoder::new(
+            File::create(&path).unwrap(),
+            flate2::Compression::default(),
+        );
+        let mut tar = tar::Builder::new(gz);
+        for (name, content) in FILES {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            tar.append_data(&mut header, name, content.as_bytes())
+                .unwrap();
+        }
+        tar.into_inner().unwrap().finish().unwrap();
+
+        let archive = Archive::open(&path).unwrap();
+        assert_eq!(archive.file_count(), 3);
+        let (key, content) = archive.get("/assets/css/site.css").unwrap();
+        assert_eq!(
+            (key.as_str(), &content[..]),
+            ("assets/css/site.css", &b"body {}"[..])
+        );
+    }
+
+    #[test]
+    fn paths_stay_inside_the_archive() {
+        assert_eq!(key("/a/./b.html").as_deref(), Some("a/b.html"));
+        assert_eq!(key("../etc/passwd"), None);
+        assert_eq!(key("a/../../b"), None);
+        let root = TempDir::new("archive-paths");
+        assert!(zip(&root).get("/%2e%2e/index.html").is_none());
+        assert!(zip(&root).get("/docs/../../").is_none());
+        assert!(Archive::open(&root.write("site.rar", "")).is_err());
+    }
+}
@@ -0,0 +1,336 @@
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use md5::{Digest, Md5};
+use std::{collections::HashMap, path::Path, sync::Arc};
+use subtle::ConstantTimeEq;
+
+use crate::error::ServeError;
+
+const APR1_MAGIC: &str = "$apr1$";
+const ITOA64: &[u8] = b"./0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+/// Checked when the user does not exist, so unknown users take as long as wrong passwords.
+const DUMMY_BCRYPT: &str = "$2b$05$oZiCKtjow.I7qdT51yOBGex/FH7rZmr7SMp5yUDz2Fl7EfQ/hedKi";
+
+enum Hash {
+    Bcrypt(String),
+    Apr1 { salt: String, checksum: String },
+}
+
+impl Hash {
+    fn parse(hash: &str) -> Option<Self> {
+        if hash.starts_with("$2a$") || hash.starts_with("$2b$") || hash.starts_with("$2y$") {
+            return Some(Hash::Bcrypt(hash.to_string()));
+        }
+        let (salt, checksum) = hash.strip_prefix(APR1_MAGIC)?.split_once('$')?;
+        if salt.is_empty() || salt.len() > 8 || checksum.len() != 22 {
+            return None;
+        }
+        Some(Hash::Apr1 {
+            salt: salt.to_string(),
+            checksum: checksum.to_string(),
+        })
+    }
+
+    fn verify(&self, password: &str) -> bool {
+        match self {
+            Hash::Bcrypt(hash) => bcrypt::verify(password, hash).unwrap_or(false),
+            Hash::Apr1 { salt, checksum } => apr1(password.as_bytes(), salt.as_bytes())
+                .as_bytes()
+                .ct_eq(checksum.as_bytes())
+                .into(),
+        }
+    }
+}
+
+/// Users loaded from an htpasswd file. Only bcrypt and apr1 hashes are supported.
+pub struct Htpasswd {
+    users: HashMap<String, Hash>,
+}
+
+impl Htpasswd {
+    pub fn from_file(path: &Path) -> Result<Self, ServeError> {
+        let content = std::fs::read_to_string(path).map_err(|e| {
+            ServeError::Htpasswd(format!("unable to read {}: {}", path.display(), e))
+        })?;
+        let mut users = HashMap::new();
+        for (number, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let malformed = |reason: &str| {
+                ServeError::Htpasswd(format!(
+                    "{} line {}: {}",
+                    path.display(),
+                    number + 1,
+                    reason
+                ))
+            };
+            let (user, hash) = line
+                .split_once(':')
+                .ok_or_else(|| malformed("expected user:hash"))?;
+            let hash = Hash::parse(hash)
+                .ok_or_else(|| malformed("unsupported hash, use bcrypt or apr1"))?;
+            users.insert(user.to_string(), hash);
+        }
+        if users.is_empty() {
+            return Err(ServeError::Htpasswd(format!(
+                "{} has no users",
+                path.display()
+            )));
+        }
+        Ok(Htpasswd { users })
+    }
+
+    pub fn check(&self, user: &str, password: &str) -> bool {
+        match self.users.get(user) {
+            Some(hash) => hash.verify(password),
+            None => {
+                let _ = bcrypt::verify(password, DUMMY_BCRYPT);
+                false
+            }
+        }
+    }
+}
+
+pub struct BasicAuth {
+    pub htpasswd: Htpasswd,
+    pub realm: String,
+}
+
+impl BasicAuth {
+    /// Checks the credentials on the blocking pool, since bcrypt takes a while by design.
+    async fn authorized(self: &Arc<Self>, credentials: Option<(String, String)>) -> bool {
+        let Some((user, password)) = credentials else {
+            return false;
+        };
+        let auth = self.clone();
+        tokio::task::spawn_blocking(move || auth.htpasswd.check(&user, &password))
+            .await
+            .unwrap_or(false)
+    }
+}
+
+/// The user and password of a Basic Authorization header. The scheme is case-insensitive.
+fn credentials(request: &Request) -> Option<(String, String)> {
+    let value = request
+        .headers()
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?;
+    let (scheme, value) = value.split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("Basic") {
+        return None;
+    }
+    let credentials = String::from_utf8(STANDARD.decode(value.trim()).ok()?).ok()?;
+    let (user, password) = credentials.split_once(':')?;
+    Some((user.to_string(), password.to_string()))
+}
+
+/// Middleware that rejects requests without valid credentials with 401.
+pub async fn basic_auth(
+    State(auth): State<Arc<BasicAuth>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if auth.authorized(credentials(&request)).await {
+        return next.run(request).await;
+    }
+    let challenge = format!(
+        "Basic realm=\"{}\", charset=\"UTF-8\"",
+        auth.realm.replace('\\', "\\\\").replace('"', "\\\"")
+    );
+    match HeaderValue::from_str(&challenge) {
+        Ok(challenge) => (
+            StatusCode::UNAUTHORIZED,
+            [(header::WWW_AUTHENTICATE, challenge)],
+        )
+            .into_response(),
+        Err(_) => StatusCode::UNAUTHORIZED.into_response(),
+    }
+}
+
+/// The apr1 variant of md5-crypt used by Apache. Returns the 22 character checksum.
+fn apr1(password: &[u8], salt: &[u8]) -> String {
+    let salt = &salt[..salt.len().min(8)];
+
+    let mut alternate = Md5::new();
+    alternate.update(password);
+    alternate.update(salt);
+    alternate.update(password);
+    let alternate = alternate.finalize();
+
+    let mut ctx = Md5::new();
+    ctx.update(password);
+    ctx.update(APR1_MAGIC);
+    ctx.update(salt);
+    for chunk in password.chunks(16) {
+        ctx.update(&alternate[..chunk.len()]);
+    }
+    let mut i = password.len();
+    while i > 0 {
+        if i & 1 == 1 {
+            ctx.update([0]);
+        } else {
+            ctx.update(&password[..1]);
+        }
+        i >>= 1;
+    }
+    let mut digest = ctx.finalize();
+
+    for round in 0..1000 {
+        let mut ctx = Md5::new();
+        if round & 1 == 1 {
+            ctx.update(password);
+        } else {
+            ctx.update(digest);
+        }
+        if round % 3 != 0 {
+            ctx.update(salt);
+        }
+        if round % 7 != 0 {
+            ctx.update(password);
+        }
+        if round & 1 == 1 {
+            ctx.update(digest);
+        } else {
+            ctx.update(password);
+        }
+        digest = ctx.finalize();
+    }
+
+    let mut out = String::with_capacity(22);
+    let mut encode = |value: u32, chars: usize| {
+        let mut value = value;
+        for _ in 0..chars {
+            out.push(ITOA64[(value & 0x3f) as usize] as char);
+            value >>= 6;
+        }
+    };
+    for (a, b, c) in [(0, 6, 12), (1, 7, 13), (2, 8, 14), (3, 9, 15), (4, 10, 5)] {
+        encode(
+            (u32::from(digest[a]) << 16) | (u32::from(digest[b]) << 8) | u32::from(digest[c]),
+            4,
+        );
+    }
+    encode(u32::from(digest[11]), 2);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+    use axum::{body::Body, middleware, routing::get, Router};
+    use tower::ServiceExt;
+
+    /// From `openssl passwd -apr1 -salt saltsalt password`.
+    const APR1_PASSWORD: &str = "$apr1$saltsalt$yAAkm4libquA.ZWLHbSBq/";
+
+    fn htpasswd(name: &str) -> Htpasswd {
+        let dir = TempDir::new(name);
+        let bcrypt = bcrypt::hash("secret", 4).unwrap();
+        let path = dir.write(
+            ".htpasswd",
+            format!("# users\n\nalice:{}\nbob:{}\n", APR1_PASSWORD, bcrypt),
+        );
+        Htpasswd::from_file(&path).unwrap()
+    }
+
+    /// Sends a request with `authorization` through the middleware. `name` is for the
+    /// htpasswd file.
+    async fn respond(name: &str, authorization: Option<&str>) -> (StatusCode, Option<HeaderValue>) {
+        let auth = Arc::new(BasicAuth {
+            htpasswd: htpasswd(name),
+            realm: "files \"here\"".to_string(),
+        });
+        let app = Router::new()
+            .route("/", get(|| async { "ok" }))
+            .layer(middleware::from_fn_with_state(auth, basic_auth));
+        let mut request = Request::builder().uri("/");
+        if let Some(authorization) = authorization {
+            request = request.header(header::AUTHORIZATION, authorization);
+        }
+        let response = app
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let challenge = response.headers().get(header::WWW_AUTHENTICATE).cloned();
+        (response.status(), challenge)
+    }
+
+    fn basic(credentials: &str) -> String {
+        format!("Basic {}", STANDARD.encode(credentials))
+    }
+
+    #[test]
+    fn apr1_matches_openssl() {
+        assert_eq!(apr1(b"password", b"saltsalt"), "yAAkm4libquA.ZWLHbSBq/");
+        assert_eq!(apr1("pässwörd".as_bytes(), b"ab"), "J/pyQu7p0O5KCJfor.v6Y/");
+    }
+
+    #[test]
+    fn check_verifies_both_hashes() {
+        let htpasswd = htpasswd("auth-check");
+        assert!(htpasswd.check("alice", "password"));
+        assert!(!htpasswd.check("alice", "Password"));
+        assert!(htpasswd.check("bob", "secret"));
+        assert!(!htpasswd.check("bob", "password"));
+        assert!(!htpasswd.check("carol", "password"));
+    }
+
+    #[test]
+    fn malformed_lines_are_rejected() {
+        let dir = TempDir::new("auth-malformed");
+        let path = dir.write(".htpasswd", "alice:{SHA}W6ph5Mm5Pz8GgiULbPgzG37mj9g=\n");
+        let error = Htpasswd::from_file(&path).err().unwrap().to_string();
+        assert!(error.contains("line 1: unsupported hash"), "{}", error);
+
+        let path = dir.write(".htpasswd", "# nobody\n");
+        assert!(Htpasswd::from_file(&path).is_err());
+    }
+
+    #[tokio::test]
+    async fn valid_login_is_let_through() {
+        let (status, _) = respond("auth-valid", Some(&basic("alice:password"))).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn scheme_is_case_insensitive() {
+        let credentials = STANDARD.encode("bob:secret");
+        for scheme in ["basic", "BASIC"] {
+            let authorization = format!("{} {}", scheme, credentials);
+            let (status, _) = respond("auth-scheme", Some(&authorization)).await;
+            assert_eq!(status, StatusCode::OK, "{}", scheme);
+        }
+        let authorization = format!("Bearer {}", credentials);
+        let (status, _) = respond("auth-scheme", Some(&authorization)).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn wrong_password_is_challenged() {
+        let (status, challenge) = respond("auth-wrong", Some(&basic("alice:wrong"))).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            challenge.unwrap(),
+            "Basic realm=\"files \\\"here\\\"\", charset=\"UTF-8\""
+        );
+    }
+
+    #[tokio::test]
+    async fn missing_header_is_challenged() {
+        let (status, challenge) = respond("auth-missing", None).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert!(challenge.is_some());
+        let (status, _) = respond("auth-bearer", Some("Bearer token")).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+}
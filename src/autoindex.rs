@@ -0,0 +1,294 @@
+use axum::{
+    extract::Request,
+    http::StatusCode,
+    response::{Html, IntoResponse, Response},
+    Json,
+};
+use clap::ValueEnum;
+use serde_json::json;
+use std::{
+    path::Path,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tower::{service_fn, ServiceExt};
+
+use crate::{boxed, dotfiles, path, FileService};
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum, Debug)]
+pub enum Format {
+    Html,
+    Json,
+}
+
+struct Entry {
+    name: String,
+    is_dir: bool,
+    size: u64,
+    modified: Option<SystemTime>,
+}
+
+/// Lists directories that have no index file. Every other request goes to `fallback`.
+/// Dotfiles are left out unless `show_hidden` is set.
+pub fn service(
+    root: &Path,
+    format: Format,
+    show_hidden: bool,
+    fallback: FileService,
+) -> FileService {
+    let root: Arc<Path> = root.canonicalize().unwrap_or(root.to_path_buf()).into();
+    boxed(service_fn(move |request: Request| {
+        let root = root.clone();
+        let fallback = fallback.clone();
+        async move {
+            let request_path = request.uri().path().to_string();
+            let dir = path::resolve(&root, &request_path)
+                .filter(|dir| dir.is_dir() && path::is_within(&root, dir));
+            let Some(dir) = dir else {
+                return fallback.oneshot(request).await;
+            };
+            let entries =
+                match tokio::task::spawn_blocking(move || list(&root, &dir, show_hidden)).await {
+                    Ok(Ok(entries)) => entries,
+                    Ok(Err(e)) => {
+                        tracing::error!("autoindex error: {}", e);
+                        return Ok(StatusCode::INTERNAL_SERVER_ERROR.into_response());
+                    }
+                    Err(e) => {
+                        tracing::error!("autoindex task failed: {}", e);
+                        return Ok(StatusCode::INTERNAL_SERVER_ERROR.into_response());
+                    }
+                };
+            Ok(match format {
+                Format::Html => render_html(&request_path, &entries),
+                Format::Json => render_json(&entries),
+            })
+        }
+    }))
+}
+
+/// Reads `dir`, leaving out entries that resolve outside of `root` through a symlink.
+fn list(root: &Path, dir: &Path, show_hidden: bool) -> std::io::Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        if !path::is_within(root, &path)
+            || (!show_hidden && dotfiles::is_hidden_name(&entry.file_name().to_string_lossy()))
+        {
+            continue;
+        }
+        let Ok(metadata) = std::fs::metadata(&path) else {
+            continue;
+        };
+        entries.push(Entry {
+            name: entry.file_name().to_string_lossy().into_owned(),
+            is_dir: metadata.is_dir(),
+            size: metadata.len(),
+            modified: metadata.modified().ok(),
+        });
+    }
+    entries.sort_by(|a, b| b.is_dir.cmp(&a.is_dir).then_with(|| a.name.cmp(&b.name)));
+    Ok(entries)
+}
+
+fn render_html(request_path: &str, entries: &[Entry]) -> Response {
+    let title = escape(&percent_encoding::percent_decode_str(request_path).decode_utf8_lossy());
+    let mut rows = String::new();
+    if request_path != "/" {
+        rows.push_str("<tr><td><a href=\"../\">../</a></td><td></td><td></td></tr>\n");
+    }
+    for entry in entries {
+        let suffix = if entry.is_dir { "/" } else { "" };
+        let href = percent_encoding::utf8_percent_encode(&entry.name, HREF).to_string();
+        let size = if entry.is_dir {
+            "-".to_string()
+        } else {
+            entry.size.to_string()
+        };
+        let modified = entry
+            .modified
+            .map(httpdate::fmt_http_date)
+            .unwrap_or_default();
+        rows.push_str(&format!(
+            "<tr><td><a href=\"{href}{suffix}\">{name}{suffix}</a></td><td>{size}</td><td>{modified}</td></tr>\n",
+            name = escape(&entry.name),
+        ));
+    }
+    Html(format!(
+        "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>Index of {title}</title></head>\n<body>\n<h1>Index of {title}</h1>\n<table>\n<tr><th>Name</th><th>Size</th><th>Modified</th></tr>\n{rows}</table>\n</body>\n</html>\n"
+    ))
+    .into_response()
+}
+
+fn render_json(entries: &[Entry]) -> Response {
+    let entries = entries
+        .iter()
+        .map(|entry| {
+            json!({
+                "name": entry.name,
+                "type": if entry.is_dir { "directory" } else { "file" },
+                "size": entry.size,
+                "modified": entry
+                    .modified
+                    .and_then(|m| m.duration_since(UNIX_EPOCH).ok())
+                    .map(|d| d.as_secs()),
+            })
+        })
+        .collect::<Vec<_>>();
+    Json(entries).into_response()
+}
+
+const HREF: &percent_encoding::AsciiSet = &percent_encoding::NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{body_string, get, TempDir};
+    use std::convert::Infallible;
+
+    /// Lists `uri` below `root`. Requests that are not for a directory get a 404.
+    async fn send(root: &TempDir, format: Format, show_hidden: bool, uri: &str) -> Response {
+        let fallback = boxed(service_fn(|_: Request| async {
+            Ok::<_, Infallible>(StatusCode::NOT_FOUND)
+        }));
+        service(root.path(), format, show_hidden, fallback)
+            .oneshot(get(uri))
+            .await
+            .unwrap()
+    }
+
+    async fn html(root: &TempDir, uri: &str) -> String {
+        let response = send(root, Format::Html, false, uri).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        body_string(response).await
+    }
+
+    /// Where each of `names` is in `html`.
+    fn positions(html: &str, names: &[&str]) -> Vec<usize> {
+        names
+            .iter()
+            .map(|name| {
+                html.find(name)
+                    .unwrap_or_else(|| panic!("{} in {}", name, html))
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn directories_come_before_files() {
+        let root = TempDir::new("autoindex-order");
+        root.write("a.txt", "a");
+        root.write("c.txt", "c");
+        root.write("b/x.txt", "x");
+        root.write("d/x.txt", "x");
+
+        let listing = html(&root, "/").await;
+        let at = positions(&listing, &[">b/<", ">d/<", ">a.txt<", ">c.txt<"]);
+        assert!(at.windows(2).all(|pair| pair[0] < pair[1]), "{}", listing);
+        assert!(!listing.contains("../"), "{}", listing);
+
+        let listing = html(&root, "/b/").await;
+        assert!(listing.contains("<a href=\"../\">../</a>"), "{}", listing);
+        assert!(
+            listing.contains("<title>Index of /b/</title>"),
+            "{}",
+            listing
+        );
+    }
+
+    #[tokio::test]
+    async fn names_are_escaped_and_links_encoded() {
+        let root = TempDir::new("autoindex-escape");
+        root.write("a&b <c>.txt", "a");
+        root.write("say \"hi\"/x.txt", "x");
+
+        let listing = html(&root, "/").await;
+        assert!(
+            listing.contains("<a href=\"a%26b%20%3Cc%3E.txt\">a&amp;b &lt;c&gt;.txt</a>"),
+            "{}",
+            listing
+        );
+        assert!(
+            listing.contains("<a href=\"say%20%22hi%22/\">say &quot;hi&quot;/</a>"),
+            "{}",
+            listing
+        );
+        let listing = html(&root, "/say%20%22hi%22/").await;
+        assert!(
+            listing.contains("<h1>Index of /say &quot;hi&quot;/</h1>"),
+            "{}",
+            listing
+        );
+    }
+
+    #[tokio::test]
+    async fn json_listing() {
+        let root = TempDir::new("autoindex-json");
+        root.write("a.txt", "hello");
+        root.write("b/x.txt", "x");
+
+        let response = send(&root, Format::Json, false, "/").await;
+        assert_eq!(response.headers()["content-type"], "application/json");
+        let entries: serde_json::Value =
+            serde_json::from_str(&body_string(response).await).unwrap();
+        let entries = entries.as_array().unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0]["name"], "b");
+        assert_eq!(entries[0]["type"], "directory");
+        assert_eq!(entries[1]["name"], "a.txt");
+        assert_eq!(entries[1]["type"], "file");
+        assert_eq!(entries[1]["size"], 5);
+        assert!(entries[1]["modified"].as_u64().is_some_and(|m| m > 0));
+    }
+
+    #[tokio::test]
+    async fn dotfiles_are_listed_only_when_shown() {
+        let root = TempDir::new("autoindex-dotfiles");
+        root.write(".env", "secret");
+        root.write(".git/config", "config");
+        root.write("a.txt", "a");
+
+        let listing = html(&root, "/").await;
+        assert!(listing.contains(">a.txt<"), "{}", listing);
+        assert!(!listing.contains(".env"), "{}", listing);
+        assert!(!listing.contains(".git"), "{}", listing);
+
+        let response = send(&root, Format::Html, true, "/").await;
+        let listing = body_string(response).await;
+        assert!(listing.contains(">.env<"), "{}", listing);
+        assert!(listing.contains(">.git/<"), "{}", listing);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn symlinks_out_of_the_root_are_not_listed() {
+        use std::os::unix::fs::symlink;
+
+        let outside = TempDir::new("autoindex-symlinks-outside");
+        let secret = outside.write("secret.txt", "secret");
+        let root = TempDir::new("autoindex-symlinks-root");
+        let file = root.write("docs/a.txt", "a");
+        symlink(&file, root.path().join("inside.txt")).unwrap();
+        symlink(&secret, root.path().join("outside.txt")).unwrap();
+        symlink(outside.path(), root.path().join("outside")).unwrap();
+
+        let listing = html(&root, "/").await;
+        assert!(listing.contains(">inside.txt<"), "{}", listing);
+        assert!(!listing.contains("outside"), "{}", listing);
+        let response = send(&root, Format::Html, false, "/outside/").await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,152 @@
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+use std::{collections::HashMap, path::Path, str::FromStr, sync::Arc};
+
+use crate::error::ServeError;
+
+/// A `Cache-Control` value for files with the given extension, given as `ext=value`.
+#[derive(Clone, Debug)]
+pub struct Rule {
+    pub extension: String,
+    pub value: HeaderValue,
+}
+
+impl FromStr for Rule {
+    type Err = ServeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (extension, value) = s
+            .split_once('=')
+            .ok_or_else(|| ServeError::InvalidHeader(format!("{} must be in EXT=VALUE form", s)))?;
+        let value = HeaderValue::from_str(value.trim()).map_err(|_| {
+            ServeError::InvalidHeader(format!("{} is not a valid Cache-Control value", value))
+        })?;
+        Ok(Rule {
+            extension: extension.trim().trim_start_matches('.').to_lowercase(),
+            value,
+        })
+    }
+}
+
+pub struct CacheControl {
+    rules: HashMap<String, HeaderValue>,
+    default: Option<HeaderValue>,
+}
+
+impl CacheControl {
+    pub fn new(rules: &[Rule], default: Option<HeaderValue>) -> Self {
+        CacheControl {
+            rules: rules
+                .iter()
+                .map(|rule| (rule.extension.clone(), rule.value.clone()))
+                .collect(),
+            default,
+        }
+    }
+
+    fn value_for(&self, request_path: &str) -> Option<&HeaderValue> {
+        let path = if request_path.ends_with('/') {
+            "index.html"
+        } else {
+            request_path
+        };
+        Path::new(path)
+            .extension()
+            .and_then(|extension| self.rules.get(&extension.to_string_lossy().to_lowercase()))
+            .or(self.default.as_ref())
+    }
+}
+
+/// Sets `Cache-Control` on successful file responses that don't have one yet.
+pub async fn apply(
+    State(cache_control): State<Arc<CacheControl>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let value = cache_control.value_for(request.uri().path()).cloned();
+    let mut response = next.run(request).await;
+    let served = response.status().is_success() || response.status().as_u16() == 304;
+    if let Some(value) = value.filter(|_| served) {
+        response
+            .headers_mut()
+            .entry(header::CACHE_CONTROL)
+            .or_insert(value);
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::get;
+    use axum::{middleware, routing::get as route, Router};
+    use tower::ServiceExt;
+
+    fn cache_control(default: Option<&str>) -> CacheControl {
+        let rules = ["js=max-age=31536000, immutable", ".HTML=no-cache"]
+            .iter()
+            .map(|rule| rule.parse().unwrap())
+            .collect::<Vec<Rule>>();
+        CacheControl::new(&rules, default.map(|d| HeaderValue::from_str(d).unwrap()))
+    }
+
+    async fn header(cache_control: CacheControl, uri: &str) -> Option<HeaderValue> {
+        let app = Router::new()
+            .route("/app.js", route(|| async { "js" }))
+            .route(
+                "/own.js",
+                route(|| async { ([(header::CACHE_CONTROL, "private")], "js") }),
+            )
+            .layer(middleware::from_fn_with_state(
+                Arc::new(cache_control),
+                apply,
+            ));
+        let response = app.oneshot(get(uri)).await.unwrap();
+        response.headers().get(header::CACHE_CONTROL).cloned()
+    }
+
+    #[test]
+    fn matched_extension() {
+        let cache_control = cache_control(Some("max-age=60"));
+        assert_eq!(
+            cache_control.value_for("/static/App.JS").unwrap(),
+            "max-age=31536000, immutable"
+        );
+        assert_eq!(cache_control.value_for("/a.html").unwrap(), "no-cache");
+        assert_eq!(cache_control.value_for("/docs/").unwrap(), "no-cache");
+    }
+
+    #[test]
+    fn default_for_other_extensions() {
+        let cache_control = cache_control(Some("max-age=60"));
+        assert_eq!(cache_control.value_for("/a.png").unwrap(), "max-age=60");
+        assert_eq!(cache_control.value_for("/README").unwrap(), "max-age=60");
+    }
+
+    #[test]
+    fn unmatched_without_default() {
+        let cache_control = cache_control(None);
+        assert_eq!(cache_control.value_for("/a.png"), None);
+        assert_eq!(cache_control.value_for("/README"), None);
+    }
+
+    #[test]
+    fn invalid_rules_are_errors() {
+        assert!("js".parse::<Rule>().is_err());
+        assert!("js=a\nb".parse::<Rule>().is_err());
+    }
+
+    #[tokio::test]
+    async fn only_file_responses_without_one_get_it() {
+        let value = header(cache_control(Some("max-age=60")), "/app.js").await;
+        assert_eq!(value.unwrap(), "max-age=31536000, immutable");
+        let value = header(cache_control(Some("max-age=60")), "/own.js").await;
+        assert_eq!(value.unwrap(), "private");
+        let value = header(cache_control(Some("max-age=60")), "/missing.js").await;
+        assert_eq!(value, None);
+    }
+}
@@ -0,0 +1,200 @@
+use axum::{
+    extract::{Request, State},
+    http::{header, uri::Authority, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use clap::ValueEnum;
+use std::{net::IpAddr, sync::Arc};
+
+use crate::tls;
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum, Debug)]
+pub enum Mode {
+    /// redirect www.example.com to example.com.
+    WwwToBare,
+    /// redirect example.com to www.example.com.
+    BareToWww,
+    /// leave the host alone.
+    Off,
+}
+
+/// Parses the domain to canonicalize, with or without `www.`. It is kept without.
+pub fn parse_domain(s: &str) -> Result<String, String> {
+    let domain = s.trim().to_ascii_lowercase();
+    let domain = domain.strip_prefix("www.").unwrap_or(&domain);
+    if domain.is_empty() || domain.contains(['/', ':']) || domain.parse::<Authority>().is_err() {
+        return Err(format!("{} is not a host name like example.com", s));
+    }
+    Ok(domain.to_string())
+}
+
+/// Redirects requests between the `www.` and the bare form of a host name.
+pub struct CanonicalHost {
+    pub mode: Mode,
+    /// Only this domain, without `www.`, is redirected. Any is when there is none.
+    pub domain: Option<String>,
+}
+
+impl CanonicalHost {
+    /// The host name to redirect `host` to, if it is not the canonical one. What it returns is
+    /// canonical itself, so following the redirect does not lead to another one.
+    fn target(&self, host: &str) -> Option<String> {
+        let host = host.to_ascii_lowercase();
+        let bare = host.strip_prefix("www.");
+        let domain = bare.unwrap_or(&host);
+        if self.domain.as_deref().is_some_and(|only| only != domain) {
+            return None;
+        }
+        match self.mode {
+            Mode::WwwToBare => bare.filter(|bare| !bare.is_empty()).map(str::to_string),
+            // Addresses and names like localhost have no www form.
+            Mode::BareToWww if bare.is_none() && is_domain(&host) => Some(format!("www.{}", host)),
+            Mode::BareToWww | Mode::Off => None,
+        }
+    }
+}
+
+fn is_domain(host: &str) -> bool {
+    host.contains('.') && !host.starts_with('[') && host.parse::<IpAddr>().is_err()
+}
+
+/// Middleware that answers requests for a host that is not the canonical one with a 301 to the
+/// canonical host. The scheme, port, path and query are kept, so it does not undo the redirect
+/// from HTTP to HTTPS.
+pub async fn apply(
+    State(canonical): State<Arc<CanonicalHost>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let authority = request
+        .headers()
+        .get(header::HOST)
+        .and_then(|host| host.to_str().ok())
+        .and_then(|host| host.parse::<Authority>().ok())
+        .or_else(|| request.uri().authority().cloned());
+    let Some(authority) = authority else {
+        return next.run(request).await;
+    };
+    let Some(host) = canonical.target(authority.host()) else {
+        return next.run(request).await;
+    };
+    let scheme = if tls::is_tls(&request) {
+        "https"
+    } else {
+        "http"
+    };
+    let port = authority
+        .port_u16()
+        .map(|port| format!(":{}", port))
+        .unwrap_or_default();
+    let path_and_query = request
+        .uri()
+        .path_and_query()
+        .map(|p| p.as_str())
+        .unwrap_or("/");
+    let location = format!("{}://{}{}{}", scheme, host, port, path_and_query);
+    match HeaderValue::from_str(&location) {
+        Ok(location) => (
+            StatusCode::MOVED_PERMANENTLY,
+            [(header::LOCATION, location)],
+        )
+            .into_response(),
+        Err(_) => next.run(request).await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tls::ClientCertificate;
+    use axum::{body::Body, middleware, routing::get, Router};
+    use tower::ServiceExt;
+
+    async fn send(mode: Mode, domain: Option<&str>, host: &str, tls: bool) -> Response {
+        let canonical = CanonicalHost {
+            mode,
+            domain: domain.map(|domain| parse_domain(domain).unwrap()),
+        };
+        let app = Router::new()
+            .route("/{*path}", get(|| async { "ok" }))
+            .layer(middleware::from_fn_with_state(Arc::new(canonical), apply));
+        let mut request = Request::builder()
+            .uri("/docs/page?lang=en")
+            .header(header::HOST, host)
+            .body(Body::empty())
+            .unwrap();
+        if tls {
+            request.extensions_mut().insert(None::<ClientCertificate>);
+        }
+        app.oneshot(request).await.unwrap()
+    }
+
+    async fn location(mode: Mode, domain: Option<&str>, host: &str, tls: bool) -> Option<String> {
+        let response = send(mode, domain, host, tls).await;
+        let location = response.headers().get(header::LOCATION)?;
+        assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+        Some(location.to_str().unwrap().to_string())
+    }
+
+    #[test]
+    fn domains_are_kept_without_www() {
+        assert_eq!(parse_domain("WWW.Example.com").unwrap(), "example.com");
+        assert_eq!(parse_domain("example.com").unwrap(), "example.com");
+        assert!(parse_domain("www.").is_err());
+        assert!(parse_domain("example.com:8080").is_err());
+        assert!(parse_domain("https://example.com").is_err());
+    }
+
+    #[tokio::test]
+    async fn www_is_redirected_to_bare() {
+        assert_eq!(
+            location(Mode::WwwToBare, None, "www.example.com", true).await,
+            Some("https://example.com/docs/page?lang=en".to_string())
+        );
+        assert_eq!(
+            location(Mode::WwwToBare, None, "www.example.com:8080", false).await,
+            Some("http://example.com:8080/docs/page?lang=en".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn bare_is_redirected_to_www() {
+        assert_eq!(
+            location(Mode::BareToWww, None, "Example.com", true).await,
+            Some("https://www.example.com/docs/page?lang=en".to_string())
+        );
+        for host in ["localhost:3000", "127.0.0.1", "[::1]:3000"] {
+            assert_eq!(location(Mode::BareToWww, None, host, false).await, None);
+        }
+    }
+
+    #[tokio::test]
+    async fn canonical_hosts_pass_through() {
+        for (mode, host) in [
+            (Mode::WwwToBare, "example.com"),
+            (Mode::BareToWww, "www.example.com"),
+            (Mode::Off, "www.example.com"),
+        ] {
+            let response = send(mode, None, host, true).await;
+            assert_eq!(response.status(), StatusCode::OK, "{}", host);
+        }
+    }
+
+    #[tokio::test]
+    async fn only_the_given_domain_is_redirected() {
+        let domain = Some("www.example.com");
+        assert_eq!(
+            location(Mode::WwwToBare, domain, "www.example.com", true).await,
+            Some("https://example.com/docs/page?lang=en".to_string())
+        );
+        assert_eq!(
+            location(Mode::WwwToBare, domain, "www.example.org", true).await,
+            None
+        );
+        assert_eq!(
+            location(Mode::BareToWww, domain, "example.org", true).await,
+            None
+        );
+    }
+}
@@ -0,0 +1,155 @@
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::Response,
+};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+use tokio::fs;
+
+use crate::{clean_urls, path};
+
+const SEGMENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+/// The entry names of a directory and its modification time when they were read.
+type Listing = (SystemTime, Arc<[String]>);
+
+/// Finds the file a request path names when only the case of its segments differs, like
+/// `index.html` for `/INDEX.HTML`.
+pub struct CaseInsensitive {
+    root: PathBuf,
+    /// Listings by directory, valid while its modification time stays the same.
+    listings: Mutex<HashMap<PathBuf, Listing>>,
+}
+
+impl CaseInsensitive {
+    pub fn new(root: &Path) -> Self {
+        CaseInsensitive {
+            root: root.to_path_buf(),
+            listings: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The names in `dir`, from the cache unless the directory changed since.
+    async fn listing(&self, dir: &Path) -> Option<Arc<[String]>> {
+        let modified = fs::metadata(dir).await.ok()?.modified().ok()?;
+        if let Some((cached, names)) = self.listings.lock().ok()?.get(dir) {
+            if *cached == modified {
+                return Some(names.clone());
+            }
+        }
+        let mut names = Vec::new();
+        let mut read_dir = fs::read_dir(dir).await.ok()?;
+        while let Some(entry) = read_dir.next_entry().await.ok()? {
+            if let Ok(name) = entry.file_name().into_string() {
+                names.push(name);
+            }
+        }
+        // Sorted, so the same name wins every time when several differ only in case.
+        names.sort();
+        let names: Arc<[String]> = names.into();
+        self.listings
+            .lock()
+            .ok()?
+            .insert(dir.to_path_buf(), (modified, names.clone()));
+        Some(names)
+    }
+
+    /// The request path with the real names of every segment, if `request_path` does not name
+    /// a file itself but one exists that differs only in case.
+    async fn find(&self, request_path: &str) -> Option<String> {
+        let file = path::resolve(&self.root, request_path)?;
+        if fs::symlink_metadata(&file).await.is_ok() {
+            return None;
+        }
+        let decoded = percent_decode_str(request_path).decode_utf8().ok()?;
+        let mut dir = self.root.clone();
+        let mut found = String::new();
+        // resolve() accepted the path, so its segments stay below the root.
+        for segment in decoded.split('/').filter(|s| !s.is_empty() && *s != ".") {
+            let name = if fs::symlink_metadata(dir.join(segment)).await.is_ok() {
+                segment.to_string()
+            } else {
+                let lower = segment.to_lowercase();
+                self.listing(&dir)
+                    .await?
+                    .iter()
+                    .find(|name| name.to_lowercase() == lower)?
+                    .clone()
+            };
+            found.push('/');
+            found.push_str(&utf8_percent_encode(&name, SEGMENT).to_string());
+            dir.push(name);
+        }
+        if request_path.ends_with('/') {
+            found.push('/');
+        }
+        Some(found)
+    }
+}
+
+/// Middleware that rewrites a request path that names no file to the file that differs only in
+/// case, if there is one. Other requests are passed on as they are and may get a 404.
+pub async fn apply(
+    State(case): State<Arc<CaseInsensitive>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let request_path = request.uri().path().to_string();
+    if let Some(found) = case.find(&request_path).await {
+        tracing::debug!("serving {} as {}", request_path, found);
+        clean_urls::set_path(&mut request, &found);
+    }
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[tokio::test]
+    async fn finds_names_that_differ_in_case() {
+        let root = TempDir::new("case-find");
+        root.write("index.html", "home");
+        root.write("Docs/Read Me.txt", "read me");
+        let case = CaseInsensitive::new(root.path());
+
+        assert_eq!(
+            case.find("/INDEX.HTML").await.as_deref(),
+            Some("/index.html")
+        );
+        assert_eq!(
+            case.find("/docs/read%20me.TXT").await.as_deref(),
+            Some("/Docs/Read%20Me.txt")
+        );
+        assert_eq!(case.find("/DOCS/").await.as_deref(), Some("/Docs/"));
+        // Paths that name a file are left alone.
+        assert_eq!(case.find("/index.html").await, None);
+        assert_eq!(case.find("/missing.html").await, None);
+        assert_eq!(case.find("/Docs/missing.txt").await, None);
+        assert_eq!(case.find("/../INDEX.HTML").await, None);
+    }
+
+    #[tokio::test]
+    async fn listings_are_read_again_when_the_directory_changes() {
+        let root = TempDir::new("case-listing");
+        root.write("a.txt", "a");
+        let case = CaseInsensitive::new(root.path());
+        assert_eq!(case.find("/B.TXT").await, None);
+
+        // Adding a file changes the modification time of the directory.
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        root.write("b.txt", "b");
+        assert_eq!(case.find("/B.TXT").await.as_deref(), Some("/b.txt"));
+    }
+}
@@ -0,0 +1,265 @@
+use axum::{
+    extract::{OriginalUri, Request, State},
+    http::{Method, Uri},
+    middleware::Next,
+    response::{IntoResponse, Redirect, Response},
+};
+use clap::ValueEnum;
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::path;
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum, Debug)]
+pub enum TrailingSlash {
+    /// redirect /blog to /blog/.
+    Add,
+    /// redirect /blog/ to /blog.
+    Remove,
+    /// serve both forms.
+    Ignore,
+}
+
+pub struct CleanUrls {
+    root: PathBuf,
+    index_files: Vec<String>,
+    extensionless: bool,
+    trailing_slash: TrailingSlash,
+}
+
+/// What a request path is served from, when it is not a file by itself.
+enum Page {
+    /// A directory with one of the index files.
+    Index,
+    /// A file with the same name plus `.html`, as request path.
+    Html(String),
+}
+
+impl CleanUrls {
+    pub fn new(
+        root: &Path,
+        index_files: &[String],
+        extensionless: bool,
+        trailing_slash: TrailingSlash,
+    ) -> Self {
+        CleanUrls {
+            root: root.to_path_buf(),
+            index_files: index_files.to_vec(),
+            extensionless,
+            trailing_slash,
+        }
+    }
+
+    async fn is_file(&self, request_path: &str) -> bool {
+        match path::resolve(&self.root, request_path) {
+            Some(file) => tokio::fs::metadata(file)
+                .await
+                .map(|metadata| metadata.is_file())
+                .unwrap_or(false),
+            None => false,
+        }
+    }
+
+    async fn exists(&self, request_path: &str) -> bool {
+        match path::resolve(&self.root, request_path) {
+            Some(file) => tokio::fs::metadata(file).await.is_ok(),
+            None => false,
+        }
+    }
+
+    async fn page(&self, request_path: &str) -> Option<Page> {
+        let trimmed = request_path.trim_end_matches('/');
+        for name in &self.index_files {
+            if self.is_file(&format!("{}/{}", trimmed, name)).await {
+                return Some(Page::Index);
+            }
+        }
+        if !self.extensionless || trimmed.is_empty() || self.exists(trimmed).await {
+            return None;
+        }
+        let html = format!("{}.html", trimmed);
+        self.is_file(&html).await.then_some(Page::Html(html))
+    }
+}
+
+/// Middleware that serves `/about` from `about.html` and redirects to the canonical form of
+/// directory and page URLs. Paths that are files by themselves are left alone.
+pub async fn apply(
+    State(clean): State<Arc<CleanUrls>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    if request.method() != Method::GET && request.method() != Method::HEAD {
+        return next.run(request).await;
+    }
+    let request_path = request.uri().path().to_string();
+    let Some(page) = clean.page(&request_path).await else {
+        return next.run(request).await;
+    };
+    let has_slash = request_path.ends_with('/');
+    match clean.trailing_slash {
+        TrailingSlash::Add if !has_slash => return redirect(&request, true),
+        TrailingSlash::Remove if has_slash && request_path != "/" => {
+            return redirect(&request, false)
+        }
+        _ => {}
+    }
+    match page {
+        Page::Html(html) => set_path(&mut request, &html),
+        // ServeDir redirects directories without a slash, which would loop with the redirect
+        // above.
+        Page::Index if !has_slash && clean.trailing_slash == TrailingSlash::Remove => {
+            set_path(&mut request, &format!("{}/", request_path))
+        }
+        Page::Index => {}
+    }
+    next.run(request).await
+}
+
+/// Redirects permanently to the original URL, including any mount prefix, with the trailing
+/// slash added or removed.
+fn redirect(request: &Request, add_slash: bool) -> Response {
+    let uri = request
+        .extensions()
+        .get::<OriginalUri>()
+        .map(|original| &original.0)
+        .unwrap_or(request.uri());
+    let trimmed = uri.path().trim_matches('/');
+    // A single leading slash, so the location can never turn into a protocol relative URL.
+    let mut location = if add_slash {
+        format!("/{}/", trimmed)
+    } else {
+        format!("/{}", trimmed)
+    };
+    if let Some(query) = uri.query() {
+        location.push('?');
+        location.push_str(query);
+    }
+    Redirect::permanent(&location).into_response()
+}
+
+pub fn set_path(request: &mut Request, request_path: &str) {
+    let path_and_query = match request.uri().query() {
+        Some(query) => format!("{}?{}", request_path, query),
+        None => request_path.to_string(),
+    };
+    let mut parts = request.uri().clone().into_parts();
+    parts.path_and_query = path_and_query.parse().ok();
+    if let Ok(uri) = Uri::from_parts(parts) {
+        *request.uri_mut() = uri;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{body_string, get, TempDir};
+    use axum::{
+        http::{header, StatusCode},
+        middleware, Router,
+    };
+    use tower::ServiceExt;
+    use tower_http::services::ServeDir;
+
+    fn site(name: &str) -> TempDir {
+        let root = TempDir::new(name);
+        root.write("about.html", "about");
+        root.write("blog/index.html", "blog");
+        root.write("notes", "notes without extension");
+        root
+    }
+
+    /// The status and the location or body of the response to `uri`.
+    async fn send(
+        root: &TempDir,
+        trailing_slash: TrailingSlash,
+        uri: &str,
+    ) -> (StatusCode, String) {
+        let clean = CleanUrls::new(
+            root.path(),
+            &["index.html".to_string()],
+            true,
+            trailing_slash,
+        );
+        let service = Router::new()
+            .fallback_service(ServeDir::new(root.path()))
+            .layer(middleware::from_fn_with_state(Arc::new(clean), apply));
+        let app = Router::new()
+            .nest_service("/docs", service.clone())
+            .merge(service);
+        let response = app.oneshot(get(uri)).await.unwrap();
+        let status = response.status();
+        match response.headers().get(header::LOCATION) {
+            Some(location) => (status, location.to_str().unwrap().to_string()),
+            None => (status, body_string(response).await),
+        }
+    }
+
+    #[tokio::test]
+    async fn page_is_served_without_extension() {
+        let root = site("clean-urls-page");
+        let ok = |body: &str| (StatusCode::OK, body.to_string());
+        assert_eq!(
+            send(&root, TrailingSlash::Ignore, "/about").await,
+            ok("about")
+        );
+        assert_eq!(
+            send(&root, TrailingSlash::Ignore, "/about.html").await,
+            ok("about")
+        );
+        // A file by itself is served as it is.
+        let notes = ok("notes without extension");
+        assert_eq!(send(&root, TrailingSlash::Ignore, "/notes").await, notes);
+        let (status, _) = send(&root, TrailingSlash::Ignore, "/missing").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn directory_index_is_resolved() {
+        let root = site("clean-urls-index");
+        let ok = (StatusCode::OK, "blog".to_string());
+        assert_eq!(send(&root, TrailingSlash::Ignore, "/blog/").await, ok);
+        assert_eq!(send(&root, TrailingSlash::Remove, "/blog").await, ok);
+    }
+
+    #[tokio::test]
+    async fn trailing_slash_is_added() {
+        let root = site("clean-urls-add");
+        let redirect = |to: &str| (StatusCode::PERMANENT_REDIRECT, to.to_string());
+        assert_eq!(
+            send(&root, TrailingSlash::Add, "/about").await,
+            redirect("/about/")
+        );
+        assert_eq!(
+            send(&root, TrailingSlash::Add, "/blog?a=1").await,
+            redirect("/blog/?a=1")
+        );
+        let ok = (StatusCode::OK, "about".to_string());
+        assert_eq!(send(&root, TrailingSlash::Add, "/about/").await, ok);
+    }
+
+    #[tokio::test]
+    async fn trailing_slash_is_removed() {
+        let root = site("clean-urls-remove");
+        let redirect = |to: &str| (StatusCode::PERMANENT_REDIRECT, to.to_string());
+        assert_eq!(
+            send(&root, TrailingSlash::Remove, "/blog/").await,
+            redirect("/blog")
+        );
+        assert_eq!(
+            send(&root, TrailingSlash::Remove, "/about/").await,
+            redirect("/about")
+        );
+        // Under a mount the redirect keeps the prefix.
+        let to_docs = redirect("/docs/blog");
+        assert_eq!(
+            send(&root, TrailingSlash::Remove, "/docs/blog/").await,
+            to_docs
+        );
+        // Never a protocol relative URL.
+        let (_, location) = send(&root, TrailingSlash::Remove, "//blog/").await;
+        assert!(!location.starts_with("//"), "{}", location);
+    }
+}
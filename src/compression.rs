@@ -0,0 +1,230 @@
+use axum::{
+    extract::{Request, State},
+    http::{header, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+use clap::ValueEnum;
+use std::sync::Arc;
+use tower_http::{
+    compression::{
+        predicate::{NotForContentType, Predicate, SizeAbove},
+        CompressionLayer,
+    },
+    CompressionLevel,
+};
+
+use crate::{headers, path};
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum, Debug)]
+pub enum Algorithm {
+    Gzip,
+    Br,
+    Deflate,
+    Zstd,
+}
+
+pub fn parse_level(s: &str) -> Result<CompressionLevel, String> {
+    match s {
+        "fastest" => Ok(CompressionLevel::Fastest),
+        "default" => Ok(CompressionLevel::Default),
+        "best" => Ok(CompressionLevel::Best),
+        _ => s.parse().map(CompressionLevel::Precise).map_err(|_| {
+            format!(
+                "{} is not a compression level, use fastest, default, best or a number",
+                s
+            )
+        }),
+    }
+}
+
+/// Formats that are compressed already, which are not worth compressing again.
+pub const COMPRESSED_TYPES: &str = "image/png,image/jpeg,image/gif,image/webp,image/avif,\
+                                    video/*,audio/*,font/woff,font/woff2,application/zip,\
+                                    application/gzip,application/zstd,application/x-bzip2,\
+                                    application/x-xz,application/x-7z-compressed,\
+                                    application/vnd.rar";
+
+/// Parses a content type like `text/html`, or all subtypes of a type like `video/*`. An empty
+/// value makes for an empty list.
+pub fn parse_type(s: &str) -> Result<String, String> {
+    let s = s.trim().to_ascii_lowercase();
+    if s.is_empty() {
+        return Ok(s);
+    }
+    match s.split_once('/') {
+        Some((kind, subtype)) if !kind.is_empty() && !subtype.is_empty() => Ok(s),
+        _ => Err(format!(
+            "{} is not a content type like text/html or video/*",
+            s
+        )),
+    }
+}
+
+/// Whether `content_type` is `pattern`, or one of its subtypes for a pattern like `video/*`.
+pub fn matches(pattern: &str, content_type: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => content_type.starts_with(prefix),
+        None => pattern == content_type,
+    }
+}
+
+/// Decides which responses are compressed. Like tower-http's default predicate, but with a
+/// configurable minimum size and content types.
+#[derive(Clone)]
+pub struct Filter {
+    min_size: SizeAbove,
+    /// Only these types are compressed, or all when empty.
+    include: Arc<[String]>,
+    exclude: Arc<[String]>,
+}
+
+impl Filter {
+    fn allows(&self, content_type: &str) -> bool {
+        let essence = content_type
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase();
+        (self.include.is_empty() || self.include.iter().any(|p| matches(p, &essence)))
+            && !self.exclude.iter().any(|p| matches(p, &essence))
+    }
+}
+
+impl Predicate for Filter {
+    fn should_compress<B>(&self, response: &axum::http::Response<B>) -> bool
+    where
+        B: axum::body::HttpBody,
+    {
+        let content_type = response
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default();
+        // Multipart ranges have no Content-Range of their own, which keeps single ranges out.
+        response.status() != StatusCode::PARTIAL_CONTENT
+            && self.min_size.should_compress(response)
+            && NotForContentType::GRPC.should_compress(response)
+            && NotForContentType::SSE.should_compress(response)
+            && self.allows(content_type)
+            && response.extensions().get::<Uncompressed>().is_none()
+    }
+}
+
+/// Middleware that adds Accept-Encoding to the Vary header of responses that depend on it: the
+/// encoded ones, those the compression layer would have compressed, and with `precompressed`
+/// all of them, since any file may have a precompressed variant. It goes outside the
+/// compression layer and merges the Vary headers of the layers below.
+pub async fn vary(State(precompressed): State<bool>, request: Request, next: Next) -> Response {
+    let mut response = next.run(request).await;
+    let response_headers = response.headers_mut();
+    if precompressed
+        || response_headers.contains_key(header::CONTENT_ENCODING)
+        || headers::varies_on(response_headers, "accept-encoding")
+    {
+        headers::add_vary(response_headers, "accept-encoding");
+    }
+    response
+}
+
+/// Marks a response that is not compressed, whatever its type.
+#[derive(Clone)]
+struct Uncompressed;
+
+/// Middleware that keeps responses to requests below one of `prefixes` from being compressed.
+/// It goes right inside the compression layer.
+pub async fn exclude_paths(
+    State(prefixes): State<Arc<[String]>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let request_path = request.uri().path();
+    let excluded = prefixes
+        .iter()
+        .any(|prefix| path::has_prefix(request_path, prefix));
+    let mut response = next.run(request).await;
+    if excluded {
+        response.extensions_mut().insert(Uncompressed);
+    }
+    response
+}
+
+fn non_empty(types: &[String]) -> Arc<[String]> {
+    types.iter().filter(|t| !t.is_empty()).cloned().collect()
+}
+
+pub fn layer(
+    algorithms: &[Algorithm],
+    level: CompressionLevel,
+    min_size: u16,
+    include: &[String],
+    exclude: &[String],
+) -> CompressionLayer<Filter> {
+    CompressionLayer::new()
+        .gzip(algorithms.contains(&Algorithm::Gzip))
+        .br(algorithms.contains(&Algorithm::Br))
+        .deflate(algorithms.contains(&Algorithm::Deflate))
+        .zstd(algorithms.contains(&Algorithm::Zstd))
+        .quality(level)
+        .compress_when(Filter {
+            min_size: SizeAbove::new(min_size),
+            include: non_empty(include),
+            exclude: non_empty(exclude),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn types(types: &str) -> Arc<[String]> {
+        types.split(',').map(|t| parse_type(t).unwrap()).collect()
+    }
+
+    fn filter(include: &str, exclude: &str) -> Filter {
+        Filter {
+            min_size: SizeAbove::new(0),
+            include: non_empty(&types(include)),
+            exclude: non_empty(&types(exclude)),
+        }
+    }
+
+    #[test]
+    fn types_are_parsed() {
+        assert_eq!(parse_type(" Video/* ").unwrap(), "video/*");
+        assert_eq!(parse_type("").unwrap(), "");
+        assert!(parse_type("html").is_err());
+        assert!(parse_type("/html").is_err());
+        assert!(parse_level("9").is_ok());
+        assert!(parse_level("fast").is_err());
+    }
+
+    #[test]
+    fn wildcards_match_subtypes() {
+        assert!(matches("video/*", "video/mp4"));
+        assert!(matches("text/html", "text/html"));
+        assert!(!matches("text/html", "text/htmlx"));
+        assert!(!matches("video/*", "audio/mp4"));
+    }
+
+    #[test]
+    fn compressed_types_are_excluded_by_default() {
+        let filter = filter("", COMPRESSED_TYPES);
+        assert!(filter.allows("text/html; charset=utf-8"));
+        assert!(filter.allows("application/javascript"));
+        for content_type in ["image/png", "Image/JPEG", "video/mp4", "application/zip"] {
+            assert!(!filter.allows(content_type), "{}", content_type);
+        }
+    }
+
+    #[test]
+    fn only_included_types_are_compressed() {
+        let filter = filter("text/*,application/json", "text/csv");
+        assert!(filter.allows("text/html"));
+        assert!(filter.allows("application/json"));
+        assert!(!filter.allows("text/csv"));
+        assert!(!filter.allows("application/javascript"));
+        assert!(!filter.allows(""));
+    }
+}
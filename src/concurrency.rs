@@ -0,0 +1,114 @@
+use axum::{
+    body::Body,
+    extract::{Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use clap::ValueEnum;
+use futures_util::StreamExt;
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum, Debug)]
+pub enum OnLimit {
+    /// wait until another request has finished.
+    Queue,
+    /// answer with 503 Service Unavailable.
+    Reject,
+}
+
+/// The number of requests that may be handled at once, shared by all listeners.
+pub struct ConcurrencyLimit {
+    permits: Arc<Semaphore>,
+    on_limit: OnLimit,
+}
+
+impl ConcurrencyLimit {
+    pub fn new(max: usize, on_limit: OnLimit) -> Self {
+        ConcurrencyLimit {
+            permits: Arc::new(Semaphore::new(max)),
+            on_limit,
+        }
+    }
+}
+
+/// Middleware that holds a permit for every request until its response body has been sent,
+/// so large downloads count for as long as they keep a file open.
+pub async fn apply(
+    State(limit): State<Arc<ConcurrencyLimit>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let permit = match limit.on_limit {
+        OnLimit::Queue => limit.permits.clone().acquire_owned().await,
+        OnLimit::Reject => match limit.permits.clone().try_acquire_owned() {
+            Ok(permit) => Ok(permit),
+            Err(_) => {
+                tracing::debug!("too many requests in flight, rejecting");
+                return StatusCode::SERVICE_UNAVAILABLE.into_response();
+            }
+        },
+    };
+    // The semaphore is never closed.
+    let Ok(permit) = permit else {
+        return StatusCode::SERVICE_UNAVAILABLE.into_response();
+    };
+    let (parts, body) = next.run(request).await.into_parts();
+    let body = body.into_data_stream().map(move |chunk| {
+        let _permit = &permit;
+        chunk
+    });
+    Response::from_parts(parts, Body::from_stream(body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{body_string, get};
+    use axum::{middleware, routing, Router};
+    use std::time::Duration;
+    use tower::ServiceExt;
+
+    fn app(max: usize, on_limit: OnLimit) -> Router {
+        Router::new()
+            .route("/", routing::get(|| async { "hello" }))
+            .layer(middleware::from_fn_with_state(
+                Arc::new(ConcurrencyLimit::new(max, on_limit)),
+                apply,
+            ))
+    }
+
+    #[tokio::test]
+    async fn requests_over_the_limit_are_rejected() {
+        let app = app(2, OnLimit::Reject);
+        // Responses hold their permits until the body is sent.
+        let first = app.clone().oneshot(get("/")).await.unwrap();
+        let second = app.clone().oneshot(get("/")).await.unwrap();
+        assert_eq!(second.status(), StatusCode::OK);
+        let third = app.clone().oneshot(get("/")).await.unwrap();
+        assert_eq!(third.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        assert_eq!(body_string(first).await, "hello");
+        let fourth = app.clone().oneshot(get("/")).await.unwrap();
+        assert_eq!(fourth.status(), StatusCode::OK);
+        drop(second);
+    }
+
+    #[tokio::test]
+    async fn requests_over_the_limit_wait() {
+        let app = app(1, OnLimit::Queue);
+        let first = app.clone().oneshot(get("/")).await.unwrap();
+        let second = tokio::spawn(app.clone().oneshot(get("/")));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!second.is_finished());
+
+        drop(first);
+        let second = tokio::time::timeout(Duration::from_secs(5), second)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert_eq!(body_string(second).await, "hello");
+    }
+}
@@ -0,0 +1,454 @@
+use axum::{
+    body::{Body, Bytes, HttpBody},
+    http::{Request, Response},
+    middleware::AddExtension,
+    BoxError, Extension,
+};
+use axum_server::{accept::Accept, Server};
+use clap::ValueEnum;
+use futures_util::future::BoxFuture;
+use http_body::{Frame, SizeHint};
+use hyper_util::rt::TokioTimer;
+use std::{
+    future::{ready, Future, Ready},
+    io,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::TcpStream,
+    time::{sleep, Instant, Sleep},
+};
+use tower::{Layer, Service};
+
+use crate::throttle::Pacer;
+
+/// The first bytes of an HTTP/2 connection, which plain HTTP/2 clients send right away.
+const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+/// How long a plain connection may take to send enough to tell its HTTP version.
+const PREFACE_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum, Debug)]
+pub enum Http2 {
+    /// offer HTTP/2 and HTTP/1.1 with TLS ALPN, and accept both over plain HTTP.
+    Auto,
+    /// only serve HTTP/2. Plain HTTP listeners only do so with --h2c.
+    Only,
+    /// only serve HTTP/1.1.
+    Off,
+}
+
+/// How accepted TCP connections are handled, shared by the HTTP and HTTPS listeners.
+#[derive(Copy, Clone, Debug)]
+pub struct ConnectionOptions {
+    pub header_read_timeout: Option<Duration>,
+    pub idle_timeout: Option<Duration>,
+    pub tcp_nodelay: bool,
+    pub http2: Http2,
+    pub h2c: bool,
+    /// Bytes per second the responses of a connection may be sent at together.
+    pub max_rate: Option<usize>,
+}
+
+impl ConnectionOptions {
+    /// Sets up the HTTP side of a server. The TCP side is set up by `acceptor`.
+    pub fn configure<A>(&self, server: &mut Server<A>) {
+        if let Some(timeout) = self.header_read_timeout {
+            server
+                .http_builder()
+                .http1()
+                .timer(TokioTimer::new())
+                .header_read_timeout(timeout);
+        }
+    }
+
+    /// The acceptor for connections TLS is set up on, which choose the protocol with ALPN.
+    pub fn acceptor(&self) -> ConnectionAcceptor {
+        ConnectionAcceptor(*self)
+    }
+
+    /// The acceptor for plain HTTP connections.
+    pub fn plain_acceptor(&self) -> PlainAcceptor {
+        PlainAcceptor(*self)
+    }
+}
+
+/// Sets `TCP_NODELAY` and the idle timeout on accepted connections.
+#[derive(Copy, Clone, Debug)]
+pub struct ConnectionAcceptor(ConnectionOptions);
+
+impl<S> Accept<TcpStream, S> for ConnectionAcceptor {
+    type Stream = IdleTimeout<TcpStream>;
+    type Service = ConnectionService<S>;
+    type Future = Ready<io::Result<(Self::Stream, Self::Service)>>;
+
+    fn accept(&self, stream: TcpStream, service: S) -> Self::Future {
+        let options = self.0;
+        let in_flight = InFlight::default();
+        let pacer = options.max_rate.map(|rate| Arc::new(Pacer::new(rate)));
+        ready(stream.set_nodelay(options.tcp_nodelay).map(|_| {
+            let service = CountInFlight {
+                inner: service,
+                in_flight: in_flight.clone(),
+            };
+            (
+                IdleTimeout::new(stream, options.idle_timeout, in_flight),
+                Extension(pacer).layer(service),
+            )
+        }))
+    }
+}
+
+/// The service of a connection, which counts its requests and gives them the `Pacer` of the
+/// connection with --max-rate.
+pub type ConnectionService<S> = AddExtension<CountInFlight<S>, Option<Arc<Pacer>>>;
+
+/// Like `ConnectionAcceptor`, and closes plain connections of an HTTP version that is not
+/// served. Plain HTTP/2 clients have to know that the server speaks it, they do not ask.
+#[derive(Copy, Clone, Debug)]
+pub struct PlainAcceptor(ConnectionOptions);
+
+impl<S> Accept<TcpStream, S> for PlainAcceptor
+where
+    S: Send + 'static,
+{
+    type Stream = IdleTimeout<TcpStream>;
+    type Service = ConnectionService<S>;
+    type Future = BoxFuture<'static, io::Result<(Self::Stream, Self::Service)>>;
+
+    fn accept(&self, stream: TcpStream, service: S) -> Self::Future {
+        let options = self.0;
+        Box::pin(async move {
+            let only_http2 = match options.http2 {
+                Http2::Off => Some(false),
+                Http2::Only if options.h2c => Some(true),
+                _ => None,
+            };
+            if let Some(only_http2) = only_http2 {
+                let is_http2 = tokio::time::timeout(PREFACE_TIMEOUT, is_http2(&stream))
+                    .await
+                    .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))??;
+                if is_http2 != only_http2 {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "the HTTP version is not served",
+                    ));
+                }
+            }
+            ConnectionAcceptor(options).accept(stream, service).await
+        })
+    }
+}
+
+/// Whether a plain connection starts with the HTTP/2 preface. Only looks at the bytes, which
+/// are still read by the server after.
+async fn is_http2(stream: &TcpStream) -> io::Result<bool> {
+    let mut buf = [0; PREFACE.len()];
+    loop {
+        let n = stream.peek(&mut buf).await?;
+        if n == 0 || buf[..n] != PREFACE[..n] {
+            return Ok(false);
+        }
+        if n == PREFACE.len() {
+            return Ok(true);
+        }
+        // Peeking returns the same bytes right away until more arrive.
+        sleep(Duration::from_millis(10)).await;
+    }
+}
+
+/// The requests of a connection that are still being answered, and when the last one was
+/// done. A connection is not idle while it has any, even when nothing is read or written,
+/// like while a slow upstream works on a proxied request.
+#[derive(Clone)]
+pub struct InFlight(Arc<Mutex<(usize, Instant)>>);
+
+impl Default for InFlight {
+    fn default() -> Self {
+        InFlight(Arc::new(Mutex::new((0, Instant::now()))))
+    }
+}
+
+impl InFlight {
+    /// Counts a request until the returned guard is dropped.
+    fn start(&self) -> InFlightGuard {
+        if let Ok(mut state) = self.0.lock() {
+            state.0 += 1;
+        }
+        InFlightGuard(self.clone())
+    }
+
+    /// Since when no request is being answered, or `None` while one is.
+    fn idle_since(&self) -> Option<Instant> {
+        match self.0.lock() {
+            Ok(state) if state.0 == 0 => Some(state.1),
+            _ => None,
+        }
+    }
+}
+
+struct InFlightGuard(InFlight);
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        if let Ok(mut state) = (self.0).0.lock() {
+            state.0 -= 1;
+            state.1 = Instant::now();
+        }
+    }
+}
+
+/// Wraps the service of a connection to count its requests in `InFlight` until their
+/// responses are sent completely.
+#[derive(Clone)]
+pub struct CountInFlight<S> {
+    inner: S,
+    in_flight: InFlight,
+}
+
+impl<S, R, B> Service<Request<R>> for CountInFlight<S>
+where
+    S: Service<Request<R>, Response = Response<B>>,
+    S::Future: Send + 'static,
+    B: HttpBody<Data = Bytes> + Send + 'static,
+    B::Error: Into<BoxError>,
+{
+    type Response = Response<Body>;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<R>) -> Self::Future {
+        let guard = self.in_flight.start();
+        let response = self.inner.call(request);
+        Box::pin(async move {
+            let response = response.await?;
+            Ok(response.map(|body| {
+                Body::new(GuardedBody {
+                    inner: Body::new(body),
+                    _guard: guard,
+                })
+            }))
+        })
+    }
+}
+
+/// A response body that keeps its request counted until it is dropped.
+struct GuardedBody {
+    inner: Body,
+    _guard: InFlightGuard,
+}
+
+impl HttpBody for GuardedBody {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, Self::Error>>> {
+        Pin::new(&mut self.get_mut().inner).poll_frame(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+/// A stream that fails once nothing was read or written for the timeout, while none of its
+/// requests is being answered. That closes connections that are kept alive without being used.
+pub struct IdleTimeout<S> {
+    inner: S,
+    timeout: Option<(Duration, Pin<Box<Sleep>>)>,
+    in_flight: InFlight,
+}
+
+impl<S> IdleTimeout<S> {
+    fn new(inner: S, timeout: Option<Duration>, in_flight: InFlight) -> Self {
+        IdleTimeout {
+            inner,
+            timeout: timeout.map(|timeout| (timeout, Box::pin(sleep(timeout)))),
+            in_flight,
+        }
+    }
+
+    fn reset(&mut self) {
+        if let Some((timeout, deadline)) = self.timeout.as_mut() {
+            deadline.as_mut().reset(Instant::now() + *timeout);
+        }
+    }
+
+    /// Fails a pending read or write when the deadline has passed. While a request is being
+    /// answered, or when the last one was done after the deadline was set, the deadline is
+    /// moved instead.
+    fn poll_expired<T>(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<T>> {
+        let Some((timeout, deadline)) = self.timeout.as_mut() else {
+            return Poll::Pending;
+        };
+        while deadline.as_mut().poll(cx).is_ready() {
+            let idle_until = match self.in_flight.idle_since() {
+                Some(since) => since + *timeout,
+                None => Instant::now() + *timeout,
+            };
+            if idle_until <= Instant::now() {
+                return Poll::Ready(Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "connection was idle for too long",
+                )));
+            }
+            deadline.as_mut().reset(idle_until);
+        }
+        Poll::Pending
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for IdleTimeout<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        match Pin::new(&mut this.inner).poll_read(cx, buf) {
+            Poll::Ready(result) => {
+                this.reset();
+                Poll::Ready(result)
+            }
+            Poll::Pending => this.poll_expired(cx),
+        }
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for IdleTimeout<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        match Pin::new(&mut this.inner).poll_write(cx, buf) {
+            Poll::Ready(result) => {
+                this.reset();
+                Poll::Ready(result)
+            }
+            Poll::Pending => this.poll_expired(cx),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{routing::get, Router};
+    use std::net::SocketAddr;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    const IDLE: Duration = Duration::from_millis(300);
+
+    /// Serves `app` on a free port with the idle timeout set to `IDLE`.
+    fn serve(app: Router) -> SocketAddr {
+        serve_with(
+            app,
+            ConnectionOptions {
+                header_read_timeout: None,
+                idle_timeout: Some(IDLE),
+                tcp_nodelay: true,
+                http2: Http2::Auto,
+                h2c: false,
+                max_rate: None,
+            },
+        )
+    }
+
+    fn serve_with(app: Router, options: ConnectionOptions) -> SocketAddr {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(crate::listen::serve_plain(
+            listener,
+            app,
+            options,
+            axum_server::Handle::new(),
+        ));
+        addr
+    }
+
+    async fn slow() -> &'static str {
+        sleep(IDLE * 3).await;
+        "done"
+    }
+
+    #[tokio::test]
+    async fn slow_handler_is_not_cut_off() {
+        let addr = serve(Router::new().route("/", get(slow)));
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: test\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        assert!(response.ends_with("done"), "{}", response);
+    }
+
+    #[tokio::test]
+    async fn idle_connection_is_closed() {
+        let addr = serve(Router::new().route("/", get(|| async { "ok" })));
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: test\r\n\r\n")
+            .await
+            .unwrap();
+        let mut buf = [0; 1024];
+        let n = stream.read(&mut buf).await.unwrap();
+        assert!(buf[..n].starts_with(b"HTTP/1.1 200"));
+        let start = Instant::now();
+        let closed = tokio::time::timeout(IDLE * 10, stream.read(&mut buf)).await;
+        assert!(matches!(closed, Ok(Ok(0)) | Ok(Err(_))));
+        assert!(start.elapsed() >= IDLE / 2);
+    }
+
+    #[tokio::test]
+    async fn incomplete_headers_are_cut_off() {
+        let options = ConnectionOptions {
+            header_read_timeout: Some(IDLE),
+            idle_timeout: None,
+            tcp_nodelay: false,
+            http2: Http2::Off,
+            h2c: false,
+            max_rate: None,
+        };
+        let addr = serve_with(Router::new().route("/", get(|| async { "ok" })), options);
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: te")
+            .await
+            .unwrap();
+        let start = Instant::now();
+        let mut response = Vec::new();
+        let closed = tokio::time::timeout(IDLE * 10, stream.read_to_end(&mut response)).await;
+        assert!(closed.is_ok(), "connection still open");
+        assert!(start.elapsed() >= IDLE / 2);
+        assert!(!response.starts_with(b"HTTP/1.1 200"));
+    }
+}
@@ -0,0 +1,97 @@
+use axum::http::{HeaderName, HeaderValue, Method};
+use tower_http::cors::{AllowOrigin, Any, CorsLayer};
+
+use crate::error::ServeError;
+
+/// Builds the CORS layer. No origins means any origin.
+pub fn layer(
+    origins: &[HeaderValue],
+    methods: &[Method],
+    headers: &[HeaderName],
+    credentials: bool,
+) -> Result<CorsLayer, ServeError> {
+    let any_origin = origins.is_empty() || origins.iter().any(|origin| origin == "*");
+    if any_origin && credentials {
+        return Err(ServeError::InvalidCors(
+            "credentials can not be allowed for any origin, list the origins with --cors-origin"
+                .to_string(),
+        ));
+    }
+    // Browsers take * literally in requests with credentials, and tower-http refuses the layer.
+    if credentials && headers.iter().any(|header| header == "*") {
+        return Err(ServeError::InvalidCors(
+            "credentials can not be allowed for any header, list the headers with --cors-headers"
+                .to_string(),
+        ));
+    }
+    if credentials && methods.iter().any(|method| method == "*") {
+        return Err(ServeError::InvalidCors(
+            "credentials can not be allowed for any method, list the methods with --cors-methods"
+                .to_string(),
+        ));
+    }
+    let origins = if any_origin {
+        AllowOrigin::any()
+    } else {
+        AllowOrigin::list(origins.iter().cloned())
+    };
+    let layer = CorsLayer::new()
+        .allow_origin(origins)
+        .allow_methods(methods.to_vec())
+        .allow_credentials(credentials);
+    Ok(match headers {
+        [] => layer,
+        [wildcard] if wildcard == "*" => layer.allow_headers(Any),
+        headers => layer.allow_headers(headers.to_vec()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cors(origins: &[&str], methods: &[&str], headers: &[&str]) -> Result<CorsLayer, ServeError> {
+        let origins = origins
+            .iter()
+            .map(|origin| HeaderValue::from_str(origin).unwrap())
+            .collect::<Vec<_>>();
+        let methods = methods
+            .iter()
+            .map(|method| method.parse().unwrap())
+            .collect::<Vec<_>>();
+        let headers = headers
+            .iter()
+            .map(|header| header.parse().unwrap())
+            .collect::<Vec<_>>();
+        layer(&origins, &methods, &headers, true)
+    }
+
+    #[test]
+    fn credentials_need_explicit_origins_methods_and_headers() {
+        let origin = ["https://example.com"];
+        assert!(matches!(
+            cors(&[], &["GET"], &[]),
+            Err(ServeError::InvalidCors(_))
+        ));
+        assert!(matches!(
+            cors(&["*"], &["GET"], &[]),
+            Err(ServeError::InvalidCors(_))
+        ));
+        assert!(matches!(
+            cors(&origin, &["GET"], &["*"]),
+            Err(ServeError::InvalidCors(_))
+        ));
+        assert!(matches!(
+            cors(&origin, &["GET", "*"], &[]),
+            Err(ServeError::InvalidCors(_))
+        ));
+    }
+
+    #[test]
+    fn credentials_are_allowed_for_listed_origins() {
+        let origins = ["https://example.com", "https://example.org"];
+        let layer = cors(&origins, &["GET", "PUT"], &["x-token"]).unwrap();
+        // tower-http panics on rules browsers would refuse when the layer wraps a service.
+        let _ = tower::Layer::layer(&layer, ());
+    }
+}
@@ -0,0 +1,93 @@
+use axum::{
+    extract::{Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use clap::ValueEnum;
+use percent_encoding::percent_decode_str;
+use std::sync::Arc;
+use tower::ServiceExt;
+
+use crate::FileService;
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum, Debug)]
+pub enum Mode {
+    /// serve them like any other file.
+    Allow,
+    /// answer with 403 Forbidden.
+    Deny,
+    /// answer as if they did not exist.
+    Ignore,
+}
+
+pub struct Dotfiles {
+    mode: Mode,
+    not_found: FileService,
+}
+
+impl Dotfiles {
+    pub fn new(mode: Mode, not_found: FileService) -> Self {
+        Dotfiles { mode, not_found }
+    }
+}
+
+/// Whether a segment of the request path starts with a dot, after percent decoding. The
+/// `.well-known` directory is public by design, so it doesn't count.
+pub fn is_hidden(request_path: &str) -> bool {
+    percent_decode_str(request_path)
+        .decode_utf8_lossy()
+        .split(['/', '\\'])
+        .any(|segment| is_hidden_name(segment) && segment != ".well-known")
+}
+
+/// Whether a file name is a dotfile. `.` and `..` are not.
+pub fn is_hidden_name(name: &str) -> bool {
+    name.starts_with('.') && name != "." && name != ".."
+}
+
+/// Middleware that applies the dotfile policy before a request reaches the files.
+pub async fn apply(
+    State(dotfiles): State<Arc<Dotfiles>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if dotfiles.mode == Mode::Allow || !is_hidden(request.uri().path()) {
+        return next.run(request).await;
+    }
+    match dotfiles.mode {
+        Mode::Deny => StatusCode::FORBIDDEN.into_response(),
+        _ => match dotfiles.not_found.clone().oneshot(request).await {
+            Ok(response) => response,
+            Err(infallible) => match infallible {},
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hidden_segments_anywhere() {
+        for hidden in [
+            "/.env",
+            "/assets/.secret",
+            "/.git/config",
+            "/a/.b/c.txt",
+            "/...",
+        ] {
+            assert!(is_hidden(hidden), "{}", hidden);
+        }
+        for visible in ["/", "/index.html", "/a.b/c", "/.well-known/acme", "/a/../b"] {
+            assert!(!is_hidden(visible), "{}", visible);
+        }
+    }
+
+    #[test]
+    fn encoding_does_not_hide_a_dot() {
+        for encoded in ["/%2eenv", "/assets/%2Esecret", "/a%2f.b", "/a%5c.b"] {
+            assert!(is_hidden(encoded), "{}", encoded);
+        }
+    }
+}
@@ -0,0 +1,136 @@
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use std::{path::Path, sync::Arc};
+
+/// The characters RFC 5987 allows unencoded in an extended parameter value.
+const ATTR_CHAR: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'!')
+    .remove(b'#')
+    .remove(b'$')
+    .remove(b'&')
+    .remove(b'+')
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'^')
+    .remove(b'_')
+    .remove(b'`')
+    .remove(b'|')
+    .remove(b'~');
+
+pub struct ForceDownload {
+    /// Extensions to download, or empty for all files.
+    extensions: Vec<String>,
+}
+
+impl ForceDownload {
+    pub fn new(extensions: &[String]) -> Self {
+        ForceDownload {
+            extensions: extensions
+                .iter()
+                .map(|extension| extension.trim_start_matches('.').to_lowercase())
+                .collect(),
+        }
+    }
+
+    /// The file name to download a request path as, if it should be downloaded.
+    fn file_name(&self, request_path: &str) -> Option<String> {
+        let decoded = percent_decode_str(request_path).decode_utf8().ok()?;
+        let path = if decoded.ends_with('/') {
+            "index.html"
+        } else {
+            &decoded
+        };
+        let path = Path::new(path);
+        if !self.extensions.is_empty() {
+            let extension = path.extension()?.to_string_lossy().to_lowercase();
+            if !self.extensions.contains(&extension) {
+                return None;
+            }
+        }
+        Some(path.file_name()?.to_string_lossy().into_owned())
+    }
+}
+
+/// A `Content-Disposition` that downloads the file as `name`. Names that are not plain ASCII
+/// are sent as RFC 6266 `filename*`, with an ASCII fallback for old clients.
+fn attachment(name: &str) -> Option<HeaderValue> {
+    let is_plain = |c: char| (c.is_ascii_graphic() && c != '"' && c != '\\') || c == ' ';
+    if name.chars().all(is_plain) {
+        return HeaderValue::from_str(&format!("attachment; filename=\"{}\"", name)).ok();
+    }
+    let fallback: String = name
+        .chars()
+        .map(|c| if is_plain(c) { c } else { '_' })
+        .collect();
+    HeaderValue::from_str(&format!(
+        "attachment; filename=\"{}\"; filename*=UTF-8''{}",
+        fallback,
+        utf8_percent_encode(name, ATTR_CHAR)
+    ))
+    .ok()
+}
+
+/// Middleware that makes browsers download successful file responses instead of showing them.
+pub async fn apply(
+    State(download): State<Arc<ForceDownload>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let disposition = download
+        .file_name(request.uri().path())
+        .and_then(|name| attachment(&name));
+    let mut response = next.run(request).await;
+    if let Some(disposition) = disposition.filter(|_| response.status().is_success()) {
+        response
+            .headers_mut()
+            .insert(header::CONTENT_DISPOSITION, disposition);
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_names_are_quoted() {
+        assert_eq!(
+            attachment("report 2024.pdf").unwrap(),
+            "attachment; filename=\"report 2024.pdf\""
+        );
+    }
+
+    #[test]
+    fn other_names_are_encoded() {
+        assert_eq!(
+            attachment("Übersicht €.pdf").unwrap(),
+            "attachment; filename=\"_bersicht _.pdf\"; filename*=UTF-8''%C3%9Cbersicht%20%E2%82%AC.pdf"
+        );
+        assert_eq!(
+            attachment("say \"hi\".txt").unwrap(),
+            "attachment; filename=\"say _hi_.txt\"; filename*=UTF-8''say%20%22hi%22.txt"
+        );
+    }
+
+    #[test]
+    fn file_name_is_the_decoded_basename() {
+        let all = ForceDownload::new(&[]);
+        assert_eq!(all.file_name("/docs/%C3%9C.pdf").unwrap(), "Ü.pdf");
+        assert_eq!(all.file_name("/docs/").unwrap(), "index.html");
+        assert_eq!(all.file_name("/%FF"), None);
+    }
+
+    #[test]
+    fn only_listed_extensions_are_downloaded() {
+        let some = ForceDownload::new(&[".PDF".to_string(), "zip".to_string()]);
+        assert_eq!(some.file_name("/a.pdf").unwrap(), "a.pdf");
+        assert_eq!(some.file_name("/a.Zip").unwrap(), "a.Zip");
+        assert_eq!(some.file_name("/a.html"), None);
+        assert_eq!(some.file_name("/README"), None);
+    }
+}
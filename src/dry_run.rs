@@ -0,0 +1,144 @@
+use clap::{parser::ValueSource, Arg, ArgAction, ArgMatches, Command};
+
+/// Options that name keys, certificates and passwords, or where to find them. They are shown
+/// as given, in a section of their own.
+const SECRETS: &[&str] = &[
+    "basic_auth",
+    "cert",
+    "key",
+    "cert_pem_env",
+    "key_pem_env",
+    "sni_certs",
+    "client_ca",
+    "ocsp_staple",
+    "self_signed_out",
+    "acme_cache",
+];
+
+/// The options in `matches` as TOML, one key per option by its long name. Values that do not
+/// come from the command line are marked with the environment variable they were read from,
+/// or as the default. Options without a value are left out.
+pub fn summary(command: &Command, matches: &ArgMatches) -> String {
+    let mut out = String::from("# the options serve runs with\n");
+    let mut secrets = String::new();
+    table(&mut out, &mut secrets, "", command, matches);
+    if let Some((name, sub_matches)) = matches.subcommand() {
+        if let Some(subcommand) = command.find_subcommand(name) {
+            out.push_str(&format!("\n[{}]\n", name));
+            table(
+                &mut out,
+                &mut secrets,
+                &format!("{}.", name),
+                subcommand,
+                sub_matches,
+            );
+        }
+    }
+    if !secrets.is_empty() {
+        out.push_str("\n# keys, certificates and passwords, as given\n[secrets]\n");
+        out.push_str(&secrets);
+    }
+    out
+}
+
+/// Writes a line for each option of `command` to `out`, or to `secrets` with `prefix` before
+/// its name.
+fn table(
+    out: &mut String,
+    secrets: &mut String,
+    prefix: &str,
+    command: &Command,
+    matches: &ArgMatches,
+) {
+    for arg in command.get_arguments() {
+        let id = arg.get_id().as_str();
+        let Some(value) = value(arg, matches) else {
+            continue;
+        };
+        let name = arg.get_long().unwrap_or(id);
+        let source = match matches.value_source(id) {
+            Some(ValueSource::EnvVariable) => arg
+                .get_env()
+                .map(|var| format!("  # ${}", var.to_string_lossy()))
+                .unwrap_or_default(),
+            Some(ValueSource::DefaultValue) => "  # default".to_string(),
+            _ => String::new(),
+        };
+        if SECRETS.contains(&id) {
+            secrets.push_str(&format!("{}{} = {}{}\n", prefix, name, value, source));
+        } else {
+            out.push_str(&format!("{} = {}{}\n", name, value, source));
+        }
+    }
+}
+
+/// The TOML value of `arg`: a boolean for flags, an array for options that can be repeated and
+/// a string for everything else, as it was given.
+fn value(arg: &Arg, matches: &ArgMatches) -> Option<String> {
+    let id = arg.get_id().as_str();
+    let string =
+        |raw: &std::ffi::OsStr| serde_json::to_string(&raw.to_string_lossy()).unwrap_or_default();
+    match arg.get_action() {
+        ArgAction::SetTrue | ArgAction::SetFalse => Some(matches.get_flag(id).to_string()),
+        ArgAction::Append => {
+            let values = matches.get_raw(id)?.map(string).collect::<Vec<_>>();
+            Some(format!("[{}]", values.join(", ")))
+        }
+        ArgAction::Set => matches.get_raw(id)?.next().map(string),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ServeArgs;
+    use clap::CommandFactory;
+
+    fn summary_of(args: &[&str]) -> String {
+        let command = ServeArgs::command();
+        let matches = command
+            .clone()
+            .try_get_matches_from(std::iter::once("serve").chain(args.iter().copied()))
+            .unwrap();
+        summary(&command, &matches)
+    }
+
+    #[test]
+    fn options_are_toml_keys() {
+        let summary = summary_of(&["site", "--port", "5000", "--cors", "--header", "a: b"]);
+        assert!(summary.contains("\npath = \"site\"\n"), "{}", summary);
+        assert!(summary.contains("\nport = \"5000\"\n"), "{}", summary);
+        assert!(summary.contains("\ncors = true\n"), "{}", summary);
+        assert!(summary.contains("\nheader = [\"a: b\"]\n"), "{}", summary);
+        assert!(
+            summary.contains("\nlog-level = \"error\"  # default\n"),
+            "{}",
+            summary
+        );
+        assert!(!summary.contains("help"), "{}", summary);
+        assert!(!summary.contains("[tls]"), "{}", summary);
+        assert!(!summary.contains("[secrets]"), "{}", summary);
+    }
+
+    #[test]
+    fn keys_and_certificates_are_kept_apart() {
+        let summary = summary_of(&[
+            "--basic-auth",
+            "users.htpasswd",
+            "tls",
+            "--cert",
+            "cert.pem",
+            "--key",
+            "key.pem",
+        ]);
+        let (options, secrets) = summary.split_once("[secrets]\n").unwrap();
+        assert!(options.contains("\n[tls]\n"), "{}", summary);
+        assert!(!options.contains(".pem"), "{}", summary);
+        assert!(!options.contains("users.htpasswd"), "{}", summary);
+        assert_eq!(
+            secrets,
+            "basic-auth = \"users.htpasswd\"\ntls.cert = \"cert.pem\"\ntls.key = \"key.pem\"\n"
+        );
+    }
+}
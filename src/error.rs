@@ -0,0 +1,115 @@
+use std::fmt;
+
+#[derive(Debug)]
+pub enum ServeError {
+    Io(std::io::Error),
+    /// Files or directories given on the command line can not be used, one message each.
+    Invalid(Vec<String>),
+    Notify(notify::Error),
+    /// A path or URL prefix given on the command line can not be used.
+    InvalidPath(String),
+    /// The htpasswd file for basic authentication can not be read or parsed.
+    Htpasswd(String),
+    /// A response header given on the command line is not a valid HTTP header.
+    InvalidHeader(String),
+    /// An error page given on the command line can not be used.
+    InvalidErrorPage(String),
+    /// The CORS options can not be combined.
+    InvalidCors(String),
+    /// An IP address or CIDR network given on the command line can not be parsed.
+    InvalidNetwork(String),
+    /// A reverse proxy given on the command line can not be used.
+    InvalidProxy(String),
+    /// A redirect or rewrite rule given on the command line can not be parsed.
+    InvalidRule(String),
+    /// Options were given that can not be used together.
+    Conflict(String),
+    Metrics(metrics_exporter_prometheus::BuildError),
+    /// The archive to serve can not be read.
+    InvalidArchive(String),
+    /// The TLS configuration can not be set up.
+    Tls(String),
+    /// A certificate can not be obtained from the ACME server.
+    Acme(String),
+    /// The sockets or notifications of systemd can not be used.
+    Systemd(String),
+    /// The CA bundle for client certificates can not be read or parsed.
+    ClientCa(String),
+    /// The environment variable a certificate or key should be read from is not set.
+    MissingEnv(String),
+    /// A certificate or private key is not valid PEM, or holds none.
+    InvalidPem(String),
+    /// An OCSP response can not be read, fetched or parsed.
+    Ocsp(String),
+    /// A hook command can not be run, or failed.
+    Hook(String),
+    /// Spans can not be exported to the OpenTelemetry collector.
+    #[cfg(feature = "otlp")]
+    Otlp(String),
+}
+
+impl fmt::Display for ServeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ServeError::Io(e) => write!(f, "io error: {}", e),
+            ServeError::Invalid(problems) => {
+                write!(f, "invalid options:")?;
+                for problem in problems {
+                    write!(f, "\n  {}", problem)?;
+                }
+                Ok(())
+            }
+            ServeError::Notify(e) => write!(f, "watcher error: {}", e),
+            ServeError::InvalidPath(msg) => write!(f, "invalid path: {}", msg),
+            ServeError::Htpasswd(msg) => write!(f, "htpasswd: {}", msg),
+            ServeError::InvalidHeader(msg) => write!(f, "invalid header: {}", msg),
+            ServeError::InvalidErrorPage(msg) => write!(f, "invalid error page: {}", msg),
+            ServeError::InvalidCors(msg) => write!(f, "invalid CORS configuration: {}", msg),
+            ServeError::InvalidNetwork(msg) => write!(f, "invalid network: {}", msg),
+            ServeError::InvalidProxy(msg) => write!(f, "invalid proxy: {}", msg),
+            ServeError::InvalidRule(msg) => write!(f, "invalid rule: {}", msg),
+            ServeError::Conflict(msg) => write!(f, "conflicting options: {}", msg),
+            ServeError::Metrics(e) => write!(f, "metrics error: {}", e),
+            ServeError::InvalidArchive(msg) => write!(f, "invalid archive: {}", msg),
+            ServeError::Tls(msg) => write!(f, "tls error: {}", msg),
+            ServeError::Acme(msg) => write!(f, "acme error: {}", msg),
+            ServeError::Systemd(msg) => write!(f, "systemd: {}", msg),
+            ServeError::ClientCa(msg) => write!(f, "client CA: {}", msg),
+            ServeError::MissingEnv(var) => write!(f, "environment variable {} is not set", var),
+            ServeError::InvalidPem(msg) => write!(f, "invalid PEM: {}", msg),
+            ServeError::Ocsp(msg) => write!(f, "ocsp: {}", msg),
+            ServeError::Hook(msg) => write!(f, "{}", msg),
+            #[cfg(feature = "otlp")]
+            ServeError::Otlp(msg) => write!(f, "otlp: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for ServeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ServeError::Io(e) => Some(e),
+            ServeError::Notify(e) => Some(e),
+            ServeError::Metrics(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for ServeError {
+    fn from(e: std::io::Error) -> Self {
+        ServeError::Io(e)
+    }
+}
+
+impl From<notify::Error> for ServeError {
+    fn from(e: notify::Error) -> Self {
+        ServeError::Notify(e)
+    }
+}
+
+impl From<metrics_exporter_prometheus::BuildError> for ServeError {
+    fn from(e: metrics_exporter_prometheus::BuildError) -> Self {
+        ServeError::Metrics(e)
+    }
+}
@@ -0,0 +1,166 @@
+use axum::{
+    body::{Body, Bytes},
+    extract::{Request, State},
+    http::{header, HeaderValue, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+use std::{collections::HashMap, path::PathBuf, str::FromStr, sync::Arc};
+
+use crate::error::ServeError;
+
+/// A page to answer responses with the given status with, given as `CODE=PATH`.
+#[derive(Clone, Debug)]
+pub struct ErrorPage {
+    pub status: StatusCode,
+    pub path: PathBuf,
+}
+
+impl FromStr for ErrorPage {
+    type Err = ServeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (status, path) = s.split_once('=').ok_or_else(|| {
+            ServeError::InvalidErrorPage(format!("{} must be in CODE=PATH form", s))
+        })?;
+        let status = status
+            .trim()
+            .parse::<StatusCode>()
+            .ok()
+            .filter(|status| status.is_client_error() || status.is_server_error())
+            .ok_or_else(|| {
+                ServeError::InvalidErrorPage(format!("{} is not a 4xx or 5xx status code", status))
+            })?;
+        Ok(ErrorPage {
+            status,
+            path: PathBuf::from(path),
+        })
+    }
+}
+
+/// The content of every configured error page, read once at startup.
+pub struct ErrorPages {
+    pages: HashMap<StatusCode, (HeaderValue, Bytes)>,
+}
+
+impl ErrorPages {
+    pub fn load(pages: &[ErrorPage]) -> Result<Self, ServeError> {
+        let mut loaded = HashMap::new();
+        for page in pages {
+            let content = std::fs::read(&page.path).map_err(|e| {
+                ServeError::InvalidErrorPage(format!(
+                    "unable to read {}: {}",
+                    page.path.display(),
+                    e
+                ))
+            })?;
+            let content_type = mime_guess::from_path(&page.path)
+                .first_raw()
+                .map(HeaderValue::from_static)
+                .unwrap_or(HeaderValue::from_static("application/octet-stream"));
+            loaded.insert(page.status, (content_type, Bytes::from(content)));
+        }
+        Ok(ErrorPages { pages: loaded })
+    }
+}
+
+/// A response extension for bodies that are pages of their own, like a 404 page of a mount,
+/// which are sent as they are.
+#[derive(Clone, Copy)]
+pub struct OwnPage;
+
+/// Middleware that replaces the body of responses with a configured error page. The status and
+/// headers like `WWW-Authenticate` are kept.
+pub async fn apply(State(pages): State<Arc<ErrorPages>>, request: Request, next: Next) -> Response {
+    let response = next.run(request).await;
+    if response.extensions().get::<OwnPage>().is_some() {
+        return response;
+    }
+    let Some((content_type, content)) = pages.pages.get(&response.status()) else {
+        return response;
+    };
+    let (mut parts, _) = response.into_parts();
+    parts
+        .headers
+        .insert(header::CONTENT_TYPE, content_type.clone());
+    parts.headers.remove(header::CONTENT_LENGTH);
+    parts.headers.remove(header::CONTENT_ENCODING);
+    Response::from_parts(parts, Body::from(content.clone()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{body_string, get, TempDir};
+    use axum::{middleware, response::IntoResponse, routing::get as route, Router};
+    use tower::ServiceExt;
+
+    async fn send(pages: &[ErrorPage], uri: &str) -> Response {
+        let pages = Arc::new(ErrorPages::load(pages).unwrap());
+        Router::new()
+            .route(
+                "/private",
+                route(|| async {
+                    let challenge = [(header::WWW_AUTHENTICATE, "Basic realm=\"x\"")];
+                    (StatusCode::FORBIDDEN, challenge, "forbidden").into_response()
+                }),
+            )
+            .route(
+                "/own",
+                route(|| async {
+                    let mut response = (StatusCode::FORBIDDEN, "own page").into_response();
+                    response.extensions_mut().insert(OwnPage);
+                    response
+                }),
+            )
+            .route("/ok", route(|| async { "ok" }))
+            .layer(middleware::from_fn_with_state(pages, apply))
+            .oneshot(get(uri))
+            .await
+            .unwrap()
+    }
+
+    fn pages(root: &TempDir) -> Vec<ErrorPage> {
+        let forbidden = root.write("403.html", "<h1>Forbidden</h1>");
+        let error = root.write("50x.txt", "try again later");
+        vec![
+            format!("403={}", forbidden.display()).parse().unwrap(),
+            format!("500={}", error.display()).parse().unwrap(),
+        ]
+    }
+
+    #[tokio::test]
+    async fn forbidden_gets_its_page() {
+        let root = TempDir::new("error-pages-403");
+        let response = send(&pages(&root), "/private").await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "text/html");
+        assert!(response.headers().contains_key(header::WWW_AUTHENTICATE));
+        assert_eq!(body_string(response).await, "<h1>Forbidden</h1>");
+    }
+
+    #[tokio::test]
+    async fn other_responses_are_kept() {
+        let root = TempDir::new("error-pages-other");
+        let pages = pages(&root);
+        assert_eq!(body_string(send(&pages, "/ok").await).await, "ok");
+        assert_eq!(body_string(send(&pages, "/own").await).await, "own page");
+        let response = send(&pages, "/missing").await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(body_string(response).await, "");
+    }
+
+    #[test]
+    fn only_error_codes_are_accepted() {
+        let page = "404=./404.html".parse::<ErrorPage>().unwrap();
+        assert_eq!(page.status, StatusCode::NOT_FOUND);
+        for invalid in ["200=ok.html", "abc=x.html", "404", "99=x"] {
+            assert!(invalid.parse::<ErrorPage>().is_err(), "{}", invalid);
+        }
+        let missing = ErrorPage {
+            status: StatusCode::FORBIDDEN,
+            path: PathBuf::from("/nonexistent/403.html"),
+        };
+        assert!(ErrorPages::load(&[missing]).is_err());
+    }
+}
@@ -0,0 +1,290 @@
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderMap, HeaderValue, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use clap::ValueEnum;
+use sha1::{Digest, Sha1};
+use std::{
+    collections::HashMap,
+    io::Read,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::path;
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum, Debug)]
+pub enum Mode {
+    /// hash of the file contents
+    Strong,
+    /// derived from modification time and size
+    Weak,
+    Off,
+}
+
+type Version = (SystemTime, u64);
+
+pub struct Etags {
+    root: PathBuf,
+    mode: Mode,
+    /// Strong tags by file, valid while the modification time and size stay the same.
+    hashes: Mutex<HashMap<PathBuf, (Version, HeaderValue)>>,
+}
+
+impl Etags {
+    pub fn new(root: &Path, mode: Mode) -> Self {
+        Etags {
+            root: root.to_path_buf(),
+            mode,
+            hashes: Mutex::new(HashMap::new()),
+        }
+    }
+
+    async fn etag(self: &Arc<Self>, request_path: &str) -> Option<HeaderValue> {
+        let mut file = path::resolve(&self.root, request_path)?;
+        if request_path.ends_with('/') {
+            file.push("index.html");
+        }
+        let metadata = tokio::fs::metadata(&file).await.ok()?;
+        if !metadata.is_file() {
+            return None;
+        }
+        let version = (metadata.modified().ok()?, metadata.len());
+        match self.mode {
+            Mode::Off => None,
+            Mode::Weak => {
+                let mtime = version.0.duration_since(UNIX_EPOCH).ok()?.as_nanos();
+                HeaderValue::from_str(&format!("W/\"{:x}-{:x}\"", mtime, version.1)).ok()
+            }
+            Mode::Strong => {
+                if let Some((cached, etag)) = self.hashes.lock().ok()?.get(&file) {
+                    if *cached == version {
+                        return Some(etag.clone());
+                    }
+                }
+                let etags = self.clone();
+                tokio::task::spawn_blocking(move || {
+                    let etag = HeaderValue::from_str(&format!("\"{}\"", hash(&file).ok()?)).ok()?;
+                    etags
+                        .hashes
+                        .lock()
+                        .ok()?
+                        .insert(file, (version, etag.clone()));
+                    Some(etag)
+                })
+                .await
+                .ok()?
+            }
+        }
+    }
+}
+
+/// SHA-1 of the content, so tags stay the same across restarts and builds.
+fn hash(file: &Path) -> std::io::Result<String> {
+    let mut file = std::fs::File::open(file)?;
+    let mut hasher = Sha1::new();
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let read = file.read(&mut buf)?;
+        if read == 0 {
+            let digest = hasher.finalize();
+            return Ok(digest.iter().map(|byte| format!("{:02x}", byte)).collect());
+        }
+        hasher.update(&buf[..read]);
+    }
+}
+
+/// Whether an `If-None-Match` header matches `etag`, using the weak comparison RFC 9110 asks
+/// for. Tags of precompressed variants match as well.
+fn matches(headers: &HeaderMap, etag: &HeaderValue) -> Option<bool> {
+    let if_none_match = headers.get(header::IF_NONE_MATCH)?.to_str().ok()?;
+    let etag = etag
+        .to_str()
+        .ok()?
+        .trim_start_matches("W/")
+        .trim_matches('"');
+    Some(if_none_match.split(',').any(|tag| {
+        let tag = tag.trim().trim_start_matches("W/").trim_matches('"');
+        tag == "*" || tag == etag || tag.split_once('-').is_some_and(|(tag, _)| tag == etag)
+    }))
+}
+
+/// Precompressed variants are different representations, so a strong tag must differ too.
+fn for_encoding(etag: HeaderValue, response: &Response) -> HeaderValue {
+    let Some(encoding) = response
+        .headers()
+        .get(header::CONTENT_ENCODING)
+        .and_then(|encoding| encoding.to_str().ok())
+    else {
+        return etag;
+    };
+    match etag.to_str() {
+        Ok(tag) if !tag.starts_with("W/") => {
+            HeaderValue::from_str(&format!("{}-{}\"", tag.trim_end_matches('"'), encoding))
+                .unwrap_or(etag)
+        }
+        _ => etag,
+    }
+}
+
+/// Adds an `ETag` to file responses and answers `If-None-Match` with 304.
+pub async fn apply(State(etags): State<Arc<Etags>>, mut request: Request, next: Next) -> Response {
+    if !matches!(*request.method(), Method::GET | Method::HEAD) {
+        return next.run(request).await;
+    }
+    let Some(etag) = etags.etag(request.uri().path()).await else {
+        return next.run(request).await;
+    };
+    match matches(request.headers(), &etag) {
+        Some(true) => {
+            // A single tag names the representation the client has, which can be a
+            // precompressed variant.
+            let etag = request
+                .headers()
+                .get(header::IF_NONE_MATCH)
+                .filter(|tags| !tags.as_bytes().contains(&b','))
+                .filter(|tag| tag.as_bytes() != b"*")
+                .cloned()
+                .unwrap_or(etag);
+            return (StatusCode::NOT_MODIFIED, [(header::ETAG, etag)]).into_response();
+        }
+        // If-None-Match takes precedence over If-Modified-Since.
+        Some(false) => {
+            request.headers_mut().remove(header::IF_MODIFIED_SINCE);
+        }
+        None => {}
+    }
+    let mut response = next.run(request).await;
+    if matches!(
+        response.status(),
+        StatusCode::OK | StatusCode::PARTIAL_CONTENT
+    ) {
+        let etag = for_encoding(etag, &response);
+        response.headers_mut().insert(header::ETAG, etag);
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{get, TempDir};
+    use axum::{middleware, Router};
+    use tower::ServiceExt;
+    use tower_http::services::ServeDir;
+
+    async fn send(root: &TempDir, mode: Mode, request: Request) -> Response {
+        let etags = Arc::new(Etags::new(root.path(), mode));
+        Router::new()
+            .fallback_service(ServeDir::new(root.path()))
+            .layer(middleware::from_fn_with_state(etags, apply))
+            .oneshot(request)
+            .await
+            .unwrap()
+    }
+
+    fn if_none_match(uri: &str, tags: &str) -> Request {
+        let mut request = get(uri);
+        let tags = HeaderValue::from_str(tags).unwrap();
+        request.headers_mut().insert(header::IF_NONE_MATCH, tags);
+        request
+    }
+
+    #[tokio::test]
+    async fn matching_etag_is_not_modified() {
+        let root = TempDir::new("etag-match");
+        root.write("a.txt", "a");
+        let response = send(&root, Mode::Strong, get("/a.txt")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let etag = response.headers()[header::ETAG]
+            .to_str()
+            .unwrap()
+            .to_string();
+        assert!(etag.starts_with('"'), "{}", etag);
+
+        for tags in [
+            etag.clone(),
+            format!("\"other\", {}", etag),
+            "*".to_string(),
+        ] {
+            let response = send(&root, Mode::Strong, if_none_match("/a.txt", &tags)).await;
+            assert_eq!(response.status(), StatusCode::NOT_MODIFIED, "{}", tags);
+        }
+    }
+
+    #[tokio::test]
+    async fn other_etag_gets_the_file() {
+        let root = TempDir::new("etag-other");
+        root.write("a.txt", "a");
+        let mut request = if_none_match("/a.txt", "\"other\"");
+        // Would be 304 on its own, but If-None-Match takes precedence.
+        let date = HeaderValue::from_str(&httpdate::fmt_http_date(SystemTime::now())).unwrap();
+        request
+            .headers_mut()
+            .insert(header::IF_MODIFIED_SINCE, date);
+        let response = send(&root, Mode::Strong, request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers().contains_key(header::ETAG));
+    }
+
+    #[tokio::test]
+    async fn strong_etag_follows_the_contents() {
+        let root = TempDir::new("etag-contents");
+        root.write("a.txt", "a");
+        let etags = Arc::new(Etags::new(root.path(), Mode::Strong));
+        let first = etags.etag("/a.txt").await.unwrap();
+        root.write("a.txt", "bb");
+        let second = etags.etag("/a.txt").await.unwrap();
+        assert_ne!(first, second);
+        assert_eq!(etags.etag("/missing.txt").await, None);
+    }
+
+    #[tokio::test]
+    async fn strong_etag_is_stable() {
+        let root = TempDir::new("etag-stable");
+        root.write("a.txt", "a");
+        let etags = Arc::new(Etags::new(root.path(), Mode::Strong));
+        // SHA-1 of "a", the same in every build and on every machine.
+        assert_eq!(
+            etags.etag("/a.txt").await.unwrap(),
+            "\"86f7e437faa5a7fce15d1ddcb9eaeaea377667b8\""
+        );
+    }
+
+    #[tokio::test]
+    async fn weak_etag_is_marked() {
+        let root = TempDir::new("etag-weak");
+        root.write("index.html", "home");
+        let response = send(&root, Mode::Weak, get("/")).await;
+        let etag = response.headers()[header::ETAG]
+            .to_str()
+            .unwrap()
+            .to_string();
+        assert!(etag.starts_with("W/\""), "{}", etag);
+        let tag = etag.trim_start_matches("W/");
+        let response = send(&root, Mode::Weak, if_none_match("/", tag)).await;
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+    }
+
+    #[test]
+    fn precompressed_variants_get_their_own_tag() {
+        let response = ([(header::CONTENT_ENCODING, "br")], "").into_response();
+        let etag = for_encoding(HeaderValue::from_static("\"abc\""), &response);
+        assert_eq!(etag, "\"abc-br\"");
+        let etag = for_encoding(HeaderValue::from_static("W/\"abc\""), &response);
+        assert_eq!(etag, "W/\"abc\"");
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::IF_NONE_MATCH,
+            HeaderValue::from_static("\"abc-br\""),
+        );
+        assert_eq!(
+            matches(&headers, &HeaderValue::from_static("\"abc\"")),
+            Some(true)
+        );
+    }
+}
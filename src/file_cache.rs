@@ -0,0 +1,279 @@
+use axum::{
+    body::{Body, Bytes},
+    extract::{Request, State},
+    http::{header, HeaderMap, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use crate::path;
+
+const HITS: &str = "file_cache_hits_total";
+const MISSES: &str = "file_cache_misses_total";
+
+type Version = (SystemTime, u64);
+
+struct Entry {
+    version: Version,
+    headers: HeaderMap,
+    body: Bytes,
+    last_used: u64,
+}
+
+/// Least recently used file responses, up to `capacity` bytes of bodies in total.
+struct Lru {
+    entries: HashMap<PathBuf, Entry>,
+    /// The files by the tick they were last used at, oldest first.
+    order: BTreeMap<u64, PathBuf>,
+    tick: u64,
+    size: usize,
+}
+
+pub struct FileCache {
+    root: PathBuf,
+    capacity: usize,
+    max_file_size: usize,
+    lru: Mutex<Lru>,
+}
+
+impl FileCache {
+    pub fn new(root: &Path, capacity: usize, max_file_size: usize) -> Self {
+        FileCache {
+            root: root.to_path_buf(),
+            capacity,
+            max_file_size: max_file_size.min(capacity),
+            lru: Mutex::new(Lru {
+                entries: HashMap::new(),
+                order: BTreeMap::new(),
+                tick: 0,
+                size: 0,
+            }),
+        }
+    }
+
+    /// The file a request path is served from and its version, if it is small enough to cache.
+    async fn file(&self, request_path: &str) -> Option<(PathBuf, Version)> {
+        let mut file = path::resolve(&self.root, request_path)?;
+        if request_path.ends_with('/') {
+            file.push("index.html");
+        }
+        let metadata = tokio::fs::metadata(&file).await.ok()?;
+        if !metadata.is_file() || metadata.len() > self.max_file_size as u64 {
+            return None;
+        }
+        Some((file, (metadata.modified().ok()?, metadata.len())))
+    }
+
+    fn get(&self, file: &Path, version: Version) -> Option<Response> {
+        let mut lru = self.lru.lock().ok()?;
+        let lru = &mut *lru;
+        lru.tick += 1;
+        let entry = lru.entries.get_mut(file)?;
+        if entry.version != version {
+            lru.order.remove(&entry.last_used);
+            lru.size -= entry.body.len();
+            lru.entries.remove(file);
+            return None;
+        }
+        lru.order.remove(&entry.last_used);
+        lru.order.insert(lru.tick, file.to_path_buf());
+        entry.last_used = lru.tick;
+        let mut response = Response::new(Body::from(entry.body.clone()));
+        *response.headers_mut() = entry.headers.clone();
+        Some(response)
+    }
+
+    fn insert(&self, file: PathBuf, version: Version, headers: HeaderMap, body: Bytes) {
+        let Ok(mut lru) = self.lru.lock() else {
+            return;
+        };
+        let lru = &mut *lru;
+        lru.tick += 1;
+        if let Some(old) = lru.entries.remove(&file) {
+            lru.order.remove(&old.last_used);
+            lru.size -= old.body.len();
+        }
+        while lru.size + body.len() > self.capacity {
+            let Some((_, oldest)) = lru.order.pop_first() else {
+                break;
+            };
+            if let Some(evicted) = lru.entries.remove(&oldest) {
+                tracing::debug!("evicting {} from the file cache", oldest.display());
+                lru.size -= evicted.body.len();
+            }
+        }
+        lru.size += body.len();
+        lru.order.insert(lru.tick, file.clone());
+        lru.entries.insert(
+            file,
+            Entry {
+                version,
+                headers,
+                body,
+                last_used: lru.tick,
+            },
+        );
+    }
+}
+
+/// Conditional and range requests are left to `ServeDir`, which knows how to answer them.
+fn is_plain(request: &Request) -> bool {
+    matches!(*request.method(), Method::GET | Method::HEAD)
+        && ![
+            header::RANGE,
+            header::IF_RANGE,
+            header::IF_MODIFIED_SINCE,
+            header::IF_UNMODIFIED_SINCE,
+        ]
+        .iter()
+        .any(|name| request.headers().contains_key(name))
+}
+
+/// Serves small files from memory. A cached response is used for as long as the file keeps
+/// the modification time and size it had when it was read.
+pub async fn apply(State(cache): State<Arc<FileCache>>, request: Request, next: Next) -> Response {
+    if !is_plain(&request) {
+        return next.run(request).await;
+    }
+    let Some((file, version)) = cache.file(request.uri().path()).await else {
+        return next.run(request).await;
+    };
+    if let Some(response) = cache.get(&file, version) {
+        tracing::debug!("file cache hit for {}", file.display());
+        metrics::counter!(HITS).increment(1);
+        return response;
+    }
+    tracing::debug!("file cache miss for {}", file.display());
+    metrics::counter!(MISSES).increment(1);
+
+    let is_head = request.method() == Method::HEAD;
+    let response = next.run(request).await;
+    // A precompressed variant depends on the request, so only the identity is cached.
+    if is_head
+        || response.status() != StatusCode::OK
+        || response.headers().contains_key(header::CONTENT_ENCODING)
+    {
+        return response;
+    }
+    let (mut parts, body) = response.into_parts();
+    let body = match axum::body::to_bytes(body, usize::MAX).await {
+        Ok(body) => body,
+        Err(e) => {
+            tracing::error!("unable to read {}: {}", file.display(), e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    // The file can change between looking up its version and reading it.
+    if body.len() as u64 == version.1 {
+        parts.headers.remove(header::DATE);
+        cache.insert(file, version, parts.headers.clone(), body.clone());
+    }
+    Response::from_parts(parts, Body::from(body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{body_string, get, TempDir};
+    use axum::{middleware, Router};
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+    use tower::{service_fn, ServiceExt};
+    use tower_http::services::ServeDir;
+
+    /// Serves `root` through a cache, counting the requests that reach the disk.
+    struct Cached {
+        app: Router,
+        reads: Arc<AtomicUsize>,
+    }
+
+    impl Cached {
+        fn new(root: &TempDir, capacity: usize) -> Self {
+            let reads = Arc::new(AtomicUsize::new(0));
+            let serve_dir = ServeDir::new(root.path());
+            let counted = reads.clone();
+            let disk = service_fn(move |request: Request| {
+                counted.fetch_add(1, Ordering::SeqCst);
+                serve_dir.clone().oneshot(request)
+            });
+            let cache = Arc::new(FileCache::new(root.path(), capacity, capacity));
+            let app = Router::new()
+                .fallback_service(disk)
+                .layer(middleware::from_fn_with_state(cache, apply));
+            Cached { app, reads }
+        }
+
+        /// The body of `uri` and whether it came from the disk.
+        async fn get(&self, uri: &str) -> (String, bool) {
+            let before = self.reads.load(Ordering::SeqCst);
+            let response = self.app.clone().oneshot(get(uri)).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            let body = body_string(response).await;
+            (body, self.reads.load(Ordering::SeqCst) > before)
+        }
+    }
+
+    #[tokio::test]
+    async fn second_request_is_a_hit() {
+        let root = TempDir::new("file-cache-hit");
+        root.write("a.txt", "aaaa");
+        let cached = Cached::new(&root, 100);
+        assert_eq!(cached.get("/a.txt").await, ("aaaa".to_string(), true));
+        assert_eq!(cached.get("/a.txt").await, ("aaaa".to_string(), false));
+    }
+
+    #[tokio::test]
+    async fn changed_file_is_read_again() {
+        let root = TempDir::new("file-cache-mtime");
+        root.write("a.txt", "aaaa");
+        let cached = Cached::new(&root, 100);
+        cached.get("/a.txt").await;
+        // The same size, so only the modification time tells.
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        root.write("a.txt", "bbbb");
+        assert_eq!(cached.get("/a.txt").await, ("bbbb".to_string(), true));
+        assert_eq!(cached.get("/a.txt").await, ("bbbb".to_string(), false));
+    }
+
+    #[tokio::test]
+    async fn least_recently_used_is_evicted() {
+        let root = TempDir::new("file-cache-evict");
+        for name in ["a", "b", "c"] {
+            root.write(&format!("{}.txt", name), name.repeat(6));
+        }
+        // Room for two of them.
+        let cached = Cached::new(&root, 12);
+        cached.get("/a.txt").await;
+        cached.get("/b.txt").await;
+        assert!(!cached.get("/a.txt").await.1);
+        cached.get("/c.txt").await;
+        assert!(!cached.get("/a.txt").await.1);
+        assert!(!cached.get("/c.txt").await.1);
+        assert!(cached.get("/b.txt").await.1);
+    }
+
+    #[tokio::test]
+    async fn large_files_and_ranges_are_not_cached() {
+        let root = TempDir::new("file-cache-large");
+        root.write("large.txt", "x".repeat(20));
+        let cached = Cached::new(&root, 12);
+        assert!(cached.get("/large.txt").await.1);
+        assert!(cached.get("/large.txt").await.1);
+
+        root.write("a.txt", "aaaa");
+        let mut request = get("/a.txt");
+        let range = axum::http::HeaderValue::from_static("bytes=0-1");
+        request.headers_mut().insert(header::RANGE, range);
+        let response = cached.app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert!(cached.get("/a.txt").await.1);
+    }
+}
@@ -0,0 +1,213 @@
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::{header, HeaderMap},
+    middleware::Next,
+    response::Response,
+};
+use ipnet::IpNet;
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
+
+use crate::error::ServeError;
+
+/// The address of the client that sent a request. Behind a trusted proxy this is the address
+/// the proxy forwarded, otherwise the address of the connection.
+#[derive(Copy, Clone, Debug)]
+pub struct ClientIp(pub IpAddr);
+
+/// Parses a network like 10.0.0.0/8. A bare address is a network of one.
+pub fn parse_network(s: &str) -> Result<IpNet, ServeError> {
+    s.parse::<IpNet>()
+        .or_else(|_| s.parse::<IpAddr>().map(IpNet::from))
+        .map_err(|_| {
+            ServeError::InvalidNetwork(format!("{} is not an IP address or CIDR network", s))
+        })
+}
+
+/// Addresses from the `Forwarded` header, or from `X-Forwarded-For` if there is none, in the
+/// order the proxies added them. `None` marks an entry that is not an IP address.
+fn forwarded_for(headers: &HeaderMap) -> Vec<Option<IpAddr>> {
+    let values = |name: &str| {
+        headers
+            .get_all(name)
+            .iter()
+            .flat_map(|value| value.to_str().unwrap_or_default().split(','))
+            .map(str::trim)
+            .collect::<Vec<_>>()
+    };
+    let forwarded = values(header::FORWARDED.as_str());
+    if !forwarded.is_empty() {
+        return forwarded
+            .into_iter()
+            .map(|element| {
+                let node = element.split(';').find_map(|pair| {
+                    let (key, value) = pair.split_once('=')?;
+                    key.trim()
+                        .eq_ignore_ascii_case("for")
+                        .then(|| value.trim().trim_matches('"'))
+                })?;
+                parse_node(node)
+            })
+            .collect();
+    }
+    values("x-forwarded-for")
+        .into_iter()
+        .map(parse_node)
+        .collect()
+}
+
+/// Turns IPv4-mapped IPv6 addresses, as seen on a dual-stack listener, into IPv4 addresses.
+fn canonical(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+        IpAddr::V4(_) => ip,
+    }
+}
+
+/// Parses `1.2.3.4`, `1.2.3.4:80`, `::1` or `[::1]:80`.
+fn parse_node(node: &str) -> Option<IpAddr> {
+    node.parse::<IpAddr>()
+        .or_else(|_| node.parse::<SocketAddr>().map(|addr| addr.ip()))
+        .or_else(|_| node.trim_start_matches('[').trim_end_matches(']').parse())
+        .ok()
+}
+
+/// Middleware that adds the [`ClientIp`] of the request. Forwarded addresses are read from right
+/// to left for as long as the hop that added them is trusted, so a client can not pass itself
+/// off as someone else by sending the headers directly.
+pub async fn client_ip(
+    State(trusted): State<Arc<[IpNet]>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let Some(ConnectInfo(peer)) = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .copied()
+    else {
+        return next.run(request).await;
+    };
+    let client = resolve(&trusted, peer.ip(), request.headers());
+    request.extensions_mut().insert(ClientIp(client));
+    next.run(request).await
+}
+
+/// The client address of a request from `peer`, following the forwarded addresses of trusted
+/// proxies.
+pub fn resolve(trusted: &[IpNet], peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+    let is_trusted = |ip: &IpAddr| trusted.iter().any(|network| network.contains(ip));
+    let mut client = canonical(peer);
+    if is_trusted(&client) {
+        for hop in forwarded_for(headers).into_iter().rev() {
+            match hop {
+                Some(ip) => client = canonical(ip),
+                None => break,
+            }
+            if !is_trusted(&client) {
+                break;
+            }
+        }
+    }
+    client
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn headers(pairs: &[(&str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            let name = header::HeaderName::from_bytes(name.as_bytes()).unwrap();
+            headers.append(name, HeaderValue::from_str(value).unwrap());
+        }
+        headers
+    }
+
+    fn client(trusted: &[&str], peer: &str, pairs: &[(&str, &str)]) -> String {
+        let trusted = trusted
+            .iter()
+            .map(|network| parse_network(network).unwrap())
+            .collect::<Vec<_>>();
+        resolve(&trusted, peer.parse().unwrap(), &headers(pairs)).to_string()
+    }
+
+    #[test]
+    fn networks_and_addresses() {
+        assert_eq!(
+            parse_network("10.0.0.0/8").unwrap().to_string(),
+            "10.0.0.0/8"
+        );
+        assert_eq!(
+            parse_network("10.0.0.1").unwrap().to_string(),
+            "10.0.0.1/32"
+        );
+        assert_eq!(parse_network("::1").unwrap().to_string(), "::1/128");
+        assert!(parse_network("10.0.0.0/33").is_err());
+        assert!(parse_network("proxy").is_err());
+    }
+
+    #[test]
+    fn trusted_proxy_forwards_the_client() {
+        let xff = [("x-forwarded-for", "203.0.113.7")];
+        assert_eq!(client(&["10.0.0.0/8"], "10.0.0.2", &xff), "203.0.113.7");
+        let forwarded = [("forwarded", "for=\"[2001:db8::7]:4711\";proto=https")];
+        assert_eq!(
+            client(&["10.0.0.0/8"], "10.0.0.2", &forwarded),
+            "2001:db8::7"
+        );
+    }
+
+    #[test]
+    fn untrusted_peer_is_the_client() {
+        let xff = [("x-forwarded-for", "203.0.113.7")];
+        assert_eq!(client(&["10.0.0.0/8"], "192.0.2.1", &xff), "192.0.2.1");
+        assert_eq!(client(&[], "10.0.0.2", &xff), "10.0.0.2");
+    }
+
+    #[test]
+    fn chain_is_followed_while_trusted() {
+        // The client claims 1.1.1.1, which the untrusted hop 198.51.100.9 passed on.
+        let xff = [
+            ("x-forwarded-for", "1.1.1.1, 198.51.100.9"),
+            ("x-forwarded-for", "10.0.0.3"),
+        ];
+        assert_eq!(client(&["10.0.0.0/8"], "10.0.0.2", &xff), "198.51.100.9");
+        let all = ["10.0.0.0/8", "198.51.100.0/24"];
+        assert_eq!(client(&all, "10.0.0.2", &xff), "1.1.1.1");
+    }
+
+    #[test]
+    fn forwarded_wins_over_x_forwarded_for() {
+        let both = [
+            ("x-forwarded-for", "203.0.113.1"),
+            ("forwarded", "for=203.0.113.2"),
+        ];
+        assert_eq!(client(&["10.0.0.0/8"], "10.0.0.2", &both), "203.0.113.2");
+    }
+
+    #[test]
+    fn malformed_values_stop_the_chain() {
+        let trusted = ["10.0.0.0/8"];
+        for value in ["unknown", "", "not an ip", "300.1.1.1"] {
+            let xff = [("x-forwarded-for", value)];
+            assert_eq!(client(&trusted, "10.0.0.2", &xff), "10.0.0.2", "{}", value);
+        }
+        let forwarded = [("forwarded", "for=_hidden, for=10.0.0.5")];
+        assert_eq!(client(&trusted, "10.0.0.2", &forwarded), "10.0.0.5");
+        let forwarded = [("forwarded", "proto=https")];
+        assert_eq!(client(&trusted, "10.0.0.2", &forwarded), "10.0.0.2");
+    }
+
+    #[test]
+    fn mapped_addresses_are_ipv4() {
+        let xff = [("x-forwarded-for", "::ffff:203.0.113.7")];
+        assert_eq!(
+            client(&["10.0.0.0/8"], "::ffff:10.0.0.2", &xff),
+            "203.0.113.7"
+        );
+    }
+}
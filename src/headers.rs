@@ -0,0 +1,128 @@
+use axum::http::{header, HeaderMap, HeaderName, HeaderValue};
+use std::str::FromStr;
+
+use crate::error::ServeError;
+
+/// A response header given as `Name: Value`.
+#[derive(Clone, Debug)]
+pub struct Header {
+    pub name: HeaderName,
+    pub value: HeaderValue,
+}
+
+impl FromStr for Header {
+    type Err = ServeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, value) = s.split_once(':').ok_or_else(|| {
+            ServeError::InvalidHeader(format!("{} must be in \"Name: Value\" form", s))
+        })?;
+        let name = HeaderName::from_str(name.trim())
+            .map_err(|_| ServeError::InvalidHeader(format!("{} is not a valid name", name)))?;
+        let value = HeaderValue::from_str(value.trim()).map_err(|_| {
+            ServeError::InvalidHeader(format!("{} is not a valid value for {}", value, name))
+        })?;
+        Ok(Header { name, value })
+    }
+}
+
+/// The names in all Vary headers of `headers`.
+fn vary_names(headers: &HeaderMap) -> Vec<&str> {
+    headers
+        .get_all(header::VARY)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .collect()
+}
+
+/// Whether the Vary headers of `headers` list `name`.
+pub fn varies_on(headers: &HeaderMap, name: &str) -> bool {
+    vary_names(headers)
+        .iter()
+        .any(|vary| vary.eq_ignore_ascii_case(name))
+}
+
+/// Adds `name` to the Vary header of `headers`. Names that are there already, from however many
+/// Vary headers, are kept once.
+pub fn add_vary(headers: &mut HeaderMap, name: &str) {
+    let mut names = Vec::new();
+    for vary in vary_names(headers).into_iter().chain([name]) {
+        if !names.iter().any(|n: &&str| n.eq_ignore_ascii_case(vary)) {
+            names.push(vary);
+        }
+    }
+    let value = if names.contains(&"*") {
+        "*".to_string()
+    } else {
+        names.join(", ")
+    };
+    if let Ok(value) = HeaderValue::from_str(&value) {
+        headers.insert(header::VARY, value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header_is_parsed() {
+        let header: Header = "X-Frame-Options:  DENY ".parse().unwrap();
+        assert_eq!(header.name, "x-frame-options");
+        assert_eq!(header.value, "DENY");
+        let header: Header = "Link: <https://example.com>; rel=preconnect"
+            .parse()
+            .unwrap();
+        assert_eq!(header.value, "<https://example.com>; rel=preconnect");
+    }
+
+    #[test]
+    fn invalid_headers_are_errors() {
+        for invalid in [
+            "X-Frame-Options DENY",
+            "Bad Name: value",
+            "X-Test: a\u{7f}b",
+        ] {
+            assert!(invalid.parse::<Header>().is_err(), "{}", invalid);
+        }
+    }
+
+    fn vary(values: &[&str]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for value in values {
+            headers.append(header::VARY, HeaderValue::from_str(value).unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn vary_names_are_merged() {
+        let mut headers = vary(&[]);
+        add_vary(&mut headers, "accept-encoding");
+        assert_eq!(headers[header::VARY], "accept-encoding");
+
+        let mut headers = vary(&["Origin", "Accept-Language, origin"]);
+        add_vary(&mut headers, "accept-encoding");
+        let values: Vec<_> = headers.get_all(header::VARY).iter().collect();
+        assert_eq!(values, ["Origin, Accept-Language, accept-encoding"]);
+
+        let mut headers = vary(&["Accept-Encoding"]);
+        add_vary(&mut headers, "accept-encoding");
+        assert_eq!(headers[header::VARY], "Accept-Encoding");
+
+        let mut headers = vary(&["*"]);
+        add_vary(&mut headers, "accept-encoding");
+        assert_eq!(headers[header::VARY], "*");
+    }
+
+    #[test]
+    fn vary_names_are_found() {
+        let headers = vary(&["Origin", "Accept-Encoding"]);
+        assert!(varies_on(&headers, "accept-encoding"));
+        assert!(varies_on(&headers, "origin"));
+        assert!(!varies_on(&headers, "accept-language"));
+    }
+}
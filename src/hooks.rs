@@ -0,0 +1,93 @@
+use std::{ffi::OsStr, process::Stdio};
+use tokio::process::Command;
+
+use crate::error::ServeError;
+
+/// The environment variables hooks get from serve's own environment. Everything else they get
+/// is set for them.
+const KEPT_VARS: &[&str] = &[
+    "PATH",
+    "HOME",
+    "USER",
+    "LANG",
+    "TZ",
+    "TMPDIR",
+    "SYSTEMROOT",
+    "COMSPEC",
+    "PATHEXT",
+    "TEMP",
+    "TMP",
+];
+
+/// A shell command that runs `command`, with sh on unix and cmd on Windows. `args` are passed
+/// on to it, as `$1` and up with sh. It gets a minimal environment plus `envs`.
+fn shell(command: &str, args: &[&OsStr], envs: &[(&str, &OsStr)]) -> Command {
+    #[cfg(unix)]
+    let mut shell = {
+        let mut shell = Command::new("sh");
+        shell.arg("-c").arg(command).arg("serve");
+        shell
+    };
+    #[cfg(not(unix))]
+    let mut shell = {
+        let mut shell = Command::new("cmd");
+        shell.arg("/C").arg(command);
+        shell
+    };
+    shell.args(args).stdin(Stdio::null()).env_clear();
+    for (name, value) in std::env::vars_os() {
+        let kept = name
+            .to_str()
+            .is_some_and(|name| KEPT_VARS.contains(&name.to_ascii_uppercase().as_str()));
+        if kept {
+            shell.env(name, value);
+        }
+    }
+    shell.envs(envs.iter().copied());
+    shell
+}
+
+/// Waits for the `name` hook `command` to finish. What it prints goes to the log, stdout as
+/// info and stderr as warnings.
+async fn wait(name: &str, command: &str, mut shell: Command) -> Result<(), ServeError> {
+    let output = shell
+        .output()
+        .await
+        .map_err(|e| ServeError::Hook(format!("unable to run {} hook {}: {}", name, command, e)))?;
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        tracing::info!("{} hook: {}", name, line);
+    }
+    for line in String::from_utf8_lossy(&output.stderr).lines() {
+        tracing::warn!("{} hook: {}", name, line);
+    }
+    if !output.status.success() {
+        return Err(ServeError::Hook(format!(
+            "{} hook {} failed: {}",
+            name, command, output.status
+        )));
+    }
+    tracing::debug!("{} hook {} finished", name, command);
+    Ok(())
+}
+
+/// Runs the `name` hook `command` with `args` and the environment variables in `envs`, and
+/// waits for it to finish.
+pub async fn run(
+    name: &str,
+    command: &str,
+    args: &[&OsStr],
+    envs: &[(&str, &OsStr)],
+) -> Result<(), ServeError> {
+    wait(name, command, shell(command, args, envs)).await
+}
+
+/// Runs the `name` hook `command` in the background, like [`run`]. Failures are logged.
+pub fn spawn(name: &'static str, command: &str, args: &[&OsStr], envs: &[(&str, &OsStr)]) {
+    let shell = shell(command, args, envs);
+    let command = command.to_string();
+    tokio::spawn(async move {
+        if let Err(e) = wait(name, &command, shell).await {
+            tracing::error!("{}", e);
+        }
+    });
+}
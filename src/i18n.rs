@@ -0,0 +1,203 @@
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderMap},
+    middleware::Next,
+    response::Response,
+};
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::{clean_urls, headers, index_files, path};
+
+/// Serves `page.de.html` for `/page` or `/page.html`, and `index.de.html` for directories, to
+/// clients that prefer German.
+pub struct I18n {
+    root: PathBuf,
+    default_lang: Option<String>,
+}
+
+impl I18n {
+    pub fn new(root: &Path, default_lang: Option<&str>) -> Self {
+        I18n {
+            root: root.to_path_buf(),
+            default_lang: default_lang.map(str::to_string),
+        }
+    }
+
+    /// The request path of the best localized variant of `request_path` that exists.
+    async fn variant(&self, request_path: &str, headers: &HeaderMap) -> Option<String> {
+        let (base, extension) = if request_path.ends_with('/') {
+            let (base, extension) = index_files::DEFAULT.rsplit_once('.')?;
+            (format!("{}{}", request_path, base), extension)
+        } else {
+            let (dir, name) = request_path.rsplit_once('/')?;
+            match name.rsplit_once('.') {
+                Some((base, extension)) if !base.is_empty() => {
+                    (format!("{}/{}", dir, base), extension)
+                }
+                _ => (request_path.to_string(), "html"),
+            }
+        };
+        let mut langs = accepted_languages(headers);
+        langs.extend(self.default_lang.clone());
+        for lang in langs {
+            let candidate = format!("{}.{}.{}", base, lang, extension);
+            let Some(file) = path::resolve(&self.root, &candidate) else {
+                continue;
+            };
+            let is_file = tokio::fs::metadata(file)
+                .await
+                .is_ok_and(|metadata| metadata.is_file());
+            if is_file {
+                return Some(candidate);
+            }
+        }
+        None
+    }
+}
+
+/// Parses a language tag like `en` or `pt-BR`.
+pub fn parse_lang(s: &str) -> Result<String, String> {
+    if !is_lang(s) {
+        return Err(format!("{} is not a language tag like en or pt-BR", s));
+    }
+    Ok(s.to_lowercase())
+}
+
+/// Whether `tag` looks like a language tag, so it can not change the path.
+fn is_lang(tag: &str) -> bool {
+    !tag.is_empty()
+        && tag.split('-').all(|subtag| {
+            (1..=8).contains(&subtag.len()) && subtag.chars().all(|c| c.is_ascii_alphanumeric())
+        })
+}
+
+/// The languages of an `Accept-Language` header, most preferred first. A tag like `de-at` is
+/// followed by `de`, and languages with `q=0` are left out.
+fn accepted_languages(headers: &HeaderMap) -> Vec<String> {
+    let Some(value) = headers
+        .get(header::ACCEPT_LANGUAGE)
+        .and_then(|value| value.to_str().ok())
+    else {
+        return Vec::new();
+    };
+    let mut weighted: Vec<(f32, String)> = value
+        .split(',')
+        .filter_map(|item| {
+            let mut params = item.split(';');
+            let tag = params.next()?.trim().to_lowercase();
+            let mut quality = 1.0;
+            for param in params {
+                if let Some(q) = param.trim().strip_prefix("q=") {
+                    quality = q.trim().parse().ok()?;
+                }
+            }
+            (quality > 0.0 && is_lang(&tag)).then_some((quality, tag))
+        })
+        .collect();
+    // Stable, so languages with the same quality stay in the order they were sent.
+    weighted.sort_by(|a, b| b.0.total_cmp(&a.0));
+    let mut langs = Vec::new();
+    for (_, tag) in weighted {
+        let primary = tag.split('-').next().unwrap_or_default().to_string();
+        for lang in [tag, primary] {
+            if !langs.contains(&lang) {
+                langs.push(lang);
+            }
+        }
+    }
+    langs
+}
+
+/// Middleware that serves the localized variant of a file for the client's `Accept-Language`,
+/// or the file itself when there is none.
+pub async fn apply(State(i18n): State<Arc<I18n>>, mut request: Request, next: Next) -> Response {
+    let request_path = request.uri().path().to_string();
+    if let Some(variant) = i18n.variant(&request_path, request.headers()).await {
+        tracing::debug!("serving {} as {}", request_path, variant);
+        clean_urls::set_path(&mut request, &variant);
+    }
+    let mut response = next.run(request).await;
+    headers::add_vary(response.headers_mut(), "accept-language");
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+    use axum::http::HeaderValue;
+
+    fn accept(languages: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::ACCEPT_LANGUAGE,
+            HeaderValue::from_str(languages).unwrap(),
+        );
+        headers
+    }
+
+    fn site(name: &str) -> TempDir {
+        let root = TempDir::new(name);
+        root.write("page.html", "page");
+        root.write("page.en.html", "page en");
+        root.write("page.de.html", "page de");
+        root.write("docs/index.de.html", "docs de");
+        root.write("plain.html", "plain");
+        root
+    }
+
+    #[test]
+    fn languages_are_ordered_by_quality() {
+        assert_eq!(
+            accepted_languages(&accept("fr;q=0.5, de-AT, en;q=0.8, it;q=0, *")),
+            ["de-at", "de", "en", "fr"]
+        );
+        assert!(accepted_languages(&HeaderMap::new()).is_empty());
+    }
+
+    #[test]
+    fn language_tags_are_checked() {
+        assert_eq!(parse_lang("pt-BR").unwrap(), "pt-br");
+        assert!(parse_lang("../etc").is_err());
+        assert!(parse_lang("").is_err());
+    }
+
+    #[tokio::test]
+    async fn matching_language_is_served() {
+        let root = site("i18n-matching");
+        let i18n = I18n::new(root.path(), None);
+        let german = accept("fr, de;q=0.9, en;q=0.8");
+        for path in ["/page", "/page.html"] {
+            let variant = i18n.variant(path, &german).await;
+            assert_eq!(variant.as_deref(), Some("/page.de.html"));
+        }
+        let variant = i18n.variant("/docs/", &german).await;
+        assert_eq!(variant.as_deref(), Some("/docs/index.de.html"));
+    }
+
+    #[tokio::test]
+    async fn default_language_is_the_fallback() {
+        let root = site("i18n-default");
+        let i18n = I18n::new(root.path(), Some("en"));
+        let variant = i18n.variant("/page", &accept("fr")).await;
+        assert_eq!(variant.as_deref(), Some("/page.en.html"));
+        let variant = i18n.variant("/page", &HeaderMap::new()).await;
+        assert_eq!(variant.as_deref(), Some("/page.en.html"));
+    }
+
+    #[tokio::test]
+    async fn files_without_variants_are_served_as_they_are() {
+        let root = site("i18n-none");
+        let i18n = I18n::new(root.path(), Some("en"));
+        assert_eq!(i18n.variant("/plain.html", &accept("de")).await, None);
+        assert_eq!(
+            i18n.variant("/page", &accept("fr")).await.as_deref(),
+            Some("/page.en.html")
+        );
+        let i18n = I18n::new(root.path(), None);
+        assert_eq!(i18n.variant("/page", &accept("fr")).await, None);
+    }
+}
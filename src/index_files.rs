@@ -0,0 +1,54 @@
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::Response,
+};
+use std::{path::PathBuf, sync::Arc};
+
+use crate::{clean_urls, path};
+
+/// The index file `ServeDir` serves by itself.
+pub const DEFAULT: &str = "index.html";
+
+/// The file names that are tried, in order, for a directory request.
+pub struct IndexFiles {
+    root: PathBuf,
+    names: Vec<String>,
+}
+
+impl IndexFiles {
+    pub fn new(root: &std::path::Path, names: &[String]) -> Self {
+        IndexFiles {
+            root: root.to_path_buf(),
+            names: names.to_vec(),
+        }
+    }
+
+    async fn find(&self, request_path: &str) -> Option<&str> {
+        let dir = path::resolve(&self.root, request_path)?;
+        for name in &self.names {
+            let is_file = tokio::fs::metadata(dir.join(name))
+                .await
+                .is_ok_and(|metadata| metadata.is_file());
+            if is_file {
+                return Some(name);
+            }
+        }
+        None
+    }
+}
+
+/// Middleware that serves a directory from the first of the index files that exists in it.
+pub async fn apply(
+    State(index_files): State<Arc<IndexFiles>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let request_path = request.uri().path().to_string();
+    if request_path.ends_with('/') {
+        if let Some(name) = index_files.find(&request_path).await {
+            clean_urls::set_path(&mut request, &format!("{}{}", request_path, name));
+        }
+    }
+    next.run(request).await
+}
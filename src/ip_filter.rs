@@ -0,0 +1,147 @@
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use ipnet::IpNet;
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
+
+use crate::forwarded;
+
+/// Which client addresses may send requests.
+pub struct IpFilter {
+    allow: Vec<IpNet>,
+    deny: Vec<IpNet>,
+    trusted_proxies: Arc<[IpNet]>,
+}
+
+impl IpFilter {
+    pub fn new(allow: &[IpNet], deny: &[IpNet], trusted_proxies: Arc<[IpNet]>) -> Self {
+        IpFilter {
+            allow: allow.to_vec(),
+            deny: deny.to_vec(),
+            trusted_proxies,
+        }
+    }
+
+    /// A denied network wins over an allowed one. Without allowed networks, everyone that is
+    /// not denied is allowed.
+    fn is_allowed(&self, ip: &IpAddr) -> bool {
+        if self.deny.iter().any(|network| network.contains(ip)) {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(|network| network.contains(ip))
+    }
+}
+
+/// Middleware that answers requests from clients that are not allowed with 403 Forbidden. The
+/// client is the connecting peer, or the address a trusted proxy forwarded for it.
+pub async fn apply(State(filter): State<Arc<IpFilter>>, request: Request, next: Next) -> Response {
+    let client = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(peer)| {
+            forwarded::resolve(&filter.trusted_proxies, peer.ip(), request.headers())
+        });
+    match client {
+        Some(ip) if filter.is_allowed(&ip) => next.run(request).await,
+        Some(ip) => {
+            tracing::debug!("refusing request from {}", ip);
+            StatusCode::FORBIDDEN.into_response()
+        }
+        None => StatusCode::FORBIDDEN.into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::get;
+    use axum::{middleware, routing, Router};
+    use tower::ServiceExt;
+
+    fn networks(networks: &[&str]) -> Vec<IpNet> {
+        networks
+            .iter()
+            .map(|network| network.parse().unwrap())
+            .collect()
+    }
+
+    fn filter(allow: &[&str], deny: &[&str]) -> IpFilter {
+        IpFilter::new(
+            &networks(allow),
+            &networks(deny),
+            Arc::from(networks(&["10.0.0.1/32"])),
+        )
+    }
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    #[test]
+    fn allowed_networks() {
+        let filter = filter(&["192.168.0.0/16", "::1/128"], &[]);
+        assert!(filter.is_allowed(&ip("192.168.1.2")));
+        assert!(filter.is_allowed(&ip("::1")));
+        assert!(!filter.is_allowed(&ip("192.169.0.1")));
+        assert!(!filter.is_allowed(&ip("::2")));
+    }
+
+    #[test]
+    fn denied_networks() {
+        let filter = filter(&[], &["192.168.0.0/16"]);
+        assert!(!filter.is_allowed(&ip("192.168.1.2")));
+        assert!(filter.is_allowed(&ip("192.169.0.1")));
+    }
+
+    #[test]
+    fn deny_wins_over_allow() {
+        let filter = filter(&["192.168.0.0/16"], &["192.168.1.0/24"]);
+        assert!(filter.is_allowed(&ip("192.168.2.1")));
+        assert!(!filter.is_allowed(&ip("192.168.1.1")));
+    }
+
+    async fn status(filter: IpFilter, peer: [u8; 4], forwarded_for: Option<&str>) -> StatusCode {
+        let app = Router::new()
+            .route("/", routing::get(|| async { "hello" }))
+            .layer(middleware::from_fn_with_state(Arc::new(filter), apply));
+        let mut request = get("/");
+        request
+            .extensions_mut()
+            .insert(ConnectInfo(SocketAddr::from((peer, 4711))));
+        if let Some(forwarded_for) = forwarded_for {
+            request
+                .headers_mut()
+                .insert("x-forwarded-for", forwarded_for.parse().unwrap());
+        }
+        app.oneshot(request).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn refused_clients_get_403() {
+        let only_one = || filter(&["192.0.2.1/32"], &[]);
+        assert_eq!(
+            status(only_one(), [192, 0, 2, 1], None).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            status(only_one(), [192, 0, 2, 2], None).await,
+            StatusCode::FORBIDDEN
+        );
+        // Only a trusted proxy can forward for someone else.
+        let forwarded = Some("192.0.2.1");
+        assert_eq!(
+            status(only_one(), [10, 0, 0, 1], forwarded).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            status(only_one(), [10, 0, 0, 2], forwarded).await,
+            StatusCode::FORBIDDEN
+        );
+    }
+}
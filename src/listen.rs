@@ -0,0 +1,136 @@
+use axum::Router;
+use socket2::{Domain, Protocol, Socket, Type};
+use std::net::{SocketAddr, TcpListener};
+
+use crate::connection::ConnectionOptions;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Scheme {
+    Tls,
+    Plain,
+}
+
+/// An address to listen on, with the scheme to serve on it if one was given.
+#[derive(Copy, Clone, Debug)]
+pub struct ListenAddr {
+    pub addr: SocketAddr,
+    pub scheme: Option<Scheme>,
+}
+
+/// Parses `ADDR:PORT`, optionally followed by `:tls` or `:plain`.
+pub fn parse(s: &str) -> Result<ListenAddr, String> {
+    let (addr, scheme) = match s.rsplit_once(':') {
+        Some((addr, "tls")) => (addr, Some(Scheme::Tls)),
+        Some((addr, "plain")) => (addr, Some(Scheme::Plain)),
+        _ => (s, None),
+    };
+    let addr = addr
+        .parse()
+        .map_err(|_| format!("{} is not ADDR:PORT, ADDR:PORT:tls or ADDR:PORT:plain", s))?;
+    Ok(ListenAddr { addr, scheme })
+}
+
+/// A bound socket and whether it serves HTTPS.
+pub struct Listener {
+    pub listener: TcpListener,
+    pub tls: bool,
+}
+
+/// How the TCP listeners serve binds are set up.
+#[derive(Copy, Clone, Debug)]
+pub struct SocketOptions {
+    /// Most connections the OS queues up before they are accepted.
+    pub backlog: i32,
+    /// Sets `SO_REUSEADDR` where it is not set anyway.
+    pub reuse_address: bool,
+    /// Sets `SO_REUSEPORT`, so other sockets can listen on the same port.
+    pub reuse_port: bool,
+}
+
+impl Default for SocketOptions {
+    fn default() -> Self {
+        SocketOptions {
+            backlog: 1024,
+            reuse_address: false,
+            reuse_port: false,
+        }
+    }
+}
+
+/// Binds a TCP listener. The unspecified IPv6 address (`::`) is bound as dual-stack, so IPv4
+/// clients are accepted too, unless the platform does not allow it.
+pub fn bind_tcp(addr: SocketAddr, options: SocketOptions) -> std::io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    if addr.is_ipv6() && addr.ip().is_unspecified() {
+        if let Err(e) = socket.set_only_v6(false) {
+            tracing::warn!("unable to enable dual-stack listener: {}", e);
+        }
+    }
+    // On Windows SO_REUSEADDR lets another socket take over the port, so it is only set there
+    // when asked for.
+    if cfg!(not(windows)) || options.reuse_address {
+        socket.set_reuse_address(true)?;
+    }
+    if options.reuse_port {
+        set_reuse_port(&socket)?;
+    }
+    socket.bind(&addr.into())?;
+    socket.listen(options.backlog)?;
+    Ok(socket.into())
+}
+
+#[cfg(all(unix, not(any(target_os = "solaris", target_os = "illumos"))))]
+fn set_reuse_port(socket: &Socket) -> std::io::Result<()> {
+    socket.set_reuse_port(true)
+}
+
+#[cfg(not(all(unix, not(any(target_os = "solaris", target_os = "illumos")))))]
+fn set_reuse_port(_: &Socket) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "--reuse-port is not supported on this platform",
+    ))
+}
+
+/// Serves `app` over plain HTTP on `listener` until `handle` shuts it down.
+pub async fn serve_plain(
+    listener: TcpListener,
+    app: Router,
+    connection: ConnectionOptions,
+    handle: axum_server::Handle,
+) -> std::io::Result<()> {
+    if let Ok(addr) = listener.local_addr() {
+        tracing::info!("listening on {}", addr);
+    }
+    let mut server = axum_server::from_tcp(listener).acceptor(connection.plain_acceptor());
+    connection.configure(&mut server);
+    server
+        .handle(handle)
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_taken_port_can_not_be_bound_again() {
+        let first = bind_tcp("127.0.0.1:0".parse().unwrap(), SocketOptions::default()).unwrap();
+        let addr = first.local_addr().unwrap();
+        assert!(bind_tcp(addr, SocketOptions::default()).is_err());
+    }
+
+    #[cfg(all(unix, not(any(target_os = "solaris", target_os = "illumos"))))]
+    #[test]
+    fn reuse_port_lets_two_listeners_share_a_port() {
+        let options = SocketOptions {
+            reuse_port: true,
+            ..SocketOptions::default()
+        };
+        let first = bind_tcp("127.0.0.1:0".parse().unwrap(), options).unwrap();
+        let addr = first.local_addr().unwrap();
+        let second = bind_tcp(addr, options).unwrap();
+        assert_eq!(second.local_addr().unwrap(), addr);
+    }
+}
@@ -0,0 +1,194 @@
+use axum::{
+    body::Body,
+    extract::{Request, State},
+    http::{header, StatusCode},
+    middleware::Next,
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
+};
+use futures_util::stream::{self, Stream};
+use notify::{Config, RecommendedWatcher, RecursiveMode, Watcher};
+use std::{convert::Infallible, path::PathBuf, time::Duration};
+use tokio::{
+    select,
+    sync::{broadcast, mpsc},
+    time::sleep,
+};
+
+use crate::shutdown_signal;
+
+/// Path of the event stream the injected script listens to.
+pub const PATH: &str = "/__serve/live-reload";
+
+/// Changes that arrive within this time of each other cause a single reload.
+const DEBOUNCE: Duration = Duration::from_millis(100);
+
+/// Watches `dirs` recursively and sends a message on the returned channel for every burst of
+/// changes. The watcher lives as long as the spawned task.
+pub fn watch(dirs: Vec<PathBuf>) -> notify::Result<broadcast::Sender<()>> {
+    let (events_tx, mut events_rx) = mpsc::unbounded_channel();
+    let mut watcher = RecommendedWatcher::new(
+        move |res: notify::Result<notify::Event>| match res {
+            Ok(event) if !event.kind.is_access() => {
+                let _ = events_tx.send(());
+            }
+            Ok(_) => {}
+            Err(e) => tracing::error!("watcher error: {}", e),
+        },
+        Config::default(),
+    )?;
+    for dir in &dirs {
+        watcher.watch(dir, RecursiveMode::Recursive)?;
+    }
+
+    let (reload_tx, _) = broadcast::channel(1);
+    let reload = reload_tx.clone();
+    tokio::spawn(async move {
+        let _watcher = watcher;
+        while events_rx.recv().await.is_some() {
+            sleep(DEBOUNCE).await;
+            while events_rx.try_recv().is_ok() {}
+            tracing::debug!("files changed, reloading browsers");
+            let _ = reload.send(());
+        }
+    });
+    Ok(reload_tx)
+}
+
+/// The event stream for the injected script. It ends on shutdown, so open pages do not hold up
+/// the graceful shutdown.
+pub async fn events(
+    State(reload): State<broadcast::Sender<()>>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let stream = stream::unfold(reload.subscribe(), |mut rx| async move {
+        select! {
+            // A lagged receiver missed messages that would all be reloads anyway.
+            changed = rx.recv() => match changed {
+                Ok(()) | Err(broadcast::error::RecvError::Lagged(_)) => {
+                    Some((Ok(Event::default().data("reload")), rx))
+                }
+                Err(broadcast::error::RecvError::Closed) => None,
+            },
+            _ = shutdown_signal() => None,
+        }
+    });
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+/// Middleware that adds the reload script to complete, uncompressed HTML responses.
+pub async fn inject(request: Request, next: Next) -> Response {
+    let response = next.run(request).await;
+    let is_html = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("text/html"));
+    if response.status() != StatusCode::OK
+        || !is_html
+        || response.headers().contains_key(header::CONTENT_ENCODING)
+    {
+        return response;
+    }
+    let (mut parts, body) = response.into_parts();
+    let mut html = match axum::body::to_bytes(body, usize::MAX).await {
+        Ok(bytes) => bytes.to_vec(),
+        Err(e) => {
+            tracing::error!("unable to read HTML response: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    let at = html
+        .windows(7)
+        .rposition(|window| window.eq_ignore_ascii_case(b"</body>"))
+        .unwrap_or(html.len());
+    let script = format!(
+        "<script>new EventSource(\"{}\").onmessage = () => location.reload();</script>",
+        PATH
+    );
+    html.splice(at..at, script.bytes());
+    parts.headers.remove(header::CONTENT_LENGTH);
+    Response::from_parts(parts, Body::from(html))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{body_string, get};
+    use axum::{middleware, routing, Router};
+    use tower::ServiceExt;
+
+    const SCRIPT: &str =
+        "<script>new EventSource(\"/__serve/live-reload\").onmessage = () => location.reload();</script>";
+
+    /// Sends a request through `inject` to a route that answers with `status`, `headers` and
+    /// `body`.
+    async fn send(
+        status: StatusCode,
+        headers: &'static [(&'static str, &'static str)],
+        body: &'static str,
+    ) -> Response {
+        let app = Router::new()
+            .route(
+                "/",
+                routing::get(move || async move {
+                    let mut response = (status, body).into_response();
+                    for (name, value) in headers {
+                        response.headers_mut().insert(*name, value.parse().unwrap());
+                    }
+                    response
+                }),
+            )
+            .layer(middleware::from_fn(inject));
+        app.oneshot(get("/")).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn the_script_goes_before_the_last_closing_body_tag() {
+        let html = "<body><pre></body></pre></BODY>\n";
+        let response = send(
+            StatusCode::OK,
+            &[
+                ("content-type", "text/html; charset=utf-8"),
+                ("content-length", "32"),
+            ],
+            html,
+        )
+        .await;
+        // The length of the page without the script is dropped, the router sets the new one.
+        let length = response.headers()[header::CONTENT_LENGTH].clone();
+        let body = body_string(response).await;
+        assert_eq!(body, format!("<body><pre></body></pre>{}</BODY>\n", SCRIPT));
+        assert_eq!(length, body.len().to_string());
+    }
+
+    #[tokio::test]
+    async fn the_script_is_appended_without_a_closing_body_tag() {
+        let response = send(
+            StatusCode::OK,
+            &[("content-type", "text/html")],
+            "<p>hi</p>",
+        )
+        .await;
+        assert_eq!(body_string(response).await, format!("<p>hi</p>{}", SCRIPT));
+    }
+
+    #[tokio::test]
+    async fn other_responses_are_left_alone() {
+        let html = "<body></body>";
+        for (status, headers) in [
+            (StatusCode::OK, &[("content-type", "text/css")][..]),
+            (StatusCode::NOT_FOUND, &[("content-type", "text/html")][..]),
+            (
+                StatusCode::OK,
+                &[("content-type", "text/html"), ("content-encoding", "gzip")][..],
+            ),
+        ] {
+            let response = send(status, headers, html).await;
+            assert_eq!(response.status(), status);
+            assert_eq!(response.headers()[header::CONTENT_LENGTH], "13");
+            assert_eq!(body_string(response).await, html, "{:?}", headers);
+        }
+    }
+}
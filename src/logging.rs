@@ -0,0 +1,244 @@
+use axum::{
+    body::Body,
+    extract::{Request, State},
+    http,
+    middleware::Next,
+    response::Response,
+};
+use futures_util::StreamExt;
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    path::PathBuf,
+    sync::Arc,
+    time::Duration,
+};
+use tower_http::{
+    trace::{DefaultOnRequest, DefaultOnResponse, MakeSpan, OnRequest, OnResponse},
+    LatencyUnit,
+};
+use tracing::{field, Level, Span};
+
+#[cfg(feature = "otlp")]
+use crate::otlp;
+use crate::{forwarded, path, redact::Redacted, tls};
+
+/// Makes the span of every request that is not excluded or left out by sampling. Its fields
+/// are what the events of the request are logged with: `request_id` with --request-id,
+/// `status` once the response is known, and `file` and `bytes` once they are.
+#[derive(Clone)]
+pub struct RequestSpan {
+    pub level: Level,
+    pub exclude: Arc<[String]>,
+    pub sample_rate: f64,
+    /// With --log-headers, the headers whose values are left out.
+    pub redacted: Option<Arc<[String]>>,
+}
+
+impl MakeSpan<Body> for RequestSpan {
+    fn make_span(&mut self, request: &Request) -> Span {
+        let path = request.uri().path();
+        if self
+            .exclude
+            .iter()
+            .any(|prefix| path::has_prefix(path, prefix))
+            || !sampled(self.sample_rate)
+        {
+            return Span::none();
+        }
+        let span = request_span(self.level, request);
+        if let Some(redacted) = self.redacted.as_deref() {
+            let headers = Redacted {
+                headers: request.headers(),
+                redacted,
+            };
+            span.record("request_headers", field::display(headers));
+        }
+        span
+    }
+}
+
+/// Whether to record a request, with a chance of `rate`.
+fn sampled(rate: f64) -> bool {
+    // Every RandomState has new keys, so its hashes make do as random numbers.
+    rate >= 1.0 || (RandomState::new().build_hasher().finish() as f64) < rate * u64::MAX as f64
+}
+
+/// The span of a request, at a level that is only known at runtime.
+fn request_span(level: Level, request: &Request) -> Span {
+    macro_rules! span {
+        ($level:expr) => {
+            tracing::span!(
+                $level,
+                "request",
+                method = %request.method(),
+                uri = %request.uri(),
+                path = request.uri().path(),
+                version = ?request.version(),
+                client = tls::client_subject(request),
+                client_ip = request
+                    .extensions()
+                    .get::<forwarded::ClientIp>()
+                    .map(|ip| field::display(ip.0)),
+                request_id = field::Empty,
+                file = field::Empty,
+                status = field::Empty,
+                bytes = field::Empty,
+                request_headers = field::Empty,
+                response_headers = field::Empty,
+            )
+        };
+    }
+    let span = match level {
+        Level::ERROR => span!(Level::ERROR),
+        Level::WARN => span!(Level::WARN),
+        Level::INFO => span!(Level::INFO),
+        Level::DEBUG => span!(Level::DEBUG),
+        _ => span!(Level::TRACE),
+    };
+    #[cfg(feature = "otlp")]
+    otlp::set_parent(&span, request.headers());
+    span
+}
+
+/// Logs that a request started, like the default of tower-http, but not for excluded requests.
+#[derive(Clone, Default)]
+pub struct RequestEvent(DefaultOnRequest);
+
+impl<B> OnRequest<B> for RequestEvent {
+    fn on_request(&mut self, request: &http::Request<B>, span: &Span) {
+        if !span.is_none() {
+            self.0.on_request(request, span)
+        }
+    }
+}
+
+/// Records the status on the span and logs the response. Excluded requests have no span, and
+/// no response event either.
+#[derive(Clone)]
+pub struct ResponseEvent {
+    inner: DefaultOnResponse,
+    redacted: Option<Arc<[String]>>,
+}
+
+impl ResponseEvent {
+    pub fn new(level: Level, latency_unit: LatencyUnit, redacted: Option<Arc<[String]>>) -> Self {
+        ResponseEvent {
+            inner: DefaultOnResponse::new()
+                .level(level)
+                .latency_unit(latency_unit),
+            redacted,
+        }
+    }
+}
+
+impl<B> OnResponse<B> for ResponseEvent {
+    fn on_response(self, response: &http::Response<B>, latency: Duration, span: &Span) {
+        if span.is_none() {
+            return;
+        }
+        span.record("status", response.status().as_u16());
+        if let Some(redacted) = self.redacted.as_deref() {
+            let headers = Redacted {
+                headers: response.headers(),
+                redacted,
+            };
+            span.record("response_headers", field::display(headers));
+        }
+        self.inner.on_response(response, latency, span)
+    }
+}
+
+/// The number of body bytes sent so far. Once the body is dropped, which hyper does as soon as
+/// it has sent all of it, the count is recorded on the span and logged.
+struct Sent {
+    span: Span,
+    level: Level,
+    bytes: usize,
+}
+
+impl Drop for Sent {
+    fn drop(&mut self) {
+        self.span.record("bytes", self.bytes);
+        let _entered = self.span.enter();
+        macro_rules! event {
+            ($level:expr) => {
+                tracing::event!($level, bytes = self.bytes, "finished sending response")
+            };
+        }
+        match self.level {
+            Level::ERROR => event!(Level::ERROR),
+            Level::WARN => event!(Level::WARN),
+            Level::INFO => event!(Level::INFO),
+            Level::DEBUG => event!(Level::DEBUG),
+            _ => event!(Level::TRACE),
+        }
+    }
+}
+
+/// Middleware that counts the bytes of the response body, after ranges were cut out of it but
+/// before it is compressed. It runs inside the span of the request.
+pub async fn count_bytes(State(level): State<Level>, request: Request, next: Next) -> Response {
+    let span = Span::current();
+    let response = next.run(request).await;
+    if span.is_none() {
+        return response;
+    }
+    let mut sent = Sent {
+        span,
+        level,
+        bytes: 0,
+    };
+    let (parts, body) = response.into_parts();
+    let body = body.into_data_stream().map(move |chunk| {
+        // Borrowing all of it moves the count into the closure, so it is dropped with the body.
+        let sent = &mut sent;
+        if let Ok(bytes) = &chunk {
+            sent.bytes += bytes.len();
+        }
+        chunk
+    });
+    Response::from_parts(parts, Body::from_stream(body))
+}
+
+/// Middleware that records the file below `root` a successful response was served from. It
+/// goes innermost, where the request path has been rewritten to the file.
+pub async fn record_file(
+    State(root): State<Arc<PathBuf>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let span = Span::current();
+    let request_path = request.uri().path().to_string();
+    let response = next.run(request).await;
+    if span.is_none() || !response.status().is_success() {
+        return response;
+    }
+    let Some(mut file) = path::resolve(&root, &request_path) else {
+        return response;
+    };
+    if request_path.ends_with('/') {
+        file.push("index.html");
+    }
+    // Fallbacks like the --spa index answer for paths that are no file.
+    if tokio::fs::metadata(&file)
+        .await
+        .is_ok_and(|metadata| metadata.is_file())
+    {
+        span.record("file", field::display(file.display()));
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sample_rates() {
+        assert!((0..100).all(|_| sampled(1.0)));
+        assert!((0..100).all(|_| !sampled(0.0)));
+        let hits = (0..1000).filter(|_| sampled(0.5)).count();
+        assert!((300..700).contains(&hits), "{}", hits);
+    }
+}
@@ -1,21 +1,143 @@
-use axum::{http::StatusCode, Router};
-use axum_server::tls_rustls::RustlsConfig;
-use clap::{Args, Parser, Subcommand, ValueEnum};
-use event::{DataChange, ModifyKind};
-use notify::*;
+mod access_log;
+mod archive;
+mod auth;
+mod autoindex;
+mod cache_control;
+mod canonical_host;
+mod case_insensitive;
+mod clean_urls;
+mod compression;
+mod concurrency;
+mod connection;
+mod cors;
+mod dotfiles;
+mod download;
+mod dry_run;
+mod error;
+mod error_pages;
+mod etag;
+mod file_cache;
+mod forwarded;
+mod headers;
+mod hooks;
+mod i18n;
+mod index_files;
+mod ip_filter;
+mod listen;
+mod live_reload;
+mod logging;
+mod maintenance;
+mod methods;
+mod metrics;
+mod mime_types;
+mod ocsp;
+#[cfg(feature = "otlp")]
+mod otlp;
+mod path;
+mod pattern;
+mod pid_file;
+mod pregenerate;
+mod proxy;
+mod ranges;
+mod redact;
+mod redirects;
+mod request_id;
+mod rewrites;
+mod rolling_file;
+mod root_watch;
+mod security_headers;
+mod server_header;
+mod size;
+mod symlinks;
+mod systemd;
+#[cfg(test)]
+mod test_util;
+mod throttle;
+mod tls;
+#[cfg(unix)]
+mod unix;
+mod upload;
+mod version;
+mod webdav;
+
+use access_log::AccessLog;
+use archive::Archive;
+use auth::{BasicAuth, Htpasswd};
+use axum::{
+    extract::Request,
+    http::{header, HeaderName, HeaderValue, Method, StatusCode},
+    middleware,
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use cache_control::CacheControl;
+use canonical_host::CanonicalHost;
+use case_insensitive::CaseInsensitive;
+use clap::{ArgAction, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
+use clean_urls::{CleanUrls, TrailingSlash};
+use concurrency::{ConcurrencyLimit, OnLimit};
+use connection::{ConnectionOptions, Http2};
+use dotfiles::Dotfiles;
+use download::ForceDownload;
+use error::ServeError;
+use error_pages::{ErrorPage, ErrorPages};
+use etag::Etags;
+use file_cache::FileCache;
+use futures_util::future;
+use headers::Header;
+use i18n::I18n;
+use index_files::IndexFiles;
+use ip_filter::IpFilter;
+use ipnet::IpNet;
+use listen::{bind_tcp, ListenAddr, Listener, Scheme, SocketOptions};
+use logging::{RequestEvent, RequestSpan, ResponseEvent};
+use maintenance::Maintenance;
+use mime_types::{MimeType, MimeTypes};
+#[cfg(feature = "otlp")]
+use otlp::Otlp;
+use pid_file::PidFile;
+use pregenerate::Pregenerate;
+use proxy::Proxy;
+use redirects::Redirect;
+use rewrites::Rewrite;
+use rolling_file::{Compress, RollingFile, Rotation};
+use security_headers::SecurityHeaders;
+use serde_json::json;
+use server_header::ServerHeader;
 use std::{
-    net::{Ipv4Addr, SocketAddr},
-    path::PathBuf,
+    convert::Infallible,
+    ffi::OsStr,
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
+    process::ExitCode,
+    str::FromStr,
+    sync::Arc,
     time::Duration,
 };
-use tokio::{join, runtime::Handle, time::sleep};
+use symlinks::Symlinks;
+use tls::Tls;
+use tokio::{select, signal};
+use tower::{service_fn, util::BoxCloneSyncService, Layer, Service, ServiceExt};
 use tower_http::{
-    compression::CompressionLayer,
+    limit::RequestBodyLimitLayer,
     services::{ServeDir, ServeFile},
+    set_header::SetResponseHeaderLayer,
     set_status::SetStatus,
-    trace::{self, TraceLayer},
+    timeout::TimeoutLayer,
+    trace::TraceLayer,
+    CompressionLevel, LatencyUnit,
 };
 use tracing::Level;
+use tracing_subscriber::{
+    filter::{self, FilterExt, LevelFilter},
+    layer::SubscriberExt,
+    registry::LookupSpan,
+    util::SubscriberInitExt,
+    Layer as _,
+};
+use upload::Upload;
+use webdav::WebDav;
 
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Debug)]
 enum LogLevel {
@@ -38,14 +160,105 @@ impl From<LogLevel> for Level {
     }
 }
 
-#[derive(Args, Debug)]
-struct Tls {
-    /// path to the certificate file.
-    #[clap(short, long)]
-    cert: PathBuf,
-    /// path to the private key file.
-    #[clap(short, long)]
-    key: PathBuf,
+/// A directory served under a URL prefix, with its own 404 page if it has one.
+#[derive(Clone, Debug)]
+struct Mount {
+    prefix: String,
+    path: PathBuf,
+    not_found: Option<PathBuf>,
+    ok: bool,
+    readonly: bool,
+}
+
+impl Mount {
+    /// The 404 page of the mount, or the global one.
+    fn not_found_page(&self, args: &ServeArgs) -> FileService {
+        match self.not_found.as_deref() {
+            Some(path) => page_service(Some(path), self.ok),
+            None => not_found_page(args),
+        }
+    }
+}
+
+impl FromStr for Mount {
+    type Err = ServeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (prefix, rest) = s.split_once('=').ok_or_else(|| {
+            ServeError::InvalidPath(format!("mount {} must be in PREFIX=DIR form", s))
+        })?;
+        let prefix = prefix.trim_end_matches('/');
+        if !prefix.starts_with('/') {
+            return Err(ServeError::InvalidPath(format!(
+                "mount prefix {} must start with / and must not be /",
+                prefix
+            )));
+        }
+        let mut fields = rest.split(',');
+        let path = fields.next().unwrap_or_default();
+        if path.is_empty() {
+            return Err(ServeError::InvalidPath(format!(
+                "mount {} has no directory",
+                s
+            )));
+        }
+        let (mut not_found, mut ok, mut readonly) = (None, false, false);
+        for field in fields {
+            match field.split_once('=') {
+                Some(("not-found", file)) if !file.is_empty() => not_found = Some(file.into()),
+                None if field == "ok" => ok = true,
+                None if field == "readonly" => readonly = true,
+                _ => {
+                    return Err(ServeError::InvalidPath(format!(
+                        "mount {} has {}, which is not not-found=FILE, ok or readonly",
+                        s, field
+                    )))
+                }
+            }
+        }
+        if ok && not_found.is_none() {
+            return Err(ServeError::InvalidPath(format!(
+                "mount {} has ok but no not-found=FILE",
+                s
+            )));
+        }
+        Ok(Mount {
+            prefix: prefix.to_string(),
+            path: path.into(),
+            not_found,
+            ok,
+            readonly,
+        })
+    }
+}
+
+/// Rejects mounts and proxies that share a prefix or where one prefix is nested in another,
+/// because only one of them could ever be reached.
+fn validate_mounts(mounts: &[Mount], proxies: &[Proxy]) -> Result<(), ServeError> {
+    let prefixes: Vec<&str> = mounts
+        .iter()
+        .map(|mount| mount.prefix.as_str())
+        .chain(proxies.iter().map(|proxy| proxy.prefix.as_str()))
+        .collect();
+    for (i, a) in prefixes.iter().enumerate() {
+        for b in &prefixes[i + 1..] {
+            if path::has_prefix(a, b) || path::has_prefix(b, a) {
+                return Err(ServeError::InvalidPath(format!(
+                    "prefixes {} and {} overlap",
+                    a, b
+                )));
+            }
+        }
+    }
+    for a in mounts {
+        if !a.path.is_dir() {
+            return Err(ServeError::InvalidPath(format!(
+                "mount directory {} does not exist",
+                a.path.display()
+            )));
+        }
+    }
+    Ok(())
 }
 
 #[derive(Subcommand, Debug)]
@@ -54,148 +267,3244 @@ enum Subcommands {
     Tls(Tls),
 }
 
+/// The level of request spans and their response events.
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum, Debug)]
+enum TraceLevel {
+    /// no request spans at all.
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+impl TraceLevel {
+    fn level(self) -> Option<Level> {
+        match self {
+            TraceLevel::Off => None,
+            TraceLevel::Error => Some(Level::ERROR),
+            TraceLevel::Warn => Some(Level::WARN),
+            TraceLevel::Info => Some(Level::INFO),
+            TraceLevel::Debug => Some(Level::DEBUG),
+        }
+    }
+}
+
+/// Parses a share from 0 to 1.
+fn parse_ratio(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(ratio) if (0.0..=1.0).contains(&ratio) => Ok(ratio),
+        _ => Err(format!("{} is not a number from 0 to 1", s)),
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum, Debug)]
+enum LogLatencyUnit {
+    Micros,
+    Millis,
+    Seconds,
+}
+
+impl From<LogLatencyUnit> for LatencyUnit {
+    fn from(unit: LogLatencyUnit) -> Self {
+        match unit {
+            LogLatencyUnit::Micros => LatencyUnit::Micros,
+            LogLatencyUnit::Millis => LatencyUnit::Millis,
+            LogLatencyUnit::Seconds => LatencyUnit::Seconds,
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum, Debug)]
+enum LogFormat {
+    Compact,
+    Pretty,
+    Json,
+}
+
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 struct ServeArgs {
     #[clap(subcommand)]
     subcommand: Option<Subcommands>,
     /// path to the directory to serve. Defaults to the current directory.
+    #[clap(env = "SERVE_PATH")]
     path: Option<PathBuf>,
     /// port to listen on.
-    #[clap(short, long, default_value_t = 3000)]
+    #[clap(short, long, env = "SERVE_PORT", default_value_t = 3000)]
     port: u16,
-    /// address to listen on.
-    #[clap(short, long, default_value = "127.0.0.1")]
-    addr: Ipv4Addr,
+    /// address to listen on. `::` listens on IPv6 and, where supported, IPv4.
+    #[clap(short, long, env = "SERVE_ADDR", default_value = "127.0.0.1")]
+    addr: IpAddr,
+    /// address and port to listen on instead of --addr and --port, e.g. [::1]:3000. Can be
+    /// repeated to listen on several at once. With tls, a :plain suffix serves plain HTTP on
+    /// that address, or redirects it with --redirect-http. A :tls suffix needs tls.
+    #[clap(long, value_name = "ADDR:PORT", value_parser = listen::parse)]
+    listen: Vec<ListenAddr>,
+    /// print the address of each listener to stdout once it is bound, one per line. Useful
+    /// with --port 0, which lets the OS pick a free port.
+    #[clap(long)]
+    print_addr: bool,
+    /// serve on the sockets systemd passes with socket activation instead of binding --addr,
+    /// --port or --listen, which are bound as usual when there are none. With tls the passed
+    /// sockets serve HTTPS, the --redirect-http listener is still bound by serve.
+    #[clap(long)]
+    systemd: bool,
+    /// write the process ID to this file once the server is listening, and remove it on
+    /// shutdown.
+    #[clap(long, value_name = "PATH")]
+    pid_file: Option<PathBuf>,
+    /// tell systemd when the server is ready and when it is stopping, for services with
+    /// Type=notify.
+    #[clap(long)]
+    sd_notify: bool,
+    /// shell command to run once the listeners are bound, e.g. to register with service
+    /// discovery. It runs in the background with a minimal environment plus SERVE_ADDR and
+    /// SERVE_PORT, the address and port of the first listener. What it prints is logged.
+    #[clap(long, value_name = "CMD")]
+    on_start: Option<String>,
+    /// wait for --on-start to finish before serving, and do not start when it fails.
+    #[clap(long, requires = "on_start")]
+    on_start_required: bool,
+    /// shell command to run once the server has shut down gracefully, before serve exits. It
+    /// gets the same environment as --on-start.
+    #[clap(long, value_name = "CMD")]
+    on_shutdown: Option<String>,
+    /// seconds a client has to send the complete headers of a request once it started, before
+    /// the connection is closed. 0 means no limit.
+    #[clap(long, default_value_t = 30)]
+    header_read_timeout: u64,
+    /// seconds a connection may go without any data sent or received before it is closed,
+    /// which ends unused kept alive connections and clients that stop reading. Time spent
+    /// working on a request, like waiting for a proxied upstream, does not count. 0 means no
+    /// limit.
+    #[clap(long, default_value_t = 60)]
+    keep_alive_timeout: u64,
+    /// send small responses right away instead of waiting to fill a TCP packet
+    /// (TCP_NODELAY).
+    #[clap(long)]
+    tcp_nodelay: bool,
+    /// most connections to queue up before they are accepted. The OS may cap it, on Linux at
+    /// net.core.somaxconn.
+    #[clap(long, value_name = "N", default_value_t = 1024, value_parser = clap::value_parser!(i32).range(1..))]
+    listen_backlog: i32,
+    /// set SO_REUSEADDR on TCP listeners, so a restarted server can bind the port while
+    /// connections of the old one are still closing. Always on except on Windows, where it
+    /// lets another socket take over the port.
+    #[clap(long)]
+    reuse_addr: bool,
+    /// set SO_REUSEPORT on TCP listeners, so several instances can listen on the same port
+    /// and the OS spreads new connections between them. Only on Unix, except Solaris and
+    /// illumos.
+    #[clap(long)]
+    reuse_port: bool,
+    /// which HTTP versions to serve on TCP listeners. With tls the version is negotiated with
+    /// ALPN, over plain HTTP only --h2c makes only work.
+    #[clap(long, value_enum, default_value_t = Http2::Auto)]
+    http2: Http2,
+    /// with --http2 only, serve plain HTTP listeners over HTTP/2 with prior knowledge (h2c)
+    /// too, and close connections that start with HTTP/1.1. Plain listeners accept h2c next to
+    /// HTTP/1.1 by default.
+    #[clap(long)]
+    h2c: bool,
     /// log level.
-    #[clap(value_enum, default_value_t = LogLevel::Error, long, short)]
+    #[clap(value_enum, default_value_t = LogLevel::Error, long, short, env = "SERVE_LOG_LEVEL")]
     log_level: LogLevel,
-    /// compression layer is enabled by default.
+    /// leave the startup output and other messages outside of requests out of the log, except
+    /// warnings and errors. Requests are still logged at --log-level. The access log and
+    /// exported spans are not affected.
+    #[clap(long, conflicts_with_all = ["silent", "print_addr", "dry_run"])]
+    quiet: bool,
+    /// write nothing to stdout, not even errors that happen while serving. The access log and
+    /// exported spans are not affected. Errors that stop serve still go to stderr.
+    #[clap(long, conflicts_with_all = ["print_addr", "dry_run"])]
+    silent: bool,
+    /// log format. json includes the fields of the current request span.
+    #[clap(value_enum, default_value_t = LogFormat::Compact, long, env = "SERVE_LOG_FORMAT")]
+    log_format: LogFormat,
+    /// export request spans to an OpenTelemetry collector at this OTLP/HTTP URL, e.g.
+    /// http://localhost:4318. Requests with a traceparent header continue its trace.
+    #[cfg(feature = "otlp")]
+    #[clap(long, value_name = "URL")]
+    otlp_endpoint: Option<axum::http::Uri>,
+    /// share of new traces to export, from 0 to 1. Traces continued from a sampled
+    /// traceparent are always exported.
+    #[cfg(feature = "otlp")]
+    #[clap(long, default_value_t = 1.0, value_parser = parse_ratio, requires = "otlp_endpoint")]
+    otlp_sampling_ratio: f64,
+    /// write a line per request to this file, separate from the log.
+    #[clap(long, value_name = "PATH")]
+    access_log: Option<PathBuf>,
+    /// format of access log lines: common, combined or a template like "{method} {path}
+    /// {status} {latency}". Fields are remote_addr, time, method, uri, path, version, status,
+    /// bytes, latency, referer and user_agent.
+    #[clap(
+        long,
+        value_parser = access_log::parse_format,
+        default_value = "common",
+        requires = "access_log"
+    )]
+    access_log_format: access_log::Format,
+    /// when to start a new access log file. The old one is renamed to PATH.1, PATH.2 and so on.
+    #[clap(long, value_enum, default_value_t = Rotation::Never, requires = "access_log")]
+    access_log_rotation: Rotation,
+    /// size at which --access-log-rotation size starts a new file, e.g. 10m.
+    #[clap(long, value_parser = size::parse, default_value = "10m", requires = "access_log")]
+    access_log_max_size: usize,
+    /// number of rotated access log files to keep.
+    #[clap(long, default_value_t = 7, requires = "access_log")]
+    access_log_max_files: usize,
+    /// compress rotated access log files. The current file is not compressed.
+    #[clap(long, value_enum, requires = "access_log")]
+    access_log_compress: Option<Compress>,
+    /// leave requests below this path, like /healthz, out of the request log and the access
+    /// log. Can be repeated.
+    #[clap(long, value_name = "PREFIX", value_parser = path::parse_route)]
+    log_exclude_path: Vec<String>,
+    /// level of the span of every request and the event logged with its response.
+    #[clap(long, value_enum, default_value_t = TraceLevel::Info)]
+    trace_level: TraceLevel,
+    /// share of requests to record a span for, from 0 to 1.
+    #[clap(long, default_value_t = 1.0, value_parser = parse_ratio)]
+    trace_sample_rate: f64,
+    /// unit of the latency logged with every response.
+    #[clap(long, value_enum, default_value_t = LogLatencyUnit::Millis)]
+    log_latency_unit: LogLatencyUnit,
+    /// log the request and response headers with every response.
+    #[clap(long)]
+    log_headers: bool,
+    /// comma-separated headers whose values --log-headers leaves out. An empty value
+    /// redacts none.
+    #[clap(
+        long,
+        value_name = "NAMES",
+        value_delimiter = ',',
+        value_parser = redact::parse_name,
+        default_value = redact::SENSITIVE,
+        requires = "log_headers"
+    )]
+    log_redact_header: Vec<String>,
+    /// give every request an id, the one in --request-id-header if it has a valid one or a new
+    /// UUID. It is sent back with the response, passed on to proxies and logged with the request.
     #[clap(long)]
+    request_id: bool,
+    /// header that carries the request id.
+    #[clap(
+        long,
+        value_name = "NAME",
+        default_value = "x-request-id",
+        requires = "request_id"
+    )]
+    request_id_header: HeaderName,
+    /// compression layer is enabled by default. Can not be combined with the other compression
+    /// options.
+    #[clap(long, conflicts_with_all = [
+        "compression_algorithms",
+        "compression_level",
+        "compression_min_size",
+        "compression_include_type",
+        "compression_exclude_type",
+        "compression_exclude_path",
+    ])]
     disable_compression: bool,
+    /// comma-separated compression algorithms to offer.
+    #[clap(
+        long,
+        value_enum,
+        value_delimiter = ',',
+        default_value = "gzip,br,deflate,zstd"
+    )]
+    compression_algorithms: Vec<compression::Algorithm>,
+    /// serve sibling .br, .zst, .gz and .zz files when the client accepts that encoding. They are
+    /// preferred over compressing on the fly.
+    #[clap(long)]
+    precompressed: bool,
+    /// write .br and .gz siblings of the files below PATH and the mounts on startup, and serve
+    /// them as with --precompressed. Siblings that are not older than their file are kept.
+    /// Files smaller than --compression-min-size are skipped.
+    #[clap(long, conflicts_with_all = ["archive", "file"])]
+    pregenerate_compressed: bool,
+    /// comma-separated content types to write compressed siblings of with
+    /// --pregenerate-compressed. Defaults to text-like formats.
+    #[clap(
+        long,
+        value_name = "TYPES",
+        value_delimiter = ',',
+        value_parser = compression::parse_type,
+        default_value = pregenerate::TEXT_TYPES,
+        requires = "pregenerate_compressed"
+    )]
+    pregenerate_types: Vec<String>,
+    /// compression level: fastest, default, best or a number.
+    #[clap(long, default_value = "default", value_parser = compression::parse_level)]
+    compression_level: CompressionLevel,
+    /// responses smaller than this many bytes are not compressed.
+    #[clap(long, default_value_t = 32)]
+    compression_min_size: u16,
+    /// comma-separated content types to compress, like text/*,application/json. Other types
+    /// are not compressed. Defaults to all types.
+    #[clap(long, value_name = "TYPES", value_delimiter = ',', value_parser = compression::parse_type)]
+    compression_include_type: Vec<String>,
+    /// comma-separated content types to never compress. Defaults to formats that are
+    /// compressed already, like most images, video, audio and archives. An empty value
+    /// excludes none.
+    #[clap(
+        long,
+        value_name = "TYPES",
+        value_delimiter = ',',
+        value_parser = compression::parse_type,
+        default_value = compression::COMPRESSED_TYPES
+    )]
+    compression_exclude_type: Vec<String>,
+    /// never compress responses below this path, like a streaming endpoint. Can be repeated.
+    #[clap(long, value_name = "PREFIX", value_parser = path::parse_route)]
+    compression_exclude_path: Vec<String>,
     /// path to 404 page. By default, 404 is empty.
     #[clap(long)]
     not_found: Option<PathBuf>,
+    /// serve index.html with 200 for page requests (Accept: text/html) that match no file, for
+    /// single-page apps. Clean URLs are tried first, other requests get --not-found.
+    #[clap(long)]
+    spa: bool,
     /// override with 200 OK. Useful for SPA. Requires --not-found.
     #[clap(long, requires = "not_found")]
     ok: bool,
+    /// page to send as the body of responses with this status, e.g. 500=./50x.html. Can be
+    /// repeated. --not-found takes precedence for 404.
+    #[clap(long)]
+    error_page: Vec<ErrorPage>,
+    /// serve a directory under a URL prefix, e.g. /assets=./static. Can be repeated. Other paths
+    /// are served from PATH. ,not-found=FILE gives the mount its own 404 page, which ,ok sends
+    /// with 200 OK. Other mounts use --not-found. ,readonly answers every method but GET, HEAD
+    /// and OPTIONS below the prefix with 405, also where --upload would take it.
+    #[clap(long, value_name = "PREFIX=DIR[,not-found=FILE][,ok][,readonly]")]
+    mount: Vec<Mount>,
+    /// send requests below a URL prefix to another server, e.g. /api=http://localhost:3000.
+    /// The prefix is replaced with the path of the URL. Can be repeated.
+    #[clap(long, value_name = "PREFIX=URL")]
+    proxy: Vec<Proxy>,
+    /// redirect paths that match a pattern, e.g. /blog/*=/news/:splat or /old=/new:302. :name
+    /// matches a path segment and a final * the rest. They take precedence over files. Can be
+    /// repeated, the first match wins.
+    #[clap(long, value_name = "FROM=TO[:STATUS]")]
+    redirect: Vec<Redirect>,
+    /// redirect requests with 301 between the www. and the bare form of a host name. The
+    /// scheme, port, path and query are kept. IP addresses and names without a dot, like
+    /// localhost, are never redirected to a www. form.
+    #[clap(long, value_enum, default_value_t = canonical_host::Mode::Off)]
+    canonical_redirect: canonical_host::Mode,
+    /// only redirect this domain with --canonical-redirect, given with or without www.
+    /// Requests for other hosts are served as they are.
+    #[clap(long, value_name = "HOST", value_parser = canonical_host::parse_domain, requires = "canonical_redirect")]
+    canonical_host: Option<String>,
+    /// accept PUT and POST requests below this URL prefix, e.g. /uploads, and write their
+    /// bodies to files in --upload-dir. Limit their size with --max-body-size.
+    #[clap(long, value_name = "PREFIX", value_parser = upload::parse_prefix, requires = "upload_dir")]
+    upload: Option<String>,
+    /// directory to write uploaded files to, keeping the path below the upload prefix.
+    #[clap(long, requires = "upload")]
+    upload_dir: Option<PathBuf>,
+    /// replace existing files with uploads instead of answering 409.
+    #[clap(long, requires = "upload")]
+    upload_overwrite: bool,
+    /// let WebDAV clients list PATH with PROPFIND, so it can be mounted as a network drive.
+    /// Combine with --basic-auth to require a login.
+    #[clap(long, conflicts_with_all = ["archive", "file"])]
+    webdav: bool,
+    /// also let WebDAV clients change PATH with PUT, DELETE, MKCOL, COPY and MOVE.
+    #[clap(long, requires = "webdav")]
+    webdav_write: bool,
+    /// serve paths that match a pattern from another path without redirecting, e.g.
+    /// /app/*=/index.html. Patterns are like --redirect. Only files under PATH are rewritten,
+    /// not mounts or proxies. Can be repeated, the first match wins.
+    #[clap(long, value_name = "FROM=TO")]
+    rewrite: Vec<Rewrite>,
+    /// file to serve for directory requests. Can be repeated, the first one that exists is
+    /// served.
+    #[clap(long = "index-file", value_name = "NAME", default_value = index_files::DEFAULT)]
+    index_files: Vec<String>,
+    /// serve localized files like page.de.html for /page to clients whose Accept-Language
+    /// prefers them. Files without a variant for the language are served as they are.
+    #[clap(long)]
+    i18n: bool,
+    /// language to serve when the client accepts none of the localized files, e.g. en.
+    #[clap(long, value_name = "LANG", value_parser = i18n::parse_lang, requires = "i18n")]
+    i18n_default_lang: Option<String>,
+    /// list the contents of directories that have no index file.
+    #[clap(long)]
+    autoindex: bool,
+    /// format of directory listings.
+    #[clap(long, value_enum, default_value_t = autoindex::Format::Html, requires = "autoindex")]
+    autoindex_format: autoindex::Format,
+    /// how to answer requests for paths with a segment that starts with a dot, like /.env or
+    /// /.git/config. /.well-known is always served.
+    #[clap(long, value_enum, default_value_t = dotfiles::Mode::Ignore)]
+    dotfiles: dotfiles::Mode,
+    /// serve files that a symlink points to outside of PATH. With false they are answered as if
+    /// they did not exist.
+    #[clap(long, default_value_t = true, action = ArgAction::Set)]
+    follow_symlinks: bool,
+    /// reload open pages when files change. Adds a script to HTML responses.
+    #[clap(long)]
+    live_reload: bool,
+    /// answer 503 while PATH is deleted, instead of 404s, and serve it again once it is back.
+    #[clap(long, conflicts_with_all = ["archive", "file"])]
+    watch_root: bool,
+    /// answer every request with 503 and this page, e.g. during a deployment. The health
+    /// check, metrics and version paths keep working. On Unix, SIGHUP turns maintenance mode
+    /// off, and on again with the page read anew.
+    #[clap(long, value_name = "PATH")]
+    maintenance: Option<PathBuf>,
+    /// start with maintenance mode off, to turn it on later with SIGHUP.
+    #[clap(long, requires = "maintenance")]
+    maintenance_off: bool,
+    /// seconds to send in the Retry-After header of the maintenance page.
+    #[clap(long, value_name = "SECONDS", default_value_t = 300)]
+    maintenance_retry_after: u64,
+    /// serve /about from about.html when there is no file named about.
+    #[clap(long)]
+    clean_urls: bool,
+    /// redirect directories with an index.html and clean URLs to the form with or without a
+    /// trailing slash.
+    #[clap(long, value_enum, default_value_t = TrailingSlash::Ignore)]
+    trailing_slash: TrailingSlash,
+    /// serve /INDEX.HTML from index.html when there is no file with the exact name, for sites
+    /// moved from case-insensitive file systems.
+    #[clap(long)]
+    case_insensitive: bool,
+    /// Cache-Control value for files with an extension, e.g. "js=max-age=31536000". Can be
+    /// repeated.
+    #[clap(long, value_name = "EXT=VALUE")]
+    cache_control: Vec<cache_control::Rule>,
+    /// Cache-Control value for files that don't match any --cache-control extension.
+    #[clap(long, value_name = "VALUE")]
+    cache_control_default: Option<HeaderValue>,
+    /// Content-Type for files with an extension, e.g. "wasm=application/wasm". Takes precedence
+    /// over the guessed type. Can be repeated.
+    #[clap(long = "mime", value_name = "EXT=TYPE")]
+    mime_types: Vec<MimeType>,
+    /// make browsers download files instead of showing them, with Content-Disposition.
+    #[clap(long)]
+    force_download: bool,
+    /// comma-separated extensions to download, like --force-download for only those files.
+    #[clap(long, value_name = "EXT", value_delimiter = ',')]
+    force_download_ext: Vec<String>,
+    /// send ETag headers and answer If-None-Match with 304 Not Modified.
+    #[clap(long, value_enum, default_value_t = etag::Mode::Off)]
+    etag: etag::Mode,
+    /// require basic authentication with users from an htpasswd file. Supports bcrypt and apr1
+    /// hashes.
+    #[clap(long, value_name = "PATH")]
+    basic_auth: Option<PathBuf>,
+    /// realm reported to clients by basic authentication.
+    #[clap(long, default_value = "serve", requires = "basic_auth")]
+    basic_auth_realm: String,
+    /// add a header to every response, e.g. "Cache-Control: no-cache". Can be repeated, the last
+    /// value for a name wins.
+    #[clap(long = "header", value_name = "NAME: VALUE")]
+    headers: Vec<Header>,
+    /// send X-Content-Type-Options, X-Frame-Options and Referrer-Policy headers, and
+    /// Strict-Transport-Security over TLS. --header replaces any of them.
+    #[clap(long)]
+    security_headers: bool,
+    /// Content-Security-Policy to send with --security-headers, e.g. "default-src 'self'".
+    #[clap(long, value_name = "POLICY", requires = "security_headers")]
+    csp: Option<HeaderValue>,
+    /// seconds browsers remember to only use HTTPS, sent in Strict-Transport-Security over
+    /// TLS. Defaults to a year. Also sends the header without --security-headers, and like
+    /// those --header replaces it. 0 makes browsers forget.
+    #[clap(long, value_name = "SECS")]
+    hsts_max_age: Option<u64>,
+    /// apply Strict-Transport-Security to all subdomains too.
+    #[clap(long)]
+    hsts_include_subdomains: bool,
+    /// allow the domain onto the HSTS preload lists browsers ship with. Needs
+    /// --hsts-include-subdomains and a --hsts-max-age of at least a year.
+    #[clap(long, requires = "hsts_include_subdomains")]
+    hsts_preload: bool,
+    /// answer CORS requests. Allows any origin unless --cors-origin is given.
+    #[clap(long)]
+    cors: bool,
+    /// origin allowed to make CORS requests. Can be repeated.
+    #[clap(long, requires = "cors")]
+    cors_origin: Vec<HeaderValue>,
+    /// comma-separated methods allowed in CORS requests.
+    #[clap(
+        long,
+        value_delimiter = ',',
+        default_value = "GET,HEAD,OPTIONS",
+        requires = "cors"
+    )]
+    cors_methods: Vec<Method>,
+    /// comma-separated request headers allowed in CORS requests, or *.
+    #[clap(long, value_delimiter = ',', requires = "cors")]
+    cors_headers: Vec<HeaderName>,
+    /// allow CORS requests with credentials. Requires --cors-origin.
+    #[clap(long, requires = "cors")]
+    cors_credentials: bool,
+    /// listen on a Unix domain socket instead of --addr and --port. Can not be used with TLS.
+    #[cfg(unix)]
+    #[clap(long, value_name = "PATH", conflicts_with = "systemd")]
+    unix_socket: Option<PathBuf>,
+    /// octal permissions of the Unix domain socket, e.g. 660.
+    #[cfg(unix)]
+    #[clap(long, value_parser = unix::parse_mode, requires = "unix_socket")]
+    unix_socket_mode: Option<u32>,
+    /// comma separated proxies, as addresses or CIDR networks, whose X-Forwarded-For and
+    /// Forwarded headers are used to log the client address.
+    #[clap(long, value_delimiter = ',', value_parser = forwarded::parse_network)]
+    trusted_proxies: Vec<IpNet>,
+    /// only accept requests from this address or CIDR network. Can be repeated.
+    #[clap(long, value_name = "CIDR", value_parser = forwarded::parse_network)]
+    allow: Vec<IpNet>,
+    /// refuse requests from this address or CIDR network with 403, even if --allow matches. Can
+    /// be repeated.
+    #[clap(long, value_name = "CIDR", value_parser = forwarded::parse_network)]
+    deny: Vec<IpNet>,
+    /// reject requests with a body larger than this with 413, e.g. 10m. Accepts k, m and g
+    /// suffixes.
+    #[clap(long, value_parser = size::parse)]
+    max_body_size: Option<usize>,
+    /// answer health checks on this path, e.g. /healthz. It takes precedence over files, skips
+    /// authentication and is not logged.
+    #[clap(long, value_parser = path::parse_route)]
+    health_path: Option<String>,
+    /// expose Prometheus metrics on this path, e.g. /metrics. Like the health check it skips
+    /// authentication and is not logged.
+    #[clap(long, value_parser = path::parse_route)]
+    metrics_path: Option<String>,
+    /// report the version, git commit and build time as JSON on this path, e.g. /version. Like
+    /// the health check it skips authentication and is not logged.
+    #[clap(long, value_parser = path::parse_route)]
+    version_path: Option<String>,
+    /// seconds a request may take until the response starts, answered with 408 when exceeded.
+    /// Sending the body of a large file is not limited.
+    #[clap(long)]
+    request_timeout: Option<u64>,
+    /// seconds to wait for in-flight requests to finish on shutdown.
+    #[clap(long, default_value_t = 30)]
+    shutdown_timeout: u64,
+    /// keep up to this many bytes of file contents in memory, e.g. 64m. Files are read again
+    /// when their modification time or size changes.
+    #[clap(long, value_parser = size::parse)]
+    cache_size: Option<usize>,
+    /// largest file to keep in memory with --cache-size.
+    #[clap(long, value_parser = size::parse, default_value = "1m", requires = "cache_size")]
+    cache_max_file_size: usize,
+    /// serve files from a .zip, .tar or .tar.gz archive instead of PATH, read into memory at
+    /// startup.
+    #[clap(long, conflicts_with = "path")]
+    archive: Option<PathBuf>,
+    /// serve only this file instead of PATH, for every path or only at --file-at.
+    #[clap(long, value_name = "PATH", conflicts_with_all = ["path", "archive"])]
+    file: Option<PathBuf>,
+    /// serve --file at this path, e.g. /download.zip, and answer others with 404.
+    #[clap(long, value_name = "PATH", value_parser = path::parse_route, requires = "file")]
+    file_at: Option<String>,
+    /// send the responses of each connection at no more than this many bytes per second
+    /// together, e.g. 256k, to simulate a slow network. On a Unix domain socket the limit is
+    /// per response. 0 means unlimited.
+    #[clap(long, value_parser = size::parse)]
+    max_rate: Option<usize>,
+    /// most requests to handle at once, across all listeners. A request counts until its
+    /// response body has been sent.
+    #[clap(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    max_connections: Option<u32>,
+    /// what to do with requests over --max-connections.
+    #[clap(long, value_enum, default_value_t = OnLimit::Queue, requires = "max_connections")]
+    on_limit: OnLimit,
+    /// send this Server header with every response, including redirects and error pages.
+    #[clap(long, value_name = "VALUE")]
+    server_header: Option<HeaderValue>,
+    /// remove the Server header from every response, also one set with --header.
+    #[clap(long, conflicts_with = "server_header")]
+    no_server_header: bool,
+    /// check the options, print them and exit without serving.
+    #[clap(long)]
+    dry_run: bool,
+    /// skip checking at startup that the files and directories in the options exist and can
+    /// be read.
+    #[clap(long)]
+    no_validate: bool,
 }
 
 impl ServeArgs {
     pub fn get_path(&self) -> PathBuf {
         self.path.clone().unwrap_or(".".into())
     }
+
+    /// Rejects combinations of options that clap can not tell apart, where one of them would
+    /// otherwise be ignored.
+    fn check_conflicts(&self) -> Result<(), ServeError> {
+        let conflict = |msg: &str| Err(ServeError::Conflict(msg.to_string()));
+        let tls = self.subcommand.as_ref().map(|Subcommands::Tls(tls)| tls);
+        #[cfg(unix)]
+        if self.unix_socket.is_some() {
+            if tls.is_some() {
+                return conflict("TLS is not supported on a Unix domain socket");
+            }
+            if !self.allow.is_empty() || !self.deny.is_empty() {
+                return conflict(
+                    "--allow and --deny need client addresses, which a Unix domain socket does not have",
+                );
+            }
+            if self.on_start.is_some() || self.on_shutdown.is_some() {
+                return conflict(
+                    "--on-start and --on-shutdown need a TCP listener to tell the address of",
+                );
+            }
+        }
+        if self.h2c && self.http2 != Http2::Only {
+            return conflict("--h2c only applies to --http2 only");
+        }
+        if self.hsts_preload
+            && self
+                .hsts_max_age
+                .is_some_and(|max_age| max_age < security_headers::HSTS_MAX_AGE)
+        {
+            return conflict("--hsts-preload needs a --hsts-max-age of at least 31536000");
+        }
+        let Some(tls) = tls else {
+            if self.hsts_max_age.is_some() || self.hsts_include_subdomains {
+                return conflict(
+                    "Strict-Transport-Security is only sent over TLS, which needs the tls subcommand",
+                );
+            }
+            return match self.listen.iter().find(|l| l.scheme == Some(Scheme::Tls)) {
+                Some(listen) => Err(ServeError::Conflict(format!(
+                    "--listen {}:tls needs the tls subcommand",
+                    listen.addr
+                ))),
+                None => Ok(()),
+            };
+        };
+        let has_plain = self.listen.iter().any(|l| l.scheme == Some(Scheme::Plain));
+        if tls.redirect_http && !has_plain {
+            let https_ports = self
+                .listen_addrs()
+                .into_iter()
+                .filter(|listen| listen.scheme != Some(Scheme::Plain))
+                .map(|listen| tls.redirect_https_port.unwrap_or(listen.addr.port()));
+            for port in https_ports {
+                if port == tls.redirect_from_port {
+                    return Err(ServeError::Conflict(format!(
+                        "--redirect-from-port {} is also the HTTPS port, pick another with \
+                         --redirect-from-port or --port",
+                        port
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that the files and directories the options name can be used, and reports every
+    /// one that can not at once.
+    fn validate(&self) -> Result<(), ServeError> {
+        let mut problems = Vec::new();
+        let mut file = |option: &str, path: &Path| {
+            if let Err(e) = std::fs::File::open(path).and_then(|file| file.metadata()) {
+                problems.push(format!("{} {}: {}", option, path.display(), e));
+            } else if !path.is_file() {
+                problems.push(format!("{} {} is not a file", option, path.display()));
+            }
+        };
+        if let Some(archive) = &self.archive {
+            file("--archive", archive);
+        }
+        if let Some(single) = &self.file {
+            file("--file", single);
+        }
+        if let Some(not_found) = &self.not_found {
+            file("--not-found", not_found);
+        }
+        for page in &self.error_page {
+            file("--error-page", &page.path);
+        }
+        if let Some(maintenance) = &self.maintenance {
+            file("--maintenance", maintenance);
+        }
+        for mount in &self.mount {
+            if let Some(not_found) = &mount.not_found {
+                file("--mount", not_found);
+            }
+        }
+        if let Some(htpasswd) = &self.basic_auth {
+            file("--basic-auth", htpasswd);
+        }
+        if let Some(Subcommands::Tls(tls)) = &self.subcommand {
+            let files = [
+                ("--cert", &tls.cert),
+                ("--key", &tls.key),
+                ("--client-ca", &tls.client_ca),
+                ("--ocsp-staple", &tls.ocsp_staple),
+            ];
+            for (option, path) in files {
+                if let Some(path) = path {
+                    file(option, path);
+                }
+            }
+            for sni in &tls.sni_certs {
+                file("--sni-cert", &sni.cert);
+                file("--sni-cert", &sni.key);
+            }
+        }
+        if self.archive.is_none() && self.file.is_none() {
+            let root = self.get_path();
+            if let Err(e) = std::fs::read_dir(&root) {
+                problems.push(format!("PATH {}: {}", root.display(), e));
+            }
+        }
+        // Files that are written only need a directory to go in.
+        let written = [
+            ("--access-log", &self.access_log),
+            ("--pid-file", &self.pid_file),
+        ];
+        for (option, path) in written {
+            let Some(path) = path else { continue };
+            let dir = match path.parent() {
+                Some(dir) if !dir.as_os_str().is_empty() => dir,
+                _ => Path::new("."),
+            };
+            if path.is_dir() {
+                problems.push(format!("{} {} is a directory", option, path.display()));
+            } else if !dir.is_dir() {
+                problems.push(format!(
+                    "{} {}: directory {} does not exist",
+                    option,
+                    path.display(),
+                    dir.display()
+                ));
+            }
+        }
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(ServeError::Invalid(problems))
+        }
+    }
+
+    fn listen_addrs(&self) -> Vec<ListenAddr> {
+        if self.listen.is_empty() {
+            return vec![ListenAddr {
+                addr: SocketAddr::from((self.addr, self.port)),
+                scheme: None,
+            }];
+        }
+        self.listen.clone()
+    }
+
+    fn connection_options(&self) -> ConnectionOptions {
+        let seconds = |secs: u64| (secs > 0).then(|| Duration::from_secs(secs));
+        ConnectionOptions {
+            header_read_timeout: seconds(self.header_read_timeout),
+            idle_timeout: seconds(self.keep_alive_timeout),
+            tcp_nodelay: self.tcp_nodelay,
+            http2: self.http2,
+            h2c: self.h2c,
+            max_rate: self.max_rate.filter(|rate| *rate > 0),
+        }
+    }
+
+    /// Whether sibling files are served as precompressed variants.
+    fn precompressed(&self) -> bool {
+        self.precompressed || self.pregenerate_compressed
+    }
+
+    /// Writes the compressed siblings of --pregenerate-compressed.
+    fn pregenerate(&self) -> Result<(), ServeError> {
+        if !self.pregenerate_compressed {
+            return Ok(());
+        }
+        let types = self
+            .pregenerate_types
+            .iter()
+            .filter(|t| !t.is_empty())
+            .cloned()
+            .collect::<Vec<_>>();
+        let pregenerate = Pregenerate {
+            types: &types,
+            min_size: self.compression_min_size.into(),
+        };
+        let roots =
+            std::iter::once(self.get_path()).chain(self.mount.iter().map(|m| m.path.clone()));
+        for root in roots {
+            let written = pregenerate.run(&root)?;
+            tracing::info!(
+                "wrote {} compressed files below {}",
+                written,
+                root.display()
+            );
+        }
+        Ok(())
+    }
+
+    fn socket_options(&self) -> SocketOptions {
+        SocketOptions {
+            backlog: self.listen_backlog,
+            reuse_address: self.reuse_addr,
+            reuse_port: self.reuse_port,
+        }
+    }
+
+    fn server_header(&self) -> Option<ServerHeader> {
+        if self.no_server_header {
+            return Some(ServerHeader::Remove);
+        }
+        self.server_header.clone().map(ServerHeader::Set)
+    }
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    let args = ServeArgs::parse();
-    let addr = SocketAddr::from((args.addr, args.port));
+type FileService = BoxCloneSyncService<Request, Response, Infallible>;
 
-    tracing_subscriber::fmt()
-        .with_max_level(<LogLevel as Into<Level>>::into(args.log_level))
-        .compact()
-        .init();
+fn boxed<S>(service: S) -> FileService
+where
+    S: Service<Request, Error = Infallible> + Clone + Send + Sync + 'static,
+    S::Response: IntoResponse,
+    S::Future: Send + 'static,
+{
+    BoxCloneSyncService::new(service.map_response(IntoResponse::into_response))
+}
 
-    let serve_dir = ServeDir::new(args.get_path());
+/// Answers the requests `ServeDir` found no file for, which already include directories
+/// with an index file and clean URLs. The first that applies answers:
+///
+/// 1. a listing of the directory, with --autoindex,
+/// 2. index.html for page navigations, with --spa,
+/// 3. the 404 page.
+fn fallback_service(root: &Path, args: &ServeArgs, not_found: FileService) -> FileService {
+    let mut service = not_found;
+    if args.spa {
+        service = spa_service(root, service);
+    }
+    if args.autoindex {
+        service = autoindex::service(
+            root,
+            args.autoindex_format,
+            args.dotfiles == dotfiles::Mode::Allow,
+            service,
+        );
+    }
+    service
+}
 
-    let app = Router::new().layer(
-        TraceLayer::new_for_http()
-            .make_span_with(trace::DefaultMakeSpan::new().level(Level::INFO))
-            .on_response(trace::DefaultOnResponse::new().level(Level::INFO)),
-    );
+/// The custom 404 page, or an empty 404.
+fn not_found_page(args: &ServeArgs) -> FileService {
+    page_service(args.not_found.as_deref(), args.ok)
+}
 
-    let app = if let Some(path) = args.not_found.as_ref() {
-        tracing::info!("custom 404 page");
-        let serve_dir = if args.ok {
-            tracing::info!("overriding 404 with 200 OK");
-            serve_dir.fallback(SetStatus::new(ServeFile::new(path), StatusCode::OK))
+/// Answers with the 404 page `path`, with 200 OK if `ok`, or with an empty 404.
+fn page_service(path: Option<&Path>, ok: bool) -> FileService {
+    let status = if ok {
+        StatusCode::OK
+    } else {
+        StatusCode::NOT_FOUND
+    };
+    match path {
+        // A 404 --error-page does not replace it, whether it is the global page or a mount's.
+        Some(path) => boxed(ServiceExt::<Request>::map_response(
+            SetStatus::new(ServeFile::new(path), status),
+            |mut response| {
+                response.extensions_mut().insert(error_pages::OwnPage);
+                response
+            },
+        )),
+        None => boxed(service_fn(|_: Request| async {
+            Ok(StatusCode::NOT_FOUND.into_response())
+        })),
+    }
+}
+
+/// Whether the request is a page navigation, as opposed to one for a script or image.
+fn accepts_html(request: &Request) -> bool {
+    request
+        .headers()
+        .get_all(header::ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .any(|value| value.contains("text/html"))
+}
+
+/// Answers page navigations with `root/index.html` and 200, so client side routes work on
+/// reload. Other requests, like a missing script, go to `fallback`.
+fn spa_service(root: &Path, fallback: FileService) -> FileService {
+    let index = root.join("index.html");
+    if !index.is_file() {
+        tracing::warn!("--spa is set but {} does not exist", index.display());
+    }
+    let index = SetStatus::new(ServeFile::new(index), StatusCode::OK);
+    boxed(service_fn(move |request: Request| {
+        let index = index.clone();
+        let fallback = fallback.clone();
+        async move {
+            if accepts_html(&request) {
+                index
+                    .oneshot(request)
+                    .await
+                    .map(IntoResponse::into_response)
+            } else {
+                fallback.oneshot(request).await
+            }
+        }
+    }))
+}
+
+/// Serves files from `root`, answering with `not_found` where there is none.
+fn file_service(root: &Path, args: &ServeArgs, not_found: FileService) -> FileService {
+    let mut serve_dir = ServeDir::new(root);
+    if args.precompressed() {
+        serve_dir = serve_dir
+            .precompressed_br()
+            .precompressed_zstd()
+            .precompressed_gzip()
+            .precompressed_deflate();
+    }
+    let mut service = boxed(serve_dir.fallback(fallback_service(root, args, not_found.clone())));
+    if let Some(capacity) = args.cache_size {
+        let cache = Arc::new(FileCache::new(root, capacity, args.cache_max_file_size));
+        service = boxed(middleware::from_fn_with_state(cache, file_cache::apply).layer(service));
+    }
+    if args.etag != etag::Mode::Off {
+        let etags = Arc::new(Etags::new(root, args.etag));
+        service = boxed(middleware::from_fn_with_state(etags, etag::apply).layer(service));
+    }
+    if args.trace_level.level().is_some() {
+        let root = Arc::new(root.to_path_buf());
+        service = boxed(middleware::from_fn_with_state(root, logging::record_file).layer(service));
+    }
+    if !args.follow_symlinks {
+        let symlinks = Arc::new(Symlinks::new(root, not_found.clone()));
+        service = boxed(middleware::from_fn_with_state(symlinks, symlinks::apply).layer(service));
+    }
+    service = with_mime_types(service, args);
+    service = with_force_download(service, args);
+    service = with_cache_control(service, args);
+    service = boxed(middleware::from_fn(ranges::apply).layer(service));
+    // Rewrites before the middlewares above, which look for index.html in directories.
+    if args.index_files != [index_files::DEFAULT] {
+        let index_files = Arc::new(IndexFiles::new(root, &args.index_files));
+        service =
+            boxed(middleware::from_fn_with_state(index_files, index_files::apply).layer(service));
+    }
+    if args.i18n {
+        let i18n = Arc::new(I18n::new(root, args.i18n_default_lang.as_deref()));
+        service = boxed(middleware::from_fn_with_state(i18n, i18n::apply).layer(service));
+    }
+    if args.clean_urls || args.trailing_slash != TrailingSlash::Ignore {
+        let clean_urls = CleanUrls::new(
+            root,
+            &args.index_files,
+            args.clean_urls,
+            args.trailing_slash,
+        );
+        service = boxed(
+            middleware::from_fn_with_state(Arc::new(clean_urls), clean_urls::apply).layer(service),
+        );
+    }
+    if args.case_insensitive {
+        let case = Arc::new(CaseInsensitive::new(root));
+        service =
+            boxed(middleware::from_fn_with_state(case, case_insensitive::apply).layer(service));
+    }
+    with_dotfiles(service, args, not_found)
+}
+
+/// Serves files from an archive instead of a directory. Middlewares that need files on disk,
+/// like ETags and clean URLs, are not available.
+fn archive_service(archive: Archive, args: &ServeArgs) -> FileService {
+    let not_found = not_found_page(args);
+    let service = archive::service(Arc::new(archive), args.spa, not_found.clone());
+    let service = with_force_download(with_mime_types(service, args), args);
+    with_dotfiles(with_cache_control(service, args), args, not_found)
+}
+
+/// Serves --file for every request, or only for --file-at.
+fn single_file_service(args: &ServeArgs) -> Result<FileService, ServeError> {
+    let Some(file) = args.file.as_ref().filter(|file| file.is_file()) else {
+        return Err(ServeError::InvalidPath(format!(
+            "{} is not a file",
+            args.file.as_deref().unwrap_or(Path::new("")).display()
+        )));
+    };
+    let serve_file = ServeFile::new(file);
+    // ServeFile only answers single ranges.
+    let serve_file = boxed(middleware::from_fn(ranges::apply).layer(serve_file));
+    let Some(at) = args.file_at.clone() else {
+        return Ok(serve_file);
+    };
+    let not_found = not_found_page(args);
+    Ok(boxed(service_fn(move |request: Request| {
+        let service = if request.uri().path() == at {
+            serve_file.clone()
         } else {
-            serve_dir.not_found_service(ServeFile::new(path))
+            not_found.clone()
         };
-        app.fallback_service(serve_dir)
+        service.oneshot(request)
+    })))
+}
+
+fn with_force_download(service: FileService, args: &ServeArgs) -> FileService {
+    if !args.force_download && args.force_download_ext.is_empty() {
+        return service;
+    }
+    let download = Arc::new(ForceDownload::new(&args.force_download_ext));
+    boxed(middleware::from_fn_with_state(download, download::apply).layer(service))
+}
+
+fn with_dotfiles(service: FileService, args: &ServeArgs, not_found: FileService) -> FileService {
+    if args.dotfiles == dotfiles::Mode::Allow {
+        return service;
+    }
+    let dotfiles = Arc::new(Dotfiles::new(args.dotfiles, not_found));
+    boxed(middleware::from_fn_with_state(dotfiles, dotfiles::apply).layer(service))
+}
+
+fn with_mime_types(service: FileService, args: &ServeArgs) -> FileService {
+    if args.mime_types.is_empty() {
+        return service;
+    }
+    let mime_types = Arc::new(MimeTypes::new(&args.mime_types));
+    boxed(middleware::from_fn_with_state(mime_types, mime_types::apply).layer(service))
+}
+
+fn with_cache_control(service: FileService, args: &ServeArgs) -> FileService {
+    if args.cache_control.is_empty() && args.cache_control_default.is_none() {
+        return service;
+    }
+    let cache_control = CacheControl::new(&args.cache_control, args.cache_control_default.clone());
+    boxed(
+        middleware::from_fn_with_state(Arc::new(cache_control), cache_control::apply)
+            .layer(service),
+    )
+}
+
+/// Without the otlp feature there is no exporter.
+#[cfg(not(feature = "otlp"))]
+enum Otlp {}
+
+/// Sets up the log, and the span export with --otlp-endpoint. The exporter has to be kept
+/// until exit to send the last spans.
+fn init_logging(args: &ServeArgs) -> Result<Option<Otlp>, ServeError> {
+    let level = if args.silent {
+        LevelFilter::OFF
     } else {
-        app.fallback_service(serve_dir)
+        LevelFilter::from_level(args.log_level.into())
     };
+    // With --quiet, what is logged outside of requests, like the startup output, is left out
+    // unless it is a warning or an error.
+    let quiet = args.quiet;
+    let requests = filter::dynamic_filter_fn(move |metadata, cx| {
+        !quiet
+            || metadata.is_span()
+            || *metadata.level() <= Level::WARN
+            || cx.lookup_current().is_some()
+    });
+    let registry = tracing_subscriber::registry()
+        .with(fmt_layer(args.log_format).with_filter(level.and(requests)));
+    #[cfg(feature = "otlp")]
+    if let Some(endpoint) = args.otlp_endpoint.as_ref() {
+        // The request spans are exported whatever the log level is.
+        let (layer, exporter) = otlp::layer(endpoint, args.otlp_sampling_ratio)?;
+        let level = args
+            .trace_level
+            .level()
+            .map_or(LevelFilter::OFF, LevelFilter::from_level);
+        registry.with(layer.with_filter(level)).init();
+        return Ok(Some(exporter));
+    }
+    registry.init();
+    Ok(None)
+}
 
-    let app = if args.disable_compression {
-        app
+fn fmt_layer<S>(format: LogFormat) -> Box<dyn tracing_subscriber::Layer<S> + Send + Sync>
+where
+    S: tracing::Subscriber + for<'span> LookupSpan<'span>,
+{
+    let layer = tracing_subscriber::fmt::layer();
+    match format {
+        LogFormat::Compact => layer.compact().boxed(),
+        LogFormat::Pretty => layer.pretty().boxed(),
+        LogFormat::Json => layer
+            .json()
+            .with_current_span(true)
+            .with_span_list(true)
+            .boxed(),
+    }
+}
+
+async fn health() -> Json<serde_json::Value> {
+    Json(json!({ "status": "ok" }))
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    match run().await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run() -> Result<(), ServeError> {
+    let command = ServeArgs::command();
+    let matches = command.clone().get_matches();
+    let args = ServeArgs::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+
+    let _exporter = init_logging(&args)?;
+
+    args.check_conflicts()?;
+    if !args.no_validate {
+        args.validate()?;
+    }
+    validate_mounts(&args.mount, &args.proxy)?;
+
+    // Before the app is built, which watches files, opens the access log and installs the
+    // metrics recorder.
+    if args.dry_run {
+        print!("{}", dry_run::summary(&command, &matches));
+        return Ok(());
+    }
+    let app = app(&args)?;
+    let server_header = args.server_header();
+    args.pregenerate()?;
+
+    let shutdown_timeout = Duration::from_secs(args.shutdown_timeout);
+    let handle = axum_server::Handle::new();
+    tokio::spawn(graceful_shutdown(
+        handle.clone(),
+        shutdown_timeout,
+        args.sd_notify,
+    ));
+
+    #[cfg(unix)]
+    if let Some(path) = args.unix_socket.as_ref() {
+        let _pid_file = args.pid_file.as_deref().map(PidFile::create).transpose()?;
+        unix::serve(
+            app,
+            path,
+            args.unix_socket_mode,
+            shutdown_timeout,
+            args.sd_notify,
+        )
+        .await?;
+        tracing::info!("shutdown complete");
+        return Ok(());
+    }
+
+    let is_tls = args.subcommand.is_some();
+    let passed = if args.systemd {
+        systemd::listeners()?
     } else {
-        tracing::info!("compression enabled");
-        app.layer(CompressionLayer::new())
+        None
     };
+    let listeners = match passed {
+        Some(listeners) => {
+            tracing::info!("using {} sockets passed by systemd", listeners.len());
+            listeners
+                .into_iter()
+                .map(|listener| Listener {
+                    listener,
+                    tls: is_tls,
+                })
+                .collect()
+        }
+        None => {
+            let mut listeners = Vec::new();
+            for listen in args.listen_addrs() {
+                listeners.push(Listener {
+                    listener: bind_tcp(listen.addr, args.socket_options())?,
+                    tls: listen.scheme.map_or(is_tls, |scheme| scheme == Scheme::Tls),
+                });
+            }
+            listeners
+        }
+    };
+    if args.print_addr {
+        for listener in &listeners {
+            println!("{}", listener.listener.local_addr()?);
+        }
+    }
+    // Dropped, and so removed, once the servers have shut down.
+    let _pid_file = args.pid_file.as_deref().map(PidFile::create).transpose()?;
+    let addr = listeners
+        .first()
+        .map(|listener| listener.listener.local_addr())
+        .transpose()?;
+    let ip = addr.map(|addr| addr.ip().to_string());
+    let port = addr.map(|addr| addr.port().to_string());
+    let hook_envs = [
+        ("SERVE_ADDR", ip.as_deref()),
+        ("SERVE_PORT", port.as_deref()),
+    ]
+    .into_iter()
+    .filter_map(|(name, value)| Some((name, OsStr::new(value?))))
+    .collect::<Vec<_>>();
+    if let Some(command) = &args.on_start {
+        if args.on_start_required {
+            hooks::run("start", command, &[], &hook_envs).await?;
+        } else {
+            hooks::spawn("start", command, &[], &hook_envs);
+        }
+    }
+    if args.sd_notify {
+        systemd::notify("READY=1");
+    }
 
-    let service = app.into_make_service();
-
+    let connection = args.connection_options();
+    let socket = args.socket_options();
+    let on_shutdown = args.on_shutdown.clone();
     match args.subcommand {
         Some(Subcommands::Tls(tls)) => {
-            let config = RustlsConfig::from_pem_file(&tls.cert, &tls.key).await?;
-            tracing::info!("listening on {} with TLS", addr);
-
-            let (server, tls_watcher) = join!(
-                axum_server::bind_rustls(addr, config.clone()).serve(service),
-                init_certificate_watch(config, &tls)
-            );
-            server?;
-            tls_watcher?;
+            tls::start_tls_server(
+                app,
+                listeners,
+                &tls,
+                connection,
+                socket,
+                server_header,
+                handle,
+            )
+            .await?;
         }
         None => {
-            tracing::info!("listening on {}", addr);
-            axum_server::bind(addr).serve(service).await?;
+            let servers = listeners.into_iter().map(|Listener { listener, .. }| {
+                listen::serve_plain(listener, app.clone(), connection, handle.clone())
+            });
+            future::try_join_all(servers).await?;
         }
     };
+    if let Some(command) = &on_shutdown {
+        if let Err(e) = hooks::run("shutdown", command, &[], &hook_envs).await {
+            tracing::error!("{}", e);
+        }
+    }
+    tracing::info!("shutdown complete");
     Ok(())
 }
 
-async fn init_certificate_watch(
-    tls_config: RustlsConfig,
-    serve_config: &Tls,
-) -> notify::Result<()> {
-    let mut delay: u64 = 1;
-    let (tx, mut rx) = tokio::sync::mpsc::channel(1);
-    let rt = Handle::current();
-    let retry_tx = tx.clone();
-
-    let mut watcher = RecommendedWatcher::new(
-        move |res: Result<Event>| match res {
-            Ok(res) => {
-                if let EventKind::Modify(ModifyKind::Data(DataChange::Content)) = res.kind {
-                    let tx = tx.clone();
-                    rt.spawn(async move {
-                        tx.send(()).await.expect("to be able to send message");
-                    });
-                }
-            }
-            Err(e) => tracing::error!("watcher error: {}", e),
-        },
-        Config::default(),
-    )?;
+/// Builds the router that serves what the options name, without binding anything.
+fn app(args: &ServeArgs) -> Result<Router, ServeError> {
+    let mut app = Router::new();
+
+    if args.not_found.is_some() {
+        tracing::info!("custom 404 page");
+        if args.ok {
+            tracing::info!("overriding 404 with 200 OK");
+        }
+    }
 
-    watcher.watch(&serve_config.cert, RecursiveMode::NonRecursive)?;
-    watcher.watch(&serve_config.key, RecursiveMode::NonRecursive)?;
+    for mount in &args.mount {
+        tracing::info!("serving {} at {}", mount.path.display(), mount.prefix);
+        if let Some(not_found) = &mount.not_found {
+            tracing::info!(
+                "custom 404 page {} at {}",
+                not_found.display(),
+                mount.prefix
+            );
+        }
+        let service = file_service(&mount.path, args, mount.not_found_page(args));
+        app = app.nest_service(
+            &mount.prefix,
+            middleware::from_fn(methods::apply).layer(service),
+        );
+    }
 
-    while rx.recv().await.is_some() {
-        tracing::info!("reloading rustls configuration");
-        match tls_config
-            .reload_from_pem_file(serve_config.cert.clone(), serve_config.key.clone())
-            .await
-        {
-            Ok(_) => {
-                tracing::info!("rustls configuration reload successiful");
-                delay = 1;
-            }
-            Err(e) => {
-                delay *= 2;
-                tracing::error!("rustls reload error: {}", e);
-                tracing::info!("sleep {} nanoseconds before retry", delay);
-                sleep(Duration::from_nanos(delay)).await;
-                retry_tx
-                    .send(())
-                    .await
-                    .expect("to be able to send retry message");
-            }
-        };
+    for proxy in &args.proxy {
+        tracing::info!("proxying {} to {}", proxy.prefix, proxy.upstream);
+        app = app.nest_service(&proxy.prefix, proxy::service(proxy));
     }
 
-    Ok(())
+    let fallback = match args.archive.as_ref() {
+        _ if args.file.is_some() => single_file_service(args)?,
+        Some(path) => {
+            let archive = Archive::open(path)?;
+            tracing::info!(
+                "serving {} files from {}",
+                archive.file_count(),
+                path.display()
+            );
+            archive_service(archive, args)
+        }
+        None => file_service(&args.get_path(), args, not_found_page(args)),
+    };
+    let fallback = boxed(middleware::from_fn(methods::apply).layer(fallback));
+    let fallback = if args.rewrite.is_empty() {
+        fallback
+    } else {
+        let rewrites: Arc<[Rewrite]> = Arc::from(args.rewrite.clone());
+        boxed(middleware::from_fn_with_state(rewrites, rewrites::apply).layer(fallback))
+    };
+    let fallback = if args.webdav {
+        tracing::info!(
+            "webdav enabled{}",
+            if args.webdav_write {
+                " with writes"
+            } else {
+                ""
+            }
+        );
+        let dav = WebDav::new(
+            &args.get_path(),
+            args.webdav_write,
+            args.dotfiles == dotfiles::Mode::Allow,
+        );
+        boxed(middleware::from_fn_with_state(Arc::new(dav), webdav::apply).layer(fallback))
+    } else {
+        fallback
+    };
+    let fallback = if args.watch_root {
+        let watch = root_watch::watch(&args.get_path())?;
+        boxed(middleware::from_fn_with_state(watch, root_watch::apply).layer(fallback))
+    } else {
+        fallback
+    };
+    let app = app.fallback_service(fallback);
+
+    let app = if args.redirect.is_empty() {
+        app
+    } else {
+        app.layer(middleware::from_fn_with_state(
+            Arc::from(args.redirect.clone()),
+            redirects::apply,
+        ))
+    };
+
+    let app = match (args.upload.as_ref(), args.upload_dir.as_ref()) {
+        (Some(prefix), Some(dir)) => {
+            tracing::info!("uploads at {} to {}", prefix, dir.display());
+            let upload = Upload::new(prefix, dir, args.upload_overwrite, args.dotfiles)?;
+            app.layer(middleware::from_fn_with_state(
+                Arc::new(upload),
+                upload::apply,
+            ))
+        }
+        _ => app,
+    };
+
+    let readonly = args
+        .mount
+        .iter()
+        .filter(|mount| mount.readonly)
+        .map(|mount| mount.prefix.clone())
+        .collect::<Arc<[String]>>();
+    let app = if readonly.is_empty() {
+        app
+    } else {
+        app.layer(middleware::from_fn_with_state(readonly, methods::read_only))
+    };
+
+    let app = if args.live_reload {
+        app.layer(middleware::from_fn(live_reload::inject))
+    } else {
+        app
+    };
+
+    let app = if let Some(path) = args.basic_auth.as_ref() {
+        tracing::info!("basic authentication enabled");
+        let auth = BasicAuth {
+            htpasswd: Htpasswd::from_file(path)?,
+            realm: args.basic_auth_realm.clone(),
+        };
+        app.layer(middleware::from_fn_with_state(
+            Arc::new(auth),
+            auth::basic_auth,
+        ))
+    } else {
+        app
+    };
+
+    // Outside authentication, so clients are sent to the canonical host before they log in.
+    let app = if args.canonical_redirect == canonical_host::Mode::Off {
+        app
+    } else {
+        let canonical = CanonicalHost {
+            mode: args.canonical_redirect,
+            domain: args.canonical_host.clone(),
+        };
+        app.layer(middleware::from_fn_with_state(
+            Arc::new(canonical),
+            canonical_host::apply,
+        ))
+    };
+
+    let app = if let Some(timeout) = args.request_timeout {
+        app.layer(TimeoutLayer::new(Duration::from_secs(timeout)))
+    } else {
+        app
+    };
+
+    let app = if let Some(path) = args.maintenance.as_ref() {
+        let maintenance = Arc::new(Maintenance::load(
+            path,
+            !args.maintenance_off,
+            args.maintenance_retry_after,
+        )?);
+        if maintenance.is_active() {
+            tracing::info!("maintenance mode on, answering with {}", path.display());
+        }
+        #[cfg(unix)]
+        maintenance::toggle_on_hangup(maintenance.clone())?;
+        app.layer(middleware::from_fn_with_state(
+            maintenance,
+            maintenance::apply,
+        ))
+    } else {
+        app
+    };
+
+    // Inside the trace layer, so the id is recorded on the span of the request.
+    let app = if args.request_id {
+        app.layer(middleware::from_fn_with_state(
+            Arc::new(args.request_id_header.clone()),
+            request_id::apply,
+        ))
+    } else {
+        app
+    };
+
+    let app = if let Some(level) = args.trace_level.level() {
+        let exclude: Arc<[String]> = Arc::from(args.log_exclude_path.clone());
+        let redacted: Option<Arc<[String]>> = args.log_headers.then(|| {
+            args.log_redact_header
+                .iter()
+                .filter(|name| !name.is_empty())
+                .cloned()
+                .collect()
+        });
+        let latency_unit = args.log_latency_unit.into();
+        app.layer(middleware::from_fn_with_state(level, logging::count_bytes))
+            .layer(
+                TraceLayer::new_for_http()
+                    .make_span_with(RequestSpan {
+                        level,
+                        exclude,
+                        sample_rate: args.trace_sample_rate,
+                        redacted: redacted.clone(),
+                    })
+                    .on_request(RequestEvent::default())
+                    .on_response(ResponseEvent::new(level, latency_unit, redacted)),
+            )
+    } else {
+        app
+    };
+    let app = app.layer(middleware::from_fn_with_state(
+        Arc::from(args.trusted_proxies.clone()),
+        forwarded::client_ip,
+    ));
+
+    // Routes added after this point are not behind the trace and auth layers.
+    let app = if let Some(path) = args.metrics_path.as_ref() {
+        tracing::info!("metrics on {}", path);
+        let handle = metrics::install()?;
+        let prefixes = args
+            .mount
+            .iter()
+            .map(|mount| mount.prefix.clone())
+            .collect();
+        app.layer(middleware::from_fn_with_state(prefixes, metrics::track))
+            .route(path, get(move || async move { metrics::render(&handle) }))
+    } else {
+        app
+    };
+
+    let app = if let Some(path) = args.health_path.as_ref() {
+        tracing::info!("health check on {}", path);
+        app.route(path, get(health))
+    } else {
+        app
+    };
+
+    let app = if let Some(path) = args.version_path.as_ref() {
+        tracing::info!("version on {}", path);
+        app.route(path, get(version::info))
+    } else {
+        app
+    };
+
+    let app = if args.live_reload {
+        tracing::info!("live reload enabled");
+        let root = (args.archive.is_none() && args.file.is_none()).then(|| args.get_path());
+        let dirs = root
+            .into_iter()
+            .chain(args.mount.iter().map(|mount| mount.path.clone()))
+            .collect();
+        let reload = live_reload::watch(dirs)?;
+        app.route(
+            live_reload::PATH,
+            get(live_reload::events).with_state(reload),
+        )
+    } else {
+        app
+    };
+
+    let app = if let Some(limit) = args.max_body_size {
+        app.layer(RequestBodyLimitLayer::new(limit))
+    } else {
+        app
+    };
+
+    let error_pages = args
+        .error_page
+        .iter()
+        .filter(|page| page.status != StatusCode::NOT_FOUND || args.not_found.is_none())
+        .cloned()
+        .collect::<Vec<_>>();
+    let app = if error_pages.is_empty() {
+        app
+    } else {
+        app.layer(middleware::from_fn_with_state(
+            Arc::new(ErrorPages::load(&error_pages)?),
+            error_pages::apply,
+        ))
+    };
+
+    let app = if args.cors {
+        tracing::info!("CORS enabled");
+        app.layer(cors::layer(
+            &args.cors_origin,
+            &args.cors_methods,
+            &args.cors_headers,
+            args.cors_credentials,
+        )?)
+    } else {
+        app
+    };
+
+    let hsts = args.security_headers
+        || args.hsts_max_age.is_some()
+        || args.hsts_include_subdomains
+        || args.hsts_preload;
+    let app = if hsts {
+        let security = SecurityHeaders {
+            common: args.security_headers,
+            content_security_policy: args.csp.clone(),
+            strict_transport_security: Some(security_headers::hsts(
+                args.hsts_max_age.unwrap_or(security_headers::HSTS_MAX_AGE),
+                args.hsts_include_subdomains,
+                args.hsts_preload,
+            )),
+        };
+        app.layer(middleware::from_fn_with_state(
+            Arc::new(security),
+            security_headers::apply,
+        ))
+    } else {
+        app
+    };
+
+    let app = args.headers.iter().fold(app, |app, header| {
+        app.layer(SetResponseHeaderLayer::overriding(
+            header.name.clone(),
+            header.value.clone(),
+        ))
+    });
+
+    let app = if args.disable_compression {
+        app
+    } else {
+        tracing::info!("compression enabled");
+        let app = if args.compression_exclude_path.is_empty() {
+            app
+        } else {
+            app.layer(middleware::from_fn_with_state(
+                Arc::from(args.compression_exclude_path.clone()),
+                compression::exclude_paths,
+            ))
+        };
+        app.layer(compression::layer(
+            &args.compression_algorithms,
+            args.compression_level,
+            args.compression_min_size,
+            &args.compression_include_type,
+            &args.compression_exclude_type,
+        ))
+    };
+
+    let app = if args.disable_compression && !args.precompressed() {
+        app
+    } else {
+        app.layer(middleware::from_fn_with_state(
+            args.precompressed(),
+            compression::vary,
+        ))
+    };
+
+    let app = app.layer(middleware::from_fn(methods::head));
+
+    let app = match args.max_rate {
+        Some(rate) if rate > 0 => {
+            tracing::info!("responses limited to {} bytes per second", rate);
+            app.layer(middleware::from_fn_with_state(rate, throttle::apply))
+        }
+        _ => app,
+    };
+
+    let app = if let Some(max) = args.max_connections {
+        tracing::info!("at most {} requests at once", max);
+        let limit = ConcurrencyLimit::new(max as usize, args.on_limit);
+        app.layer(middleware::from_fn_with_state(
+            Arc::new(limit),
+            concurrency::apply,
+        ))
+    } else {
+        app
+    };
+
+    let app = if args.allow.is_empty() && args.deny.is_empty() {
+        app
+    } else {
+        let filter = IpFilter::new(
+            &args.allow,
+            &args.deny,
+            Arc::from(args.trusted_proxies.clone()),
+        );
+        app.layer(middleware::from_fn_with_state(
+            Arc::new(filter),
+            ip_filter::apply,
+        ))
+    };
+
+    let server_header = args.server_header();
+    let app = server_header::layer(app, server_header.as_ref());
+
+    let app = if let Some(path) = args.access_log.as_ref() {
+        tracing::info!("access log in {}", path.display());
+        let file = RollingFile::open(
+            path,
+            args.access_log_rotation,
+            args.access_log_max_size as u64,
+            args.access_log_max_files,
+            args.access_log_compress,
+        )?;
+        let access_log = AccessLog::new(
+            file,
+            args.access_log_format.clone(),
+            Arc::from(args.trusted_proxies.clone()),
+            Arc::from(args.log_exclude_path.clone()),
+        );
+        app.layer(middleware::from_fn_with_state(
+            Arc::new(access_log),
+            access_log::apply,
+        ))
+    } else {
+        app
+    };
+
+    Ok(app)
+}
+
+/// Resolves when the process receives SIGINT, or SIGTERM on unix.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        signal::ctrl_c()
+            .await
+            .expect("to be able to listen for ctrl-c");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        signal::unix::signal(signal::unix::SignalKind::terminate())
+            .expect("to be able to listen for SIGTERM")
+            .recv()
+            .await;
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+}
+
+/// Stops accepting connections on the first signal and gives in-flight requests `timeout` to
+/// finish. A second signal exits right away.
+async fn graceful_shutdown(handle: axum_server::Handle, timeout: Duration, sd_notify: bool) {
+    shutdown_signal().await;
+    if sd_notify {
+        systemd::notify("STOPPING=1");
+    }
+    tracing::info!(
+        "shutting down, draining connections for up to {:?}",
+        timeout
+    );
+    handle.graceful_shutdown(Some(timeout));
+
+    shutdown_signal().await;
+    tracing::info!("second shutdown signal received, exiting");
+    std::process::exit(1);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{body_bytes, body_string, get, request, Capture, TempDir};
+    use axum::body::Body;
+    use axum::http::{header, HeaderName, HeaderValue};
+    use flate2::{write::GzEncoder, Compression};
+    use std::io::Write;
+
+    /// Parses the options, with `root` as the path to serve.
+    fn args(root: &TempDir, options: &[&str]) -> ServeArgs {
+        let root = root.path().to_str().unwrap();
+        ServeArgs::try_parse_from(["serve", root].iter().chain(options)).unwrap()
+    }
+
+    async fn send(args: &ServeArgs, request: Request) -> Response {
+        app(args).unwrap().oneshot(request).await.unwrap()
+    }
+
+    async fn send_to(app: &Router, request: Request) -> StatusCode {
+        app.clone().oneshot(request).await.unwrap().status()
+    }
+
+    fn with_header(mut request: Request, name: HeaderName, value: &str) -> Request {
+        request
+            .headers_mut()
+            .insert(name, HeaderValue::from_str(value).unwrap());
+        request
+    }
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[tokio::test]
+    async fn precompressed_file_is_served_to_gzip_clients() {
+        let root = TempDir::new("precompressed");
+        root.write("index.html", "<p>plain</p>");
+        // Served as it is, so it only has to stand out from the plain file.
+        root.write("index.html.gz", "precompressed");
+        let args = args(&root, &["--precompressed"]);
+
+        for uri in ["/", "/index.html"] {
+            let request = with_header(get(uri), header::ACCEPT_ENCODING, "br;q=0.5, gzip");
+            let response = send(&args, request).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(response.headers()[header::CONTENT_ENCODING], "gzip");
+            assert_eq!(response.headers()[header::CONTENT_TYPE], "text/html");
+            assert_eq!(body_bytes(response).await, b"precompressed");
+        }
+
+        let response = send(&args, get("/index.html")).await;
+        assert!(response.headers().get(header::CONTENT_ENCODING).is_none());
+        assert_eq!(body_bytes(response).await, b"<p>plain</p>");
+    }
+
+    #[tokio::test]
+    async fn custom_headers_are_on_every_response() {
+        let root = TempDir::new("custom-headers");
+        root.write("index.html", "home");
+        root.write("404.html", "missing");
+        let not_found = root.path().join("404.html");
+        let args = args(
+            &root,
+            &[
+                "--header",
+                "X-Frame-Options: DENY",
+                "--header",
+                "Cache-Control: no-cache",
+                "--header",
+                "Cache-Control: no-store",
+                "--not-found",
+                not_found.to_str().unwrap(),
+            ],
+        );
+
+        let response = send(&args, get("/")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::X_FRAME_OPTIONS], "DENY");
+        assert_eq!(response.headers()[header::CACHE_CONTROL], "no-store");
+
+        let response = send(&args, get("/missing")).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(response.headers()[header::X_FRAME_OPTIONS], "DENY");
+        assert_eq!(response.headers()[header::CACHE_CONTROL], "no-store");
+        assert_eq!(body_string(response).await, "missing");
+    }
+
+    /// The only test that installs the metrics recorder, since there is one per process.
+    #[tokio::test]
+    async fn metrics_count_requests() {
+        let root = TempDir::new("metrics");
+        root.write("a.txt", "a");
+        root.write("docs/b.txt", "b");
+        let docs = format!("/docs={}", root.path().join("docs").display());
+        let args = args(&root, &["--metrics-path", "/metrics", "--mount", &docs]);
+        let app = app(&args).unwrap();
+        let scrape = || async {
+            let response = app.clone().oneshot(get("/metrics")).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            body_string(response).await
+        };
+
+        assert_eq!(send_to(&app, get("/a.txt")).await, StatusCode::OK);
+        let before = scrape().await;
+        let home = r#"http_requests_total{method="GET",route="/",status="200"}"#;
+        assert!(before.contains(&format!("{} 1\n", home)), "{}", before);
+
+        assert_eq!(send_to(&app, get("/a.txt")).await, StatusCode::OK);
+        assert_eq!(send_to(&app, get("/missing")).await, StatusCode::NOT_FOUND);
+        assert_eq!(send_to(&app, get("/docs/b.txt")).await, StatusCode::OK);
+        let after = scrape().await;
+        assert!(after.contains(&format!("{} 2\n", home)), "{}", after);
+        for series in [
+            r#"http_requests_total{method="GET",route="/",status="404"} 1"#,
+            r#"http_requests_total{method="GET",route="/docs",status="200"} 1"#,
+            r#"http_request_duration_seconds_count{method="GET",route="/",status="200"} 2"#,
+        ] {
+            assert!(after.contains(series), "{}", after);
+        }
+        // The scrapes themselves are not counted.
+        assert!(!after.contains("route=\"/metrics\""), "{}", after);
+    }
+
+    #[tokio::test]
+    async fn oversized_body_is_rejected() {
+        let root = TempDir::new("max-body-size");
+        root.write("a.txt", "a");
+        let args = args(&root, &["--max-body-size", "1k"]);
+        let request = |len: usize| {
+            Request::builder()
+                .method(Method::POST)
+                .uri("/a.txt")
+                .header(header::CONTENT_LENGTH, len)
+                .body(Body::from(vec![b'x'; len]))
+                .unwrap()
+        };
+        let response = send(&args, request(1025)).await;
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        // Within the limit it gets as far as the file, which does not take a POST.
+        let response = send(&args, request(1024)).await;
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+    }
+
+    #[tokio::test]
+    async fn not_found_page_wins_over_error_page() {
+        let root = TempDir::new("error-page-404");
+        root.write(".secret", "secret");
+        let not_found = root.write("pages/404.html", "not found page");
+        let error_404 = root.write("pages/error-404.html", "error page 404");
+        let error_403 = root.write("pages/403.html", "forbidden page");
+        let args = args(
+            &root,
+            &[
+                "--dotfiles",
+                "deny",
+                "--not-found",
+                not_found.to_str().unwrap(),
+                "--error-page",
+                &format!("404={}", error_404.display()),
+                "--error-page",
+                &format!("403={}", error_403.display()),
+            ],
+        );
+
+        let response = send(&args, get("/.secret")).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert_eq!(body_string(response).await, "forbidden page");
+        let response = send(&args, get("/missing")).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(body_string(response).await, "not found page");
+    }
+
+    #[tokio::test]
+    async fn spa_answers_navigations_with_the_index() {
+        let root = TempDir::new("spa");
+        root.write("index.html", "app");
+        root.write("style.css", "css");
+        root.write("about.html", "about");
+        let args = args(&root, &["--spa", "--clean-urls"]);
+        let navigation = |uri| with_header(get(uri), header::ACCEPT, "text/html,*/*;q=0.8");
+
+        let response = send(&args, navigation("/users/42")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(body_string(response).await, "app");
+        // Clean URLs are tried before falling back to the index.
+        let response = send(&args, navigation("/about")).await;
+        assert_eq!(body_string(response).await, "about");
+
+        let response = send(&args, get("/style.css")).await;
+        assert_eq!(body_string(response).await, "css");
+        let asset = with_header(get("/missing.js"), header::ACCEPT, "*/*");
+        let response = send(&args, asset).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn mime_overrides_win_over_the_guess() {
+        let root = TempDir::new("mime-types");
+        for name in ["model.data", "app.js", "site.css", "module.wasm"] {
+            root.write(name, "x");
+        }
+        let content_type = |args: ServeArgs, uri: &'static str| async move {
+            let response = send(&args, get(uri)).await;
+            response.headers()[header::CONTENT_TYPE].clone()
+        };
+        let overridden = || {
+            args(
+                &root,
+                &[
+                    "--mime",
+                    "data=application/octet-stream",
+                    "--mime",
+                    ".JS=text/javascript; charset=utf-8",
+                ],
+            )
+        };
+
+        let data = content_type(overridden(), "/model.data").await;
+        assert_eq!(data, "application/octet-stream");
+        let js = content_type(overridden(), "/app.js").await;
+        assert_eq!(js, "text/javascript; charset=utf-8");
+        assert_eq!(content_type(overridden(), "/site.css").await, "text/css");
+        let wasm = content_type(args(&root, &[]), "/module.wasm").await;
+        assert_eq!(wasm, "application/wasm");
+        let js = content_type(args(&root, &[]), "/app.js").await;
+        assert_eq!(js, "application/javascript");
+    }
+
+    #[tokio::test]
+    async fn header_option_replaces_a_security_header() {
+        let root = TempDir::new("security-headers");
+        root.write("index.html", "home");
+        let options = [
+            "--security-headers",
+            "--header",
+            "X-Frame-Options: SAMEORIGIN",
+        ];
+        let response = send(&args(&root, &options), get("/")).await;
+        let frame_options = response.headers().get_all(header::X_FRAME_OPTIONS);
+        assert_eq!(frame_options.iter().collect::<Vec<_>>(), ["SAMEORIGIN"]);
+        assert_eq!(
+            response.headers()[header::X_CONTENT_TYPE_OPTIONS],
+            "nosniff"
+        );
+    }
+
+    #[tokio::test]
+    async fn no_server_header_option() {
+        let root = TempDir::new("no-server-header");
+        root.write("index.html", "home");
+        let removed = args(&root, &["--no-server-header", "--header", "Server: custom"]);
+        for uri in ["/", "/missing"] {
+            let response = send(&removed, get(uri)).await;
+            assert!(!response.headers().contains_key(header::SERVER), "{}", uri);
+        }
+        let set = args(&root, &["--server-header", "serve"]);
+        let response = send(&set, get("/missing")).await;
+        assert_eq!(response.headers()[header::SERVER], "serve");
+    }
+
+    #[tokio::test]
+    async fn dotfiles_policy() {
+        let root = TempDir::new("dotfiles");
+        root.write(".env", "SECRET=1");
+        root.write("assets/.secret", "secret");
+        root.write("assets/app.js", "app");
+        let status = |mode: &'static str, uri: &'static str| {
+            let args = args(&root, &["--dotfiles", mode]);
+            async move { send(&args, get(uri)).await.status() }
+        };
+
+        for uri in ["/.env", "/assets/.secret", "/%2eenv", "/assets/%2Esecret"] {
+            assert_eq!(
+                status("ignore", uri).await,
+                StatusCode::NOT_FOUND,
+                "{}",
+                uri
+            );
+            assert_eq!(status("deny", uri).await, StatusCode::FORBIDDEN, "{}", uri);
+        }
+        assert_eq!(status("allow", "/.env").await, StatusCode::OK);
+        assert_eq!(status("allow", "/assets/.secret").await, StatusCode::OK);
+        for mode in ["ignore", "deny"] {
+            assert_eq!(status(mode, "/assets/app.js").await, StatusCode::OK);
+        }
+        // Ignore is the default.
+        let response = send(&args(&root, &[]), get("/.env")).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn symlinks_out_of_the_root() {
+        use std::os::unix::fs::symlink;
+
+        let outside = TempDir::new("symlinks-outside");
+        let secret = outside.write("secret.txt", "secret");
+        let root = TempDir::new("symlinks-root");
+        let file = root.write("docs/a.txt", "a");
+        symlink(&file, root.path().join("inside.txt")).unwrap();
+        symlink(&secret, root.path().join("outside.txt")).unwrap();
+        symlink(outside.path(), root.path().join("outside")).unwrap();
+        let status = |follow: &'static str, uri: &'static str| {
+            let args = args(&root, &["--follow-symlinks", follow]);
+            async move { send(&args, get(uri)).await.status() }
+        };
+
+        assert_eq!(status("false", "/inside.txt").await, StatusCode::OK);
+        assert_eq!(status("false", "/outside.txt").await, StatusCode::NOT_FOUND);
+        let through_dir = "/outside/secret.txt";
+        assert_eq!(status("false", through_dir).await, StatusCode::NOT_FOUND);
+        assert_eq!(status("true", "/outside.txt").await, StatusCode::OK);
+        assert_eq!(status("true", through_dir).await, StatusCode::OK);
+    }
+
+    /// Requests each of `uris` with `options`, and returns what was logged.
+    async fn log(root: &TempDir, options: &[&str], uris: &[&str]) -> String {
+        let (capture, _default) = Capture::start();
+        let args = args(root, options);
+        for uri in uris {
+            body_string(send(&args, get(uri)).await).await;
+        }
+        capture.lines().join("\n")
+    }
+
+    #[tokio::test]
+    async fn excluded_paths_are_not_logged() {
+        let root = TempDir::new("log-exclude-path");
+        root.write("a.txt", "a");
+        root.write("metrics/all", "all");
+        let uris = ["/metrics", "/metrics/all", "/a.txt"];
+        let logged = log(&root, &["--log-exclude-path", "/metrics"], &uris).await;
+        assert!(!logged.contains("uri=/metrics"), "{}", logged);
+        let a = |event| {
+            logged
+                .lines()
+                .any(|l| l.contains("uri=/a.txt") && l.contains(event))
+        };
+        assert!(a("started processing request"), "{}", logged);
+        assert!(a("finished processing request"), "{}", logged);
+
+        // Prefixes match whole path segments.
+        let logged = log(&root, &["--log-exclude-path", "/met"], &["/metrics/all"]).await;
+        assert!(logged.contains("uri=/metrics/all"), "{}", logged);
+    }
+
+    #[tokio::test]
+    async fn force_download_sets_content_disposition() {
+        let root = TempDir::new("force-download");
+        root.write("index.html", "home");
+        root.write("report.pdf", "pdf");
+        root.write("Übersicht.pdf", "pdf");
+        let args = args(&root, &["--force-download-ext", "pdf"]);
+
+        let response = send(&args, get("/report.pdf")).await;
+        assert_eq!(
+            response.headers()[header::CONTENT_DISPOSITION],
+            "attachment; filename=\"report.pdf\""
+        );
+        let response = send(&args, get("/%C3%9Cbersicht.pdf")).await;
+        assert_eq!(
+            response.headers()[header::CONTENT_DISPOSITION],
+            "attachment; filename=\"_bersicht.pdf\"; filename*=UTF-8''%C3%9Cbersicht.pdf"
+        );
+        for uri in ["/", "/missing.pdf"] {
+            let response = send(&args, get(uri)).await;
+            assert!(response
+                .headers()
+                .get(header::CONTENT_DISPOSITION)
+                .is_none());
+        }
+    }
+
+    #[tokio::test]
+    async fn index_files_are_tried_in_order() {
+        let root = TempDir::new("index-files");
+        root.write("only/index.htm", "htm");
+        root.write("both/index.htm", "htm");
+        root.write("both/default.html", "default");
+        let args = args(
+            &root,
+            &["--index-file", "default.html", "--index-file", "index.htm"],
+        );
+
+        assert_eq!(body_string(send(&args, get("/only/")).await).await, "htm");
+        assert_eq!(
+            body_string(send(&args, get("/both/")).await).await,
+            "default"
+        );
+        let response = send(&args, get("/neither/")).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn i18n_serves_the_accepted_language() {
+        let root = TempDir::new("i18n-app");
+        root.write("page.html", "page");
+        root.write("page.de.html", "Seite");
+        let args = args(&root, &["--i18n"]);
+
+        let request = with_header(get("/page.html"), header::ACCEPT_LANGUAGE, "de-DE, en");
+        let response = send(&args, request).await;
+        assert_eq!(response.headers()[header::VARY], "accept-language");
+        assert_eq!(body_string(response).await, "Seite");
+        let request = with_header(get("/page.html"), header::ACCEPT_LANGUAGE, "en");
+        assert_eq!(body_string(send(&args, request).await).await, "page");
+    }
+
+    #[tokio::test]
+    async fn redirects_win_over_files() {
+        let root = TempDir::new("redirects-app");
+        root.write("old.html", "old");
+        root.write("kept.html", "kept");
+        let args = args(&root, &["--redirect", "/old.html=/new.html"]);
+
+        let response = send(&args, get("/old.html")).await;
+        assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+        assert_eq!(response.headers()[header::LOCATION], "/new.html");
+        assert_eq!(
+            body_string(send(&args, get("/kept.html")).await).await,
+            "kept"
+        );
+    }
+
+    #[tokio::test]
+    async fn rewrites_serve_another_file_without_redirecting() {
+        let root = TempDir::new("rewrites-app");
+        root.write("index.html", "app");
+        root.write("assets/site.css", "css");
+        let args = args(
+            &root,
+            &[
+                "--rewrite",
+                "/assets/:version/*=/assets/:splat",
+                "--rewrite",
+                "/app/*=/index.html",
+            ],
+        );
+
+        let response = send(&args, get("/assets/v2/site.css")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "text/css");
+        assert_eq!(body_string(response).await, "css");
+        let response = send(&args, get("/app/users/1")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers().get(header::LOCATION).is_none());
+        assert_eq!(body_string(response).await, "app");
+    }
+
+    #[test]
+    fn connection_options_come_from_the_flags() {
+        let root = TempDir::new("connection-options");
+        let options = args(&root, &[]).connection_options();
+        assert_eq!(options.header_read_timeout, Some(Duration::from_secs(30)));
+        assert_eq!(options.idle_timeout, Some(Duration::from_secs(60)));
+        assert!(!options.tcp_nodelay);
+
+        let options = args(
+            &root,
+            &[
+                "--header-read-timeout",
+                "5",
+                "--keep-alive-timeout",
+                "0",
+                "--tcp-nodelay",
+            ],
+        )
+        .connection_options();
+        assert_eq!(options.header_read_timeout, Some(Duration::from_secs(5)));
+        assert_eq!(options.idle_timeout, None);
+        assert!(options.tcp_nodelay);
+    }
+
+    #[tokio::test]
+    async fn compressed_formats_are_not_compressed_again() {
+        let root = TempDir::new("compression-types");
+        root.write("page.html", "<p>page</p>".repeat(100));
+        root.write("image.png", [0u8; 1000]);
+        let args = args(&root, &[]);
+
+        let request = with_header(get("/page.html"), header::ACCEPT_ENCODING, "gzip");
+        let response = send(&args, request).await;
+        assert_eq!(response.headers()[header::CONTENT_ENCODING], "gzip");
+        let request = with_header(get("/image.png"), header::ACCEPT_ENCODING, "gzip");
+        let response = send(&args, request).await;
+        assert!(response.headers().get(header::CONTENT_ENCODING).is_none());
+        assert_eq!(body_bytes(response).await, [0u8; 1000]);
+    }
+
+    #[tokio::test]
+    async fn version_path_serves_json() {
+        let root = TempDir::new("version-path");
+        let response = send(&args(&root, &[]), get("/version")).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let response = send(
+            &args(&root, &["--version-path", "/version"]),
+            get("/version"),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json");
+        let info: serde_json::Value = serde_json::from_slice(&body_bytes(response).await).unwrap();
+        assert_eq!(info["version"], env!("CARGO_PKG_VERSION"));
+    }
+
+    #[tokio::test]
+    async fn range_requests() {
+        let root = TempDir::new("ranges-app");
+        root.write("file.txt", "0123456789");
+        let args = args(&root, &[]);
+
+        let response = send(&args, get("/file.txt")).await;
+        assert_eq!(response.headers()[header::ACCEPT_RANGES], "bytes");
+
+        let request = with_header(get("/file.txt"), header::RANGE, "bytes=2-4");
+        let response = send(&args, request).await;
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(response.headers()[header::CONTENT_RANGE], "bytes 2-4/10");
+        assert_eq!(body_string(response).await, "234");
+
+        let request = with_header(get("/file.txt"), header::RANGE, "bytes=0-1,8-");
+        let response = send(&args, request).await;
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        let content_type = response.headers()[header::CONTENT_TYPE].to_str().unwrap();
+        assert!(content_type.starts_with("multipart/byteranges; boundary="));
+        let body = body_string(response).await;
+        assert!(
+            body.contains("Content-Range: bytes 0-1/10\r\n\r\n01\r\n"),
+            "{}",
+            body
+        );
+        assert!(
+            body.contains("Content-Range: bytes 8-9/10\r\n\r\n89\r\n"),
+            "{}",
+            body
+        );
+
+        for range in ["bytes=10-", "bytes=10-12,20-"] {
+            let request = with_header(get("/file.txt"), header::RANGE, range);
+            let response = send(&args, request).await;
+            assert_eq!(
+                response.status(),
+                StatusCode::RANGE_NOT_SATISFIABLE,
+                "{}",
+                range
+            );
+            assert_eq!(response.headers()[header::CONTENT_RANGE], "bytes */10");
+        }
+    }
+
+    #[tokio::test]
+    async fn uploads_are_written_below_the_upload_dir() {
+        let root = TempDir::new("upload");
+        let dir = root.path().join("drop");
+        std::fs::create_dir(&dir).unwrap();
+        let args = args(
+            &root,
+            &[
+                "--upload",
+                "/uploads",
+                "--upload-dir",
+                dir.to_str().unwrap(),
+                "--max-body-size",
+                "10",
+            ],
+        );
+
+        let response = send(&args, request("PUT", "/uploads/a/b.txt", "hello")).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        assert_eq!(response.headers()[header::LOCATION], "/uploads/a/b.txt");
+        assert_eq!(
+            std::fs::read_to_string(dir.join("a/b.txt")).unwrap(),
+            "hello"
+        );
+        let response = send(&args, request("POST", "/uploads/a/b.txt", "again")).await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        assert_eq!(
+            std::fs::read_to_string(dir.join("a/b.txt")).unwrap(),
+            "hello"
+        );
+        let response = send(&args, request("PUT", "/uploads/", "hello")).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn readonly_mounts_refuse_writes() {
+        let root = TempDir::new("mount-readonly");
+        root.write("locked/a.txt", "locked");
+        root.write("open/a.txt", "open");
+        let mount = |name: &str, fields: &str| {
+            format!(
+                "/files/{}={}{}",
+                name,
+                root.path().join(name).display(),
+                fields
+            )
+        };
+        let args = args(
+            &root,
+            &[
+                "--upload",
+                "/files",
+                "--upload-dir",
+                root.path().to_str().unwrap(),
+                "--upload-overwrite",
+                "--mount",
+                &mount("locked", ",readonly"),
+                "--mount",
+                &mount("open", ""),
+            ],
+        );
+        let app = app(&args).unwrap();
+
+        for method in ["PUT", "POST", "DELETE", "MOVE"] {
+            let response = app
+                .clone()
+                .oneshot(request(method, "/files/locked/a.txt", "new"))
+                .await
+                .unwrap();
+            assert_eq!(
+                response.status(),
+                StatusCode::METHOD_NOT_ALLOWED,
+                "{}",
+                method
+            );
+            assert_eq!(response.headers()[header::ALLOW], "GET, HEAD, OPTIONS");
+        }
+        // Uploads decode the path and skip empty and `.` segments, so these write below
+        // /files/locked too.
+        for uri in [
+            "/files/%6Cocked/a.txt",
+            "/files//locked/a.txt",
+            "/files/./locked/a.txt",
+            "/files/locked/../locked/a.txt",
+        ] {
+            let response = app
+                .clone()
+                .oneshot(request("PUT", uri, "new"))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED, "{}", uri);
+        }
+        assert_eq!(
+            std::fs::read_to_string(root.path().join("locked/a.txt")).unwrap(),
+            "locked"
+        );
+        assert_eq!(
+            send_to(&app, get("/files/locked/a.txt")).await,
+            StatusCode::OK
+        );
+
+        let response = app
+            .oneshot(request("PUT", "/files/open/a.txt", "new"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            std::fs::read_to_string(root.path().join("open/a.txt")).unwrap(),
+            "new"
+        );
+    }
+
+    #[tokio::test]
+    async fn uploads_can_not_leave_the_upload_dir() {
+        let root = TempDir::new("upload-traversal");
+        let dir = root.path().join("drop");
+        std::fs::create_dir(&dir).unwrap();
+        let args = args(
+            &root,
+            &[
+                "--upload",
+                "/uploads",
+                "--upload-dir",
+                dir.to_str().unwrap(),
+            ],
+        );
+
+        for uri in [
+            "/uploads/../escaped.txt",
+            "/uploads/%2e%2e/escaped.txt",
+            "/uploads/a/..%2F..%2Fescaped.txt",
+        ] {
+            let response = send(&args, request("PUT", uri, "hello")).await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{}", uri);
+        }
+        assert!(!root.path().join("escaped.txt").exists());
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn uploads_can_not_follow_symlinks_out_of_the_upload_dir() {
+        use std::os::unix::fs::symlink;
+
+        let outside = TempDir::new("upload-symlink-outside");
+        let secret = outside.write("secret.txt", "secret");
+        let root = TempDir::new("upload-symlink");
+        let dir = root.path().join("drop");
+        std::fs::create_dir(&dir).unwrap();
+        symlink(outside.path(), dir.join("out")).unwrap();
+        symlink(&secret, dir.join("secret.txt")).unwrap();
+        let args = args(
+            &root,
+            &[
+                "--upload",
+                "/uploads",
+                "--upload-dir",
+                dir.to_str().unwrap(),
+                "--upload-overwrite",
+            ],
+        );
+
+        for uri in [
+            "/uploads/out/escaped.txt",
+            "/uploads/out/new/escaped.txt",
+            "/uploads/out/secret.txt",
+            "/uploads/secret.txt",
+        ] {
+            let response = send(&args, request("PUT", uri, "hello")).await;
+            assert_eq!(response.status(), StatusCode::FORBIDDEN, "{}", uri);
+        }
+        assert_eq!(std::fs::read_to_string(&secret).unwrap(), "secret");
+        assert_eq!(std::fs::read_dir(outside.path()).unwrap().count(), 1);
+    }
+
+    #[tokio::test]
+    async fn uploads_follow_the_dotfiles_policy() {
+        let root = TempDir::new("upload-dotfiles");
+        let dir = root.path().join("drop");
+        std::fs::create_dir(&dir).unwrap();
+        let upload = [
+            "--upload",
+            "/uploads",
+            "--upload-dir",
+            dir.to_str().unwrap(),
+        ];
+
+        for (mode, status) in [
+            ("ignore", StatusCode::NOT_FOUND),
+            ("deny", StatusCode::FORBIDDEN),
+        ] {
+            let options = [&upload[..], &["--dotfiles", mode]].concat();
+            let configured = args(&root, &options);
+            for uri in [
+                "/uploads/.env",
+                "/uploads/a/.git/config",
+                "/uploads/%2ehidden",
+            ] {
+                let response = send(&configured, request("PUT", uri, "secret")).await;
+                assert_eq!(response.status(), status, "{} {}", mode, uri);
+            }
+        }
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+
+        let configured = args(&root, &[&upload[..], &["--dotfiles", "allow"]].concat());
+        let response = send(&configured, request("PUT", "/uploads/.env", "shown")).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        assert_eq!(std::fs::read_to_string(dir.join(".env")).unwrap(), "shown");
+    }
+
+    #[tokio::test]
+    async fn oversize_uploads_are_refused() {
+        let root = TempDir::new("upload-oversize");
+        let dir = root.path().join("drop");
+        std::fs::create_dir(&dir).unwrap();
+        let args = args(
+            &root,
+            &[
+                "--upload",
+                "/uploads",
+                "--upload-dir",
+                dir.to_str().unwrap(),
+                "--max-body-size",
+                "10",
+            ],
+        );
+
+        let response = send(&args, request("PUT", "/uploads/big.txt", "x".repeat(11))).await;
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        // Without a Content-Length, the body is cut off once it is over the limit.
+        let chunks = (0..2).map(|_| Ok::<_, std::io::Error>("x".repeat(8)));
+        let body = Body::from_stream(futures_util::stream::iter(chunks));
+        let response = send(&args, request("PUT", "/uploads/streamed.txt", body)).await;
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+    }
+
+    #[tokio::test]
+    async fn trace_level_off_makes_no_spans() {
+        let root = TempDir::new("trace-off");
+        root.write("index.html", "home");
+
+        let (capture, _default) = Capture::start();
+        let response = send(&args(&root, &["--trace-level", "off"]), get("/")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(body_string(response).await, "home");
+        let lines = capture.lines();
+        assert!(
+            !lines.iter().any(|line| line.contains("request{")),
+            "{:?}",
+            lines
+        );
+
+        let response = send(&args(&root, &["--trace-level", "info"]), get("/")).await;
+        assert_eq!(body_string(response).await, "home");
+        let lines = capture.lines();
+        assert!(
+            lines.iter().any(|line| line.contains("request{")),
+            "{:?}",
+            lines
+        );
+    }
+
+    #[tokio::test]
+    async fn unsampled_requests_are_not_logged() {
+        let root = TempDir::new("trace-sample-rate");
+        root.write("a.txt", "a");
+        let logged = log(&root, &["--trace-sample-rate", "0"], &["/a.txt"]).await;
+        assert!(!logged.contains("uri=/a.txt"), "{}", logged);
+    }
+
+    #[tokio::test]
+    async fn logged_headers_leave_out_credentials() {
+        let root = TempDir::new("log-headers");
+        root.write("index.html", "home");
+        let args = args(&root, &["--trace-level", "info", "--log-headers"]);
+
+        let (capture, _default) = Capture::start();
+        let request = with_header(get("/"), header::AUTHORIZATION, "Basic c2VjcmV0");
+        let request = with_header(request, header::USER_AGENT, "test-agent");
+        body_string(send(&args, request).await).await;
+        let log = capture.lines().join("\n");
+        assert!(log.contains("authorization: [redacted]"), "{}", log);
+        assert!(log.contains("user-agent: test-agent"), "{}", log);
+        assert!(!log.contains("c2VjcmV0"), "{}", log);
+    }
+
+    #[tokio::test]
+    async fn single_file_is_served_for_every_path() {
+        let root = TempDir::new("single-file");
+        let file = root.write("report.pdf", "0123456789");
+        let file = file.to_str().unwrap();
+        let args = ServeArgs::try_parse_from(["serve", "--file", file]).unwrap();
+
+        for uri in ["/", "/any/path"] {
+            let response = send(&args, get(uri)).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(response.headers()[header::CONTENT_TYPE], "application/pdf");
+            assert_eq!(body_string(response).await, "0123456789");
+        }
+        let request = with_header(get("/"), header::RANGE, "bytes=2-3");
+        let response = send(&args, request).await;
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(body_string(response).await, "23");
+    }
+
+    #[tokio::test]
+    async fn single_file_at_a_path() {
+        let root = TempDir::new("single-file-at");
+        let file = root.write("report.pdf", "report");
+        let file = file.to_str().unwrap();
+        let args =
+            ServeArgs::try_parse_from(["serve", "--file", file, "--file-at", "/download.pdf"])
+                .unwrap();
+
+        let response = send(&args, get("/download.pdf")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(body_string(response).await, "report");
+        for uri in ["/", "/report.pdf", "/download.pdf/x"] {
+            let response = send(&args, get(uri)).await;
+            assert_eq!(response.status(), StatusCode::NOT_FOUND, "{}", uri);
+        }
+    }
+
+    #[test]
+    fn single_file_needs_a_file_and_no_path() {
+        let root = TempDir::new("single-file-invalid");
+        let dir = root.path().to_str().unwrap();
+        assert!(ServeArgs::try_parse_from(["serve", dir, "--file", "a.txt"]).is_err());
+        let args = ServeArgs::try_parse_from(["serve", "--file", dir]).unwrap();
+        assert!(app(&args).is_err());
+    }
+
+    /// Sends a request for `uri` that `ServeDir` found no file for to the fallbacks of
+    /// `options`, and returns the status and body.
+    async fn fall_back(root: &TempDir, options: &[&str], uri: &str) -> (StatusCode, String) {
+        let args = args(root, options);
+        let not_found = page_service(Some(&root.path().join("404.html")), false);
+        let fallback = fallback_service(root.path(), &args, not_found);
+        let request = with_header(get(uri), header::ACCEPT, "text/html");
+        let response = fallback.oneshot(request).await.unwrap();
+        (response.status(), body_string(response).await)
+    }
+
+    #[tokio::test]
+    async fn fallbacks_take_turns() {
+        let root = TempDir::new("fallbacks");
+        root.write("index.html", "app");
+        root.write("404.html", "missing");
+        root.write("docs/guide.txt", "guide");
+        let all = ["--autoindex", "--spa"];
+
+        // A listing for directories, before the SPA index.
+        let (status, body) = fall_back(&root, &all, "/docs/").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("guide.txt"), "{}", body);
+        // The SPA index for other navigations.
+        let (status, body) = fall_back(&root, &all, "/users/42").await;
+        assert_eq!((status, body.as_str()), (StatusCode::OK, "app"));
+        let (status, body) = fall_back(&root, &["--spa"], "/docs/").await;
+        assert_eq!((status, body.as_str()), (StatusCode::OK, "app"));
+        // The 404 page for the rest.
+        let (status, body) = fall_back(&root, &["--autoindex"], "/users/42").await;
+        assert_eq!((status, body.as_str()), (StatusCode::NOT_FOUND, "missing"));
+        let (status, body) = fall_back(&root, &[], "/docs/").await;
+        assert_eq!((status, body.as_str()), (StatusCode::NOT_FOUND, "missing"));
+    }
+
+    #[tokio::test]
+    async fn files_come_before_fallbacks() {
+        let root = TempDir::new("fallbacks-files");
+        root.write("index.html", "app");
+        root.write("docs/index.html", "docs");
+        root.write("docs/guide.html", "guide");
+        let options = ["--autoindex", "--spa", "--clean-urls"];
+        let args = args(&root, &options);
+        let navigation = |uri| with_header(get(uri), header::ACCEPT, "text/html");
+
+        // An index file before the listing, and a clean URL before the SPA index.
+        let response = send(&args, navigation("/docs/")).await;
+        assert_eq!(body_string(response).await, "docs");
+        let response = send(&args, navigation("/docs/guide")).await;
+        assert_eq!(body_string(response).await, "guide");
+        let response = send(&args, navigation("/docs/other")).await;
+        assert_eq!(body_string(response).await, "app");
+    }
+
+    fn problems(args: &ServeArgs) -> Vec<String> {
+        match args.validate() {
+            Ok(()) => Vec::new(),
+            Err(ServeError::Invalid(problems)) => problems,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    #[test]
+    fn validate_finds_a_missing_root() {
+        let root = TempDir::new("validate-root");
+        assert!(problems(&args(&root, &[])).is_empty());
+
+        let missing = root.path().join("missing");
+        let args = ServeArgs::try_parse_from(["serve", missing.to_str().unwrap()]).unwrap();
+        let problems = problems(&args);
+        assert_eq!(problems.len(), 1);
+        assert!(
+            problems[0].starts_with(&format!("PATH {}: ", missing.display())),
+            "{:?}",
+            problems
+        );
+    }
+
+    #[test]
+    fn validate_lists_every_problem() {
+        let root = TempDir::new("validate-all");
+        let key = root.write("key.pem", "key");
+        let options = [
+            "--not-found",
+            "missing.html",
+            "--pid-file",
+            "missing/serve.pid",
+            "tls",
+            "--cert",
+            "missing.pem",
+            "--key",
+            key.to_str().unwrap(),
+        ];
+        let problems = problems(&args(&root, &options));
+        assert_eq!(problems.len(), 3, "{:?}", problems);
+        assert!(problems[0].starts_with("--not-found missing.html: "));
+        assert!(problems[1].starts_with("--cert missing.pem: "));
+        assert_eq!(
+            problems[2],
+            "--pid-file missing/serve.pid: directory missing does not exist"
+        );
+    }
+
+    /// The conflict `check_conflicts` finds in `options`.
+    fn conflict(root: &TempDir, options: &[&str]) -> Option<String> {
+        match args(root, options).check_conflicts() {
+            Ok(()) => None,
+            Err(ServeError::Conflict(msg)) => Some(msg),
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    #[test]
+    fn conflicts_are_found() {
+        let root = TempDir::new("conflicts");
+        assert_eq!(conflict(&root, &[]), None);
+        let conflicts: &[(&[&str], &str)] = &[
+            (&["--h2c"], "--h2c only applies"),
+            (
+                &[
+                    "--hsts-max-age",
+                    "60",
+                    "--hsts-include-subdomains",
+                    "--hsts-preload",
+                    "tls",
+                    "--self-signed",
+                ],
+                "--hsts-preload needs",
+            ),
+            (
+                &["--hsts-max-age", "60"],
+                "Strict-Transport-Security is only sent over TLS",
+            ),
+            (
+                &["--hsts-include-subdomains"],
+                "Strict-Transport-Security is only sent over TLS",
+            ),
+            (
+                &["--listen", "127.0.0.1:8443:tls"],
+                "--listen 127.0.0.1:8443:tls needs the tls",
+            ),
+            (
+                &["--port", "80", "tls", "--self-signed", "--redirect-http"],
+                "--redirect-from-port 80 is also the HTTPS port",
+            ),
+            (
+                &[
+                    "tls",
+                    "--self-signed",
+                    "--redirect-http",
+                    "--redirect-https-port",
+                    "8080",
+                    "--redirect-from-port",
+                    "8080",
+                ],
+                "--redirect-from-port 8080 is also the HTTPS port",
+            ),
+        ];
+        for (options, expected) in conflicts {
+            let found = conflict(&root, options);
+            assert!(
+                found
+                    .as_deref()
+                    .is_some_and(|msg| msg.starts_with(expected)),
+                "{:?}: {:?}",
+                options,
+                found
+            );
+        }
+    }
+
+    #[test]
+    fn valid_combinations_are_no_conflict() {
+        let root = TempDir::new("no-conflicts");
+        let valid: &[&[&str]] = &[
+            &["--http2", "only", "--h2c"],
+            &["--hsts-max-age", "60", "tls", "--self-signed"],
+            &["--port", "8443", "tls", "--self-signed", "--redirect-http"],
+            // The plain listener takes the place of the redirect listener.
+            &[
+                "--listen",
+                "127.0.0.1:80:plain",
+                "--port",
+                "80",
+                "tls",
+                "--self-signed",
+                "--redirect-http",
+            ],
+        ];
+        for options in valid {
+            assert_eq!(conflict(&root, options), None, "{:?}", options);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unix_socket_conflicts() {
+        let root = TempDir::new("unix-conflicts");
+        let socket = root.path().join("serve.sock");
+        let socket = socket.to_str().unwrap();
+        let conflicts: &[(&[&str], &str)] = &[
+            (
+                &["--unix-socket", socket, "tls", "--self-signed"],
+                "TLS is not supported",
+            ),
+            (
+                &["--unix-socket", socket, "--allow", "10.0.0.0/8"],
+                "--allow and --deny need",
+            ),
+        ];
+        for (options, expected) in conflicts {
+            let found = conflict(&root, options);
+            assert!(
+                found
+                    .as_deref()
+                    .is_some_and(|msg| msg.starts_with(expected)),
+                "{:?}: {:?}",
+                options,
+                found
+            );
+        }
+    }
+
+    #[test]
+    fn contradicting_flags_are_refused_by_clap() {
+        let root = TempDir::new("clap-conflicts");
+        let dir = root.path().to_str().unwrap();
+        let refused: &[&[&str]] = &[
+            &["--disable-compression", "--compression-level", "best"],
+            &["--file", "a.txt"],
+            &["--archive", "a.zip"],
+            &["--quiet", "--silent"],
+            &["--server-header", "serve", "--no-server-header"],
+            &["tls", "--self-signed", "--cert", "a.pem", "--key", "a.key"],
+        ];
+        for options in refused {
+            let args: Vec<&str> = ["serve", dir]
+                .into_iter()
+                .chain(options.iter().copied())
+                .collect();
+            assert!(ServeArgs::try_parse_from(args).is_err(), "{:?}", options);
+        }
+    }
+
+    #[tokio::test]
+    async fn spans_record_the_response() {
+        let root = TempDir::new("span-fields");
+        root.write("notes.txt", "0123456789");
+        let args = args(&root, &["--trace-level", "info"]);
+
+        let (capture, _default) = Capture::start();
+        let request = with_header(get("/notes.txt"), header::RANGE, "bytes=2-5");
+        let response = send(&args, request).await;
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(body_string(response).await, "2345");
+        let lines = capture.lines();
+        let sent = lines
+            .iter()
+            .find(|line| line.contains("finished sending response"))
+            .unwrap_or_else(|| panic!("{:?}", lines));
+        assert!(sent.contains("method=GET"), "{}", sent);
+        assert!(sent.contains("path=\"/notes.txt\""), "{}", sent);
+        assert!(sent.contains("status=206"), "{}", sent);
+        assert!(sent.contains("bytes=4"), "{}", sent);
+        let file = root.path().join("notes.txt");
+        assert!(
+            sent.contains(&format!("file={}", file.display())),
+            "{}",
+            sent
+        );
+    }
+
+    #[test]
+    fn mounts_are_parsed() {
+        let mount: Mount = "/docs/=./docs,not-found=./docs/404.html,ok"
+            .parse()
+            .unwrap();
+        assert_eq!(mount.prefix, "/docs");
+        assert_eq!(mount.path, PathBuf::from("./docs"));
+        assert_eq!(mount.not_found, Some(PathBuf::from("./docs/404.html")));
+        assert!(mount.ok);
+        assert!(!mount.readonly);
+
+        let mount: Mount = "/assets=./static".parse().unwrap();
+        assert_eq!(mount.not_found, None);
+        assert!(!mount.ok);
+
+        let mount: Mount = "/assets=./static,readonly".parse().unwrap();
+        assert!(mount.readonly);
+
+        for invalid in [
+            "./static",
+            "assets=./static",
+            "/=./static",
+            "/assets=",
+            "/assets=./static,ok",
+            "/assets=./static,not-found=",
+            "/assets=./static,cache",
+        ] {
+            assert!(invalid.parse::<Mount>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn overlapping_mounts_are_refused() {
+        let root = TempDir::new("mount-overlap");
+        let dir = root.path().display();
+        let mounts = |prefixes: &[&str]| -> Vec<Mount> {
+            prefixes
+                .iter()
+                .map(|prefix| format!("{}={}", prefix, dir).parse().unwrap())
+                .collect()
+        };
+        assert!(validate_mounts(&mounts(&["/docs", "/assets", "/docs2"]), &[]).is_ok());
+        assert!(validate_mounts(&mounts(&["/docs", "/docs"]), &[]).is_err());
+        assert!(validate_mounts(&mounts(&["/docs", "/docs/api"]), &[]).is_err());
+        let missing = format!("/docs={}", root.path().join("missing").display());
+        assert!(validate_mounts(&[missing.parse().unwrap()], &[]).is_err());
+    }
+
+    #[tokio::test]
+    async fn mounts_have_their_own_not_found_page() {
+        let root = TempDir::new("mount-not-found");
+        root.write("docs/index.html", "docs");
+        let docs_404 = root.write("docs-404.html", "no such doc");
+        root.write("assets/app.js", "app");
+        let not_found = root.write("404.html", "not here");
+        let docs = format!(
+            "/docs={},not-found={}",
+            root.path().join("docs").display(),
+            docs_404.display()
+        );
+        let assets = format!("/assets={}", root.path().join("assets").display());
+        let mounted = args(
+            &root,
+            &[
+                "--not-found",
+                not_found.to_str().unwrap(),
+                "--mount",
+                &docs,
+                "--mount",
+                &assets,
+            ],
+        );
+
+        let response = send(&mounted, get("/docs/missing.html")).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(body_string(response).await, "no such doc");
+        let response = send(&mounted, get("/assets/missing.js")).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(body_string(response).await, "not here");
+        let response = send(&mounted, get("/assets/app.js")).await;
+        assert_eq!(body_string(response).await, "app");
+
+        let docs = format!("{},ok", docs);
+        let ok = args(&root, &["--mount", &docs]);
+        let response = send(&ok, get("/docs/missing.html")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(body_string(response).await, "no such doc");
+    }
+
+    #[tokio::test]
+    async fn case_insensitive_paths_are_opt_in() {
+        let root = TempDir::new("case-insensitive");
+        root.write("index.html", "home");
+        root.write("Docs/Guide.html", "guide");
+
+        let response = send(&args(&root, &[]), get("/INDEX.HTML")).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let insensitive = args(&root, &["--case-insensitive"]);
+        for (uri, body) in [("/INDEX.HTML", "home"), ("/docs/guide.HTML", "guide")] {
+            let response = send(&insensitive, get(uri)).await;
+            assert_eq!(response.status(), StatusCode::OK, "{}", uri);
+            assert_eq!(body_string(response).await, body);
+        }
+        let response = send(&insensitive, get("/MISSING.HTML")).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn large_files_are_served_byte_for_byte() {
+        let root = TempDir::new("large-file");
+        // Bytes that do not repeat within ServeDir's chunks, so a dropped or doubled chunk shows.
+        let contents: Vec<u8> = (0..5_000_000u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 24) as u8)
+            .collect();
+        root.write("video.bin", &contents);
+        let args = args(&root, &[]);
+
+        let response = send(&args, get("/video.bin")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_LENGTH], "5000000");
+        assert!(body_bytes(response).await == contents);
+
+        let request = with_header(get("/video.bin"), header::RANGE, "bytes=1000000-3999999");
+        let response = send(&args, request).await;
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert!(body_bytes(response).await == contents[1_000_000..4_000_000]);
+    }
+
+    #[tokio::test]
+    async fn files_only_allow_reading_methods() {
+        let root = TempDir::new("methods");
+        root.write("a.txt", "a");
+        let dir = root.path().join("drop");
+        std::fs::create_dir(&dir).unwrap();
+        let args = args(
+            &root,
+            &[
+                "--upload",
+                "/uploads",
+                "--upload-dir",
+                dir.to_str().unwrap(),
+            ],
+        );
+
+        for method in ["POST", "PUT", "DELETE", "PATCH"] {
+            let response = send(&args, request(method, "/a.txt", "b")).await;
+            assert_eq!(
+                response.status(),
+                StatusCode::METHOD_NOT_ALLOWED,
+                "{}",
+                method
+            );
+            assert_eq!(response.headers()[header::ALLOW], "GET, HEAD, OPTIONS");
+        }
+        assert_eq!(
+            std::fs::read_to_string(root.path().join("a.txt")).unwrap(),
+            "a"
+        );
+
+        let response = send(&args, request("OPTIONS", "/a.txt", "")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::ALLOW], "GET, HEAD, OPTIONS");
+
+        // Uploads answer their own methods.
+        let response = send(&args, request("PUT", "/uploads/b.txt", "b")).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+    }
+
+    #[tokio::test]
+    async fn hsts_has_the_configured_directives() {
+        let root = TempDir::new("hsts");
+        root.write("index.html", "home");
+        let over_tls = || {
+            let mut request = get("/");
+            request
+                .extensions_mut()
+                .insert(None::<tls::ClientCertificate>);
+            request
+        };
+        let hsts = |response: &Response| {
+            let values = response
+                .headers()
+                .get_all(header::STRICT_TRANSPORT_SECURITY);
+            values.iter().cloned().collect::<Vec<_>>()
+        };
+
+        let options = [
+            "--hsts-max-age",
+            "600",
+            "--hsts-include-subdomains",
+            "tls",
+            "--self-signed",
+        ];
+        let configured = args(&root, &options);
+        let response = send(&configured, over_tls()).await;
+        assert_eq!(hsts(&response), ["max-age=600; includeSubDomains"]);
+        let response = send(&configured, get("/")).await;
+        assert_eq!(hsts(&response), Vec::<HeaderValue>::new());
+
+        let preset = args(&root, &["--security-headers", "tls", "--self-signed"]);
+        let response = send(&preset, over_tls()).await;
+        assert_eq!(hsts(&response), ["max-age=31536000"]);
+
+        // A --header replaces the value instead of adding a second one.
+        let options = [
+            "--header",
+            "Strict-Transport-Security: max-age=60",
+            "--hsts-max-age",
+            "600",
+            "tls",
+            "--self-signed",
+        ];
+        let response = send(&args(&root, &options), over_tls()).await;
+        assert_eq!(hsts(&response), ["max-age=60"]);
+    }
+
+    #[tokio::test]
+    async fn deleted_root_is_answered_with_503_until_it_is_back() {
+        let root = TempDir::new("watch-root");
+        let site = root.write("site/index.html", "home");
+        let site = site.parent().unwrap();
+        let args =
+            ServeArgs::try_parse_from(["serve", site.to_str().unwrap(), "--watch-root"]).unwrap();
+        let app = app(&args).unwrap();
+        // The watcher tells about the change a moment after it happens.
+        let eventually = |status: StatusCode| {
+            let app = app.clone();
+            async move {
+                for _ in 0..250 {
+                    if send_to(&app, get("/")).await == status {
+                        return;
+                    }
+                    tokio::time::sleep(Duration::from_millis(20)).await;
+                }
+                panic!("never got {}", status);
+            }
+        };
+        assert_eq!(send_to(&app, get("/")).await, StatusCode::OK);
+
+        std::fs::remove_dir_all(site).unwrap();
+        eventually(StatusCode::SERVICE_UNAVAILABLE).await;
+        let response = app.clone().oneshot(get("/")).await.unwrap();
+        assert_eq!(response.headers()[header::RETRY_AFTER], "5");
+
+        root.write("site/index.html", "back");
+        eventually(StatusCode::OK).await;
+        assert_eq!(
+            body_string(app.oneshot(get("/")).await.unwrap()).await,
+            "back"
+        );
+    }
+
+    #[tokio::test]
+    async fn head_requests_get_the_length_of_generated_responses() {
+        let root = TempDir::new("head-generated");
+        root.write("dir/a.txt", "a");
+        let not_found = root.write("404.html", "missing page");
+        let options = [
+            "--autoindex",
+            "--health-path",
+            "/healthz",
+            "--error-page",
+            &format!("404={}", not_found.display()),
+        ];
+        let app = app(&args(&root, &options)).unwrap();
+        for uri in ["/dir/", "/healthz", "/missing"] {
+            let length = body_bytes(app.clone().oneshot(get(uri)).await.unwrap())
+                .await
+                .len();
+            let response = app
+                .clone()
+                .oneshot(request("HEAD", uri, Body::empty()))
+                .await
+                .unwrap();
+            assert_eq!(
+                response.headers()[header::CONTENT_LENGTH],
+                length.to_string(),
+                "{}",
+                uri
+            );
+            assert!(body_bytes(response).await.is_empty(), "{}", uri);
+        }
+    }
+
+    #[tokio::test]
+    async fn maintenance_page_is_answered_except_for_health_checks() {
+        let root = TempDir::new("maintenance");
+        root.write("index.html", "home");
+        let page = root.write("maintenance.html", "back soon");
+        let options = [
+            "--maintenance",
+            page.to_str().unwrap(),
+            "--health-path",
+            "/healthz",
+            "--error-page",
+            &format!("503={}", root.write("503.html", "error").display()),
+        ];
+        let app = app(&args(&root, &options)).unwrap();
+
+        let response = app.clone().oneshot(get("/index.html")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "300");
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "text/html");
+        assert_eq!(body_string(response).await, "back soon");
+        assert_eq!(
+            send_to(&app, request("PUT", "/new.txt", "new")).await,
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+        assert_eq!(send_to(&app, get("/healthz")).await, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn maintenance_can_start_off() {
+        let root = TempDir::new("maintenance-off");
+        root.write("index.html", "home");
+        let page = root.write("maintenance.html", "back soon");
+        let options = ["--maintenance", page.to_str().unwrap(), "--maintenance-off"];
+        let app = app(&args(&root, &options)).unwrap();
+        assert_eq!(send_to(&app, get("/")).await, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn excluded_paths_are_not_compressed() {
+        let root = TempDir::new("compression-exclude-path");
+        let page = "<p>page</p>".repeat(100);
+        root.write("page.html", &page);
+        root.write("stream/events.html", &page);
+        root.write("streaming/page.html", &page);
+        let args = args(&root, &["--compression-exclude-path", "/stream"]);
+
+        let request = with_header(get("/stream/events.html"), header::ACCEPT_ENCODING, "gzip");
+        let response = send(&args, request).await;
+        assert!(response.headers().get(header::CONTENT_ENCODING).is_none());
+        assert_eq!(body_string(response).await, page);
+        for uri in ["/page.html", "/streaming/page.html"] {
+            let request = with_header(get(uri), header::ACCEPT_ENCODING, "gzip");
+            let response = send(&args, request).await;
+            assert_eq!(
+                response.headers()[header::CONTENT_ENCODING],
+                "gzip",
+                "{}",
+                uri
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn vary_lists_accept_encoding_once() {
+        let root = TempDir::new("vary");
+        root.write("page.html", "<p>page</p>".repeat(100));
+        root.write("page.de.html", "<p>Seite</p>".repeat(100));
+        root.write("other.html", "<p>other</p>".repeat(100));
+        let vary = |response: &Response| {
+            let values = response.headers().get_all(header::VARY);
+            values.iter().cloned().collect::<Vec<_>>()
+        };
+
+        let compressed = args(&root, &[]);
+        for encoding in ["gzip", "identity"] {
+            let request = with_header(get("/other.html"), header::ACCEPT_ENCODING, encoding);
+            let response = send(&compressed, request).await;
+            assert_eq!(vary(&response), ["accept-encoding"], "{}", encoding);
+        }
+
+        let request = with_header(get("/page.html"), header::ACCEPT_LANGUAGE, "de");
+        let request = with_header(request, header::ACCEPT_ENCODING, "gzip");
+        let response = send(&args(&root, &["--i18n"]), request).await;
+        assert_eq!(response.headers()[header::CONTENT_ENCODING], "gzip");
+        assert_eq!(vary(&response), ["accept-language, accept-encoding"]);
+
+        root.write("other.html.gz", gzip(b"<p>other</p>"));
+        let response = send(&args(&root, &["--precompressed"]), get("/other.html")).await;
+        assert_eq!(vary(&response), ["accept-encoding"]);
+    }
+
+    #[tokio::test]
+    async fn requests_get_an_id() {
+        let root = TempDir::new("request-id");
+        root.write("index.html", "home");
+        let response = send(&args(&root, &[]), get("/")).await;
+        assert!(response.headers().get("x-request-id").is_none());
+
+        let with_id = args(&root, &["--request-id", "--trace-level", "info"]);
+        let (capture, _default) = Capture::start();
+        let response = send(&with_id, get("/")).await;
+        let id = response.headers()["x-request-id"]
+            .to_str()
+            .unwrap()
+            .to_string();
+        assert_eq!(id.len(), 36, "{}", id);
+        body_string(response).await;
+        let log = capture.lines().join("\n");
+        assert!(log.contains(&format!("request_id=\"{}\"", id)), "{}", log);
+        let other = send(&with_id, get("/")).await;
+        assert_ne!(other.headers()["x-request-id"], id.as_str());
+
+        let request = with_header(get("/"), HeaderName::from_static("x-request-id"), "abc-123");
+        let response = send(&with_id, request).await;
+        assert_eq!(response.headers()["x-request-id"], "abc-123");
+        let request = with_header(get("/"), HeaderName::from_static("x-request-id"), "a b");
+        let response = send(&with_id, request).await;
+        assert_ne!(response.headers()["x-request-id"], "a b");
+
+        let named = args(&root, &["--request-id", "--request-id-header", "X-Trace"]);
+        let request = with_header(get("/"), HeaderName::from_static("x-trace"), "abc-123");
+        let response = send(&named, request).await;
+        assert_eq!(response.headers()["x-trace"], "abc-123");
+        assert!(response.headers().get("x-request-id").is_none());
+    }
 }
@@ -0,0 +1,128 @@
+use axum::{
+    body::{Body, Bytes},
+    extract::{Request, State},
+    http::{header, HeaderValue, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, RwLock,
+    },
+};
+
+use crate::{error::ServeError, error_pages::OwnPage};
+
+/// Whether maintenance mode is on, and the page to answer with while it is.
+pub struct Maintenance {
+    path: PathBuf,
+    page: RwLock<(HeaderValue, Bytes)>,
+    active: AtomicBool,
+    retry_after: HeaderValue,
+}
+
+impl Maintenance {
+    pub fn load(path: &Path, active: bool, retry_after: u64) -> Result<Self, ServeError> {
+        Ok(Maintenance {
+            path: path.to_path_buf(),
+            page: RwLock::new(read_page(path)?),
+            active: AtomicBool::new(active),
+            retry_after: HeaderValue::from(retry_after),
+        })
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active.load(Ordering::Relaxed)
+    }
+
+    /// Turns maintenance mode off, or on again. When it is turned on the page is read anew, the
+    /// one read before is kept if that fails.
+    pub fn toggle(&self) {
+        if self.is_active() {
+            self.active.store(false, Ordering::Relaxed);
+            tracing::info!("maintenance mode off");
+            return;
+        }
+        match read_page(&self.path) {
+            Ok(page) => *self.page.write().unwrap() = page,
+            Err(e) => tracing::warn!("keeping the previous maintenance page: {}", e),
+        }
+        self.active.store(true, Ordering::Relaxed);
+        tracing::info!("maintenance mode on");
+    }
+}
+
+fn read_page(path: &Path) -> Result<(HeaderValue, Bytes), ServeError> {
+    let content = std::fs::read(path).map_err(|e| {
+        ServeError::InvalidPath(format!("unable to read {}: {}", path.display(), e))
+    })?;
+    let content_type = mime_guess::from_path(path)
+        .first_raw()
+        .map(HeaderValue::from_static)
+        .unwrap_or(HeaderValue::from_static("application/octet-stream"));
+    Ok((content_type, Bytes::from(content)))
+}
+
+/// Toggles maintenance mode on every SIGHUP, for as long as the process runs.
+#[cfg(unix)]
+pub fn toggle_on_hangup(maintenance: Arc<Maintenance>) -> std::io::Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangup = signal(SignalKind::hangup())?;
+    tokio::spawn(async move {
+        while hangup.recv().await.is_some() {
+            maintenance.toggle();
+        }
+    });
+    Ok(())
+}
+
+/// Middleware that answers every request with 503 and the maintenance page while maintenance
+/// mode is on.
+pub async fn apply(
+    State(maintenance): State<Arc<Maintenance>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if !maintenance.is_active() {
+        return next.run(request).await;
+    }
+    let (content_type, content) = maintenance.page.read().unwrap().clone();
+    let mut response = Response::new(Body::from(content));
+    *response.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+    let headers = response.headers_mut();
+    headers.insert(header::CONTENT_TYPE, content_type);
+    headers.insert(header::RETRY_AFTER, maintenance.retry_after.clone());
+    headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    // The page is sent as it is, not replaced by an --error-page for 503.
+    response.extensions_mut().insert(OwnPage);
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn turning_maintenance_on_reads_the_page_again() {
+        let dir = TempDir::new("maintenance-toggle");
+        let path = dir.write("maintenance.txt", "first");
+        let maintenance = Maintenance::load(&path, true, 60).unwrap();
+
+        maintenance.toggle();
+        assert!(!maintenance.is_active());
+        dir.write("maintenance.txt", "second");
+        maintenance.toggle();
+        assert!(maintenance.is_active());
+        assert_eq!(maintenance.page.read().unwrap().1, "second");
+
+        std::fs::remove_file(&path).unwrap();
+        maintenance.toggle();
+        maintenance.toggle();
+        assert!(maintenance.is_active());
+        assert_eq!(maintenance.page.read().unwrap().1, "second");
+    }
+}
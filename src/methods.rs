@@ -0,0 +1,94 @@
+use axum::{
+    body::{Body, HttpBody},
+    extract::{Request, State},
+    http::{header, HeaderValue, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::sync::Arc;
+
+use crate::path;
+
+/// The methods files are served with.
+const ALLOW: &str = "GET, HEAD, OPTIONS";
+
+/// Middleware that answers `OPTIONS` with the methods files are served with, and other methods
+/// with 405. Uploads and WebDAV answer their methods before this.
+pub async fn apply(request: Request, next: Next) -> Response {
+    match *request.method() {
+        Method::GET | Method::HEAD => next.run(request).await,
+        Method::OPTIONS => (StatusCode::OK, [(header::ALLOW, ALLOW)]).into_response(),
+        _ => (StatusCode::METHOD_NOT_ALLOWED, [(header::ALLOW, ALLOW)]).into_response(),
+    }
+}
+
+/// Middleware that refuses every method but GET, HEAD and OPTIONS below the `readonly`
+/// prefixes with 405, whatever uploads or WebDAV would do with them.
+pub async fn read_only(
+    State(readonly): State<Arc<[String]>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let reading = matches!(
+        *request.method(),
+        Method::GET | Method::HEAD | Method::OPTIONS
+    );
+    if reading {
+        return next.run(request).await;
+    }
+    // Uploads and WebDAV decode the path and skip empty and `.` segments, so /%6Cocked and
+    // //locked write below /locked too. Paths they refuse are refused here as well.
+    let locked = path::normalize(request.uri().path()).map_or(true, |request_path| {
+        readonly
+            .iter()
+            .any(|prefix| path::has_prefix(&request_path, prefix))
+    });
+    if !locked {
+        return next.run(request).await;
+    }
+    (StatusCode::METHOD_NOT_ALLOWED, [(header::ALLOW, ALLOW)]).into_response()
+}
+
+/// Turns the response to a GET request into the one to a HEAD request. The body is left out
+/// and its length goes in `Content-Length`, unless the response has one already or the length
+/// is not known up front, like that of a compressed stream.
+pub fn strip_body(response: Response) -> Response {
+    let (mut parts, body) = response.into_parts();
+    if let Some(length) = body.size_hint().exact() {
+        parts
+            .headers
+            .entry(header::CONTENT_LENGTH)
+            .or_insert_with(|| HeaderValue::from(length));
+    }
+    Response::from_parts(parts, Body::empty())
+}
+
+/// Middleware that answers HEAD requests without a body, for the responses that are generated
+/// with one, like directory listings and error pages. It goes outside the compression layer,
+/// so the length is that of what a GET request gets.
+pub async fn head(request: Request, next: Next) -> Response {
+    if request.method() != Method::HEAD {
+        return next.run(request).await;
+    }
+    strip_body(next.run(request).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::response::Html;
+
+    #[test]
+    fn head_responses_keep_the_length_of_the_body() {
+        let response = strip_body(Html("<p>listing</p>").into_response());
+        assert_eq!(response.headers()[header::CONTENT_LENGTH], "14");
+        assert_eq!(response.body().size_hint().exact(), Some(0));
+
+        let mut response = Html("ignored").into_response();
+        response
+            .headers_mut()
+            .insert(header::CONTENT_LENGTH, HeaderValue::from(1000));
+        let response = strip_body(response);
+        assert_eq!(response.headers()[header::CONTENT_LENGTH], "1000");
+    }
+}
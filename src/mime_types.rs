@@ -0,0 +1,75 @@
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+use std::{collections::HashMap, path::Path, str::FromStr, sync::Arc};
+
+use crate::error::ServeError;
+
+/// The `Content-Type` for files with the given extension, given as `ext=type`.
+#[derive(Clone, Debug)]
+pub struct MimeType {
+    pub extension: String,
+    pub content_type: HeaderValue,
+}
+
+impl FromStr for MimeType {
+    type Err = ServeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (extension, content_type) = s
+            .split_once('=')
+            .ok_or_else(|| ServeError::InvalidHeader(format!("{} must be in EXT=TYPE form", s)))?;
+        let content_type = HeaderValue::from_str(content_type.trim()).map_err(|_| {
+            ServeError::InvalidHeader(format!("{} is not a valid Content-Type", content_type))
+        })?;
+        Ok(MimeType {
+            extension: extension.trim().trim_start_matches('.').to_lowercase(),
+            content_type,
+        })
+    }
+}
+
+pub struct MimeTypes {
+    types: HashMap<String, HeaderValue>,
+}
+
+impl MimeTypes {
+    pub fn new(types: &[MimeType]) -> Self {
+        MimeTypes {
+            types: types
+                .iter()
+                .map(|mime| (mime.extension.clone(), mime.content_type.clone()))
+                .collect(),
+        }
+    }
+
+    fn content_type_for(&self, request_path: &str) -> Option<&HeaderValue> {
+        let path = if request_path.ends_with('/') {
+            "index.html"
+        } else {
+            request_path
+        };
+        Path::new(path)
+            .extension()
+            .and_then(|extension| self.types.get(&extension.to_string_lossy().to_lowercase()))
+    }
+}
+
+/// Replaces the guessed `Content-Type` of successful file responses.
+pub async fn apply(
+    State(mime_types): State<Arc<MimeTypes>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let content_type = mime_types.content_type_for(request.uri().path()).cloned();
+    let mut response = next.run(request).await;
+    if let Some(content_type) = content_type.filter(|_| response.status().is_success()) {
+        response
+            .headers_mut()
+            .insert(header::CONTENT_TYPE, content_type);
+    }
+    response
+}
@@ -0,0 +1,292 @@
+use axum::{
+    body::Body,
+    http::{header, Method, Request, Uri},
+};
+use http_body_util::BodyExt;
+use hyper_util::{
+    client::legacy::{connect::HttpConnector, Client},
+    rt::TokioExecutor,
+};
+use rustls_pki_types::CertificateDer;
+use sha1::{Digest, Sha1};
+use std::{path::Path, time::Duration};
+use x509_parser::{
+    extensions::{GeneralName, ParsedExtension},
+    oid_registry::OID_PKIX_ACCESS_DESCRIPTOR_OCSP,
+    prelude::{FromDer, X509Certificate},
+};
+
+use crate::error::ServeError;
+
+/// How often a fetched response is replaced. Responders sign responses that are valid for days
+/// and update them about daily.
+pub const REFRESH: Duration = Duration::from_secs(6 * 60 * 60);
+
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The DER `AlgorithmIdentifier` of SHA-1, which every responder supports for `CertID`.
+const SHA1_ALGORITHM: &[u8] = &[
+    0x30, 0x09, 0x06, 0x05, 0x2b, 0x0e, 0x03, 0x02, 0x1a, 0x05, 0x00,
+];
+
+/// The OCSP response to staple to `chain`, read from `file` or, with `auto`, fetched from the
+/// responder the certificate names. Without a usable response the certificate is served
+/// without one, and clients ask the responder themselves.
+pub async fn staple(file: Option<&Path>, auto: bool, chain: &[CertificateDer<'_>]) -> Vec<u8> {
+    let response = match file {
+        Some(file) => read(file).await,
+        None if auto => fetch(chain).await,
+        None => return Vec::new(),
+    };
+    match response {
+        Ok(response) => {
+            tracing::info!("stapling an OCSP response of {} bytes", response.len());
+            response
+        }
+        Err(e) => {
+            tracing::warn!("serving without an OCSP staple: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+async fn read(file: &Path) -> Result<Vec<u8>, ServeError> {
+    let response = tokio::fs::read(file)
+        .await
+        .map_err(|e| ServeError::Ocsp(format!("unable to read {}: {}", file.display(), e)))?;
+    check(&response)?;
+    Ok(response)
+}
+
+/// Asks the responder in the Authority Information Access extension of the first
+/// certificate about it. The second certificate must be its issuer.
+async fn fetch(chain: &[CertificateDer<'_>]) -> Result<Vec<u8>, ServeError> {
+    let (url, body) = request(chain)?;
+    let failed = |e: String| ServeError::Ocsp(format!("{}: {}", url, e));
+    let uri = url.parse::<Uri>().map_err(|e| failed(e.to_string()))?;
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri(uri)
+        .header(header::CONTENT_TYPE, "application/ocsp-request")
+        .body(Body::from(body))
+        .map_err(|e| failed(e.to_string()))?;
+    let client: Client<HttpConnector, Body> = Client::builder(TokioExecutor::new()).build_http();
+    let response = tokio::time::timeout(FETCH_TIMEOUT, async {
+        let response = client.request(request).await.map_err(|e| e.to_string())?;
+        if !response.status().is_success() {
+            return Err(format!("responder answered {}", response.status()));
+        }
+        let body = response.into_body().collect().await;
+        body.map(|body| body.to_bytes().to_vec())
+            .map_err(|e| e.to_string())
+    })
+    .await
+    .map_err(|_| failed("no response in time".to_string()))?
+    .map_err(failed)?;
+    check(&response)?;
+    Ok(response)
+}
+
+/// The responder URL and DER `OCSPRequest` for the first certificate of `chain`.
+fn request(chain: &[CertificateDer<'_>]) -> Result<(String, Vec<u8>), ServeError> {
+    let [cert, issuer, ..] = chain else {
+        return Err(ServeError::Ocsp(
+            "the certificate file does not include the issuer certificate".to_string(),
+        ));
+    };
+    let (cert, issuer) = (parse(cert)?, parse(issuer)?);
+    let url = responder(&cert).ok_or_else(|| {
+        ServeError::Ocsp("the certificate does not name an OCSP responder".to_string())
+    })?;
+    let cert_id = [
+        SHA1_ALGORITHM,
+        &der(0x04, &Sha1::digest(cert.issuer().as_raw())),
+        &der(
+            0x04,
+            &Sha1::digest(&issuer.public_key().subject_public_key.data),
+        ),
+        &der(0x02, cert.raw_serial()),
+    ]
+    .concat();
+    // OCSPRequest, TBSRequest, the request list, Request and CertID are all sequences.
+    let request = (0..5).fold(cert_id, |content, _| der(0x30, &content));
+    Ok((url, request))
+}
+
+fn parse(der: &[u8]) -> Result<X509Certificate<'_>, ServeError> {
+    X509Certificate::from_der(der)
+        .map(|(_, cert)| cert)
+        .map_err(|e| ServeError::Ocsp(format!("unable to parse the certificate: {}", e)))
+}
+
+fn responder(cert: &X509Certificate<'_>) -> Option<String> {
+    cert.extensions()
+        .iter()
+        .find_map(|extension| match extension.parsed_extension() {
+            ParsedExtension::AuthorityInfoAccess(access) => {
+                access.accessdescs.iter().find_map(|description| {
+                    match description.access_location {
+                        GeneralName::URI(uri)
+                            if description.access_method == OID_PKIX_ACCESS_DESCRIPTOR_OCSP =>
+                        {
+                            Some(uri.to_string())
+                        }
+                        _ => None,
+                    }
+                })
+            }
+            _ => None,
+        })
+}
+
+/// A DER value with `tag` and `content`.
+fn der(tag: u8, content: &[u8]) -> Vec<u8> {
+    let mut out = vec![tag];
+    if content.len() < 0x80 {
+        out.push(content.len() as u8);
+    } else {
+        let len = content.len().to_be_bytes();
+        let len = &len[len.iter().take_while(|byte| **byte == 0).count()..];
+        out.push(0x80 | len.len() as u8);
+        out.extend_from_slice(len);
+    }
+    out.extend_from_slice(content);
+    out
+}
+
+/// Checks that `response` is an `OCSPResponse` with the status successful, which is the only
+/// one that carries a signed response to staple.
+fn check(response: &[u8]) -> Result<(), ServeError> {
+    let invalid = || ServeError::Ocsp("not a DER encoded OCSP response".to_string());
+    let [0x30, len, rest @ ..] = response else {
+        return Err(invalid());
+    };
+    let rest = if len & 0x80 == 0 {
+        rest
+    } else {
+        rest.get(usize::from(len & 0x7f)..).ok_or_else(invalid)?
+    };
+    match rest {
+        [0x0a, 0x01, 0x00, 0xa0, ..] => Ok(()),
+        [0x0a, 0x01, status, ..] => Err(ServeError::Ocsp(format!(
+            "the responder answered with status {}",
+            status
+        ))),
+        _ => Err(invalid()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rcgen::{
+        BasicConstraints, CertificateParams, CustomExtension, IsCa, KeyPair, SerialNumber,
+    };
+
+    const RESPONDER: &str = "http://ocsp.example.com";
+
+    /// A certificate for example.com, with an Authority Information Access extension naming
+    /// `RESPONDER`, and the issuer that signed it. Also returns the public key of the issuer.
+    fn chain() -> (Vec<CertificateDer<'static>>, Vec<u8>) {
+        let issuer_key = KeyPair::generate().unwrap();
+        let mut params = CertificateParams::new(Vec::new()).unwrap();
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let issuer = params.self_signed(&issuer_key).unwrap();
+
+        let ocsp_method = [0x06, 0x08, 0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x30, 0x01];
+        let location = der(0x86, RESPONDER.as_bytes());
+        let access = der(0x30, &der(0x30, &[&ocsp_method[..], &location].concat()));
+        let mut params = CertificateParams::new(vec!["example.com".to_string()]).unwrap();
+        params.serial_number = Some(SerialNumber::from_slice(&[0x01, 0x02, 0x03]));
+        params.custom_extensions = vec![CustomExtension::from_oid_content(
+            &[1, 3, 6, 1, 5, 5, 7, 1, 1],
+            access,
+        )];
+        let cert = params
+            .signed_by(&KeyPair::generate().unwrap(), &issuer, &issuer_key)
+            .unwrap();
+        (
+            vec![cert.der().clone(), issuer.der().clone()],
+            issuer_key.public_key_raw().to_vec(),
+        )
+    }
+
+    #[test]
+    fn short_and_long_lengths() {
+        assert_eq!(der(0x04, &[0xaa; 5])[..2], [0x04, 0x05]);
+        assert_eq!(der(0x04, &[0xaa; 0x7f])[..2], [0x04, 0x7f]);
+        let long = der(0x04, &[0xaa; 0x80]);
+        assert_eq!(long[..3], [0x04, 0x81, 0x80]);
+        assert_eq!(long.len(), 3 + 0x80);
+        let longer = der(0x30, &[0xaa; 0x1234]);
+        assert_eq!(longer[..4], [0x30, 0x82, 0x12, 0x34]);
+        assert_eq!(longer.len(), 4 + 0x1234);
+    }
+
+    #[test]
+    fn only_successful_responses_are_stapled() {
+        assert!(check(&[0x30, 0x05, 0x0a, 0x01, 0x00, 0xa0, 0x00]).is_ok());
+        let long = [
+            &[0x30, 0x81, 0x85, 0x0a, 0x01, 0x00, 0xa0, 0x81, 0x80][..],
+            &[0; 0x80],
+        ]
+        .concat();
+        assert!(check(&long).is_ok());
+
+        // The status unauthorized, without a response.
+        let error = check(&[0x30, 0x03, 0x0a, 0x01, 0x06]).unwrap_err();
+        assert!(error.to_string().contains("status 6"), "{}", error);
+        for garbage in [
+            &b""[..],
+            b"<html>",
+            &[0x30],
+            &[0x30, 0x82, 0x01],
+            &[0x30, 0x02, 0x04, 0x00],
+        ] {
+            assert!(
+                matches!(check(garbage), Err(ServeError::Ocsp(_))),
+                "{:?}",
+                garbage
+            );
+        }
+    }
+
+    #[test]
+    fn the_issuer_is_needed() {
+        let (chain, _) = chain();
+        let error = request(&chain[..1]).unwrap_err();
+        assert!(error.to_string().contains("issuer"), "{}", error);
+    }
+
+    #[test]
+    fn certificates_without_a_responder_are_refused() {
+        let certified =
+            rcgen::generate_simple_self_signed(vec!["example.com".to_string()]).unwrap();
+        let chain = [certified.cert.der().clone(), certified.cert.der().clone()];
+        let error = request(&chain).unwrap_err();
+        assert!(error.to_string().contains("responder"), "{}", error);
+    }
+
+    #[test]
+    fn requests_ask_about_the_first_certificate() {
+        let (chain, issuer_key) = chain();
+        let (url, body) = request(&chain).unwrap();
+        assert_eq!(url, RESPONDER);
+
+        let issuer = parse(&chain[1]).unwrap();
+        let name_hash = Sha1::digest(issuer.subject().as_raw());
+        let key_hash = Sha1::digest(&issuer_key);
+        let expected = [
+            // OCSPRequest, TBSRequest, the request list, Request and CertID.
+            &[0x30, 0x44, 0x30, 0x42, 0x30, 0x40, 0x30, 0x3e, 0x30, 0x3c][..],
+            SHA1_ALGORITHM,
+            &[0x04, 0x14],
+            &name_hash,
+            &[0x04, 0x14],
+            &key_hash,
+            &[0x02, 0x03, 0x01, 0x02, 0x03],
+        ]
+        .concat();
+        assert_eq!(body, expected);
+    }
+}
@@ -0,0 +1,84 @@
+use axum::http::{HeaderMap, Uri};
+use opentelemetry::{
+    global,
+    propagation::Extractor,
+    trace::{TraceContextExt, TracerProvider},
+};
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::{
+    propagation::TraceContextPropagator,
+    trace::{Sampler, SdkTracerProvider, Tracer},
+    Resource,
+};
+use tracing::Span;
+use tracing_opentelemetry::{OpenTelemetryLayer, OpenTelemetrySpanExt};
+use tracing_subscriber::registry::LookupSpan;
+
+use crate::error::ServeError;
+
+/// Sends the spans that are left when dropped.
+pub struct Otlp(SdkTracerProvider);
+
+impl Drop for Otlp {
+    fn drop(&mut self) {
+        if let Err(e) = self.0.shutdown() {
+            eprintln!("unable to export the last spans: {}", e);
+        }
+    }
+}
+
+/// A layer that exports spans over OTLP/HTTP to `endpoint`, which gets the usual /v1/traces
+/// path if it has none. Traces that come with a sampled `traceparent` are always kept, others
+/// with a chance of `ratio`.
+pub fn layer<S>(
+    endpoint: &Uri,
+    ratio: f64,
+) -> Result<(OpenTelemetryLayer<S, Tracer>, Otlp), ServeError>
+where
+    S: tracing::Subscriber + for<'span> LookupSpan<'span>,
+{
+    let endpoint = if endpoint.path() == "/" {
+        format!("{}v1/traces", endpoint)
+    } else {
+        endpoint.to_string()
+    };
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .with_endpoint(endpoint)
+        .build()
+        .map_err(|e| ServeError::Otlp(e.to_string()))?;
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(
+            ratio,
+        ))))
+        .with_resource(Resource::builder().with_service_name("serve").build())
+        .build();
+    global::set_text_map_propagator(TraceContextPropagator::new());
+    let tracer = provider.tracer("serve");
+    Ok((
+        tracing_opentelemetry::layer().with_tracer(tracer),
+        Otlp(provider),
+    ))
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|name| name.as_str()).collect()
+    }
+}
+
+/// Makes `span` part of the trace of the `traceparent` header, if the request has one.
+pub fn set_parent(span: &Span, headers: &HeaderMap) {
+    let context =
+        global::get_text_map_propagator(|propagator| propagator.extract(&HeaderExtractor(headers)));
+    if context.span().span_context().is_valid() {
+        let _ = span.set_parent(context);
+    }
+}
@@ -0,0 +1,58 @@
+use percent_encoding::percent_decode_str;
+use std::path::{Component, Path, PathBuf};
+
+/// Maps a request path onto `root` the same way `ServeDir` does. Returns `None` for paths that
+/// try to leave the root, like `..` segments or absolute and drive prefixed components.
+pub fn resolve(root: &Path, request_path: &str) -> Option<PathBuf> {
+    let decoded = percent_decode_str(request_path.trim_start_matches('/'))
+        .decode_utf8()
+        .ok()?;
+    let mut path = root.to_path_buf();
+    for component in Path::new(&*decoded).components() {
+        match component {
+            Component::Normal(segment)
+                if Path::new(segment)
+                    .components()
+                    .all(|c| matches!(c, Component::Normal(_))) =>
+            {
+                path.push(segment)
+            }
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+    Some(path)
+}
+
+/// The request path the way [`resolve`] reads it: decoded, without empty and `.` segments, like
+/// /a/b for /a//./%62. Returns `None` for paths that `resolve` refuses.
+pub fn normalize(request_path: &str) -> Option<String> {
+    let resolved = resolve(Path::new(""), request_path)?;
+    let segments = resolved
+        .iter()
+        .map(|segment| segment.to_str())
+        .collect::<Option<Vec<_>>>()?;
+    Some(format!("/{}", segments.join("/")))
+}
+
+/// Whether `path`, with symlinks resolved, is inside the canonical `root`.
+pub fn is_within(root: &Path, path: &Path) -> bool {
+    path.canonicalize()
+        .map(|path| path.starts_with(root))
+        .unwrap_or(false)
+}
+
+/// Parses the path of a built-in endpoint like `/healthz`.
+pub fn parse_route(s: &str) -> Result<String, String> {
+    if !s.starts_with('/') || s.len() < 2 || s.contains(['{', '}', '*']) {
+        return Err(format!("{} must start with / and be a plain path", s));
+    }
+    Ok(s.to_string())
+}
+
+/// Whether `request_path` is `prefix` or below it, like /app/x for /app but not /apple.
+pub fn has_prefix(request_path: &str, prefix: &str) -> bool {
+    request_path
+        .strip_prefix(prefix.trim_end_matches('/'))
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
@@ -0,0 +1,168 @@
+use crate::error::ServeError;
+
+/// A request path pattern like `/blog/:year/*`, for redirect and rewrite rules. `:name` matches one path
+/// segment and a final `*` matches the rest of the path, including nothing.
+#[derive(Clone, Debug)]
+pub struct Pattern {
+    segments: Vec<Segment>,
+}
+
+#[derive(Clone, Debug)]
+enum Segment {
+    Literal(String),
+    Param(String),
+    Splat,
+}
+
+/// The values a path matched a pattern with, by name. The `*` is named `splat`.
+pub struct Captures(Vec<(String, String)>);
+
+impl Pattern {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        if !s.starts_with('/') {
+            return Err(format!("{} must start with /", s));
+        }
+        let parts: Vec<&str> = split(s).collect();
+        let mut segments = Vec::new();
+        for (i, part) in parts.iter().enumerate() {
+            let segment = if *part == "*" {
+                if i + 1 != parts.len() {
+                    return Err(format!("{} has a * that is not at the end", s));
+                }
+                Segment::Splat
+            } else if let Some(name) = part.strip_prefix(':') {
+                if !is_name(name) || name == "splat" {
+                    return Err(format!("{} has an invalid parameter :{}", s, name));
+                }
+                Segment::Param(name.to_string())
+            } else {
+                Segment::Literal(part.to_string())
+            };
+            segments.push(segment);
+        }
+        Ok(Pattern { segments })
+    }
+
+    /// Matches a whole request path. Trailing slashes are ignored.
+    pub fn matches(&self, request_path: &str) -> Option<Captures> {
+        let mut parts = split(request_path);
+        let mut captures = Vec::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::Splat => {
+                    let rest: Vec<&str> = parts.by_ref().collect();
+                    captures.push(("splat".to_string(), rest.join("/")));
+                }
+                Segment::Param(name) => captures.push((name.clone(), parts.next()?.to_string())),
+                Segment::Literal(literal) => {
+                    if parts.next()? != literal {
+                        return None;
+                    }
+                }
+            }
+        }
+        parts.next().is_none().then_some(Captures(captures))
+    }
+}
+
+impl Captures {
+    /// Replaces `:name` and `:splat` in `template` with the values they matched. Other words
+    /// after a colon, like the port of a URL, are left alone.
+    pub fn substitute(&self, template: &str) -> String {
+        let mut result = String::new();
+        let mut rest = template;
+        while let Some(start) = rest.find(':') {
+            result.push_str(&rest[..start]);
+            let after = &rest[start + 1..];
+            let len = after
+                .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                .unwrap_or(after.len());
+            match self.0.iter().find(|(name, _)| name == &after[..len]) {
+                Some((_, value)) if len > 0 => result.push_str(value),
+                _ => {
+                    result.push(':');
+                    result.push_str(&after[..len]);
+                }
+            }
+            rest = &after[len..];
+        }
+        result.push_str(rest);
+        result
+    }
+}
+
+/// Splits a rule given as `FROM=TO` into the pattern and the destination.
+pub fn parse_rule<'a>(kind: &str, s: &'a str) -> Result<(Pattern, &'a str), ServeError> {
+    let (from, to) = s.split_once('=').ok_or_else(|| {
+        ServeError::InvalidRule(format!("{} {} must be in FROM=TO form", kind, s))
+    })?;
+    if to.is_empty() {
+        return Err(ServeError::InvalidRule(format!(
+            "{} {} has no destination",
+            kind, s
+        )));
+    }
+    Ok((Pattern::parse(from).map_err(ServeError::InvalidRule)?, to))
+}
+
+fn split(path: &str) -> impl Iterator<Item = &str> {
+    path.split('/').filter(|part| !part.is_empty())
+}
+
+fn is_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn captures(pattern: &str, path: &str) -> Option<Vec<(String, String)>> {
+        Pattern::parse(pattern).unwrap().matches(path).map(|c| c.0)
+    }
+
+    fn pair(name: &str, value: &str) -> (String, String) {
+        (name.to_string(), value.to_string())
+    }
+
+    #[test]
+    fn literal_patterns_match_the_whole_path() {
+        assert_eq!(captures("/old", "/old"), Some(vec![]));
+        assert_eq!(captures("/old", "/old/"), Some(vec![]));
+        assert_eq!(captures("/old", "/older"), None);
+        assert_eq!(captures("/old", "/old/page"), None);
+    }
+
+    #[test]
+    fn params_and_splats_are_captured() {
+        assert_eq!(
+            captures("/blog/:year/*", "/blog/2024/01/post"),
+            Some(vec![pair("year", "2024"), pair("splat", "01/post")])
+        );
+        assert_eq!(
+            captures("/blog/:year/*", "/blog/2024"),
+            Some(vec![pair("year", "2024"), pair("splat", "")])
+        );
+        assert_eq!(captures("/blog/:year/*", "/blog"), None);
+    }
+
+    #[test]
+    fn invalid_patterns() {
+        assert!(Pattern::parse("blog").is_err());
+        assert!(Pattern::parse("/*/post").is_err());
+        assert!(Pattern::parse("/:").is_err());
+        assert!(Pattern::parse("/:splat").is_err());
+    }
+
+    #[test]
+    fn captures_are_substituted() {
+        let captures = Pattern::parse("/blog/:year/*")
+            .unwrap()
+            .matches("/blog/2024/post")
+            .unwrap();
+        assert_eq!(
+            captures.substitute("http://example.com:8080/:year/:splat/:other"),
+            "http://example.com:8080/2024/post/:other"
+        );
+    }
+}
@@ -0,0 +1,91 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::error::ServeError;
+
+/// A file with the process ID, removed again when it is dropped on shutdown.
+pub struct PidFile(PathBuf);
+
+impl PidFile {
+    /// Writes the PID to `path`. A file left by a process that is still running is an error,
+    /// one left by a process that is gone is overwritten.
+    pub fn create(path: &Path) -> Result<Self, ServeError> {
+        if let Ok(content) = fs::read_to_string(path) {
+            match content.trim().parse::<u32>() {
+                Ok(pid) if pid != std::process::id() && is_running(pid) => {
+                    return Err(ServeError::InvalidPath(format!(
+                        "{} belongs to process {}, which is still running",
+                        path.display(),
+                        pid
+                    )));
+                }
+                _ => tracing::info!("overwriting stale pid file {}", path.display()),
+            }
+        }
+        fs::write(path, format!("{}\n", std::process::id()))?;
+        Ok(PidFile(path.to_path_buf()))
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.0) {
+            tracing::warn!("unable to remove pid file {}: {}", self.0.display(), e);
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn is_running(pid: u32) -> bool {
+    Path::new("/proc").join(pid.to_string()).exists()
+}
+
+/// Without /proc there is no portable check, so the file is taken to be stale.
+#[cfg(not(target_os = "linux"))]
+fn is_running(_pid: u32) -> bool {
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn pid_is_written_and_removed() {
+        let dir = TempDir::new("pid-file");
+        let path = dir.path().join("serve.pid");
+        let pid_file = PidFile::create(&path).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            format!("{}\n", std::process::id())
+        );
+        drop(pid_file);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn stale_file_is_overwritten() {
+        let dir = TempDir::new("pid-file-stale");
+        // Above the largest PID Linux hands out.
+        let path = dir.write("serve.pid", "4294967295\n");
+        let _pid_file = PidFile::create(&path).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            format!("{}\n", std::process::id())
+        );
+        let path = dir.write("garbage.pid", "not a pid");
+        assert!(PidFile::create(&path).is_ok());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn file_of_a_running_process_is_an_error() {
+        let dir = TempDir::new("pid-file-running");
+        let path = dir.write("serve.pid", "1\n");
+        assert!(PidFile::create(&path).is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "1\n");
+    }
+}
@@ -0,0 +1,158 @@
+use flate2::{write::GzEncoder, Compression};
+use std::{
+    fs,
+    io::{self, Write},
+    path::Path,
+};
+
+use crate::compression;
+
+/// Text-like formats that are worth compressing ahead of time.
+pub const TEXT_TYPES: &str = "text/*,application/javascript,application/json,\
+                              application/xml,application/wasm,application/manifest+json,\
+                              image/svg+xml";
+
+type Encode = fn(&[u8]) -> io::Result<Vec<u8>>;
+
+/// The encodings written next to each file, by file name extension.
+const ENCODINGS: [(&str, Encode); 2] = [("br", brotli), ("gz", gzip)];
+
+/// Which files below a directory get compressed siblings.
+pub struct Pregenerate<'a> {
+    /// Content types to compress, like `text/*`.
+    pub types: &'a [String],
+    /// Files smaller than this many bytes are left alone.
+    pub min_size: u64,
+}
+
+impl Pregenerate<'_> {
+    /// Writes a `.br` and a `.gz` sibling for every matching file below `root`, unless there is
+    /// one already that is not older than the file. Returns how many were written. Hidden files
+    /// and symlinked directories are skipped.
+    pub fn run(&self, root: &Path) -> io::Result<usize> {
+        let mut written = 0;
+        for entry in fs::read_dir(root)? {
+            let entry = entry?;
+            if entry.file_name().to_string_lossy().starts_with('.') {
+                continue;
+            }
+            let path = entry.path();
+            let file_type = entry.file_type()?;
+            if file_type.is_dir() {
+                written += self.run(&path)?;
+                continue;
+            }
+            let metadata = fs::metadata(&path)?;
+            if !metadata.is_file() || metadata.len() < self.min_size || !self.matches(&path) {
+                continue;
+            }
+            let mut content = None;
+            for (extension, encode) in ENCODINGS {
+                let mut sibling = path.clone().into_os_string();
+                sibling.push(".");
+                sibling.push(extension);
+                let fresh = fs::metadata(&sibling)
+                    .and_then(|sibling| sibling.modified())
+                    .is_ok_and(|modified| Some(modified) >= metadata.modified().ok());
+                if fresh {
+                    continue;
+                }
+                let content = match &mut content {
+                    Some(content) => content,
+                    None => content.insert(fs::read(&path)?),
+                };
+                fs::write(&sibling, encode(content)?)?;
+                written += 1;
+            }
+        }
+        Ok(written)
+    }
+
+    fn matches(&self, path: &Path) -> bool {
+        let Some(content_type) = mime_guess::from_path(path).first_raw() else {
+            return false;
+        };
+        self.types
+            .iter()
+            .any(|pattern| compression::matches(pattern, content_type))
+    }
+}
+
+fn brotli(content: &[u8]) -> io::Result<Vec<u8>> {
+    let mut encoder = brotli::CompressorWriter::new(Vec::new(), 4096, 11, 22);
+    encoder.write_all(content)?;
+    encoder.flush()?;
+    Ok(encoder.into_inner())
+}
+
+fn gzip(content: &[u8]) -> io::Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+    encoder.write_all(content)?;
+    encoder.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    fn pregenerate(root: &TempDir) -> usize {
+        let types = TEXT_TYPES
+            .split(',')
+            .map(|t| compression::parse_type(t).unwrap())
+            .collect::<Vec<_>>();
+        Pregenerate {
+            types: &types,
+            min_size: 10,
+        }
+        .run(root.path())
+        .unwrap()
+    }
+
+    #[test]
+    fn text_files_get_compressed_siblings() {
+        let root = TempDir::new("pregenerate");
+        let page = "<p>page</p>".repeat(100);
+        root.write("index.html", &page);
+        root.write("css/site.css", "body { color: red; }".repeat(10));
+        root.write("logo.png", [0u8; 100]);
+        root.write("small.txt", "small");
+        root.write(".hidden/page.html", &page);
+
+        assert_eq!(pregenerate(&root), 4);
+        let dir = root.path();
+        assert!(dir.join("index.html.br").is_file());
+        assert!(dir.join("index.html.gz").is_file());
+        assert!(dir.join("css/site.css.br").is_file());
+        assert!(!dir.join("logo.png.br").exists());
+        assert!(!dir.join("logo.png.gz").exists());
+        assert!(!dir.join("small.txt.br").exists());
+        assert!(!dir.join(".hidden/page.html.br").exists());
+
+        let mut decoded = Vec::new();
+        brotli::BrotliDecompress(
+            &mut fs::File::open(dir.join("index.html.br")).unwrap(),
+            &mut decoded,
+        )
+        .unwrap();
+        assert_eq!(decoded, page.as_bytes());
+    }
+
+    #[test]
+    fn up_to_date_siblings_are_kept() {
+        let root = TempDir::new("pregenerate-fresh");
+        root.write("index.html", "<p>page</p>".repeat(100));
+        assert_eq!(pregenerate(&root), 2);
+        assert_eq!(pregenerate(&root), 0);
+
+        // A sibling older than its file is written again.
+        let old = std::time::SystemTime::UNIX_EPOCH;
+        fs::File::options()
+            .write(true)
+            .open(root.path().join("index.html.gz"))
+            .unwrap()
+            .set_modified(old)
+            .unwrap();
+        assert_eq!(pregenerate(&root), 1);
+    }
+}
@@ -0,0 +1,277 @@
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, Request},
+    http::{header, uri::Scheme, HeaderMap, HeaderName, HeaderValue, StatusCode, Uri, Version},
+    response::{IntoResponse, Response},
+};
+use hyper_util::{
+    client::legacy::{connect::HttpConnector, Client},
+    rt::TokioExecutor,
+};
+use std::{convert::Infallible, net::SocketAddr, str::FromStr};
+use tower::service_fn;
+
+use crate::{boxed, error::ServeError, tls, FileService};
+
+/// Headers that only apply to one connection, and are not passed on by proxies.
+const HOP_BY_HOP: [HeaderName; 7] = [
+    header::CONNECTION,
+    HeaderName::from_static("keep-alive"),
+    header::PROXY_AUTHENTICATE,
+    header::PROXY_AUTHORIZATION,
+    header::TE,
+    header::TRAILER,
+    header::UPGRADE,
+];
+
+/// Requests below a URL prefix that are sent to an upstream server, given as `PREFIX=URL`.
+#[derive(Clone, Debug)]
+pub struct Proxy {
+    pub prefix: String,
+    pub upstream: Uri,
+}
+
+impl FromStr for Proxy {
+    type Err = ServeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (prefix, upstream) = s.split_once('=').ok_or_else(|| {
+            ServeError::InvalidProxy(format!("proxy {} must be in PREFIX=URL form", s))
+        })?;
+        let prefix = prefix.trim_end_matches('/');
+        if !prefix.starts_with('/') {
+            return Err(ServeError::InvalidProxy(format!(
+                "proxy prefix {} must start with / and must not be /",
+                prefix
+            )));
+        }
+        let upstream = upstream
+            .parse::<Uri>()
+            .map_err(|_| ServeError::InvalidProxy(format!("{} is not a URL", upstream)))?;
+        if upstream.scheme() != Some(&Scheme::HTTP) || upstream.authority().is_none() {
+            return Err(ServeError::InvalidProxy(format!(
+                "{} must be an http:// URL",
+                upstream
+            )));
+        }
+        Ok(Proxy {
+            prefix: prefix.to_string(),
+            upstream,
+        })
+    }
+}
+
+/// The upstream URL for a request path below the prefix, which replaces the prefix with the
+/// path of the upstream URL.
+fn upstream_uri(upstream: &Uri, request_uri: &Uri) -> Result<Uri, axum::http::Error> {
+    let base = upstream.path().trim_end_matches('/');
+    let mut path_and_query = format!("{}{}", base, request_uri.path());
+    if let Some(query) = request_uri.query() {
+        path_and_query.push('?');
+        path_and_query.push_str(query);
+    }
+    Uri::builder()
+        .scheme(Scheme::HTTP)
+        .authority(upstream.authority().map_or("", |a| a.as_str()))
+        .path_and_query(path_and_query)
+        .build()
+}
+
+fn remove_hop_by_hop(headers: &mut HeaderMap) {
+    // Connection can name more headers that only apply to this connection.
+    let named: Vec<HeaderName> = headers
+        .get_all(header::CONNECTION)
+        .iter()
+        .flat_map(|value| value.to_str().unwrap_or_default().split(','))
+        .filter_map(|name| HeaderName::from_str(name.trim()).ok())
+        .collect();
+    for name in HOP_BY_HOP.iter().chain(&named) {
+        headers.remove(name);
+    }
+}
+
+/// Adds `X-Forwarded-For`, `X-Forwarded-Host` and `X-Forwarded-Proto` for the upstream. The
+/// upstream gets its own `Host`.
+fn forward_headers(request: &mut Request) {
+    let proto = if tls::is_tls(request) {
+        "https"
+    } else {
+        "http"
+    };
+    let peer = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(peer)| peer.ip());
+    let headers = request.headers_mut();
+    remove_hop_by_hop(headers);
+    if let Some(host) = headers.remove(header::HOST) {
+        headers.insert("x-forwarded-host", host);
+    }
+    headers.insert("x-forwarded-proto", HeaderValue::from_static(proto));
+    if let Some(peer) = peer {
+        let forwarded_for = match headers
+            .get("x-forwarded-for")
+            .and_then(|value| value.to_str().ok())
+        {
+            Some(earlier) => format!("{}, {}", earlier, peer),
+            None => peer.to_string(),
+        };
+        if let Ok(value) = HeaderValue::from_str(&forwarded_for) {
+            headers.insert("x-forwarded-for", value);
+        }
+    }
+}
+
+/// Sends requests to the upstream and streams its responses back. Bodies are streamed both
+/// ways, so large uploads and downloads are not buffered. Upstreams that can not be reached
+/// are answered with 502.
+pub fn service(proxy: &Proxy) -> FileService {
+    let client: Client<HttpConnector, Body> = Client::builder(TokioExecutor::new()).build_http();
+    let upstream = proxy.upstream.clone();
+    boxed(service_fn(move |mut request: Request| {
+        let client = client.clone();
+        let upstream = upstream.clone();
+        async move {
+            let uri = match upstream_uri(&upstream, request.uri()) {
+                Ok(uri) => uri,
+                Err(_) => return Ok::<_, Infallible>(StatusCode::BAD_REQUEST.into_response()),
+            };
+            forward_headers(&mut request);
+            *request.uri_mut() = uri;
+            // The upstream is spoken to over HTTP/1.1, whatever the client used.
+            let version = std::mem::replace(request.version_mut(), Version::HTTP_11);
+            match client.request(request).await {
+                Ok(response) => {
+                    let (mut parts, body) = response.into_parts();
+                    remove_hop_by_hop(&mut parts.headers);
+                    parts.version = version;
+                    Ok(Response::from_parts(parts, Body::new(body)))
+                }
+                Err(e) => {
+                    tracing::warn!("proxy to {} failed: {}", upstream, e);
+                    Ok(StatusCode::BAD_GATEWAY.into_response())
+                }
+            }
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{body_string, get};
+    use axum::{routing, Router};
+    use tokio::net::TcpListener;
+    use tower::ServiceExt;
+
+    /// An upstream that answers with what it was sent.
+    async fn upstream() -> SocketAddr {
+        let echo = |request: Request| async move {
+            let headers: Vec<String> = [
+                "host",
+                "x-forwarded-for",
+                "x-forwarded-host",
+                "x-forwarded-proto",
+                "keep-alive",
+            ]
+            .iter()
+            .map(|name| {
+                let value = request.headers().get(*name).and_then(|v| v.to_str().ok());
+                format!("{}={}", name, value.unwrap_or("-"))
+            })
+            .collect();
+            let (method, uri) = (request.method().clone(), request.uri().clone());
+            let body = axum::body::to_bytes(request.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            let body = String::from_utf8_lossy(&body);
+            let mut response =
+                format!("{} {} {} {}", method, uri, headers.join(" "), body).into_response();
+            response
+                .headers_mut()
+                .insert("x-upstream", HeaderValue::from_static("yes"));
+            response
+                .headers_mut()
+                .insert(header::CONNECTION, HeaderValue::from_static("x-private"));
+            response
+                .headers_mut()
+                .insert("x-private", HeaderValue::from_static("secret"));
+            response
+        };
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = Router::new().fallback(routing::any(echo));
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        addr
+    }
+
+    fn proxy(s: &str) -> Proxy {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn proxies_are_parsed() {
+        let api = proxy("/api/=http://localhost:3000/v1");
+        assert_eq!(api.prefix, "/api");
+        assert_eq!(api.upstream, "http://localhost:3000/v1");
+        for invalid in [
+            "/api",
+            "api=http://localhost",
+            "/=http://localhost",
+            "/api=https://localhost",
+            "/api=localhost",
+        ] {
+            assert!(invalid.parse::<Proxy>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn upstream_path_is_prefixed() {
+        let upstream = Uri::from_static("http://localhost:3000/v1/");
+        let uri = upstream_uri(&upstream, &Uri::from_static("/users?page=2")).unwrap();
+        assert_eq!(uri, "http://localhost:3000/v1/users?page=2");
+        let upstream = Uri::from_static("http://localhost:3000");
+        let uri = upstream_uri(&upstream, &Uri::from_static("/users")).unwrap();
+        assert_eq!(uri, "http://localhost:3000/users");
+    }
+
+    #[tokio::test]
+    async fn upstream_response_is_returned() {
+        let addr = upstream().await;
+        let service = service(&proxy(&format!("/api=http://{}/v1", addr)));
+        let mut request = Request::builder()
+            .method("POST")
+            .uri("/users?page=2")
+            .header(header::HOST, "example.com")
+            .header("x-forwarded-for", "192.0.2.1")
+            .header(header::CONNECTION, "keep-alive")
+            .header("keep-alive", "timeout=5")
+            .body(Body::from("the body"))
+            .unwrap();
+        request
+            .extensions_mut()
+            .insert(ConnectInfo(SocketAddr::from(([192, 0, 2, 2], 4711))));
+        let response = service.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["x-upstream"], "yes");
+        assert!(response.headers().get("x-private").is_none());
+        assert_eq!(
+            body_string(response).await,
+            format!(
+                "POST /v1/users?page=2 host={} x-forwarded-for=192.0.2.1, 192.0.2.2 \
+                 x-forwarded-host=example.com x-forwarded-proto=http keep-alive=- the body",
+                addr
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn unreachable_upstream_is_a_bad_gateway() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+        let service = service(&proxy(&format!("/api=http://{}", addr)));
+        let response = service.oneshot(get("/")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+    }
+}
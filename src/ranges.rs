@@ -0,0 +1,341 @@
+use axum::{
+    body::{Body, Bytes},
+    extract::Request,
+    http::{header, HeaderMap, HeaderValue, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use futures_util::{stream, StreamExt};
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    ops::RangeInclusive,
+    sync::Arc,
+};
+
+/// Requests for more ranges than this get the whole content, which the spec allows, so a
+/// request can not make for a response much larger than the file.
+const MAX_RANGES: usize = 64;
+
+/// What a `Range` header asks for of content `len` bytes long.
+pub enum Ranges {
+    Full,
+    /// Ascending ranges that neither overlap nor touch.
+    Partial(Vec<RangeInclusive<u64>>),
+    Unsatisfiable,
+}
+
+/// Parses `bytes=start-end`, `bytes=start-` and `bytes=-suffix`, or a comma-separated list of
+/// them. Overlapping ranges are merged, and malformed headers are answered with the full
+/// content.
+pub fn parse(value: &str, len: u64) -> Ranges {
+    let Some(specs) = value.strip_prefix("bytes=") else {
+        return Ranges::Full;
+    };
+    let mut ranges = Vec::new();
+    let mut any = false;
+    for spec in specs
+        .split(',')
+        .map(str::trim)
+        .filter(|spec| !spec.is_empty())
+    {
+        any = true;
+        let Some((start, end)) = spec.split_once('-') else {
+            return Ranges::Full;
+        };
+        let range = match (start.parse::<u64>(), end.parse::<u64>()) {
+            (Ok(start), Ok(end)) if start <= end => start..=end.min(len.saturating_sub(1)),
+            (Ok(start), Err(_)) if end.is_empty() => start..=len.saturating_sub(1),
+            (Err(_), Ok(suffix)) if start.is_empty() => {
+                if suffix == 0 {
+                    continue;
+                }
+                len.saturating_sub(suffix)..=len.saturating_sub(1)
+            }
+            _ => return Ranges::Full,
+        };
+        if *range.start() < len {
+            ranges.push(range);
+        }
+    }
+    if !any || ranges.len() > MAX_RANGES {
+        return Ranges::Full;
+    }
+    if ranges.is_empty() {
+        return Ranges::Unsatisfiable;
+    }
+    ranges.sort_by_key(|range| *range.start());
+    let mut merged: Vec<RangeInclusive<u64>> = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if *range.start() <= last.end() + 1 => {
+                *last = *last.start()..=*last.end().max(range.end());
+            }
+            _ => merged.push(range),
+        }
+    }
+    Ranges::Partial(merged)
+}
+
+/// A 416 for content `len` bytes long.
+pub fn unsatisfiable(len: u64) -> Response {
+    (
+        StatusCode::RANGE_NOT_SATISFIABLE,
+        [(header::CONTENT_RANGE, format!("bytes */{}", len))],
+    )
+        .into_response()
+}
+
+/// Turns the full response for content `len` bytes long into a 206 with the given ranges. One
+/// range is sent as is, more as a `multipart/byteranges` body. The body is streamed, and
+/// reading it stops after the last range.
+pub fn partial(response: Response, ranges: Vec<RangeInclusive<u64>>, len: u64) -> Response {
+    let (mut parts, body) = response.into_parts();
+    parts.status = StatusCode::PARTIAL_CONTENT;
+    let content_type = parts.headers.remove(header::CONTENT_TYPE);
+    let (pieces, closing) = if let [range] = ranges.as_slice() {
+        let content_range = format!("bytes {}-{}/{}", range.start(), range.end(), len);
+        if let Ok(value) = HeaderValue::from_str(&content_range) {
+            parts.headers.insert(header::CONTENT_RANGE, value);
+        }
+        if let Some(content_type) = content_type {
+            parts.headers.insert(header::CONTENT_TYPE, content_type);
+        }
+        (vec![(range.clone(), Bytes::new())], Bytes::new())
+    } else {
+        let boundary = format!("{:016x}", RandomState::new().build_hasher().finish());
+        let content_type = content_type
+            .as_ref()
+            .and_then(|value| value.to_str().ok())
+            .map(|value| format!("Content-Type: {}\r\n", value))
+            .unwrap_or_default();
+        let pieces = ranges
+            .into_iter()
+            .map(|range| {
+                let head = format!(
+                    "\r\n--{}\r\n{}Content-Range: bytes {}-{}/{}\r\n\r\n",
+                    boundary,
+                    content_type,
+                    range.start(),
+                    range.end(),
+                    len
+                );
+                (range, Bytes::from(head))
+            })
+            .collect();
+        let multipart = format!("multipart/byteranges; boundary={}", boundary);
+        if let Ok(value) = HeaderValue::from_str(&multipart) {
+            parts.headers.insert(header::CONTENT_TYPE, value);
+        }
+        (pieces, Bytes::from(format!("\r\n--{}--\r\n", boundary)))
+    };
+    let content_length = pieces
+        .iter()
+        .map(|(range, head)| range.end() - range.start() + 1 + head.len() as u64)
+        .sum::<u64>()
+        + closing.len() as u64;
+    parts
+        .headers
+        .insert(header::CONTENT_LENGTH, HeaderValue::from(content_length));
+
+    let pieces: Arc<[(RangeInclusive<u64>, Bytes)]> = Arc::from(pieces);
+    let body = stream::unfold(
+        (body.into_data_stream(), 0u64, 0usize),
+        move |(mut data, offset, mut index)| {
+            let pieces = pieces.clone();
+            let closing = closing.clone();
+            async move {
+                if index == pieces.len() {
+                    return None;
+                }
+                let chunk = match data.next().await? {
+                    Ok(chunk) => chunk,
+                    Err(e) => return Some((vec![Err(e)], (data, offset, pieces.len()))),
+                };
+                let end = offset + chunk.len() as u64;
+                let mut out = Vec::new();
+                while let Some((range, head)) = pieces.get(index) {
+                    if *range.start() >= end {
+                        break;
+                    }
+                    if *range.start() >= offset && !head.is_empty() {
+                        out.push(Ok(head.clone()));
+                    }
+                    let from = range.start().max(&offset) - offset;
+                    let to = (range.end() + 1).min(end) - offset;
+                    out.push(Ok(chunk.slice(from as usize..to as usize)));
+                    if range.end() + 1 > end {
+                        break;
+                    }
+                    index += 1;
+                }
+                if index == pieces.len() && !closing.is_empty() {
+                    out.push(Ok(closing));
+                }
+                Some((out, (data, end, index)))
+            }
+        },
+    )
+    .flat_map(stream::iter);
+    Response::from_parts(parts, Body::from_stream(body))
+}
+
+/// Whether an `If-Range` validator still matches the response, so its ranges may be sent. Only
+/// strong ETags and exact modification dates match.
+fn if_range_matches(if_range: &HeaderValue, headers: &HeaderMap) -> bool {
+    let validator = if if_range.as_bytes().starts_with(b"\"") {
+        header::ETAG
+    } else {
+        header::LAST_MODIFIED
+    };
+    headers.get(validator) == Some(if_range)
+}
+
+/// Middleware that answers requests for more than one range, which `ServeDir` refuses, from
+/// the full response. Single ranges are left to `ServeDir`, which reads only the range.
+pub async fn apply(mut request: Request, next: Next) -> Response {
+    let Some(range) = request
+        .headers()
+        .get(header::RANGE)
+        .and_then(|value| value.to_str().ok())
+        .filter(|value| value.contains(','))
+        .map(str::to_string)
+        .filter(|_| request.method() == Method::GET)
+    else {
+        return next.run(request).await;
+    };
+    request.headers_mut().remove(header::RANGE);
+    let if_range = request.headers_mut().remove(header::IF_RANGE);
+    let response = next.run(request).await;
+    let len = response
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok());
+    let Some(len) = len.filter(|_| response.status() == StatusCode::OK) else {
+        return response;
+    };
+    if let Some(if_range) = if_range {
+        if !if_range_matches(&if_range, response.headers()) {
+            return response;
+        }
+    }
+    match parse(&range, len) {
+        Ranges::Full => response,
+        Ranges::Partial(ranges) => partial(response, ranges, len),
+        Ranges::Unsatisfiable => unsatisfiable(len),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::body_string;
+
+    /// The ranges `value` asks for of 100 bytes, `None` for the full content and an empty list
+    /// when it can not be satisfied.
+    fn ranges(value: &str) -> Option<Vec<RangeInclusive<u64>>> {
+        match parse(value, 100) {
+            Ranges::Full => None,
+            Ranges::Partial(ranges) => Some(ranges),
+            Ranges::Unsatisfiable => Some(Vec::new()),
+        }
+    }
+
+    /// A full response with the letters a to y four times, sent in chunks of 7 bytes.
+    fn full() -> Response {
+        let content: Vec<u8> = (0..100).map(|i| b'a' + i % 25).collect();
+        let chunks: Vec<Result<Vec<u8>, std::io::Error>> =
+            content.chunks(7).map(|chunk| Ok(chunk.to_vec())).collect();
+        let mut response = Response::new(Body::from_stream(stream::iter(chunks)));
+        response
+            .headers_mut()
+            .insert(header::CONTENT_TYPE, HeaderValue::from_static("text/plain"));
+        response
+    }
+
+    #[test]
+    fn single_ranges() {
+        assert_eq!(ranges("bytes=0-9"), Some(vec![0..=9]));
+        assert_eq!(ranges("bytes=90-"), Some(vec![90..=99]));
+        assert_eq!(ranges("bytes=-10"), Some(vec![90..=99]));
+        assert_eq!(ranges("bytes=-200"), Some(vec![0..=99]));
+        assert_eq!(ranges("bytes=50-500"), Some(vec![50..=99]));
+    }
+
+    #[test]
+    fn multiple_ranges_are_sorted_and_merged() {
+        assert_eq!(
+            ranges("bytes=50-59, 0-9,5-19,20-24"),
+            Some(vec![0..=24, 50..=59])
+        );
+        assert_eq!(ranges("bytes=0-9,200-300"), Some(vec![0..=9]));
+    }
+
+    #[test]
+    fn unsatisfiable_ranges() {
+        assert_eq!(ranges("bytes=100-"), Some(vec![]));
+        assert_eq!(ranges("bytes=100-200,300-400"), Some(vec![]));
+        assert_eq!(ranges("bytes=-0"), Some(vec![]));
+        let response = unsatisfiable(100);
+        assert_eq!(response.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(response.headers()[header::CONTENT_RANGE], "bytes */100");
+    }
+
+    #[test]
+    fn malformed_ranges_get_the_full_content() {
+        for value in [
+            "items=0-9",
+            "bytes=",
+            "bytes=9-0",
+            "bytes=a-b",
+            "bytes=0-9,x",
+        ] {
+            assert_eq!(ranges(value), None, "{}", value);
+        }
+        let many: Vec<String> = (0..=MAX_RANGES).map(|i| format!("{}-{}", i, i)).collect();
+        assert_eq!(ranges(&format!("bytes={}", many.join(","))), None);
+    }
+
+    #[tokio::test]
+    async fn single_range_is_sent_as_is() {
+        let response = partial(full(), vec![5..=16], 100);
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(response.headers()[header::CONTENT_RANGE], "bytes 5-16/100");
+        assert_eq!(response.headers()[header::CONTENT_LENGTH], "12");
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "text/plain");
+        assert_eq!(body_string(response).await, "fghijklmnopq");
+    }
+
+    #[tokio::test]
+    async fn multiple_ranges_are_multipart() {
+        let response = partial(full(), vec![0..=2, 6..=7, 97..=99], 100);
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert!(response.headers().get(header::CONTENT_RANGE).is_none());
+        let content_type = response.headers()[header::CONTENT_TYPE].to_str().unwrap();
+        let boundary = content_type
+            .strip_prefix("multipart/byteranges; boundary=")
+            .unwrap()
+            .to_string();
+        let length: usize = response.headers()[header::CONTENT_LENGTH]
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        let body = body_string(response).await;
+        assert_eq!(body.len(), length);
+        let part = |range: &str, content: &str| {
+            format!(
+                "\r\n--{}\r\nContent-Type: text/plain\r\nContent-Range: bytes {}/100\r\n\r\n{}",
+                boundary, range, content
+            )
+        };
+        let expected = [
+            part("0-2", "abc"),
+            part("6-7", "gh"),
+            part("97-99", "wxy"),
+            format!("\r\n--{}--\r\n", boundary),
+        ];
+        assert_eq!(body, expected.concat());
+    }
+}
@@ -0,0 +1,72 @@
+use axum::http::{HeaderMap, HeaderName};
+use std::fmt;
+
+/// Headers that carry credentials, which --log-headers leaves out by default.
+pub const SENSITIVE: &str = "authorization,proxy-authorization,cookie,set-cookie";
+
+/// Parses a header name to redact. An empty value makes for an empty list.
+pub fn parse_name(s: &str) -> Result<String, String> {
+    let s = s.trim();
+    if s.is_empty() {
+        return Ok(String::new());
+    }
+    HeaderName::from_bytes(s.as_bytes())
+        .map(|name| name.as_str().to_string())
+        .map_err(|_| format!("{} is not a header name", s))
+}
+
+/// Formats headers like `name: value, name: value` for the log, with the values of the
+/// `redacted` ones left out.
+pub struct Redacted<'a> {
+    pub headers: &'a HeaderMap,
+    pub redacted: &'a [String],
+}
+
+impl fmt::Display for Redacted<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (name, value)) in self.headers.iter().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            if self
+                .redacted
+                .iter()
+                .any(|redacted| redacted == name.as_str())
+            {
+                write!(f, "{}: [redacted]", name)?;
+            } else {
+                write!(f, "{}: {}", name, String::from_utf8_lossy(value.as_bytes()))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    #[test]
+    fn redacted_values_are_left_out() {
+        let mut headers = HeaderMap::new();
+        headers.insert("authorization", HeaderValue::from_static("Basic c2VjcmV0"));
+        headers.insert("accept", HeaderValue::from_static("text/html"));
+        let redacted: Vec<String> = SENSITIVE.split(',').map(str::to_string).collect();
+        let formatted = Redacted {
+            headers: &headers,
+            redacted: &redacted,
+        };
+        assert_eq!(
+            formatted.to_string(),
+            "authorization: [redacted], accept: text/html"
+        );
+    }
+
+    #[test]
+    fn names_are_lowercased() {
+        assert_eq!(parse_name(" X-Api-Key ").unwrap(), "x-api-key");
+        assert_eq!(parse_name("").unwrap(), "");
+        assert!(parse_name("no spaces").is_err());
+    }
+}
@@ -0,0 +1,170 @@
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::{str::FromStr, sync::Arc};
+
+use crate::{
+    error::ServeError,
+    pattern::{self, Pattern},
+};
+
+/// Redirects requests for paths that match a pattern, given as `FROM=TO[:STATUS]`, like
+/// `/blog/*=/news/:splat:302`. The status defaults to 301.
+#[derive(Clone, Debug)]
+pub struct Redirect {
+    from: Pattern,
+    to: String,
+    status: StatusCode,
+}
+
+impl FromStr for Redirect {
+    type Err = ServeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (from, to) = pattern::parse_rule("redirect", s)?;
+        let (to, status) = match to.rsplit_once(':') {
+            Some((to, status))
+                if status.len() == 3 && status.bytes().all(|b| b.is_ascii_digit()) =>
+            {
+                let status = status
+                    .parse::<StatusCode>()
+                    .ok()
+                    .filter(|status| [301, 302, 303, 307, 308].contains(&status.as_u16()))
+                    .ok_or_else(|| {
+                        ServeError::InvalidRule(format!(
+                            "{} is not a redirect status, use 301, 302, 303, 307 or 308",
+                            status
+                        ))
+                    })?;
+                (to, status)
+            }
+            _ => (to, StatusCode::MOVED_PERMANENTLY),
+        };
+        if to.is_empty() {
+            return Err(ServeError::InvalidRule(format!(
+                "redirect {} has no destination",
+                s
+            )));
+        }
+        Ok(Redirect {
+            from,
+            to: to.to_string(),
+            status,
+        })
+    }
+}
+
+/// Middleware that answers requests matching one of the redirects, the first that matches
+/// wins. The query is kept unless the destination has one.
+pub async fn apply(
+    State(redirects): State<Arc<[Redirect]>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let uri = request.uri();
+    let found = redirects.iter().find_map(|redirect| {
+        let captures = redirect.from.matches(uri.path())?;
+        Some((redirect, captures.substitute(&redirect.to)))
+    });
+    let Some((redirect, mut location)) = found else {
+        return next.run(request).await;
+    };
+    if let Some(query) = uri.query().filter(|_| !location.contains('?')) {
+        location.push('?');
+        location.push_str(query);
+    }
+    match HeaderValue::from_str(&location) {
+        Ok(location) => (redirect.status, [(header::LOCATION, location)]).into_response(),
+        Err(_) => StatusCode::BAD_REQUEST.into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::get;
+    use axum::{middleware, routing, Router};
+    use tower::ServiceExt;
+
+    async fn redirect(rules: &[&str], uri: &str) -> (StatusCode, Option<String>) {
+        let redirects: Arc<[Redirect]> = rules.iter().map(|rule| rule.parse().unwrap()).collect();
+        let app = Router::new()
+            .fallback(routing::get(|| async { "file" }))
+            .layer(middleware::from_fn_with_state(redirects, apply));
+        let response = app.oneshot(get(uri)).await.unwrap();
+        let location = response.headers().get(header::LOCATION);
+        (
+            response.status(),
+            location.map(|location| location.to_str().unwrap().to_string()),
+        )
+    }
+
+    #[test]
+    fn statuses() {
+        assert_eq!(
+            "/a=/b".parse::<Redirect>().unwrap().status,
+            StatusCode::MOVED_PERMANENTLY
+        );
+        let redirect = "/a=http://example.com:8080/b:307"
+            .parse::<Redirect>()
+            .unwrap();
+        assert_eq!(redirect.status, StatusCode::TEMPORARY_REDIRECT);
+        assert_eq!(redirect.to, "http://example.com:8080/b");
+        // A port is not a status.
+        let redirect = "/a=http://example.com:8080".parse::<Redirect>().unwrap();
+        assert_eq!(redirect.to, "http://example.com:8080");
+        assert!("/a=/b:200".parse::<Redirect>().is_err());
+        assert!("/a=:301".parse::<Redirect>().is_err());
+    }
+
+    #[test]
+    fn rules_need_a_destination() {
+        assert_eq!("/a=/b".parse::<Redirect>().unwrap().to, "/b");
+        assert!("/a".parse::<Redirect>().is_err());
+        assert!("/a=".parse::<Redirect>().is_err());
+    }
+
+    #[tokio::test]
+    async fn exact_redirect() {
+        let rules = ["/old=/new:302"];
+        assert_eq!(
+            redirect(&rules, "/old?page=2").await,
+            (StatusCode::FOUND, Some("/new?page=2".to_string()))
+        );
+        assert_eq!(redirect(&rules, "/older").await, (StatusCode::OK, None));
+    }
+
+    #[tokio::test]
+    async fn wildcard_redirect_substitutes_captures() {
+        let rules = ["/blog/:year/*=https://example.com/news/:year/:splat?from=blog"];
+        assert_eq!(
+            redirect(&rules, "/blog/2024/01/post?page=2").await,
+            (
+                StatusCode::MOVED_PERMANENTLY,
+                Some("https://example.com/news/2024/01/post?from=blog".to_string())
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn first_match_wins() {
+        let rules = ["/docs/old=/docs/new:308", "/docs/*=/manual/:splat"];
+        assert_eq!(
+            redirect(&rules, "/docs/old").await,
+            (
+                StatusCode::PERMANENT_REDIRECT,
+                Some("/docs/new".to_string())
+            )
+        );
+        assert_eq!(
+            redirect(&rules, "/docs/other").await,
+            (
+                StatusCode::MOVED_PERMANENTLY,
+                Some("/manual/other".to_string())
+            )
+        );
+    }
+}
@@ -0,0 +1,93 @@
+use axum::{
+    extract::{Request, State},
+    http::{HeaderName, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    sync::Arc,
+};
+use tracing::Span;
+
+/// Longest id taken over from a request. Longer ones are replaced, so clients can not blow up
+/// every log line.
+const MAX_LEN: usize = 128;
+
+/// Whether `value` is an id to keep: printable ASCII without spaces, and not too long.
+fn is_valid(value: &HeaderValue) -> bool {
+    let bytes = value.as_bytes();
+    !bytes.is_empty() && bytes.len() <= MAX_LEN && bytes.iter().all(u8::is_ascii_graphic)
+}
+
+/// A random version 4 UUID.
+fn new_id() -> String {
+    // Every RandomState has new keys, so its hashes make do as random numbers.
+    let random = || RandomState::new().build_hasher().finish().to_be_bytes();
+    let mut bytes = [random(), random()].concat();
+    bytes[6] = bytes[6] & 0x0f | 0x40;
+    bytes[8] = bytes[8] & 0x3f | 0x80;
+    let hex = bytes
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<String>();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+/// Middleware that gives every request an id in the `name` header, the one it came with if
+/// that is valid or a new UUID. The id is passed on to proxied upstreams, sent back with the
+/// response and recorded on the span of the request.
+pub async fn apply(
+    State(name): State<Arc<HeaderName>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let id = match request.headers().get(&*name) {
+        Some(id) if is_valid(id) => id.clone(),
+        _ => {
+            // A UUID is always a valid header value.
+            let id = HeaderValue::from_str(&new_id()).expect("UUID header value");
+            request.headers_mut().insert((*name).clone(), id.clone());
+            id
+        }
+    };
+    if let Ok(id) = id.to_str() {
+        Span::current().record("request_id", id);
+    }
+    let mut response = next.run(request).await;
+    response.headers_mut().insert((*name).clone(), id);
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ids_are_uuids() {
+        let id = new_id();
+        let groups: Vec<usize> = id.split('-').map(str::len).collect();
+        assert_eq!(groups, [8, 4, 4, 4, 12]);
+        assert!(id.chars().all(|c| c == '-' || c.is_ascii_hexdigit()));
+        assert_eq!(&id[14..15], "4");
+        assert!("89ab".contains(&id[19..20]), "{}", id);
+        assert_ne!(new_id(), id);
+    }
+
+    #[test]
+    fn ids_are_checked() {
+        assert!(is_valid(&HeaderValue::from_static("abc-123")));
+        assert!(!is_valid(&HeaderValue::from_static("")));
+        assert!(!is_valid(&HeaderValue::from_static("a b")));
+        let long = "a".repeat(MAX_LEN + 1);
+        assert!(!is_valid(&HeaderValue::from_str(&long).unwrap()));
+    }
+}
@@ -0,0 +1,105 @@
+use axum::{
+    extract::{Request, State},
+    http::{StatusCode, Uri},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::{str::FromStr, sync::Arc};
+
+use crate::{
+    error::ServeError,
+    pattern::{self, Pattern},
+};
+
+/// Serves paths that match a pattern from another path, given as `FROM=TO`, like
+/// `/assets/:version/*=/assets/:splat`. The client is not redirected.
+#[derive(Clone, Debug)]
+pub struct Rewrite {
+    from: Pattern,
+    to: String,
+}
+
+impl FromStr for Rewrite {
+    type Err = ServeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (from, to) = pattern::parse_rule("rewrite", s)?;
+        if !to.starts_with('/') {
+            return Err(ServeError::InvalidRule(format!(
+                "rewrite destination {} must be a path starting with /",
+                to
+            )));
+        }
+        Ok(Rewrite {
+            from,
+            to: to.to_string(),
+        })
+    }
+}
+
+/// Middleware that changes the path of requests matching one of the rewrites, the first that
+/// matches wins. The query is kept unless the destination has one.
+pub async fn apply(
+    State(rewrites): State<Arc<[Rewrite]>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let uri = request.uri();
+    let Some(mut path_and_query) = rewrites.iter().find_map(|rewrite| {
+        let captures = rewrite.from.matches(uri.path())?;
+        Some(captures.substitute(&rewrite.to))
+    }) else {
+        return next.run(request).await;
+    };
+    if let Some(query) = uri.query().filter(|_| !path_and_query.contains('?')) {
+        path_and_query.push('?');
+        path_and_query.push_str(query);
+    }
+    let mut parts = uri.clone().into_parts();
+    parts.path_and_query = match path_and_query.parse() {
+        Ok(path_and_query) => Some(path_and_query),
+        Err(_) => return StatusCode::BAD_REQUEST.into_response(),
+    };
+    match Uri::from_parts(parts) {
+        Ok(rewritten) => {
+            tracing::debug!("rewriting {} to {}", request.uri(), rewritten);
+            *request.uri_mut() = rewritten;
+        }
+        Err(_) => return StatusCode::BAD_REQUEST.into_response(),
+    }
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{body_string, get};
+    use axum::{middleware, routing, Router};
+    use tower::ServiceExt;
+
+    async fn rewrite(rules: &[&str], uri: &str) -> String {
+        let rewrites: Arc<[Rewrite]> = rules.iter().map(|rule| rule.parse().unwrap()).collect();
+        let app = Router::new()
+            .fallback(routing::get(|uri: Uri| async move { uri.to_string() }))
+            .layer(middleware::from_fn_with_state(rewrites, apply));
+        body_string(app.oneshot(get(uri)).await.unwrap()).await
+    }
+
+    #[test]
+    fn destinations_are_paths() {
+        assert!("/a=/b".parse::<Rewrite>().is_ok());
+        assert!("/a=http://example.com/b".parse::<Rewrite>().is_err());
+        assert!("/a=".parse::<Rewrite>().is_err());
+    }
+
+    #[tokio::test]
+    async fn captures_are_substituted() {
+        let rules = ["/assets/:version/*=/assets/:splat", "/app/*=/index.html"];
+        assert_eq!(
+            rewrite(&rules, "/assets/v2/css/site.css?x=1").await,
+            "/assets/css/site.css?x=1"
+        );
+        assert_eq!(rewrite(&rules, "/app/users/1").await, "/index.html");
+        assert_eq!(rewrite(&rules, "/other").await, "/other");
+    }
+}
@@ -0,0 +1,325 @@
+use clap::ValueEnum;
+use flate2::{write::GzEncoder, Compression};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    thread::{self, JoinHandle},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum, Debug)]
+pub enum Rotation {
+    /// keep appending to one file.
+    Never,
+    Daily,
+    Hourly,
+    Minutely,
+    /// start a new file when the current one reaches the maximum size.
+    Size,
+}
+
+impl Rotation {
+    /// The number of the UTC day, hour or minute `time` falls in.
+    fn period(self, time: SystemTime) -> u64 {
+        let secs = time
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        match self {
+            Rotation::Daily => secs / 86400,
+            Rotation::Hourly => secs / 3600,
+            Rotation::Minutely => secs / 60,
+            Rotation::Never | Rotation::Size => 0,
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum, Debug)]
+pub enum Compress {
+    /// PATH.1.gz
+    Gzip,
+    /// PATH.1.zst
+    Zstd,
+}
+
+impl Compress {
+    fn extension(self) -> &'static str {
+        match self {
+            Compress::Gzip => ".gz",
+            Compress::Zstd => ".zst",
+        }
+    }
+
+    /// Writes `from` compressed to `to`, and removes it once that is done.
+    fn compress(self, from: &Path, to: &Path) -> io::Result<()> {
+        let mut input = BufReader::new(File::open(from)?);
+        let output = BufWriter::new(File::create(to)?);
+        let mut output = match self {
+            Compress::Gzip => {
+                let mut encoder = GzEncoder::new(output, Compression::default());
+                io::copy(&mut input, &mut encoder)?;
+                encoder.finish()?
+            }
+            Compress::Zstd => {
+                let mut encoder = zstd::Encoder::new(output, 0)?;
+                io::copy(&mut input, &mut encoder)?;
+                encoder.finish()?
+            }
+        };
+        output.flush()?;
+        fs::remove_file(from)
+    }
+}
+
+/// A file that is appended to and rotated to `PATH.1`, `PATH.2`, ... by time or size. Only
+/// `max_files` rotated files are kept, compressed with `compress` if it is set.
+pub struct RollingFile {
+    path: PathBuf,
+    rotation: Rotation,
+    max_size: u64,
+    max_files: usize,
+    compress: Option<Compress>,
+    /// Compresses the file rotated last, off the thread that writes the log.
+    compressing: Option<JoinHandle<()>>,
+    /// Closed while rotating, since Windows can not rename open files.
+    file: Option<BufWriter<File>>,
+    size: u64,
+    period: u64,
+}
+
+impl RollingFile {
+    pub fn open(
+        path: &Path,
+        rotation: Rotation,
+        max_size: u64,
+        max_files: usize,
+        compress: Option<Compress>,
+    ) -> io::Result<Self> {
+        let file = append(path)?;
+        let metadata = file.metadata()?;
+        // A file left from an earlier period is rotated on the first write.
+        let period = rotation.period(metadata.modified().unwrap_or_else(|_| SystemTime::now()));
+        let mut rolling = RollingFile {
+            path: path.to_path_buf(),
+            rotation,
+            max_size,
+            max_files,
+            compress,
+            compressing: None,
+            file: Some(BufWriter::new(file)),
+            size: metadata.len(),
+            period,
+        };
+        // A file whose compression was cut short by an earlier exit.
+        if rolling.compress.is_some() && rolling.uncompressed().exists() {
+            rolling.compress_rotated();
+        }
+        Ok(rolling)
+    }
+
+    /// Writes a line, rotating first if it is due.
+    pub fn write_line(&mut self, line: &str) -> io::Result<()> {
+        let len = line.len() as u64 + 1;
+        let due = match self.rotation {
+            Rotation::Never => false,
+            Rotation::Size => self.size > 0 && self.size + len > self.max_size,
+            _ => self.rotation.period(SystemTime::now()) != self.period,
+        };
+        if due {
+            self.rotate()?;
+        }
+        let file = match self.file.as_mut() {
+            Some(file) => file,
+            None => self.file.insert(BufWriter::new(append(&self.path)?)),
+        };
+        writeln!(file, "{}", line)?;
+        self.size += len;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        match self.file.as_mut() {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+
+    fn rotated(&self, n: usize) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{}", n));
+        if let Some(compress) = self.compress {
+            name.push(compress.extension());
+        }
+        name.into()
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        if let Some(mut file) = self.file.take() {
+            file.flush()?;
+        }
+        // The files are renamed below, so the last one has to be compressed first.
+        if let Some(compressing) = self.compressing.take() {
+            let _ = compressing.join();
+        }
+        self.size = 0;
+        self.period = self.rotation.period(SystemTime::now());
+        if self.max_files == 0 {
+            fs::remove_file(&self.path)?;
+            return Ok(());
+        }
+        let _ = fs::remove_file(self.rotated(self.max_files));
+        for n in (1..self.max_files).rev() {
+            let from = self.rotated(n);
+            if from.exists() {
+                fs::rename(&from, self.rotated(n + 1))?;
+            }
+        }
+        if self.compress.is_none() {
+            return fs::rename(&self.path, self.rotated(1));
+        }
+        fs::rename(&self.path, self.uncompressed())?;
+        self.compress_rotated();
+        Ok(())
+    }
+
+    /// Where the file rotated last waits to be compressed.
+    fn uncompressed(&self) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(".1");
+        name.into()
+    }
+
+    fn compress_rotated(&mut self) {
+        let Some(compress) = self.compress else {
+            return;
+        };
+        let (uncompressed, rotated) = (self.uncompressed(), self.rotated(1));
+        self.compressing = Some(thread::spawn(move || {
+            if let Err(e) = compress.compress(&uncompressed, &rotated) {
+                tracing::error!("unable to compress {}: {}", uncompressed.display(), e);
+            }
+        }));
+    }
+}
+
+impl Drop for RollingFile {
+    fn drop(&mut self) {
+        if let Some(compressing) = self.compressing.take() {
+            let _ = compressing.join();
+        }
+    }
+}
+
+fn append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    fn read(path: PathBuf) -> String {
+        fs::read_to_string(path).unwrap()
+    }
+
+    #[test]
+    fn size_rotation_starts_a_new_file() {
+        let dir = TempDir::new("rolling-size");
+        let path = dir.path().join("serve.log");
+        let mut file = RollingFile::open(&path, Rotation::Size, 20, 2, None).unwrap();
+        file.write_line("first line").unwrap();
+        file.flush().unwrap();
+        assert!(!dir.path().join("serve.log.1").exists());
+        file.write_line("second line").unwrap();
+        file.flush().unwrap();
+        assert_eq!(read(dir.path().join("serve.log.1")), "first line\n");
+        assert_eq!(read(path.clone()), "second line\n");
+
+        file.write_line("third line").unwrap();
+        file.write_line("fourth line").unwrap();
+        file.flush().unwrap();
+        assert_eq!(read(path), "fourth line\n");
+        assert_eq!(read(dir.path().join("serve.log.1")), "third line\n");
+        assert_eq!(read(dir.path().join("serve.log.2")), "second line\n");
+        assert!(!dir.path().join("serve.log.3").exists());
+    }
+
+    #[test]
+    fn size_rotation_continues_an_existing_file() {
+        let dir = TempDir::new("rolling-existing");
+        let path = dir.write("serve.log", "0123456789012345\n");
+        let mut file = RollingFile::open(&path, Rotation::Size, 20, 1, None).unwrap();
+        file.write_line("next").unwrap();
+        file.flush().unwrap();
+        assert_eq!(read(dir.path().join("serve.log.1")), "0123456789012345\n");
+        assert_eq!(read(path), "next\n");
+    }
+
+    #[test]
+    fn without_rotated_files_the_log_starts_over() {
+        let dir = TempDir::new("rolling-no-files");
+        let path = dir.path().join("serve.log");
+        let mut file = RollingFile::open(&path, Rotation::Size, 10, 0, None).unwrap();
+        file.write_line("first line").unwrap();
+        file.write_line("second line").unwrap();
+        file.flush().unwrap();
+        assert_eq!(read(path), "second line\n");
+        assert!(!dir.path().join("serve.log.1").exists());
+    }
+
+    fn gunzip(path: PathBuf) -> String {
+        let mut text = String::new();
+        io::Read::read_to_string(
+            &mut flate2::read::GzDecoder::new(File::open(path).unwrap()),
+            &mut text,
+        )
+        .unwrap();
+        text
+    }
+
+    #[test]
+    fn rotated_files_are_compressed() {
+        let dir = TempDir::new("rolling-gzip");
+        let path = dir.path().join("serve.log");
+        let mut file =
+            RollingFile::open(&path, Rotation::Size, 20, 2, Some(Compress::Gzip)).unwrap();
+        file.write_line("first line").unwrap();
+        file.write_line("second line").unwrap();
+        file.write_line("third line").unwrap();
+        // Dropping waits for the compression.
+        drop(file);
+        assert_eq!(read(path), "third line\n");
+        assert_eq!(gunzip(dir.path().join("serve.log.1.gz")), "second line\n");
+        assert_eq!(gunzip(dir.path().join("serve.log.2.gz")), "first line\n");
+        assert!(!dir.path().join("serve.log.1").exists());
+        assert!(!dir.path().join("serve.log.3.gz").exists());
+    }
+
+    #[test]
+    fn rotated_files_are_compressed_with_zstd() {
+        let dir = TempDir::new("rolling-zstd");
+        let path = dir.path().join("serve.log");
+        let mut file =
+            RollingFile::open(&path, Rotation::Size, 20, 1, Some(Compress::Zstd)).unwrap();
+        file.write_line("first line").unwrap();
+        file.write_line("second line").unwrap();
+        drop(file);
+        let rotated = zstd::decode_all(File::open(dir.path().join("serve.log.1.zst")).unwrap());
+        assert_eq!(rotated.unwrap(), b"first line\n");
+        assert_eq!(read(path), "second line\n");
+    }
+
+    #[test]
+    fn compression_cut_short_is_finished_on_open() {
+        let dir = TempDir::new("rolling-unfinished");
+        let path = dir.write("serve.log", "current\n");
+        dir.write("serve.log.1", "rotated\n");
+        let file = RollingFile::open(&path, Rotation::Size, 20, 2, Some(Compress::Gzip)).unwrap();
+        drop(file);
+        assert_eq!(gunzip(dir.path().join("serve.log.1.gz")), "rotated\n");
+        assert!(!dir.path().join("serve.log.1").exists());
+        assert_eq!(read(path), "current\n");
+    }
+}
@@ -0,0 +1,87 @@
+use axum::{
+    extract::{Request, State},
+    http::{header, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use notify::{Config, RecommendedWatcher, RecursiveMode, Watcher};
+use std::{
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+use tokio::sync::mpsc;
+
+use crate::{error::ServeError, tls};
+
+/// Whether the served directory is there, kept up to date by a watcher.
+pub struct RootWatch {
+    present: AtomicBool,
+}
+
+/// Watches whether `root` exists and is a directory. The watch is on its parent, because a
+/// watch on the directory itself ends when it is deleted. The watcher lives as long as the
+/// spawned task.
+pub fn watch(root: &Path) -> Result<Arc<RootWatch>, ServeError> {
+    let root = root.canonicalize().or_else(|_| tls::watch_target(root))?;
+    let state = Arc::new(RootWatch {
+        present: AtomicBool::new(root.is_dir()),
+    });
+    if !state.present.load(Ordering::Relaxed) {
+        tracing::warn!("{} is not a directory, answering 503", root.display());
+    }
+
+    let (events_tx, mut events_rx) = mpsc::unbounded_channel();
+    let watched = root.clone();
+    let mut watcher = RecommendedWatcher::new(
+        move |res: notify::Result<notify::Event>| match res {
+            Ok(event) if event.paths.contains(&watched) => {
+                let _ = events_tx.send(());
+            }
+            Ok(_) => {}
+            Err(e) => tracing::error!("watcher error: {}", e),
+        },
+        Config::default(),
+    )?;
+    // The root of the file system can not be deleted, watching it is as good as any.
+    let dir = root.parent().unwrap_or(&root);
+    watcher.watch(dir, RecursiveMode::NonRecursive)?;
+
+    let watch = state.clone();
+    tokio::spawn(async move {
+        let _watcher = watcher;
+        while events_rx.recv().await.is_some() {
+            let present = tokio::fs::metadata(&root)
+                .await
+                .is_ok_and(|metadata| metadata.is_dir());
+            if watch.present.swap(present, Ordering::Relaxed) == present {
+                continue;
+            }
+            if present {
+                tracing::info!("{} is back, serving it again", root.display());
+            } else {
+                tracing::warn!(
+                    "{} was removed, answering 503 until it is back",
+                    root.display()
+                );
+            }
+        }
+    });
+    Ok(state)
+}
+
+/// Middleware that answers 503 while the served directory is missing, instead of the 404s and
+/// errors the file services would give.
+pub async fn apply(State(watch): State<Arc<RootWatch>>, request: Request, next: Next) -> Response {
+    if watch.present.load(Ordering::Relaxed) {
+        return next.run(request).await;
+    }
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        [(header::RETRY_AFTER, "5")],
+        "The site is not available right now. Please try again in a moment.\n",
+    )
+        .into_response()
+}
@@ -0,0 +1,141 @@
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+use std::sync::Arc;
+
+use crate::tls;
+
+/// How long browsers remember to use HTTPS by default, a year. Preload lists ask for at least
+/// that.
+pub const HSTS_MAX_AGE: u64 = 31536000;
+
+pub struct SecurityHeaders {
+    /// Whether to send X-Content-Type-Options, X-Frame-Options and Referrer-Policy.
+    pub common: bool,
+    pub content_security_policy: Option<HeaderValue>,
+    pub strict_transport_security: Option<HeaderValue>,
+}
+
+/// The `Strict-Transport-Security` value with the given directives.
+pub fn hsts(max_age: u64, include_subdomains: bool, preload: bool) -> HeaderValue {
+    let mut value = format!("max-age={}", max_age);
+    if include_subdomains {
+        value.push_str("; includeSubDomains");
+    }
+    if preload {
+        value.push_str("; preload");
+    }
+    HeaderValue::from_str(&value).expect("HSTS directives are valid header values")
+}
+
+/// Middleware that adds security headers to responses that don't have them yet.
+/// `Strict-Transport-Security` is only sent on requests that came in over TLS.
+pub async fn apply(
+    State(security): State<Arc<SecurityHeaders>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let is_tls = tls::is_tls(&request);
+    let mut response = next.run(request).await;
+    let headers = response.headers_mut();
+    if security.common {
+        headers
+            .entry(header::X_CONTENT_TYPE_OPTIONS)
+            .or_insert(HeaderValue::from_static("nosniff"));
+        headers
+            .entry(header::X_FRAME_OPTIONS)
+            .or_insert(HeaderValue::from_static("DENY"));
+        headers
+            .entry(header::REFERRER_POLICY)
+            .or_insert(HeaderValue::from_static("strict-origin-when-cross-origin"));
+    }
+    if let Some(csp) = security.content_security_policy.as_ref() {
+        headers
+            .entry(header::CONTENT_SECURITY_POLICY)
+            .or_insert(csp.clone());
+    }
+    if let Some(hsts) = security
+        .strict_transport_security
+        .as_ref()
+        .filter(|_| is_tls)
+    {
+        headers
+            .entry(header::STRICT_TRANSPORT_SECURITY)
+            .or_insert(hsts.clone());
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_util::get, tls::ClientCertificate};
+    use axum::{middleware, routing::get as route, Router};
+    use tower::ServiceExt;
+
+    fn app() -> Router {
+        let security = SecurityHeaders {
+            common: true,
+            content_security_policy: Some(HeaderValue::from_static("default-src 'self'")),
+            strict_transport_security: Some(hsts(HSTS_MAX_AGE, false, false)),
+        };
+        Router::new()
+            .route("/", route(|| async { "ok" }))
+            .route(
+                "/framed",
+                route(|| async { ([(header::X_FRAME_OPTIONS, "SAMEORIGIN")], "ok") }),
+            )
+            .layer(middleware::from_fn_with_state(Arc::new(security), apply))
+    }
+
+    /// A request as the TLS listener passes it on, without a client certificate.
+    fn over_tls(uri: &str) -> Request {
+        let mut request = get(uri);
+        request.extensions_mut().insert(None::<ClientCertificate>);
+        request
+    }
+
+    #[test]
+    fn hsts_directives() {
+        assert_eq!(hsts(600, false, false), "max-age=600");
+        assert_eq!(hsts(600, true, false), "max-age=600; includeSubDomains");
+        assert_eq!(
+            hsts(HSTS_MAX_AGE, true, true),
+            "max-age=31536000; includeSubDomains; preload"
+        );
+    }
+
+    #[tokio::test]
+    async fn hsts_only_over_tls() {
+        let response = app().oneshot(over_tls("/")).await.unwrap();
+        let hsts = &response.headers()[header::STRICT_TRANSPORT_SECURITY];
+        assert_eq!(hsts, "max-age=31536000");
+
+        let response = app().oneshot(get("/")).await.unwrap();
+        let headers = response.headers();
+        assert!(!headers.contains_key(header::STRICT_TRANSPORT_SECURITY));
+        assert_eq!(headers[header::X_CONTENT_TYPE_OPTIONS], "nosniff");
+    }
+
+    #[tokio::test]
+    async fn preset_headers_are_added() {
+        let response = app().oneshot(over_tls("/")).await.unwrap();
+        let headers = response.headers();
+        assert_eq!(headers[header::X_CONTENT_TYPE_OPTIONS], "nosniff");
+        assert_eq!(headers[header::X_FRAME_OPTIONS], "DENY");
+        assert_eq!(
+            headers[header::REFERRER_POLICY],
+            "strict-origin-when-cross-origin"
+        );
+        assert_eq!(
+            headers[header::CONTENT_SECURITY_POLICY],
+            "default-src 'self'"
+        );
+
+        let response = app().oneshot(get("/framed")).await.unwrap();
+        assert_eq!(response.headers()[header::X_FRAME_OPTIONS], "SAMEORIGIN");
+    }
+}
@@ -0,0 +1,76 @@
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderValue},
+    middleware::{self, Next},
+    response::Response,
+    Router,
+};
+
+/// What to do with the `Server` header of every response.
+#[derive(Clone, Debug)]
+pub enum ServerHeader {
+    Set(HeaderValue),
+    Remove,
+}
+
+async fn apply(
+    State(server_header): State<ServerHeader>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let mut response = next.run(request).await;
+    match server_header {
+        ServerHeader::Set(value) => {
+            response.headers_mut().insert(header::SERVER, value);
+        }
+        ServerHeader::Remove => {
+            response.headers_mut().remove(header::SERVER);
+        }
+    }
+    response
+}
+
+/// Applies `server_header`, if any, to all responses of `app`.
+pub fn layer(app: Router, server_header: Option<&ServerHeader>) -> Router {
+    match server_header {
+        Some(server_header) => {
+            app.layer(middleware::from_fn_with_state(server_header.clone(), apply))
+        }
+        None => app,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::get;
+    use axum::routing::get as route;
+    use tower::ServiceExt;
+
+    /// The Server headers of a response that an upstream gave one.
+    async fn server(server_header: Option<&ServerHeader>) -> Vec<HeaderValue> {
+        let app = Router::new().route(
+            "/",
+            route(|| async { ([(header::SERVER, "upstream/1.0")], "ok") }),
+        );
+        let response = layer(app, server_header).oneshot(get("/")).await.unwrap();
+        response
+            .headers()
+            .get_all(header::SERVER)
+            .iter()
+            .cloned()
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn header_is_absent_when_removed() {
+        assert!(server(Some(&ServerHeader::Remove)).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn header_is_replaced() {
+        let set = ServerHeader::Set(HeaderValue::from_static("serve"));
+        assert_eq!(server(Some(&set)).await, ["serve"]);
+        assert_eq!(server(None).await, ["upstream/1.0"]);
+    }
+}
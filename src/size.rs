@@ -0,0 +1,44 @@
+/// Parses a size in bytes with an optional k, m or g suffix for KiB, MiB and GiB.
+pub fn parse(s: &str) -> Result<usize, String> {
+    let invalid = || format!("{} is not a size, use bytes or a number with k, m or g", s);
+    let (number, multiplier) = match s.char_indices().last() {
+        Some((i, 'k' | 'K')) => (&s[..i], 1 << 10),
+        Some((i, 'm' | 'M')) => (&s[..i], 1 << 20),
+        Some((i, 'g' | 'G')) => (&s[..i], 1 << 30),
+        _ => (s, 1),
+    };
+    number
+        .parse::<usize>()
+        .ok()
+        .and_then(|n| n.checked_mul(multiplier))
+        .ok_or_else(invalid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizes_with_suffixes() {
+        assert_eq!(parse("0"), Ok(0));
+        assert_eq!(parse("1500"), Ok(1500));
+        assert_eq!(parse("64k"), Ok(64 * 1024));
+        assert_eq!(parse("10M"), Ok(10 * 1024 * 1024));
+        assert_eq!(parse("2g"), Ok(2 * 1024 * 1024 * 1024));
+    }
+
+    #[test]
+    fn invalid_sizes() {
+        for invalid in [
+            "",
+            "k",
+            "1.5m",
+            "-1",
+            "10 k",
+            "10kb",
+            "99999999999999999999g",
+        ] {
+            assert!(parse(invalid).is_err(), "{}", invalid);
+        }
+    }
+}
@@ -0,0 +1,60 @@
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::Response,
+};
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tower::ServiceExt;
+
+use crate::{path, FileService};
+
+/// Keeps requests from reaching files outside of the root through a symlink.
+pub struct Symlinks {
+    /// The canonical root.
+    root: PathBuf,
+    not_found: FileService,
+}
+
+impl Symlinks {
+    pub fn new(root: &Path, not_found: FileService) -> Self {
+        Symlinks {
+            root: root.canonicalize().unwrap_or(root.to_path_buf()),
+            not_found,
+        }
+    }
+
+    /// Whether the file or directory a request path names is outside the root. Paths that
+    /// don't exist are left to the file service.
+    async fn escapes(&self, request_path: &str) -> bool {
+        let Some(mut file) = path::resolve(&self.root, request_path) else {
+            return false;
+        };
+        if request_path.ends_with('/') && tokio::fs::metadata(file.join("index.html")).await.is_ok()
+        {
+            file.push("index.html");
+        }
+        match tokio::fs::canonicalize(&file).await {
+            Ok(canonical) => !canonical.starts_with(&self.root),
+            Err(_) => false,
+        }
+    }
+}
+
+/// Middleware that answers requests that resolve outside of the root with the 404 page.
+pub async fn apply(
+    State(symlinks): State<Arc<Symlinks>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if !symlinks.escapes(request.uri().path()).await {
+        return next.run(request).await;
+    }
+    tracing::debug!("{} resolves outside of the root", request.uri().path());
+    match symlinks.not_found.clone().oneshot(request).await {
+        Ok(response) => response,
+        Err(infallible) => match infallible {},
+    }
+}
@@ -0,0 +1,90 @@
+use std::net::TcpListener;
+
+use crate::error::ServeError;
+
+/// The first file descriptor systemd passes, see sd_listen_fds(3).
+#[cfg(unix)]
+const LISTEN_FDS_START: i32 = 3;
+
+/// Takes the listening sockets systemd passed with socket activation. Returns `None` when
+/// there are none, or they are meant for another process. Called once, on startup.
+#[cfg(unix)]
+pub fn listeners() -> Result<Option<Vec<TcpListener>>, ServeError> {
+    use socket2::{SockRef, Socket, Type};
+    use std::os::fd::{BorrowedFd, FromRawFd};
+
+    let Ok(fds) = std::env::var("LISTEN_FDS") else {
+        return Ok(None);
+    };
+    if let Ok(pid) = std::env::var("LISTEN_PID") {
+        if pid.parse() != Ok(std::process::id()) {
+            return Ok(None);
+        }
+    }
+    let count = fds
+        .parse::<i32>()
+        .ok()
+        .filter(|count| (0..=1024).contains(count))
+        .ok_or_else(|| ServeError::Systemd(format!("LISTEN_FDS={} is not a count", fds)))?;
+    // The variables are left alone, because changing the environment is not safe once other
+    // threads run. Child processes have another pid, so they do not take the sockets again,
+    // and the sockets are closed for them below.
+    if count == 0 {
+        return Ok(None);
+    }
+    let mut listeners = Vec::new();
+    for fd in LISTEN_FDS_START..LISTEN_FDS_START + count {
+        // SAFETY: systemd passes `count` sockets starting at fd 3. They are only borrowed
+        // until they are known to be open TCP sockets, and nothing else in the process owns them.
+        let borrowed = unsafe { BorrowedFd::borrow_raw(fd) };
+        let socket = SockRef::from(&borrowed);
+        let is_tcp = socket.r#type().is_ok_and(|kind| kind == Type::STREAM)
+            && socket
+                .local_addr()
+                .is_ok_and(|addr| addr.as_socket().is_some());
+        if !is_tcp {
+            return Err(ServeError::Systemd(format!(
+                "file descriptor {} is not a TCP socket",
+                fd
+            )));
+        }
+        let socket = unsafe { Socket::from_raw_fd(fd) };
+        socket.set_cloexec(true)?;
+        listeners.push(socket.into());
+    }
+    Ok(Some(listeners))
+}
+
+#[cfg(not(unix))]
+pub fn listeners() -> Result<Option<Vec<TcpListener>>, ServeError> {
+    Ok(None)
+}
+
+/// Sends a state like `READY=1` to the service manager, see sd_notify(3). Does nothing when
+/// the process was not started by systemd.
+#[cfg(unix)]
+pub fn notify(state: &str) {
+    use std::os::unix::net::UnixDatagram;
+
+    let Some(path) = std::env::var_os("NOTIFY_SOCKET") else {
+        tracing::debug!("not sending {} to systemd, NOTIFY_SOCKET is not set", state);
+        return;
+    };
+    let result = UnixDatagram::unbound().and_then(|socket| {
+        let path = path.to_string_lossy();
+        // Sockets in the abstract namespace, which only Linux has, start with @.
+        #[cfg(target_os = "linux")]
+        if let Some(name) = path.strip_prefix('@') {
+            use std::os::{linux::net::SocketAddrExt, unix::net::SocketAddr};
+            let addr = SocketAddr::from_abstract_name(name)?;
+            return socket.send_to_addr(state.as_bytes(), &addr);
+        }
+        socket.send_to(state.as_bytes(), &*path)
+    });
+    if let Err(e) = result {
+        tracing::warn!("unable to send {} to systemd: {}", state, e);
+    }
+}
+
+#[cfg(not(unix))]
+pub fn notify(_state: &str) {}
@@ -0,0 +1,149 @@
+use axum::{
+    body::{Body, Bytes},
+    extract::{Request, State},
+    middleware::Next,
+    response::Response,
+};
+use futures_util::{stream, StreamExt};
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::time::{sleep_until, Instant};
+
+/// Body data is sent in pieces of a tenth of the rate, so slow rates still stream smoothly
+/// instead of sending a whole read buffer and then pausing.
+const PIECES_PER_SECOND: usize = 10;
+
+/// Paces the response bodies of a connection, so that its concurrent HTTP/2 streams together
+/// stay within the rate, like the responses sent one after the other over HTTP/1.1.
+pub struct Pacer {
+    rate: usize,
+    /// When the next piece may be sent.
+    next: Mutex<Instant>,
+}
+
+impl Pacer {
+    pub fn new(rate: usize) -> Self {
+        Pacer {
+            rate,
+            next: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Waits until `len` more bytes may be sent. A connection that was quiet for a while does
+    /// not get to send a burst after.
+    async fn wait(&self, len: usize) {
+        let at = match self.next.lock() {
+            Ok(mut next) => {
+                let at = (*next).max(Instant::now());
+                *next = at + Duration::from_secs_f64(len as f64 / self.rate as f64);
+                at
+            }
+            Err(_) => Instant::now(),
+        };
+        sleep_until(at).await;
+    }
+}
+
+/// Middleware that sends the response bodies of a connection at no more than `rate` bytes per
+/// second together, with the `Pacer` the acceptor gives every connection. Connections without
+/// one, like on a Unix domain socket, are paced per response. The body is streamed, so large
+/// files are not buffered.
+pub async fn apply(State(rate): State<usize>, request: Request, next: Next) -> Response {
+    let pacer = request
+        .extensions()
+        .get::<Option<Arc<Pacer>>>()
+        .cloned()
+        .flatten()
+        .unwrap_or_else(|| Arc::new(Pacer::new(rate)));
+    let response = next.run(request).await;
+    let piece = (rate / PIECES_PER_SECOND).max(1);
+    let (parts, body) = response.into_parts();
+    let body = stream::unfold(
+        (body.into_data_stream(), Bytes::new()),
+        move |(mut data, mut pending)| {
+            let pacer = pacer.clone();
+            async move {
+                if pending.is_empty() {
+                    match data.next().await? {
+                        Ok(bytes) => pending = bytes,
+                        Err(e) => return Some((Err(e), (data, pending))),
+                    }
+                }
+                let bytes = pending.split_to(piece.min(pending.len()));
+                pacer.wait(bytes.len()).await;
+                Some((Ok(bytes), (data, pending)))
+            }
+        },
+    );
+    Response::from_parts(parts, Body::from_stream(body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{middleware, routing::get, Router};
+    use tower::ServiceExt;
+
+    /// Sent in pieces of 1000 bytes, ten per second.
+    const RATE: usize = 10_000;
+    const LEN: usize = 3_000;
+
+    fn app() -> Router {
+        Router::new()
+            .route("/", get(|| async { vec![b'x'; LEN] }))
+            .layer(middleware::from_fn_with_state(RATE, apply))
+    }
+
+    async fn download(request: Request) -> usize {
+        let response = app().oneshot(request).await.unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        body.len()
+    }
+
+    fn request(pacer: Option<Arc<Pacer>>) -> Request {
+        let mut request = Request::new(Body::empty());
+        request.extensions_mut().insert(pacer);
+        request
+    }
+
+    #[tokio::test]
+    async fn body_is_sent_at_the_rate() {
+        let start = Instant::now();
+        assert_eq!(download(request(None)).await, LEN);
+        // The first piece goes out right away, the other two a tenth of a second apart.
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(180), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_millis(600), "{:?}", elapsed);
+    }
+
+    #[tokio::test]
+    async fn responses_of_a_connection_share_the_rate() {
+        let pacer = Arc::new(Pacer::new(RATE));
+        let start = Instant::now();
+        let (a, b) = tokio::join!(
+            download(request(Some(pacer.clone()))),
+            download(request(Some(pacer)))
+        );
+        assert_eq!((a, b), (LEN, LEN));
+        // Six pieces over one connection take as long as one body of twice the size.
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(480), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_millis(1200), "{:?}", elapsed);
+    }
+
+    #[tokio::test]
+    async fn separate_connections_each_get_the_rate() {
+        let start = Instant::now();
+        let (a, b) = tokio::join!(
+            download(request(Some(Arc::new(Pacer::new(RATE))))),
+            download(request(Some(Arc::new(Pacer::new(RATE)))))
+        );
+        assert_eq!((a, b), (LEN, LEN));
+        let elapsed = start.elapsed();
+        assert!(elapsed < Duration::from_millis(450), "{:?}", elapsed);
+    }
+}
@@ -0,0 +1,1358 @@
+use axum::{
+    extract::Request,
+    http::{header, uri::Authority, StatusCode, Uri},
+    middleware::AddExtension,
+    response::{IntoResponse, Redirect, Response},
+    Extension, Router,
+};
+use axum_server::{
+    accept::Accept,
+    tls_rustls::{RustlsAcceptor, RustlsConfig},
+};
+use clap::{ArgGroup, Args, ValueEnum};
+use futures_util::{
+    future::{self, BoxFuture},
+    FutureExt, StreamExt,
+};
+use notify::{
+    event::ModifyKind, Config, Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher,
+};
+use rustls::{
+    crypto::aws_lc_rs,
+    server::WantsServerCert,
+    server::{danger::ClientCertVerifier, ClientHello, ResolvesServerCert, WebPkiClientVerifier},
+    sign::CertifiedKey,
+    ConfigBuilder, RootCertStore, ServerConfig, SupportedCipherSuite, SupportedProtocolVersion,
+};
+use rustls_acme::{caches::DirCache, AcmeConfig, AcmeState};
+use rustls_pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer};
+use std::{
+    collections::{hash_map::RandomState, HashMap},
+    ffi::OsString,
+    fmt,
+    hash::{BuildHasher, Hasher},
+    net::{SocketAddr, TcpListener},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{net::TcpStream, select, task::JoinHandle, time::sleep};
+use tokio_rustls::server::TlsStream;
+use tower::Layer;
+
+use crate::{
+    connection::{ConnectionAcceptor, ConnectionOptions, ConnectionService, Http2, IdleTimeout},
+    error::ServeError,
+    hooks,
+    listen::{self, bind_tcp, Listener, SocketOptions},
+    ocsp,
+    server_header::{self, ServerHeader},
+};
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum, Debug)]
+pub enum RedirectStatus {
+    Temporary,
+    Permanent,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum, Debug)]
+pub enum ClientAuth {
+    /// connections without a valid client certificate are rejected.
+    Required,
+    /// clients may connect without a certificate. Certificates that are sent are still verified.
+    Optional,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum, Debug)]
+pub enum TlsVersion {
+    #[value(name = "1.2")]
+    Tls12,
+    #[value(name = "1.3")]
+    Tls13,
+}
+
+static TLS13_ONLY: &[&SupportedProtocolVersion] = &[&rustls::version::TLS13];
+
+impl TlsVersion {
+    /// Protocol versions from this one up, newest first.
+    fn and_later(self) -> &'static [&'static SupportedProtocolVersion] {
+        match self {
+            TlsVersion::Tls12 => rustls::ALL_VERSIONS,
+            TlsVersion::Tls13 => TLS13_ONLY,
+        }
+    }
+}
+
+fn version_name(version: &SupportedProtocolVersion) -> &'static str {
+    if version.version == rustls::ProtocolVersion::TLSv1_2 {
+        "1.2"
+    } else {
+        "1.3"
+    }
+}
+
+fn suite_name(suite: &SupportedCipherSuite) -> String {
+    format!("{:?}", suite.suite())
+}
+
+fn parse_cipher_suite(s: &str) -> Result<SupportedCipherSuite, String> {
+    aws_lc_rs::ALL_CIPHER_SUITES
+        .iter()
+        .find(|suite| suite_name(suite).eq_ignore_ascii_case(s))
+        .copied()
+        .ok_or_else(|| {
+            let names = aws_lc_rs::ALL_CIPHER_SUITES
+                .iter()
+                .map(suite_name)
+                .collect::<Vec<_>>();
+            format!("unknown cipher suite, use one of {}", names.join(", "))
+        })
+}
+
+/// A certificate for some SNI names.
+#[derive(Clone, Debug)]
+pub struct SniCert {
+    pub domains: Vec<String>,
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+fn parse_sni_cert(s: &str) -> Result<SniCert, String> {
+    let mut parts = s.splitn(3, ':');
+    let (Some(domains), Some(cert), Some(key)) = (parts.next(), parts.next(), parts.next()) else {
+        return Err(format!("{} is not DOMAINS:CERT:KEY", s));
+    };
+    let domains = domains
+        .split(',')
+        .filter(|domain| !domain.is_empty())
+        .map(str::to_ascii_lowercase)
+        .collect::<Vec<_>>();
+    if domains.is_empty() || cert.is_empty() || key.is_empty() {
+        return Err(format!("{} is not DOMAINS:CERT:KEY", s));
+    }
+    Ok(SniCert {
+        domains,
+        cert: cert.into(),
+        key: key.into(),
+    })
+}
+
+/// Orders and renews the ACME certificate while it is polled.
+type AcmeEvents = AcmeState<std::io::Error>;
+
+#[derive(Args, Debug)]
+#[clap(
+    group(ArgGroup::new("generated").args(["self_signed", "acme"])),
+    group(ArgGroup::new("certificate").args(["cert", "cert_pem_env"]))
+)]
+pub struct Tls {
+    /// path to the certificate file.
+    #[clap(short, long, required_unless_present_any = ["self_signed", "acme", "sni_certs", "cert_pem_env", "key_pem_env"], requires = "key")]
+    pub cert: Option<PathBuf>,
+    /// path to the private key file.
+    #[clap(short, long, required_unless_present_any = ["self_signed", "acme", "sni_certs", "cert_pem_env", "key_pem_env"], requires = "cert")]
+    pub key: Option<PathBuf>,
+    /// environment variable that holds the PEM encoded certificate, instead of --cert. It is
+    /// read once on startup.
+    #[clap(long, value_name = "VAR", conflicts_with_all = ["cert", "key", "self_signed", "acme"], requires = "key_pem_env")]
+    pub cert_pem_env: Option<String>,
+    /// environment variable that holds the PEM encoded private key, instead of --key.
+    #[clap(long, value_name = "VAR", conflicts_with_all = ["cert", "key", "self_signed", "acme"], requires = "cert_pem_env")]
+    pub key_pem_env: Option<String>,
+    /// certificate for clients that ask for one of DOMAINS with SNI, e.g.
+    /// example.com,*.example.com:example.pem:example.key. Can be repeated. Other names get
+    /// --cert, or no certificate without it.
+    #[clap(long = "sni-cert", value_name = "DOMAINS:CERT:KEY", value_parser = parse_sni_cert, conflicts_with_all = ["self_signed", "acme"])]
+    pub sni_certs: Vec<SniCert>,
+    /// generate a self-signed certificate on startup instead of loading one. For local development only.
+    #[clap(long, conflicts_with_all = ["cert", "key"])]
+    pub self_signed: bool,
+    /// obtain and renew a certificate for --domain from Let's Encrypt, using the TLS-ALPN-01
+    /// challenge. The server must be reachable on port 443 of the domain.
+    #[clap(long, conflicts_with_all = ["cert", "key", "self_signed"])]
+    pub acme: bool,
+    /// domain or IP address the self-signed or ACME certificate is valid for. Can be repeated.
+    #[clap(long = "domain", default_value = "localhost", requires = "generated")]
+    pub domains: Vec<String>,
+    /// directory to write the generated cert.pem and key.pem to.
+    #[clap(long, requires = "self_signed")]
+    pub self_signed_out: Option<PathBuf>,
+    /// contact email for the ACME account, used for expiry notices. Can be repeated.
+    #[clap(long, requires = "acme")]
+    pub acme_email: Vec<String>,
+    /// directory to keep the ACME account and certificates in. Without it a new certificate is
+    /// requested on every start, which runs into rate limits quickly.
+    #[clap(long, value_name = "DIR", requires = "acme")]
+    pub acme_cache: Option<PathBuf>,
+    /// use the Let's Encrypt staging environment, whose certificates are not trusted by
+    /// browsers but which has much higher rate limits. For testing.
+    #[clap(long, requires = "acme")]
+    pub acme_staging: bool,
+    /// DER encoded OCSP response to staple to the certificate, e.g. from `openssl ocsp
+    /// -respout`. Reloaded when it changes.
+    #[clap(long, value_name = "PATH", requires = "certificate")]
+    pub ocsp_staple: Option<PathBuf>,
+    /// fetch the OCSP response to staple from the responder named in the certificate, and
+    /// refresh it every few hours. The certificate file must include the issuer.
+    #[clap(long, requires = "certificate", conflicts_with = "ocsp_staple")]
+    pub ocsp_auto: bool,
+    /// CA bundle to verify client certificates against. Enables mutual TLS.
+    #[clap(long)]
+    pub client_ca: Option<PathBuf>,
+    /// whether clients must present a certificate signed by --client-ca.
+    #[clap(long, value_enum, default_value_t = ClientAuth::Required, requires = "client_ca")]
+    pub client_auth: ClientAuth,
+    /// oldest TLS version to accept.
+    #[clap(long, value_enum, default_value_t = TlsVersion::Tls12)]
+    pub tls_min_version: TlsVersion,
+    /// comma separated cipher suites to offer, e.g. TLS13_AES_256_GCM_SHA384. Defaults to all supported suites.
+    #[clap(long, value_delimiter = ',', value_parser = parse_cipher_suite)]
+    pub tls_cipher_suites: Vec<SupportedCipherSuite>,
+    /// redirect plain HTTP requests to HTTPS.
+    #[clap(long)]
+    pub redirect_http: bool,
+    /// port to listen on for plain HTTP requests to redirect.
+    #[clap(long, default_value_t = 80, requires = "redirect_http")]
+    pub redirect_from_port: u16,
+    /// HTTPS port to redirect to. Defaults to --port.
+    #[clap(long, requires = "redirect_http")]
+    pub redirect_https_port: Option<u16>,
+    /// status of the redirect response.
+    #[clap(long, value_enum, default_value_t = RedirectStatus::Permanent, requires = "redirect_http")]
+    pub redirect_status: RedirectStatus,
+    /// how many times to retry reloading a changed certificate that can not be loaded, before
+    /// giving up until the files change again. The last good certificate is served meanwhile.
+    /// 0 means no limit.
+    #[clap(long, value_name = "N", default_value_t = 0)]
+    pub cert_reload_max_retries: u32,
+    /// milliseconds before the first retry of a failed reload. It doubles with every retry, and
+    /// each delay is shortened by up to half at random.
+    #[clap(long, value_name = "MS", default_value_t = 2)]
+    pub cert_reload_base_delay: u64,
+    /// most milliseconds between retries of a failed reload.
+    #[clap(long, value_name = "MS", default_value_t = 30_000)]
+    pub cert_reload_max_delay: u64,
+    /// shell command to run in the background after the certificate was reloaded, e.g. to
+    /// notify a sidecar. The --cert path is passed as its first argument and in SERVE_CERT.
+    #[clap(long, value_name = "CMD")]
+    pub on_cert_reload: Option<String>,
+}
+
+/// Serves `app` over TLS on the TLS listeners and reloads the certificate when it changes on
+/// disk. The plain listeners serve `app` too, or redirect to HTTPS with `--redirect-http`.
+pub async fn start_tls_server(
+    app: Router,
+    listeners: Vec<Listener>,
+    tls: &Tls,
+    connection: ConnectionOptions,
+    socket: SocketOptions,
+    server_header: Option<ServerHeader>,
+    handle: axum_server::Handle,
+) -> Result<(), ServeError> {
+    let (tls_listeners, plain_listeners): (Vec<_>, Vec<_>) =
+        listeners.into_iter().partition(|listener| listener.tls);
+    let addrs = tls_listeners
+        .iter()
+        .map(|listener| listener.listener.local_addr())
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut acme = None;
+    let config = if tls.cert.is_some() || tls.cert_pem_env.is_some() || !tls.sni_certs.is_empty() {
+        load_server_config(tls, connection.http2).await?
+    } else if tls.acme {
+        let state = acme_state(tls)?;
+        let config = acme_server_config(tls, state.resolver(), connection.http2)?;
+        acme = Some(state);
+        config
+    } else {
+        self_signed_config(tls, connection.http2).await?
+    };
+    let suites = usable_suites(config.crypto_provider(), tls.tls_min_version.and_later())
+        .collect::<Vec<_>>();
+    let mut versions = suites
+        .iter()
+        .map(|suite| version_name(suite.version()))
+        .collect::<Vec<_>>();
+    versions.sort_unstable_by(|a, b| b.cmp(a));
+    versions.dedup();
+    tracing::info!(
+        "TLS versions {} with cipher suites {}",
+        versions.join(", "),
+        suites
+            .into_iter()
+            .map(suite_name)
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+    let config = RustlsConfig::from_config(Arc::new(config));
+
+    let servers = tls_listeners
+        .into_iter()
+        .zip(&addrs)
+        .map(|(Listener { listener, .. }, addr)| {
+            tracing::info!("listening on {} with TLS", addr);
+            let mut server = axum_server::from_tcp_rustls(listener, config.clone()).map(|rustls| {
+                ClientCertificateAcceptor(
+                    rustls.acceptor(connection.acceptor()),
+                    connection.http2 == Http2::Only,
+                )
+            });
+            connection.configure(&mut server);
+            server.handle(handle.clone()).serve(
+                app.clone()
+                    .into_make_service_with_connect_info::<SocketAddr>(),
+            )
+        });
+    let plain = plain_listeners
+        .into_iter()
+        .map(|Listener { listener, .. }| {
+            if !tls.redirect_http {
+                return listen::serve_plain(listener, app.clone(), connection, handle.clone())
+                    .boxed();
+            }
+            let addr = listener.local_addr().ok();
+            // The HTTPS port on the same address, or else the first one.
+            let https_port = tls.redirect_https_port.unwrap_or_else(|| {
+                addrs
+                    .iter()
+                    .find(|https| Some(https.ip()) == addr.map(|addr| addr.ip()))
+                    .or(addrs.first())
+                    .map_or(443, SocketAddr::port)
+            });
+            serve_redirect(
+                listener,
+                https_port,
+                tls.redirect_status,
+                connection,
+                server_header.clone(),
+                handle.clone(),
+            )
+            .boxed()
+        });
+    let has_plain = plain.len() > 0;
+    let redirect = async {
+        // Plain listeners that were asked for take the place of --redirect-from-port.
+        if !tls.redirect_http || has_plain {
+            return Ok(());
+        }
+        // One redirect listener per address, to the first HTTPS port on that address.
+        let mut redirect_addrs = Vec::<SocketAddr>::new();
+        for addr in &addrs {
+            if !redirect_addrs.iter().any(|other| other.ip() == addr.ip()) {
+                redirect_addrs.push(*addr);
+            }
+        }
+        future::try_join_all(redirect_addrs.into_iter().map(|addr| {
+            init_http_to_https_redirect(
+                addr,
+                tls,
+                connection,
+                socket,
+                server_header.clone(),
+                handle.clone(),
+            )
+        }))
+        .await
+        .map(|_| ())
+    };
+    // Every listener shuts down through `handle`, so they are joined and all get to drain.
+    let servers = async {
+        tokio::try_join!(
+            async { Ok::<_, ServeError>(future::try_join_all(servers).await?) },
+            async { Ok(future::try_join_all(plain).await?) },
+            redirect
+        )
+        .map(|_| ())
+    };
+    // Only ends with an error, the servers are what decides when serving is over.
+    let background = async {
+        tokio::try_join!(
+            async {
+                Ok::<_, ServeError>(
+                    init_certificate_watch(config.clone(), tls, connection.http2).await?,
+                )
+            },
+            async {
+                drive_acme(acme).await;
+                Ok(())
+            }
+        )?;
+        std::future::pending().await
+    };
+    select! {
+        servers = servers => servers,
+        background = background => background,
+    }
+}
+
+/// Writes a private key that only its owner may read on unix, also when the file was there
+/// before with wider permissions.
+async fn write_private_key(path: &Path, key: &str) -> std::io::Result<()> {
+    use tokio::io::AsyncWriteExt;
+
+    let mut options = tokio::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    let mut file = options.open(path).await?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(std::fs::Permissions::from_mode(0o600))
+            .await?;
+    }
+    file.write_all(key.as_bytes()).await?;
+    file.flush().await
+}
+
+/// Generates a certificate for `--domain` that only lives as long as the process, and writes
+/// it to `--self-signed-out` when given.
+async fn self_signed_config(tls: &Tls, http2: Http2) -> Result<ServerConfig, ServeError> {
+    let certified = rcgen::generate_simple_self_signed(tls.domains.clone())
+        .map_err(|e| ServeError::Tls(format!("unable to generate a certificate: {}", e)))?;
+    let cert = certified.cert.pem();
+    let key = certified.key_pair.serialize_pem();
+    if let Some(dir) = &tls.self_signed_out {
+        tokio::fs::create_dir_all(dir).await?;
+        tokio::fs::write(dir.join("cert.pem"), &cert).await?;
+        write_private_key(&dir.join("key.pem"), &key).await?;
+        tracing::info!("wrote self-signed certificate to {}", dir.display());
+    }
+    tracing::warn!(
+        "using a self-signed certificate for {}",
+        tls.domains.join(", ")
+    );
+    server_config(
+        tls,
+        vec![certified.cert.der().clone()],
+        PrivateKeyDer::Pkcs8(certified.key_pair.serialize_der().into()),
+        Vec::new(),
+        http2,
+    )
+}
+
+/// A certificate chain and its private key.
+type Pair = (Vec<CertificateDer<'static>>, PrivateKeyDer<'static>);
+
+/// Reads a certificate chain and its private key.
+async fn read_pair(cert: &Path, key: &Path) -> Result<Pair, ServeError> {
+    let cert_pem = tokio::fs::read(cert).await?;
+    let key_pem = tokio::fs::read(key).await?;
+    parse_pair(&cert_pem, &key_pem, cert.display(), key.display())
+}
+
+/// Parses the PEM of a pair, `cert_source` and `key_source` name where it came from.
+fn parse_pair(
+    cert_pem: &[u8],
+    key_pem: &[u8],
+    cert_source: impl fmt::Display,
+    key_source: impl fmt::Display,
+) -> Result<Pair, ServeError> {
+    let chain = CertificateDer::pem_slice_iter(cert_pem)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| ServeError::InvalidPem(format!("{}: {}", cert_source, e)))?;
+    if chain.is_empty() {
+        return Err(ServeError::InvalidPem(format!(
+            "{}: {}",
+            cert_source,
+            rustls_pki_types::pem::Error::NoItemsFound
+        )));
+    }
+    let key_der = PrivateKeyDer::from_pem_slice(key_pem)
+        .map_err(|e| ServeError::InvalidPem(format!("{}: {}", key_source, e)))?;
+    Ok((chain, key_der))
+}
+
+/// The value of the environment variable `var`, which is not necessarily UTF-8.
+fn read_env(var: &str) -> Result<Vec<u8>, ServeError> {
+    std::env::var_os(var)
+        .map(OsString::into_encoded_bytes)
+        .ok_or_else(|| ServeError::MissingEnv(var.to_string()))
+}
+
+/// The pair of --cert and --key, or of --cert-pem-env and --key-pem-env, with the name of the
+/// certificate for messages about it.
+async fn default_pair(tls: &Tls) -> Result<Option<(String, Pair)>, ServeError> {
+    match (&tls.cert, &tls.key, &tls.cert_pem_env, &tls.key_pem_env) {
+        (Some(cert), Some(key), _, _) => {
+            let pair = read_pair(cert, key).await?;
+            Ok(Some((cert.display().to_string(), pair)))
+        }
+        (_, _, Some(cert), Some(key)) => {
+            let cert_source = format!("${}", cert);
+            let pair = parse_pair(
+                &read_env(cert)?,
+                &read_env(key)?,
+                &cert_source,
+                format_args!("${}", key),
+            )?;
+            Ok(Some((cert_source, pair)))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Builds the configuration from --cert and --key, or their environment variables, and the
+/// --sni-cert pairs if there are any.
+async fn load_server_config(tls: &Tls, http2: Http2) -> Result<ServerConfig, ServeError> {
+    let default = match default_pair(tls).await? {
+        Some((source, (chain, key))) => {
+            let ocsp = ocsp::staple(tls.ocsp_staple.as_deref(), tls.ocsp_auto, &chain).await;
+            Some((source, chain, key, ocsp))
+        }
+        None => None,
+    };
+    if tls.sni_certs.is_empty() {
+        if let Some((_, chain, key, ocsp)) = default {
+            return server_config(tls, chain, key, ocsp, http2);
+        }
+    }
+    let default = default
+        .map(|(source, chain, key, ocsp)| certified_key(&source, chain, &key, ocsp))
+        .transpose()?;
+    let mut names = HashMap::new();
+    for sni in &tls.sni_certs {
+        let (chain, key) = read_pair(&sni.cert, &sni.key).await?;
+        let ocsp = ocsp::staple(None, tls.ocsp_auto, &chain).await;
+        let certified = certified_key(sni.cert.display(), chain, &key, ocsp)?;
+        for domain in &sni.domains {
+            names.insert(domain.clone(), certified.clone());
+        }
+    }
+    tracing::info!("serving certificates for {} SNI names", names.len());
+    let resolver = SniResolver { names, default };
+    let mut config = config_builder(tls)?.with_cert_resolver(Arc::new(resolver));
+    config.alpn_protocols = alpn_protocols(http2);
+    Ok(config)
+}
+
+fn certified_key(
+    cert: impl fmt::Display,
+    chain: Vec<CertificateDer<'static>>,
+    key: &PrivateKeyDer<'static>,
+    ocsp: Vec<u8>,
+) -> Result<Arc<CertifiedKey>, ServeError> {
+    let invalid = |e: rustls::Error| ServeError::Tls(format!("{}: {}", cert, e));
+    let key = aws_lc_rs::sign::any_supported_type(key).map_err(invalid)?;
+    let mut certified = CertifiedKey::new(chain, key);
+    certified.keys_match().map_err(invalid)?;
+    certified.ocsp = (!ocsp.is_empty()).then_some(ocsp);
+    Ok(Arc::new(certified))
+}
+
+/// Picks the certificate by the SNI name the client asks for. `*.example.com` stands for the
+/// names one level below example.com. Clients that ask for another name, or none, get the
+/// default certificate.
+#[derive(Debug)]
+struct SniResolver {
+    names: HashMap<String, Arc<CertifiedKey>>,
+    default: Option<Arc<CertifiedKey>>,
+}
+
+impl ResolvesServerCert for SniResolver {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        let name = client_hello.server_name().map(str::to_ascii_lowercase);
+        name.and_then(|name| {
+            self.names.get(&name).or_else(|| {
+                let (_, parent) = name.split_once('.')?;
+                self.names.get(&format!("*.{}", parent))
+            })
+        })
+        .or(self.default.as_ref())
+        .cloned()
+    }
+}
+
+fn acme_state(tls: &Tls) -> Result<AcmeEvents, ServeError> {
+    if tls.domains == ["localhost"] {
+        return Err(ServeError::Acme(
+            "--acme needs the --domain to get a certificate for".to_string(),
+        ));
+    }
+    if tls.acme_cache.is_none() {
+        tracing::warn!("no --acme-cache, a new certificate is requested on every start");
+    }
+    tracing::info!(
+        "requesting a certificate for {} from Let's Encrypt{}",
+        tls.domains.join(", "),
+        if tls.acme_staging { " staging" } else { "" }
+    );
+    Ok(AcmeConfig::new(&tls.domains)
+        .contact(
+            tls.acme_email
+                .iter()
+                .map(|email| format!("mailto:{}", email)),
+        )
+        .cache_option(tls.acme_cache.clone().map(DirCache::new))
+        .directory_lets_encrypt(!tls.acme_staging)
+        .state())
+}
+
+/// Polls the ACME state, which orders a certificate when there is no valid one in the cache
+/// and renews it before it expires. Failed orders are retried with a backoff.
+async fn drive_acme(acme: Option<AcmeEvents>) {
+    let Some(mut acme) = acme else {
+        return std::future::pending().await;
+    };
+    while let Some(event) = acme.next().await {
+        match event {
+            Ok(event) => tracing::info!("acme: {:?}", event),
+            Err(e) => tracing::error!("acme: {}", e),
+        }
+    }
+}
+
+/// Builds the rustls configuration shared by certificates from disk and self-signed ones. An
+/// empty `ocsp` staples nothing.
+fn server_config(
+    tls: &Tls,
+    chain: Vec<CertificateDer<'static>>,
+    key: PrivateKeyDer<'static>,
+    ocsp: Vec<u8>,
+    http2: Http2,
+) -> Result<ServerConfig, ServeError> {
+    let mut config = config_builder(tls)?
+        .with_single_cert_with_ocsp(chain, key, ocsp)
+        .map_err(|e| ServeError::Tls(e.to_string()))?;
+    config.alpn_protocols = alpn_protocols(http2);
+    Ok(config)
+}
+
+/// Like `server_config`, with the certificate from ACME. The resolver also answers the
+/// TLS-ALPN-01 challenge, so the challenge protocol is offered as well.
+fn acme_server_config(
+    tls: &Tls,
+    resolver: Arc<dyn ResolvesServerCert>,
+    http2: Http2,
+) -> Result<ServerConfig, ServeError> {
+    let mut config = config_builder(tls)?.with_cert_resolver(resolver);
+    config.alpn_protocols = alpn_protocols(http2);
+    config
+        .alpn_protocols
+        .push(rustls_acme::acme::ACME_TLS_ALPN_NAME.to_vec());
+    Ok(config)
+}
+
+/// The protocols to offer with ALPN, most preferred first.
+fn alpn_protocols(http2: Http2) -> Vec<Vec<u8>> {
+    match http2 {
+        Http2::Auto => vec![b"h2".to_vec(), b"http/1.1".to_vec()],
+        Http2::Only => vec![b"h2".to_vec()],
+        Http2::Off => vec![b"http/1.1".to_vec()],
+    }
+}
+
+/// The protocol versions, cipher suites and client authentication from the options.
+fn config_builder(tls: &Tls) -> Result<ConfigBuilder<ServerConfig, WantsServerCert>, ServeError> {
+    let mut provider = aws_lc_rs::default_provider();
+    if !tls.tls_cipher_suites.is_empty() {
+        provider.cipher_suites = tls.tls_cipher_suites.clone();
+    }
+    let versions = tls.tls_min_version.and_later();
+    if usable_suites(&provider, versions).next().is_none() {
+        return Err(ServeError::Tls(format!(
+            "none of the cipher suites can be used with TLS {} or later",
+            version_name(versions[versions.len() - 1])
+        )));
+    }
+    let builder = ServerConfig::builder_with_provider(Arc::new(provider))
+        .with_protocol_versions(versions)
+        .map_err(|e| ServeError::Tls(e.to_string()))?;
+    Ok(match &tls.client_ca {
+        Some(ca) => builder.with_client_cert_verifier(client_verifier(ca, tls.client_auth)?),
+        None => builder.with_no_client_auth(),
+    })
+}
+
+/// Cipher suites of `provider` that can be negotiated with one of `versions`.
+fn usable_suites<'a>(
+    provider: &'a rustls::crypto::CryptoProvider,
+    versions: &'a [&'static SupportedProtocolVersion],
+) -> impl Iterator<Item = &'a SupportedCipherSuite> {
+    provider
+        .cipher_suites
+        .iter()
+        .filter(|suite| versions.contains(&suite.version()))
+}
+
+fn client_verifier(
+    ca: &Path,
+    client_auth: ClientAuth,
+) -> Result<Arc<dyn ClientCertVerifier>, ServeError> {
+    let invalid = |reason: String| ServeError::ClientCa(format!("{}: {}", ca.display(), reason));
+    let mut roots = RootCertStore::empty();
+    for cert in CertificateDer::pem_file_iter(ca).map_err(|e| invalid(e.to_string()))? {
+        let cert = cert.map_err(|e| invalid(e.to_string()))?;
+        roots.add(cert).map_err(|e| invalid(e.to_string()))?;
+    }
+    let builder = WebPkiClientVerifier::builder(Arc::new(roots));
+    let builder = match client_auth {
+        ClientAuth::Required => builder,
+        ClientAuth::Optional => builder.allow_unauthenticated(),
+    };
+    builder.build().map_err(|e| invalid(e.to_string()))
+}
+
+/// The verified client certificate of a mutual TLS connection. Every request on the connection
+/// carries an `Option<ClientCertificate>` extension, which is `None` without a certificate.
+#[derive(Clone, Debug)]
+pub struct ClientCertificate {
+    pub subject: String,
+}
+
+/// Returns the subject of the client certificate the request was sent with, if any.
+pub fn client_subject(request: &Request) -> Option<&str> {
+    request
+        .extensions()
+        .get::<Option<ClientCertificate>>()?
+        .as_ref()
+        .map(|cert| cert.subject.as_str())
+}
+
+/// Whether the request was received over TLS.
+pub fn is_tls(request: &Request) -> bool {
+    request
+        .extensions()
+        .get::<Option<ClientCertificate>>()
+        .is_some()
+}
+
+/// Wraps the rustls acceptor to add the client certificate to the requests of a connection.
+/// With `true`, connections that did not negotiate HTTP/2 with ALPN are closed, which clients
+/// that do not send ALPN at all would otherwise get around.
+#[derive(Clone)]
+struct ClientCertificateAcceptor(RustlsAcceptor<ConnectionAcceptor>, bool);
+
+impl<S> Accept<TcpStream, S> for ClientCertificateAcceptor
+where
+    S: Send + 'static,
+{
+    type Stream = TlsStream<IdleTimeout<TcpStream>>;
+    type Service = AddExtension<ConnectionService<S>, Option<ClientCertificate>>;
+    type Future = BoxFuture<'static, std::io::Result<(Self::Stream, Self::Service)>>;
+
+    fn accept(&self, stream: TcpStream, service: S) -> Self::Future {
+        let (acceptor, only_http2) = (self.0.clone(), self.1);
+        Box::pin(async move {
+            let (stream, service) = acceptor.accept(stream, service).await?;
+            if only_http2 && stream.get_ref().1.alpn_protocol() != Some(b"h2") {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "the client did not negotiate HTTP/2",
+                ));
+            }
+            let certificate = stream
+                .get_ref()
+                .1
+                .peer_certificates()
+                .and_then(|chain| chain.first())
+                .and_then(|cert| x509_parser::parse_x509_certificate(cert).ok())
+                .map(|(_, cert)| ClientCertificate {
+                    subject: cert.subject().to_string(),
+                });
+            Ok((stream, Extension(certificate).layer(service)))
+        })
+    }
+}
+
+/// Listens for plain HTTP on `--redirect-from-port` and redirects every request to the same
+/// host and path over HTTPS.
+async fn init_http_to_https_redirect(
+    addr: SocketAddr,
+    tls: &Tls,
+    connection: ConnectionOptions,
+    socket: SocketOptions,
+    server_header: Option<ServerHeader>,
+    handle: axum_server::Handle,
+) -> Result<(), ServeError> {
+    let https_port = tls.redirect_https_port.unwrap_or(addr.port());
+    // Only left for sockets passed by systemd, the ports of others are checked at startup.
+    if tls.redirect_from_port == https_port {
+        return Err(ServeError::Conflict(format!(
+            "--redirect-from-port {} is also the HTTPS port",
+            https_port
+        )));
+    }
+    let from = SocketAddr::new(addr.ip(), tls.redirect_from_port);
+    let listener = bind_tcp(from, socket)?;
+    serve_redirect(
+        listener,
+        https_port,
+        tls.redirect_status,
+        connection,
+        server_header,
+        handle,
+    )
+    .await?;
+    Ok(())
+}
+
+/// Redirects every plain HTTP request on `listener` to the same host and path on `https_port`.
+async fn serve_redirect(
+    listener: TcpListener,
+    https_port: u16,
+    status: RedirectStatus,
+    connection: ConnectionOptions,
+    server_header: Option<ServerHeader>,
+    handle: axum_server::Handle,
+) -> std::io::Result<()> {
+    if let Ok(from) = listener.local_addr() {
+        tracing::info!("redirecting HTTP on {} to HTTPS port {}", from, https_port);
+    }
+    let app = Router::new()
+        .fallback(move |request: Request| async move { redirect(&request, https_port, status) });
+    let app = server_header::layer(app, server_header.as_ref());
+    let mut server = axum_server::from_tcp(listener).acceptor(connection.plain_acceptor());
+    connection.configure(&mut server);
+    server.handle(handle).serve(app.into_make_service()).await
+}
+
+fn redirect(request: &Request, https_port: u16, status: RedirectStatus) -> Response {
+    let host = request
+        .headers()
+        .get(header::HOST)
+        .and_then(|host| host.to_str().ok())
+        .or_else(|| request.uri().host());
+    let Some(authority) = host.and_then(|host| https_authority(host, https_port)) else {
+        tracing::warn!("unable to redirect request without a valid host");
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+    let path_and_query = request
+        .uri()
+        .path_and_query()
+        .map(|p| p.as_str())
+        .unwrap_or("/");
+    let Ok(uri) = Uri::builder()
+        .scheme("https")
+        .authority(authority)
+        .path_and_query(path_and_query)
+        .build()
+    else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+    match status {
+        RedirectStatus::Temporary => Redirect::temporary(&uri.to_string()).into_response(),
+        RedirectStatus::Permanent => Redirect::permanent(&uri.to_string()).into_response(),
+    }
+}
+
+/// Replaces the port of a `Host` header value with the HTTPS port. The port is left out when it
+/// is 443. IPv6 literals keep their brackets.
+fn https_authority(host: &str, https_port: u16) -> Option<Authority> {
+    let host = host.parse::<Authority>().ok()?;
+    let name = host.host();
+    let name = if name.contains(':') && !name.starts_with('[') {
+        format!("[{}]", name)
+    } else {
+        name.to_string()
+    };
+    let authority = if https_port == 443 {
+        name
+    } else {
+        format!("{}:{}", name, https_port)
+    };
+    authority.parse().ok()
+}
+
+/// Returns the path of a watched file inside its canonical parent directory, which is how
+/// the watcher reports events for it.
+pub fn watch_target(path: &Path) -> std::io::Result<PathBuf> {
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let name = path.file_name().ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("{} is not a file path", path.display()),
+        )
+    })?;
+    Ok(parent.canonicalize()?.join(name))
+}
+
+/// How retries of a failed reload are spaced, from the `--cert-reload-*` options.
+#[derive(Copy, Clone, Debug)]
+struct Backoff {
+    base_delay: Duration,
+    max_delay: Duration,
+    /// Retries are given up after this many, or never when there is no limit.
+    max_retries: Option<u32>,
+}
+
+impl Backoff {
+    fn new(tls: &Tls) -> Self {
+        Backoff {
+            base_delay: Duration::from_millis(tls.cert_reload_base_delay),
+            max_delay: Duration::from_millis(tls.cert_reload_max_delay),
+            max_retries: (tls.cert_reload_max_retries > 0).then_some(tls.cert_reload_max_retries),
+        }
+    }
+
+    /// The delay before retry `attempt` without jitter, doubling from the base delay up to the
+    /// max delay.
+    fn delay(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        self.base_delay.saturating_mul(factor).min(self.max_delay)
+    }
+}
+
+/// Somewhere between half of `delay` and all of it, so servers that share the certificate
+/// storage do not all retry at once.
+fn jitter(delay: Duration) -> Duration {
+    // Every RandomState has new keys, so its hashes make do as random numbers.
+    let random = RandomState::new().build_hasher().finish() as f64 / u64::MAX as f64;
+    delay / 2 + (delay / 2).mul_f64(random)
+}
+
+/// Counts the retries of a failed reload. A successful reload starts over.
+struct Retries {
+    backoff: Backoff,
+    attempt: u32,
+}
+
+impl Retries {
+    fn new(backoff: Backoff) -> Self {
+        Retries {
+            backoff,
+            attempt: 0,
+        }
+    }
+
+    /// The delay before the next retry, without jitter, or `None` once they are used up.
+    fn next_delay(&mut self) -> Option<Duration> {
+        if self
+            .backoff
+            .max_retries
+            .is_some_and(|max| self.attempt >= max)
+        {
+            return None;
+        }
+        self.attempt = self.attempt.saturating_add(1);
+        Some(self.backoff.delay(self.attempt))
+    }
+
+    fn reset(&mut self) {
+        self.attempt = 0;
+    }
+}
+
+pub async fn init_certificate_watch(
+    tls_config: RustlsConfig,
+    serve_config: &Tls,
+    http2: Http2,
+) -> notify::Result<()> {
+    if let Some(var) = &serve_config.cert_pem_env {
+        tracing::info!(
+            "the certificate in ${} is not watched, changing it needs a restart",
+            var
+        );
+    }
+    if serve_config.cert.is_none()
+        && serve_config.sni_certs.is_empty()
+        && serve_config.ocsp_staple.is_none()
+        && !serve_config.ocsp_auto
+    {
+        // Self-signed certificates only exist in memory, and environment variables can not
+        // change from outside, so there is nothing to watch.
+        return std::future::pending().await;
+    }
+    let mut retries = Retries::new(Backoff::new(serve_config));
+    let mut retry: Option<JoinHandle<()>> = None;
+    // One pending reload is enough, later events are dropped while the channel is full.
+    let (tx, mut rx) = tokio::sync::mpsc::channel(1);
+    let retry_tx = tx.clone();
+
+    // Watching the files directly breaks when they are replaced by an atomic rename, because
+    // the watch stays attached to the old inode. Parent directories survive the rename.
+    let targets = [
+        &serve_config.cert,
+        &serve_config.key,
+        &serve_config.ocsp_staple,
+    ]
+    .into_iter()
+    .flatten()
+    .chain(
+        serve_config
+            .sni_certs
+            .iter()
+            .flat_map(|sni| [&sni.cert, &sni.key]),
+    )
+    .map(|p| watch_target(p))
+    .collect::<std::io::Result<Vec<_>>>()?;
+    let watched_paths = targets.clone();
+
+    let mut watcher = RecommendedWatcher::new(
+        move |res: notify::Result<Event>| match res {
+            Ok(res) => {
+                let relevant_kind = matches!(
+                    res.kind,
+                    EventKind::Create(_)
+                        | EventKind::Modify(
+                            ModifyKind::Any | ModifyKind::Data(_) | ModifyKind::Name(_)
+                        )
+                );
+                if relevant_kind && res.paths.iter().any(|p| watched_paths.contains(p)) {
+                    let _ = tx.try_send(());
+                }
+            }
+            Err(e) => tracing::error!("watcher error: {}", e),
+        },
+        Config::default(),
+    )?;
+
+    let mut dirs = targets
+        .iter()
+        .filter_map(|p| p.parent())
+        .collect::<Vec<_>>();
+    dirs.sort();
+    dirs.dedup();
+    for dir in dirs {
+        watcher.watch(dir, RecursiveMode::NonRecursive)?;
+    }
+
+    loop {
+        select! {
+            event = rx.recv() => if event.is_none() {
+                break;
+            },
+            // Fetched OCSP responses expire, so they are renewed like a changed certificate.
+            _ = sleep(ocsp::REFRESH), if serve_config.ocsp_auto => {}
+        }
+        if let Some(retry) = retry.take() {
+            retry.abort();
+        }
+        tracing::info!("reloading rustls configuration");
+        match load_server_config(serve_config, http2).await {
+            Ok(config) => {
+                tls_config.reload_from_config(Arc::new(config));
+                tracing::info!("rustls configuration reload successful");
+                retries.reset();
+                if let Some(command) = &serve_config.on_cert_reload {
+                    let cert = serve_config.cert.as_deref().map(Path::as_os_str);
+                    let args = cert.into_iter().collect::<Vec<_>>();
+                    let envs = args.iter().map(|cert| ("SERVE_CERT", *cert));
+                    hooks::spawn(
+                        "certificate reload",
+                        command,
+                        &args,
+                        &envs.collect::<Vec<_>>(),
+                    );
+                }
+            }
+            Err(e) => {
+                tracing::error!("rustls reload error: {}", e);
+                let Some(delay) = retries.next_delay().map(jitter) else {
+                    tracing::error!(
+                        "giving up reloading after {} retries, serving the last good \
+                         certificate until the files change again",
+                        retries.attempt
+                    );
+                    retries.reset();
+                    continue;
+                };
+                tracing::info!("retrying in {} milliseconds", delay.as_millis());
+                // Retry from a separate task, so file changes are still picked up in the meantime.
+                let retry_tx = retry_tx.clone();
+                retry = Some(tokio::spawn(async move {
+                    sleep(delay).await;
+                    let _ = retry_tx.try_send(());
+                }));
+            }
+        };
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+    use axum::body::Body;
+    use clap::Parser;
+    use std::{sync::Arc, time::Instant};
+
+    #[derive(Parser)]
+    struct Cli {
+        #[clap(flatten)]
+        tls: Tls,
+    }
+
+    fn tls(args: &[&str]) -> Tls {
+        Cli::try_parse_from(std::iter::once("tls").chain(args.iter().copied()))
+            .unwrap()
+            .tls
+    }
+
+    /// Writes a self-signed certificate for `domain` to `{name}.pem` and its key to
+    /// `{name}.key`, and returns their paths.
+    fn write_pair(dir: &TempDir, name: &str, domain: &str) -> (PathBuf, PathBuf) {
+        let certified = rcgen::generate_simple_self_signed(vec![domain.to_string()]).unwrap();
+        let cert = dir.write(&format!("{}.pem", name), certified.cert.pem());
+        let key = dir.write(&format!("{}.key", name), certified.key_pair.serialize_pem());
+        (cert, key)
+    }
+
+    /// Waits up to five seconds for `condition`.
+    async fn eventually(condition: impl Fn() -> bool) -> bool {
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_secs(5) {
+            if condition() {
+                return true;
+            }
+            sleep(Duration::from_millis(20)).await;
+        }
+        false
+    }
+
+    #[tokio::test]
+    async fn certificate_replaced_by_rename_is_reloaded() {
+        let dir = TempDir::new("tls-rename");
+        let (cert, key) = write_pair(&dir, "cert", "localhost");
+        let tls = tls(&[
+            "--cert",
+            cert.to_str().unwrap(),
+            "--key",
+            key.to_str().unwrap(),
+        ]);
+        let config = RustlsConfig::from_pem_file(&cert, &key).await.unwrap();
+        let first = config.get_inner();
+        let watched = config.clone();
+        tokio::spawn(async move { init_certificate_watch(watched, &tls, Http2::Auto).await });
+        sleep(Duration::from_millis(200)).await;
+
+        // How certbot and Kubernetes secrets replace files.
+        let (next_cert, next_key) = write_pair(&dir, "next", "localhost");
+        std::fs::rename(&next_key, &key).unwrap();
+        std::fs::rename(&next_cert, &cert).unwrap();
+
+        assert!(eventually(|| !Arc::ptr_eq(&config.get_inner(), &first)).await);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn reload_hook_runs_with_the_certificate_path() {
+        let dir = TempDir::new("tls-reload-hook");
+        let (cert, key) = write_pair(&dir, "cert", "localhost");
+        let sentinel = dir.path().join("reloaded");
+        let hook = format!("echo \"$1 $SERVE_CERT\" > {}", sentinel.display());
+        let tls = tls(&[
+            "--cert",
+            cert.to_str().unwrap(),
+            "--key",
+            key.to_str().unwrap(),
+            "--on-cert-reload",
+            &hook,
+        ]);
+        let config = RustlsConfig::from_pem_file(&cert, &key).await.unwrap();
+        tokio::spawn(async move { init_certificate_watch(config, &tls, Http2::Auto).await });
+        sleep(Duration::from_millis(200)).await;
+        assert!(!sentinel.exists());
+
+        let (next_cert, next_key) = write_pair(&dir, "next", "localhost");
+        std::fs::rename(&next_key, &key).unwrap();
+        std::fs::rename(&next_cert, &cert).unwrap();
+
+        let expected = format!("{} {}\n", cert.display(), cert.display());
+        assert!(
+            eventually(|| std::fs::read_to_string(&sentinel).is_ok_and(|s| s == expected)).await
+        );
+    }
+
+    fn location(host: &str, https_port: u16) -> String {
+        let request = Request::builder()
+            .uri("/a/b?c=d")
+            .header(header::HOST, host)
+            .body(Body::empty())
+            .unwrap();
+        let response = redirect(&request, https_port, RedirectStatus::Permanent);
+        assert_eq!(response.status(), StatusCode::PERMANENT_REDIRECT);
+        response.headers()[header::LOCATION]
+            .to_str()
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn authority_replaces_the_port() {
+        let authority = |host| https_authority(host, 8443).map(|a| a.to_string());
+        assert_eq!(
+            authority("example.com").as_deref(),
+            Some("example.com:8443")
+        );
+        assert_eq!(
+            authority("example.com:80").as_deref(),
+            Some("example.com:8443")
+        );
+        assert_eq!(
+            authority("80.example.com").as_deref(),
+            Some("80.example.com:8443")
+        );
+        assert_eq!(authority("host80:8080").as_deref(), Some("host80:8443"));
+        assert_eq!(authority("[::1]:80").as_deref(), Some("[::1]:8443"));
+        assert_eq!(authority("[::1]").as_deref(), Some("[::1]:8443"));
+        assert_eq!(authority("not a host"), None);
+    }
+
+    #[test]
+    fn authority_leaves_out_port_443() {
+        let authority = |host| https_authority(host, 443).map(|a| a.to_string());
+        assert_eq!(authority("example.com:80").as_deref(), Some("example.com"));
+        assert_eq!(authority("example80.com").as_deref(), Some("example80.com"));
+        assert_eq!(authority("[::1]:80").as_deref(), Some("[::1]"));
+    }
+
+    #[test]
+    fn redirect_keeps_the_path_and_query() {
+        assert_eq!(
+            location("example.com:80", 443),
+            "https://example.com/a/b?c=d"
+        );
+        assert_eq!(location("[::1]:80", 8443), "https://[::1]:8443/a/b?c=d");
+    }
+
+    #[test]
+    fn redirect_without_host_is_rejected() {
+        let request = Request::builder().uri("/").body(Body::empty()).unwrap();
+        let response = redirect(&request, 443, RedirectStatus::Temporary);
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn self_signed_key_is_only_readable_by_its_owner() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = TempDir::new("self-signed-out");
+        let out = dir.path().join("nested/certs");
+        let out = out.to_str().unwrap();
+        let mode = |name: &str| {
+            let path = dir.path().join("nested/certs").join(name);
+            std::fs::metadata(path).unwrap().permissions().mode() & 0o777
+        };
+        self_signed_config(
+            &tls(&["--self-signed", "--self-signed-out", out]),
+            Http2::Auto,
+        )
+        .await
+        .unwrap();
+        assert_eq!(mode("key.pem"), 0o600);
+        assert!(dir.path().join("nested/certs/cert.pem").exists());
+
+        // A key that was there before with wider permissions is narrowed.
+        let key = dir.path().join("nested/certs/key.pem");
+        std::fs::set_permissions(&key, std::fs::Permissions::from_mode(0o644)).unwrap();
+        self_signed_config(
+            &tls(&["--self-signed", "--self-signed-out", out]),
+            Http2::Auto,
+        )
+        .await
+        .unwrap();
+        assert_eq!(mode("key.pem"), 0o600);
+    }
+
+    fn backoff(args: &[&str]) -> Backoff {
+        Backoff::new(&tls(&[&["--self-signed"], args].concat()))
+    }
+
+    #[test]
+    fn reload_delay_is_capped() {
+        let backoff = backoff(&[]);
+        assert_eq!(backoff.delay(1), Duration::from_millis(2));
+        assert_eq!(backoff.delay(2), Duration::from_millis(4));
+        assert_eq!(backoff.delay(14), Duration::from_millis(16_384));
+        for attempt in (15..100).chain([u32::MAX]) {
+            assert_eq!(backoff.delay(attempt), Duration::from_millis(30_000));
+        }
+    }
+
+    #[test]
+    fn retries_follow_the_backoff_options() {
+        let backoff = backoff(&[
+            "--cert-reload-base-delay",
+            "100",
+            "--cert-reload-max-delay",
+            "1000",
+            "--cert-reload-max-retries",
+            "6",
+        ]);
+        let mut retries = Retries::new(backoff);
+        let delays = [(); 7].map(|_| retries.next_delay().map(|delay| delay.as_millis()));
+        assert_eq!(
+            delays,
+            [
+                Some(100),
+                Some(200),
+                Some(400),
+                Some(800),
+                Some(1000),
+                Some(1000),
+                None
+            ]
+        );
+    }
+
+    #[test]
+    fn retries_start_over_after_a_success() {
+        let mut retries = Retries::new(backoff(&[]));
+        let delays = [(); 3].map(|_| retries.next_delay().unwrap().as_millis());
+        assert_eq!(delays, [2, 4, 8]);
+        retries.reset();
+        assert_eq!(retries.next_delay(), Some(Duration::from_millis(2)));
+    }
+
+    #[test]
+    fn jitter_takes_at_most_half_off() {
+        let delay = Duration::from_millis(1000);
+        for _ in 0..100 {
+            let jittered = jitter(delay);
+            assert!(jittered >= delay / 2 && jittered <= delay, "{:?}", jittered);
+        }
+    }
+
+    #[tokio::test]
+    async fn failed_reload_does_not_hold_up_later_changes() {
+        let dir = TempDir::new("tls-retry");
+        let (cert, key) = write_pair(&dir, "cert", "localhost");
+        let tls = tls(&[
+            "--cert",
+            cert.to_str().unwrap(),
+            "--key",
+            key.to_str().unwrap(),
+        ]);
+        let config = RustlsConfig::from_pem_file(&cert, &key).await.unwrap();
+        let first = config.get_inner();
+        let watched = config.clone();
+        tokio::spawn(async move { init_certificate_watch(watched, &tls, Http2::Auto).await });
+        sleep(Duration::from_millis(200)).await;
+
+        // Fails, and keeps being retried with a growing delay.
+        std::fs::write(&cert, "not a certificate").unwrap();
+        sleep(Duration::from_millis(200)).await;
+        assert!(Arc::ptr_eq(&config.get_inner(), &first));
+
+        let (next_cert, next_key) = write_pair(&dir, "next", "localhost");
+        std::fs::rename(&next_key, &key).unwrap();
+        std::fs::rename(&next_cert, &cert).unwrap();
+        assert!(eventually(|| !Arc::ptr_eq(&config.get_inner(), &first)).await);
+    }
+
+    #[test]
+    fn sni_certs_are_parsed() {
+        let sni = parse_sni_cert("Example.com,*.example.com:certs/a.pem:a.key").unwrap();
+        assert_eq!(sni.domains, ["example.com", "*.example.com"]);
+        assert_eq!(sni.cert, Path::new("certs/a.pem"));
+        // The key path may have colons of its own.
+        assert_eq!(
+            parse_sni_cert("a.test:a.pem:C:/a.key").unwrap().key,
+            Path::new("C:/a.key")
+        );
+        for invalid in [
+            "a.test:a.pem",
+            ":a.pem:a.key",
+            "a.test::a.key",
+            ",:a.pem:a.key",
+        ] {
+            assert!(parse_sni_cert(invalid).is_err(), "{}", invalid);
+        }
+    }
+}
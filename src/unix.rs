@@ -0,0 +1,106 @@
+use axum::Router;
+use std::{
+    fs::{self, Permissions},
+    os::unix::{fs::FileTypeExt, fs::PermissionsExt, net::UnixStream},
+    path::Path,
+    time::Duration,
+};
+use tokio::{net::UnixListener, select, time::sleep};
+
+use crate::{error::ServeError, shutdown_signal, systemd};
+
+pub fn parse_mode(s: &str) -> Result<u32, String> {
+    u32::from_str_radix(s, 8).map_err(|_| format!("{} is not an octal file mode", s))
+}
+
+/// Removes a socket file left behind by a previous run. Fails if something is still listening
+/// on it or if the path is not a socket.
+fn remove_stale(path: &Path) -> Result<(), ServeError> {
+    let Ok(metadata) = fs::symlink_metadata(path) else {
+        return Ok(());
+    };
+    if !metadata.file_type().is_socket() {
+        return Err(ServeError::InvalidPath(format!(
+            "{} exists and is not a socket",
+            path.display()
+        )));
+    }
+    if UnixStream::connect(path).is_ok() {
+        return Err(ServeError::InvalidPath(format!(
+            "{} is in use by another process",
+            path.display()
+        )));
+    }
+    tracing::info!("removing stale socket {}", path.display());
+    fs::remove_file(path)?;
+    Ok(())
+}
+
+/// Serves `app` on a Unix domain socket until a shutdown signal, then removes the socket file.
+pub async fn serve(
+    app: Router,
+    path: &Path,
+    mode: Option<u32>,
+    shutdown_timeout: Duration,
+    sd_notify: bool,
+) -> Result<(), ServeError> {
+    remove_stale(path)?;
+    let listener = UnixListener::bind(path)?;
+    if let Some(mode) = mode {
+        fs::set_permissions(path, Permissions::from_mode(mode))?;
+    }
+    tracing::info!("listening on {}", path.display());
+    if sd_notify {
+        systemd::notify("READY=1");
+    }
+
+    let server =
+        axum::serve(listener, app.into_make_service()).with_graceful_shutdown(shutdown_signal());
+    let drain_deadline = async {
+        shutdown_signal().await;
+        sleep(shutdown_timeout).await;
+    };
+    let result = select! {
+        result = async { server.await } => result,
+        _ = drain_deadline => {
+            tracing::warn!("connections did not drain in time, closing them");
+            Ok(())
+        }
+    };
+
+    if let Err(e) = fs::remove_file(path) {
+        tracing::warn!("unable to remove socket {}: {}", path.display(), e);
+    }
+    Ok(result?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn mode_is_octal() {
+        assert_eq!(parse_mode("660"), Ok(0o660));
+        assert_eq!(parse_mode("0600"), Ok(0o600));
+        assert!(parse_mode("rw-").is_err());
+        assert!(parse_mode("8").is_err());
+    }
+
+    #[test]
+    fn only_stale_sockets_are_removed() {
+        let dir = TempDir::new("unix-stale");
+        let path = dir.path().join("serve.sock");
+        assert!(remove_stale(&path).is_ok());
+
+        let listener = std::os::unix::net::UnixListener::bind(&path).unwrap();
+        assert!(remove_stale(&path).is_err());
+        drop(listener);
+        assert!(remove_stale(&path).is_ok());
+        assert!(!path.exists());
+
+        let file = dir.write("file", "");
+        assert!(remove_stale(&file).is_err());
+        assert!(file.exists());
+    }
+}
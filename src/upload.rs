@@ -0,0 +1,187 @@
+use axum::{
+    extract::{Request, State},
+    http::{header, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use futures_util::StreamExt;
+use http_body_util::LengthLimitError;
+use std::{
+    io,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+use tokio::{fs, io::AsyncWriteExt};
+
+use crate::{dotfiles, error::ServeError, path};
+
+/// Numbers the temporary files of uploads in progress.
+static UPLOADS: AtomicU64 = AtomicU64::new(0);
+
+/// Parses the URL prefix to accept uploads below, which may be `/`.
+pub fn parse_prefix(s: &str) -> Result<String, String> {
+    if !s.starts_with('/') || s.contains(['{', '}', '*']) {
+        return Err(format!("{} must start with / and be a plain path", s));
+    }
+    Ok(s.trim_end_matches('/').to_string())
+}
+
+pub struct Upload {
+    prefix: String,
+    dir: PathBuf,
+    overwrite: bool,
+    dotfiles: dotfiles::Mode,
+}
+
+impl Upload {
+    pub fn new(
+        prefix: &str,
+        dir: &Path,
+        overwrite: bool,
+        dotfiles: dotfiles::Mode,
+    ) -> Result<Self, ServeError> {
+        if !dir.is_dir() {
+            return Err(ServeError::InvalidPath(format!(
+                "upload directory {} is not a directory",
+                dir.display()
+            )));
+        }
+        Ok(Upload {
+            prefix: prefix.to_string(),
+            // Canonical, for path::is_within.
+            dir: dir.canonicalize()?,
+            overwrite,
+            dotfiles,
+        })
+    }
+}
+
+/// Writes the body to a temporary file next to `file`, which is moved into place once the
+/// whole body has arrived. Returns whether an existing file was replaced.
+pub async fn write(file: &Path, overwrite: bool, request: Request) -> Result<bool, Response> {
+    let internal_error = |e: io::Error| {
+        tracing::error!("unable to upload {}: {}", file.display(), e);
+        StatusCode::INTERNAL_SERVER_ERROR.into_response()
+    };
+    let existing = fs::symlink_metadata(file).await.ok();
+    if existing
+        .as_ref()
+        .is_some_and(|meta| meta.is_dir() || !overwrite)
+    {
+        return Err(StatusCode::CONFLICT.into_response());
+    }
+    let parent = file.parent().unwrap_or(Path::new("."));
+    fs::create_dir_all(parent).await.map_err(internal_error)?;
+    let temp = parent.join(format!(
+        ".upload-{}-{}",
+        std::process::id(),
+        UPLOADS.fetch_add(1, Ordering::Relaxed)
+    ));
+    let result = receive(&temp, request).await;
+    let result = match result {
+        Ok(()) if overwrite => fs::rename(&temp, file).await.map_err(internal_error),
+        // Linking fails if the file was created meanwhile, where renaming would replace it.
+        Ok(()) => match fs::hard_link(&temp, file).await {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                Err(StatusCode::CONFLICT.into_response())
+            }
+            Err(e) => Err(internal_error(e)),
+        },
+        Err(e) => Err(e),
+    };
+    let _ = fs::remove_file(&temp).await;
+    result.map(|()| existing.is_some())
+}
+
+async fn receive(temp: &Path, request: Request) -> Result<(), Response> {
+    let mut out = fs::File::create(temp).await.map_err(|e| {
+        tracing::error!("unable to create {}: {}", temp.display(), e);
+        StatusCode::INTERNAL_SERVER_ERROR.into_response()
+    })?;
+    let mut body = request.into_body().into_data_stream();
+    while let Some(chunk) = body.next().await {
+        // --max-body-size ends the body with an error once it is exceeded.
+        let chunk = chunk.map_err(|e| match e.into_inner().downcast::<LengthLimitError>() {
+            Ok(_) => StatusCode::PAYLOAD_TOO_LARGE.into_response(),
+            Err(e) => {
+                tracing::debug!("upload aborted: {}", e);
+                StatusCode::BAD_REQUEST.into_response()
+            }
+        })?;
+        out.write_all(&chunk).await.map_err(|e| {
+            tracing::error!("unable to write {}: {}", temp.display(), e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        })?;
+    }
+    out.sync_all().await.map_err(|e| {
+        tracing::error!("unable to write {}: {}", temp.display(), e);
+        StatusCode::INTERNAL_SERVER_ERROR.into_response()
+    })
+}
+
+/// Middleware that writes the bodies of `PUT` and `POST` requests below the upload prefix to
+/// files in the upload directory. New files get 201 and replaced ones 200, both with the URL
+/// of the file. Other requests are passed on.
+pub async fn apply(State(upload): State<Arc<Upload>>, request: Request, next: Next) -> Response {
+    let request_path = request.uri().path().to_string();
+    if !matches!(*request.method(), Method::PUT | Method::POST)
+        || !path::has_prefix(&request_path, &upload.prefix)
+    {
+        return next.run(request).await;
+    }
+    let relative = &request_path[upload.prefix.len()..];
+    // Uploads may not create the files --dotfiles keeps from being served.
+    if dotfiles::is_hidden(relative) {
+        match upload.dotfiles {
+            dotfiles::Mode::Allow => {}
+            dotfiles::Mode::Deny => return StatusCode::FORBIDDEN.into_response(),
+            dotfiles::Mode::Ignore => return StatusCode::NOT_FOUND.into_response(),
+        }
+    }
+    let file = match path::resolve(&upload.dir, relative) {
+        Some(file) if file != upload.dir && !relative.ends_with('/') => file,
+        _ => return StatusCode::BAD_REQUEST.into_response(),
+    };
+    // A file that does not exist yet is as safe as the closest directory that does, which may
+    // be a symlink out of the upload directory.
+    let existing = file
+        .ancestors()
+        .find(|ancestor| ancestor.symlink_metadata().is_ok());
+    if !existing.is_some_and(|existing| path::is_within(&upload.dir, existing)) {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+    match write(&file, upload.overwrite, request).await {
+        Ok(replaced) => {
+            tracing::info!("uploaded {}", file.display());
+            let status = if replaced {
+                StatusCode::OK
+            } else {
+                StatusCode::CREATED
+            };
+            (
+                status,
+                [(header::LOCATION, request_path.clone())],
+                format!("{}\n", request_path),
+            )
+                .into_response()
+        }
+        Err(response) => response,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prefixes_are_plain_paths() {
+        assert_eq!(parse_prefix("/uploads/").unwrap(), "/uploads");
+        assert_eq!(parse_prefix("/").unwrap(), "");
+        assert!(parse_prefix("uploads").is_err());
+        assert!(parse_prefix("/uploads/{name}").is_err());
+    }
+}
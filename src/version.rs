@@ -0,0 +1,51 @@
+use axum::Json;
+use serde_json::{json, Value};
+
+use crate::access_log::civil_from_days;
+
+/// Set by the build script, which leaves the commit out when not built from a git checkout.
+const COMMIT: Option<&str> = option_env!("SERVE_GIT_COMMIT");
+const BUILD_TIME: &str = env!("SERVE_BUILD_TIME");
+
+/// Formats seconds since the epoch like `2000-10-10T13:55:36Z`.
+fn rfc3339(secs: u64) -> String {
+    let (days, secs_of_day) = ((secs / 86400) as i64, secs % 86400);
+    let (year, month, day) = civil_from_days(days);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60
+    )
+}
+
+/// The version of the running binary and what it was built from.
+pub async fn info() -> Json<Value> {
+    Json(json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "commit": COMMIT,
+        "build_time": BUILD_TIME.parse().ok().map(rfc3339),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn times_are_rfc3339() {
+        assert_eq!(rfc3339(0), "1970-01-01T00:00:00Z");
+        assert_eq!(rfc3339(971_186_136), "2000-10-10T13:55:36Z");
+    }
+
+    #[tokio::test]
+    async fn info_has_the_version() {
+        let Json(info) = info().await;
+        assert_eq!(info["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(info["commit"].as_str(), COMMIT);
+        assert!(info["build_time"].as_str().unwrap().ends_with('Z'));
+    }
+}
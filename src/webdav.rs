@@ -0,0 +1,528 @@
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode, Uri},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use std::{
+    fs::Metadata,
+    io,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tokio::fs;
+
+use crate::{dotfiles, path, upload};
+
+const READ_METHODS: &str = "OPTIONS, GET, HEAD, PROPFIND";
+const WRITE_METHODS: &str = "OPTIONS, GET, HEAD, PROPFIND, PUT, DELETE, MKCOL, COPY, MOVE";
+
+const HREF: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+/// WebDAV class 1 over a directory: `PROPFIND` lists it, and with `writable` the other methods
+/// change it. `GET` and `HEAD` are left to the file service.
+pub struct WebDav {
+    root: PathBuf,
+    writable: bool,
+    show_hidden: bool,
+}
+
+impl WebDav {
+    pub fn new(root: &Path, writable: bool, show_hidden: bool) -> Self {
+        WebDav {
+            root: root.canonicalize().unwrap_or(root.to_path_buf()),
+            writable,
+            show_hidden,
+        }
+    }
+
+    /// The decoded path segments and file a request path stands for. Paths that leave the root,
+    /// also through a symlink, and hidden files unless they are shown, have none.
+    fn target(&self, request_path: &str) -> Option<(Vec<String>, PathBuf)> {
+        let decoded = percent_decode_str(request_path).decode_utf8().ok()?;
+        let segments: Vec<String> = decoded
+            .split('/')
+            .filter(|segment| !segment.is_empty() && *segment != ".")
+            .map(str::to_string)
+            .collect();
+        if !self.show_hidden && segments.iter().any(|s| dotfiles::is_hidden_name(s)) {
+            return None;
+        }
+        let file = path::resolve(&self.root, request_path)?;
+        // A file that does not exist yet is as safe as the closest directory that does.
+        let existing = file
+            .ancestors()
+            .find(|ancestor| ancestor.symlink_metadata().is_ok())?;
+        path::is_within(&self.root, existing).then_some((segments, file))
+    }
+}
+
+fn href(segments: &[String], is_dir: bool) -> String {
+    let mut href = String::new();
+    for segment in segments {
+        href.push('/');
+        href.push_str(&utf8_percent_encode(segment, HREF).to_string());
+    }
+    if is_dir || segments.is_empty() {
+        href.push('/');
+    }
+    href
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// The `<D:response>` of one file or directory for `PROPFIND`.
+fn response_xml(segments: &[String], metadata: &Metadata) -> String {
+    let is_dir = metadata.is_dir();
+    let name = segments.last().map(String::as_str).unwrap_or("");
+    let mut props = format!("<D:displayname>{}</D:displayname>", escape(name));
+    if is_dir {
+        props.push_str("<D:resourcetype><D:collection/></D:resourcetype>");
+    } else {
+        let content_type = mime_guess::from_path(name)
+            .first_raw()
+            .unwrap_or("application/octet-stream");
+        props.push_str(&format!(
+            "<D:resourcetype/><D:getcontentlength>{}</D:getcontentlength>\
+             <D:getcontenttype>{}</D:getcontenttype>",
+            metadata.len(),
+            content_type
+        ));
+    }
+    if let Ok(modified) = metadata.modified() {
+        props.push_str(&format!(
+            "<D:getlastmodified>{}</D:getlastmodified>",
+            httpdate::fmt_http_date(modified)
+        ));
+    }
+    format!(
+        "<D:response><D:href>{}</D:href><D:propstat><D:prop>{}</D:prop>\
+         <D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>\n",
+        escape(&href(segments, is_dir)),
+        props
+    )
+}
+
+/// Answers `PROPFIND` with all properties of the file, and of the entries of a directory with
+/// `Depth: 1`. An infinite depth is refused, which the spec allows.
+async fn propfind(
+    dav: &WebDav,
+    segments: Vec<String>,
+    file: &Path,
+    headers: &HeaderMap,
+) -> Response {
+    let depth = match headers.get("depth").map(HeaderValue::as_bytes) {
+        Some(b"0") => 0,
+        Some(b"1") => 1,
+        _ => return StatusCode::FORBIDDEN.into_response(),
+    };
+    let Ok(metadata) = fs::metadata(file).await else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let mut xml = String::from(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:multistatus xmlns:D=\"DAV:\">\n",
+    );
+    xml.push_str(&response_xml(&segments, &metadata));
+    if depth == 1 && metadata.is_dir() {
+        match list(dav, file).await {
+            Ok(entries) => {
+                for (name, metadata) in entries {
+                    let mut segments = segments.clone();
+                    segments.push(name);
+                    xml.push_str(&response_xml(&segments, &metadata));
+                }
+            }
+            Err(e) => {
+                tracing::error!("unable to list {}: {}", file.display(), e);
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+        }
+    }
+    xml.push_str("</D:multistatus>\n");
+    (
+        StatusCode::MULTI_STATUS,
+        [(header::CONTENT_TYPE, "application/xml; charset=utf-8")],
+        xml,
+    )
+        .into_response()
+}
+
+/// The entries of `dir`, leaving out hidden ones and symlinks that lead outside the root.
+async fn list(dav: &WebDav, dir: &Path) -> io::Result<Vec<(String, Metadata)>> {
+    let mut entries = Vec::new();
+    let mut read_dir = fs::read_dir(dir).await?;
+    while let Some(entry) = read_dir.next_entry().await? {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let path = entry.path();
+        if (!dav.show_hidden && dotfiles::is_hidden_name(&name))
+            || !path::is_within(&dav.root, &path)
+        {
+            continue;
+        }
+        if let Ok(metadata) = fs::metadata(&path).await {
+            entries.push((name, metadata));
+        }
+    }
+    entries.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(entries)
+}
+
+fn internal_error(file: &Path, e: io::Error) -> Response {
+    tracing::error!("webdav error for {}: {}", file.display(), e);
+    StatusCode::INTERNAL_SERVER_ERROR.into_response()
+}
+
+/// Whether the directory a new file or directory would go in exists, which the spec requires.
+async fn has_parent(file: &Path) -> bool {
+    match file.parent() {
+        Some(parent) => fs::metadata(parent).await.is_ok_and(|m| m.is_dir()),
+        None => false,
+    }
+}
+
+async fn remove(file: &Path) -> io::Result<()> {
+    if fs::symlink_metadata(file).await?.is_dir() {
+        fs::remove_dir_all(file).await
+    } else {
+        fs::remove_file(file).await
+    }
+}
+
+/// Copies a file or directory tree, leaving out symlinks that lead outside the root.
+fn copy_all(root: &Path, from: &Path, to: &Path) -> io::Result<()> {
+    if !from.is_dir() {
+        return std::fs::copy(from, to).map(|_| ());
+    }
+    std::fs::create_dir(to)?;
+    for entry in std::fs::read_dir(from)? {
+        let path = entry?.path();
+        if path::is_within(root, &path) {
+            if let Some(name) = path.file_name() {
+                copy_all(root, &path, &to.join(name))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Answers `COPY` and `MOVE` to the `Destination` header, replacing an existing destination
+/// unless `Overwrite: F` is sent.
+async fn transfer(dav: &WebDav, file: &Path, headers: &HeaderMap, is_move: bool) -> Response {
+    let destination = headers
+        .get("destination")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<Uri>().ok())
+        .and_then(|uri| dav.target(uri.path()));
+    let Some((_, destination)) = destination else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+    if fs::symlink_metadata(file).await.is_err() {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    if destination.starts_with(file) || file == dav.root || destination == dav.root {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+    if !has_parent(&destination).await {
+        return StatusCode::CONFLICT.into_response();
+    }
+    let exists = fs::symlink_metadata(&destination).await.is_ok();
+    if exists {
+        if headers.get("overwrite").is_some_and(|value| value == "F") {
+            return StatusCode::PRECONDITION_FAILED.into_response();
+        }
+        if let Err(e) = remove(&destination).await {
+            return internal_error(&destination, e);
+        }
+    }
+    let result = if is_move {
+        fs::rename(file, &destination).await
+    } else {
+        let (root, from, to) = (dav.root.clone(), file.to_path_buf(), destination.clone());
+        tokio::task::spawn_blocking(move || copy_all(&root, &from, &to))
+            .await
+            .unwrap_or_else(|e| Err(io::Error::other(e)))
+    };
+    match result {
+        Ok(()) if exists => StatusCode::NO_CONTENT.into_response(),
+        Ok(()) => StatusCode::CREATED.into_response(),
+        Err(e) => internal_error(&destination, e),
+    }
+}
+
+async fn write(dav: &WebDav, segments: Vec<String>, file: PathBuf, request: Request) -> Response {
+    let method = request.method().as_str().to_string();
+    match method.as_str() {
+        "PUT" => {
+            if segments.is_empty() || !has_parent(&file).await {
+                return StatusCode::CONFLICT.into_response();
+            }
+            match upload::write(&file, true, request).await {
+                Ok(true) => StatusCode::NO_CONTENT.into_response(),
+                Ok(false) => StatusCode::CREATED.into_response(),
+                Err(response) => response,
+            }
+        }
+        "DELETE" => {
+            if segments.is_empty() {
+                return StatusCode::FORBIDDEN.into_response();
+            }
+            match remove(&file).await {
+                Ok(()) => StatusCode::NO_CONTENT.into_response(),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                    StatusCode::NOT_FOUND.into_response()
+                }
+                Err(e) => internal_error(&file, e),
+            }
+        }
+        "MKCOL" => {
+            if fs::symlink_metadata(&file).await.is_ok() {
+                return method_not_allowed(dav);
+            }
+            if !has_parent(&file).await {
+                return StatusCode::CONFLICT.into_response();
+            }
+            match fs::create_dir(&file).await {
+                Ok(()) => StatusCode::CREATED.into_response(),
+                Err(e) => internal_error(&file, e),
+            }
+        }
+        _ => transfer(dav, &file, request.headers(), method == "MOVE").await,
+    }
+}
+
+fn allow(dav: &WebDav) -> &'static str {
+    if dav.writable {
+        WRITE_METHODS
+    } else {
+        READ_METHODS
+    }
+}
+
+fn method_not_allowed(dav: &WebDav) -> Response {
+    (
+        StatusCode::METHOD_NOT_ALLOWED,
+        [(header::ALLOW, allow(dav))],
+    )
+        .into_response()
+}
+
+/// Middleware that answers the WebDAV methods. Every other request is passed on.
+pub async fn apply(State(dav): State<Arc<WebDav>>, request: Request, next: Next) -> Response {
+    let method = request.method().as_str();
+    let is_write = matches!(method, "PUT" | "DELETE" | "MKCOL" | "COPY" | "MOVE");
+    if !matches!(method, "OPTIONS" | "PROPFIND") && !is_write {
+        return next.run(request).await;
+    }
+    if method == "OPTIONS" {
+        return (
+            [
+                (header::HeaderName::from_static("dav"), "1"),
+                (header::ALLOW, allow(&dav)),
+            ],
+            (),
+        )
+            .into_response();
+    }
+    if is_write && !dav.writable {
+        return method_not_allowed(&dav);
+    }
+    let Some((segments, file)) = dav.target(request.uri().path()) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    if method == "PROPFIND" {
+        return propfind(&dav, segments, &file, request.headers()).await;
+    }
+    write(&dav, segments, file, request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{body_string, request, TempDir};
+    use axum::{middleware, routing, Router};
+    use tower::ServiceExt;
+
+    fn site(name: &str) -> TempDir {
+        let root = TempDir::new(name);
+        root.write("docs/a b.txt", "hello");
+        root.write("docs/.secret", "hidden");
+        root.write("docs/sub/c.html", "c");
+        root
+    }
+
+    async fn send(root: &TempDir, writable: bool, request: Request) -> Response {
+        let dav = Arc::new(WebDav::new(root.path(), writable, false));
+        Router::new()
+            .fallback(routing::get(|| async { "file" }))
+            .layer(middleware::from_fn_with_state(dav, apply))
+            .oneshot(request)
+            .await
+            .unwrap()
+    }
+
+    fn with_header(mut request: Request, name: &'static str, value: &str) -> Request {
+        request
+            .headers_mut()
+            .insert(name, HeaderValue::from_str(value).unwrap());
+        request
+    }
+
+    #[test]
+    fn hrefs_are_encoded() {
+        let segments = ["docs".to_string(), "a b&c.txt".to_string()];
+        assert_eq!(href(&segments, false), "/docs/a%20b%26c.txt");
+        assert_eq!(href(&segments[..1], true), "/docs/");
+        assert_eq!(href(&[], false), "/");
+    }
+
+    #[tokio::test]
+    async fn propfind_lists_a_directory() {
+        let root = site("webdav-propfind");
+        let listing = with_header(request("PROPFIND", "/docs", ""), "depth", "1");
+        let response = send(&root, false, listing).await;
+        assert_eq!(response.status(), StatusCode::MULTI_STATUS);
+        let xml = body_string(response).await;
+        let hrefs: Vec<&str> = xml
+            .split("<D:href>")
+            .skip(1)
+            .filter_map(|rest| rest.split_once("</D:href>"))
+            .map(|(href, _)| href)
+            .collect();
+        assert_eq!(hrefs, ["/docs/", "/docs/a%20b.txt", "/docs/sub/"]);
+        assert!(
+            xml.contains("<D:getcontentlength>5</D:getcontentlength>"),
+            "{}",
+            xml
+        );
+        assert!(xml.contains("<D:resourcetype><D:collection/></D:resourcetype>"));
+        assert!(!xml.contains("secret"));
+
+        let itself = with_header(request("PROPFIND", "/docs/", ""), "depth", "0");
+        let xml = body_string(send(&root, false, itself).await).await;
+        assert_eq!(xml.matches("<D:response>").count(), 1);
+    }
+
+    #[tokio::test]
+    async fn propfind_refusals() {
+        let root = site("webdav-propfind-refused");
+        let infinite = with_header(request("PROPFIND", "/docs", ""), "depth", "infinity");
+        let response = send(&root, false, infinite).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        for uri in ["/missing", "/docs/.secret", "/../etc"] {
+            let missing = with_header(request("PROPFIND", uri, ""), "depth", "0");
+            let response = send(&root, false, missing).await;
+            assert_eq!(response.status(), StatusCode::NOT_FOUND, "{}", uri);
+        }
+    }
+
+    #[tokio::test]
+    async fn options_advertise_dav() {
+        let root = site("webdav-options");
+        let response = send(&root, false, request("OPTIONS", "/", "")).await;
+        assert_eq!(response.headers()["dav"], "1");
+        assert_eq!(response.headers()[header::ALLOW], READ_METHODS);
+        let response = send(&root, true, request("OPTIONS", "/", "")).await;
+        assert_eq!(response.headers()[header::ALLOW], WRITE_METHODS);
+        let response = send(&root, false, request("GET", "/docs/a%20b.txt", "")).await;
+        assert_eq!(body_string(response).await, "file");
+    }
+
+    #[tokio::test]
+    async fn put_creates_and_replaces_files() {
+        let root = site("webdav-put");
+        let response = send(&root, true, request("PUT", "/docs/new.txt", "new")).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        assert_eq!(
+            fs::read_to_string(root.path().join("docs/new.txt"))
+                .await
+                .unwrap(),
+            "new"
+        );
+        let response = send(&root, true, request("PUT", "/docs/new.txt", "newer")).await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert_eq!(
+            fs::read_to_string(root.path().join("docs/new.txt"))
+                .await
+                .unwrap(),
+            "newer"
+        );
+        let response = send(&root, true, request("PUT", "/missing/new.txt", "new")).await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+    }
+
+    #[tokio::test]
+    async fn writes_need_write_access() {
+        let root = site("webdav-read-only");
+        for method in ["PUT", "DELETE", "MKCOL", "MOVE", "COPY"] {
+            let response = send(&root, false, request(method, "/docs/a%20b.txt", "")).await;
+            assert_eq!(
+                response.status(),
+                StatusCode::METHOD_NOT_ALLOWED,
+                "{}",
+                method
+            );
+            assert_eq!(response.headers()[header::ALLOW], READ_METHODS);
+        }
+        assert_eq!(
+            fs::read_to_string(root.path().join("docs/a b.txt"))
+                .await
+                .unwrap(),
+            "hello"
+        );
+    }
+
+    #[tokio::test]
+    async fn collections_are_made_moved_and_deleted() {
+        let root = site("webdav-collections");
+        let response = send(&root, true, request("MKCOL", "/docs/made", "")).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let response = send(&root, true, request("MKCOL", "/docs/made", "")).await;
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+
+        let copy = with_header(
+            request("COPY", "/docs/sub", ""),
+            "destination",
+            "/docs/made/sub",
+        );
+        assert_eq!(send(&root, true, copy).await.status(), StatusCode::CREATED);
+        assert!(root.path().join("docs/made/sub/c.html").is_file());
+        let moved = with_header(
+            request("MOVE", "/docs/a%20b.txt", ""),
+            "destination",
+            "http://localhost/docs/made/sub/c.html",
+        );
+        let moved = with_header(moved, "overwrite", "F");
+        assert_eq!(
+            send(&root, true, moved).await.status(),
+            StatusCode::PRECONDITION_FAILED
+        );
+        let moved = with_header(
+            request("MOVE", "/docs/a%20b.txt", ""),
+            "destination",
+            "/b.txt",
+        );
+        assert_eq!(send(&root, true, moved).await.status(), StatusCode::CREATED);
+        assert!(!root.path().join("docs/a b.txt").exists());
+        let escape = with_header(request("COPY", "/b.txt", ""), "destination", "/../b.txt");
+        assert_eq!(
+            send(&root, true, escape).await.status(),
+            StatusCode::BAD_REQUEST
+        );
+
+        let response = send(&root, true, request("DELETE", "/docs/made", "")).await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert!(!root.path().join("docs/made").exists());
+        let response = send(&root, true, request("DELETE", "/", "")).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+}
@@ -0,0 +1,669 @@
+//! Runs the serve binary.
+
+use rustls::{
+    client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    crypto::{aws_lc_rs, verify_tls12_signature, verify_tls13_signature, CryptoProvider},
+    pki_types::{CertificateDer, ServerName, UnixTime},
+    ClientConfig, ClientConnection, DigitallySignedStruct, SignatureScheme, StreamOwned,
+};
+use std::{
+    fs,
+    io::{BufRead, BufReader, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    path::{Path, PathBuf},
+    process::{Child, Command, ExitStatus, Output, Stdio},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+/// An empty directory for one test, removed again when it is dropped.
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("serve-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        TempDir(dir)
+    }
+
+    fn path(&self) -> &Path {
+        &self.0
+    }
+
+    /// All files and directories below, relative to it and sorted.
+    fn entries(&self) -> Vec<PathBuf> {
+        fn walk(dir: &Path, root: &Path, entries: &mut Vec<PathBuf>) {
+            for entry in fs::read_dir(dir).unwrap() {
+                let path = entry.unwrap().path();
+                entries.push(path.strip_prefix(root).unwrap().to_path_buf());
+                if path.is_dir() {
+                    walk(&path, root, entries);
+                }
+            }
+        }
+        let mut entries = Vec::new();
+        walk(&self.0, &self.0, &mut entries);
+        entries.sort();
+        entries
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+fn serve(dir: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_serve"))
+        .current_dir(dir)
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+/// A running serve, killed when it is dropped.
+struct Server {
+    child: Child,
+    addrs: Vec<SocketAddr>,
+}
+
+impl Server {
+    /// Starts serve in `dir` with --print-addr and `args`, and waits until the `listeners`
+    /// addresses are printed.
+    fn start(dir: &Path, args: &[&str], listeners: usize) -> Self {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_serve"))
+            .current_dir(dir)
+            .arg("--print-addr")
+            .args(args)
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let stdout = BufReader::new(child.stdout.take().unwrap());
+        let addrs = stdout
+            .lines()
+            .take(listeners)
+            .map(|line| line.unwrap().parse().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(addrs.len(), listeners, "serve exited early");
+        Server { child, addrs }
+    }
+
+    /// Shuts serve down like Ctrl+C does and waits for it to exit.
+    fn stop(mut self) -> ExitStatus {
+        #[cfg(unix)]
+        Command::new("kill")
+            .args(["-INT", &self.child.id().to_string()])
+            .status()
+            .unwrap();
+        #[cfg(not(unix))]
+        self.child.kill().unwrap();
+        self.child.wait().unwrap()
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Waits up to five seconds for `value` to return something.
+fn eventually<T>(value: impl Fn() -> Option<T>) -> T {
+    let start = Instant::now();
+    loop {
+        if let Some(value) = value() {
+            return value;
+        }
+        assert!(start.elapsed() < Duration::from_secs(5), "timed out");
+        std::thread::sleep(Duration::from_millis(20));
+    }
+}
+
+/// Sends a GET request for `path` over plain HTTP/1.1 and returns the whole response.
+fn http_get(addr: SocketAddr, path: &str) -> String {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    let request = format!(
+        "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+        path
+    );
+    stream.write_all(request.as_bytes()).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response
+}
+
+/// Writes a self-signed certificate for `domains` to `{name}.pem` and its key to `{name}.key`
+/// in `dir`, and returns the certificate.
+fn write_pair(dir: &TempDir, name: &str, domains: &[&str]) -> Vec<u8> {
+    let domains: Vec<String> = domains.iter().map(|domain| domain.to_string()).collect();
+    let certified = rcgen::generate_simple_self_signed(domains).unwrap();
+    let path = |extension| dir.path().join(format!("{}.{}", name, extension));
+    fs::write(path("pem"), certified.cert.pem()).unwrap();
+    fs::write(path("key"), certified.key_pair.serialize_pem()).unwrap();
+    certified.cert.der().to_vec()
+}
+
+#[cfg(unix)]
+#[test]
+fn unix_socket_serves_files_and_is_removed() {
+    use std::{
+        os::unix::{fs::PermissionsExt, net::UnixStream},
+        thread::sleep,
+        time::{Duration, Instant},
+    };
+
+    let dir = TempDir::new("unix-socket");
+    fs::write(dir.path().join("a.txt"), "over the socket").unwrap();
+    let socket = dir.path().join("serve.sock");
+    let mut child = Command::new(env!("CARGO_BIN_EXE_serve"))
+        .current_dir(dir.path())
+        .args([
+            ".",
+            "--unix-socket",
+            "serve.sock",
+            "--unix-socket-mode",
+            "600",
+        ])
+        .stdout(Stdio::null())
+        .spawn()
+        .unwrap();
+    let start = Instant::now();
+    let mut stream = loop {
+        match UnixStream::connect(&socket) {
+            Ok(stream) => break stream,
+            Err(_) if start.elapsed() < Duration::from_secs(5) => sleep(Duration::from_millis(20)),
+            Err(e) => {
+                let _ = child.kill();
+                panic!("unable to connect to {}: {}", socket.display(), e);
+            }
+        }
+    };
+
+    stream
+        .write_all(b"GET /a.txt HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+    assert!(
+        response.ends_with("\r\n\r\nover the socket"),
+        "{}",
+        response
+    );
+    let mode = fs::metadata(&socket).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+
+    let pid = child.id().to_string();
+    Command::new("kill").args(["-INT", &pid]).status().unwrap();
+    assert!(child.wait().unwrap().success());
+    assert!(!socket.exists());
+}
+
+#[cfg(unix)]
+#[test]
+fn unix_socket_rejects_tls() {
+    let dir = TempDir::new("unix-socket-tls");
+    let args = ["--unix-socket", "serve.sock", "tls", "--self-signed"];
+    let output = serve(dir.path(), &args);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("TLS is not supported on a Unix domain socket"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn startup_errors_are_readable() {
+    let dir = TempDir::new("startup-errors");
+    let output = serve(dir.path(), &["missing", "--not-found", "nope.html"]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let lines = stderr.lines().collect::<Vec<_>>();
+    assert_eq!(lines.len(), 3, "{}", stderr);
+    assert_eq!(lines[0], "error: invalid options:");
+    assert!(
+        lines[1].starts_with("  --not-found nope.html: "),
+        "{}",
+        stderr
+    );
+    assert!(lines[2].starts_with("  PATH missing: "), "{}", stderr);
+
+    let output = serve(dir.path(), &["--h2c"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "error: conflicting options: --h2c only applies to --http2 only\n"
+    );
+}
+
+/// Accepts any certificate, since the tests look at the certificates themselves.
+#[derive(Debug)]
+struct AcceptAny(CryptoProvider);
+
+impl ServerCertVerifier for AcceptAny {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+/// Connects over TLS to `addr` as `server_name`, offering the `alpn` protocols.
+fn tls_connect(
+    addr: SocketAddr,
+    server_name: &str,
+    alpn: &[&[u8]],
+) -> StreamOwned<ClientConnection, TcpStream> {
+    let provider = Arc::new(aws_lc_rs::default_provider());
+    let mut config = ClientConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(AcceptAny((*provider).clone())))
+        .with_no_client_auth();
+    config.alpn_protocols = alpn.iter().map(|protocol| protocol.to_vec()).collect();
+    let server_name = ServerName::try_from(server_name.to_string()).unwrap();
+    let connection = ClientConnection::new(Arc::new(config), server_name).unwrap();
+    let mut stream = StreamOwned::new(connection, TcpStream::connect(addr).unwrap());
+    while stream.conn.is_handshaking() {
+        stream.conn.complete_io(&mut stream.sock).unwrap();
+    }
+    stream
+}
+
+/// Sends a GET request for `path` over HTTPS/1.1 and returns the whole response.
+fn https_get(addr: SocketAddr, path: &str) -> String {
+    let mut stream = tls_connect(addr, "localhost", &[]);
+    let request = format!(
+        "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+        path
+    );
+    stream.write_all(request.as_bytes()).unwrap();
+    let mut response = Vec::new();
+    // Servers may close without close_notify once the response is sent.
+    let _ = stream.read_to_end(&mut response);
+    String::from_utf8(response).unwrap()
+}
+
+#[test]
+fn dry_run_writes_nothing() {
+    let dir = TempDir::new("dry-run");
+    fs::create_dir(dir.path().join("site")).unwrap();
+    fs::write(dir.path().join("site/a.html"), "a").unwrap();
+    let before = dir.entries();
+
+    let output = serve(
+        dir.path(),
+        &[
+            "site",
+            "--dry-run",
+            "--access-log",
+            "access.log",
+            "tls",
+            "--self-signed",
+            "--self-signed-out",
+            "certs",
+        ],
+    );
+
+    assert!(output.status.success(), "{:?}", output);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("\ndry-run = true\n"), "{}", stdout);
+    assert!(
+        stdout.contains("\naccess-log = \"access.log\"\n"),
+        "{}",
+        stdout
+    );
+    assert!(
+        stdout.contains("\n[tls]\nself-signed = true\n"),
+        "{}",
+        stdout
+    );
+    assert!(
+        stdout.ends_with("[secrets]\ntls.self-signed-out = \"certs\"\n"),
+        "{}",
+        stdout
+    );
+    assert!(!dir.path().join("access.log").exists());
+    assert_eq!(dir.entries(), before);
+}
+
+#[test]
+fn every_listener_serves_the_files() {
+    let dir = TempDir::new("listeners");
+    fs::write(dir.path().join("a.txt"), "on every port").unwrap();
+    let args = [".", "--listen", "127.0.0.1:0", "--listen", "127.0.0.1:0"];
+    let server = Server::start(dir.path(), &args, 2);
+    assert_ne!(server.addrs[0], server.addrs[1]);
+    for addr in server.addrs.clone() {
+        let response = http_get(addr, "/a.txt");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+        assert!(response.ends_with("\r\n\r\non every port"), "{}", response);
+    }
+    assert!(server.stop().success());
+}
+
+#[test]
+fn print_addr_prints_the_bound_port() {
+    let dir = TempDir::new("print-addr");
+    fs::write(dir.path().join("a.txt"), "a").unwrap();
+    let server = Server::start(dir.path(), &[".", "--port", "0"], 1);
+    let addr = server.addrs[0];
+    assert_ne!(addr.port(), 0);
+    assert!(http_get(addr, "/a.txt").starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(server.stop().success());
+
+    let args = [".", "--port", "0", "tls", "--self-signed"];
+    let server = Server::start(dir.path(), &args, 1);
+    assert_ne!(server.addrs[0].port(), 0);
+    assert!(TcpStream::connect(server.addrs[0]).is_ok());
+    assert!(server.stop().success());
+}
+
+#[cfg(unix)]
+#[test]
+fn pid_file_is_removed_on_shutdown() {
+    let dir = TempDir::new("pid-file");
+    let args = ["--port", "0", "--pid-file", "serve.pid"];
+    let server = Server::start(dir.path(), &args, 1);
+    // Written once the listeners are bound, a moment after their addresses are printed.
+    let pid = eventually(|| fs::read_to_string(dir.path().join("serve.pid")).ok());
+    assert_eq!(pid, format!("{}\n", server.child.id()));
+
+    assert!(server.stop().success());
+    assert!(!dir.path().join("serve.pid").exists());
+}
+
+#[cfg(unix)]
+#[test]
+fn hooks_see_the_bound_address() {
+    let dir = TempDir::new("hooks");
+    let args = [
+        "--port",
+        "0",
+        "--on-start",
+        "echo \"$SERVE_ADDR $SERVE_PORT $CARGO_MANIFEST_DIR\" > started",
+        "--on-shutdown",
+        "echo \"$SERVE_PORT\" > stopped",
+    ];
+    let server = Server::start(dir.path(), &args, 1);
+    let addr = server.addrs[0];
+    // Only SERVE_ADDR and SERVE_PORT are set, not serve's own environment.
+    let started = eventually(|| {
+        let started = fs::read_to_string(dir.path().join("started")).ok()?;
+        started.ends_with('\n').then_some(started)
+    });
+    assert_eq!(started, format!("{} {} \n", addr.ip(), addr.port()));
+
+    assert!(server.stop().success());
+    let stopped = fs::read_to_string(dir.path().join("stopped")).unwrap();
+    assert_eq!(stopped, format!("{}\n", addr.port()));
+}
+
+#[cfg(unix)]
+#[test]
+fn failed_required_start_hook_stops_serve() {
+    let dir = TempDir::new("hooks-required");
+    let output = serve(
+        dir.path(),
+        &["--port", "0", "--on-start", "exit 3", "--on-start-required"],
+    );
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("start hook exit 3 failed"), "{}", stderr);
+}
+
+/// Runs serve --dry-run with `vars` set and returns the options it printed.
+fn dry_run_with_env(dir: &Path, vars: &[(&str, &str)], args: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_serve"))
+        .current_dir(dir)
+        .envs(vars.iter().copied())
+        .arg("--dry-run")
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn environment_variables_are_options() {
+    let dir = TempDir::new("env");
+    fs::create_dir(dir.path().join("site")).unwrap();
+    let vars = [
+        ("SERVE_PATH", "site"),
+        ("SERVE_ADDR", "127.0.0.2"),
+        ("SERVE_PORT", "4321"),
+    ];
+
+    let stdout = dry_run_with_env(dir.path(), &vars, &[]);
+    assert!(
+        stdout.contains("\npath = \"site\"  # $SERVE_PATH\n"),
+        "{}",
+        stdout
+    );
+    assert!(
+        stdout.contains("\nport = \"4321\"  # $SERVE_PORT\naddr = \"127.0.0.2\"  # $SERVE_ADDR\n"),
+        "{}",
+        stdout
+    );
+}
+
+#[test]
+fn options_win_over_environment_variables() {
+    let dir = TempDir::new("env-precedence");
+    fs::create_dir(dir.path().join("site")).unwrap();
+    let vars = [
+        ("SERVE_PATH", "elsewhere"),
+        ("SERVE_ADDR", "127.0.0.2"),
+        ("SERVE_PORT", "4321"),
+    ];
+
+    let stdout = dry_run_with_env(dir.path(), &vars, &["site", "--port", "5000"]);
+    assert!(stdout.contains("\npath = \"site\"\n"), "{}", stdout);
+    assert!(!stdout.contains("elsewhere"), "{}", stdout);
+    assert!(
+        stdout.contains("\nport = \"5000\"\naddr = \"127.0.0.2\"  # $SERVE_ADDR\n"),
+        "{}",
+        stdout
+    );
+    let stdout = dry_run_with_env(dir.path(), &vars, &["site", "--listen", "127.0.0.3:6000"]);
+    assert!(
+        stdout.contains("\nlisten = [\"127.0.0.3:6000\"]\n"),
+        "{}",
+        stdout
+    );
+}
+
+#[test]
+fn tls_and_plain_listeners() {
+    let dir = TempDir::new("tls-and-plain");
+    fs::write(dir.path().join("a.txt"), "a").unwrap();
+    let args = [
+        ".",
+        "--listen",
+        "127.0.0.1:0:tls",
+        "--listen",
+        "127.0.0.1:0:plain",
+        "tls",
+        "--self-signed",
+    ];
+    let server = Server::start(dir.path(), &args, 2);
+    let (tls, plain) = (server.addrs[0], server.addrs[1]);
+
+    let response = https_get(tls, "/a.txt");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+    assert!(response.ends_with("\r\n\r\na"), "{}", response);
+    let response = http_get(plain, "/a.txt");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+    assert!(server.stop().success());
+}
+
+#[test]
+fn sni_names_get_their_certificates() {
+    let dir = TempDir::new("sni");
+    let a = write_pair(&dir, "a", &["a.test"]);
+    let b = write_pair(&dir, "b", &["b.test", "*.b.test"]);
+    let default = write_pair(&dir, "default", &["localhost"]);
+    let args = [
+        ".",
+        "--port",
+        "0",
+        "tls",
+        "--cert",
+        "default.pem",
+        "--key",
+        "default.key",
+        "--sni-cert",
+        "a.test:a.pem:a.key",
+        "--sni-cert",
+        "b.test,*.b.test:b.pem:b.key",
+    ];
+    let server = Server::start(dir.path(), &args, 1);
+    let addr = server.addrs[0];
+
+    let served = |name: &str| {
+        let stream = tls_connect(addr, name, &[]);
+        stream.conn.peer_certificates().unwrap()[0].to_vec()
+    };
+    assert!(served("a.test") == a);
+    assert!(served("A.Test") == a);
+    assert!(served("b.test") == b);
+    assert!(served("www.b.test") == b);
+    assert!(served("other.test") == default);
+    assert!(served("a.b.b.test") == default);
+    assert!(server.stop().success());
+}
+
+#[test]
+fn http2_is_negotiated_with_alpn() {
+    let dir = TempDir::new("alpn");
+    let server = Server::start(dir.path(), &[".", "--port", "0", "tls", "--self-signed"], 1);
+    let mut stream = tls_connect(server.addrs[0], "localhost", &[b"h2", b"http/1.1"]);
+    assert_eq!(stream.conn.alpn_protocol(), Some(&b"h2"[..]));
+    // The server starts an HTTP/2 connection with a SETTINGS frame.
+    stream
+        .write_all(b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n\0\0\0\x04\0\0\0\0\0")
+        .unwrap();
+    let mut frame_header = [0; 9];
+    stream.read_exact(&mut frame_header).unwrap();
+    assert_eq!(frame_header[3], 0x4);
+    drop(stream);
+    assert!(server.stop().success());
+
+    let args = [".", "--port", "0", "--http2", "off", "tls", "--self-signed"];
+    let server = Server::start(dir.path(), &args, 1);
+    let stream = tls_connect(server.addrs[0], "localhost", &[b"h2", b"http/1.1"]);
+    assert_eq!(stream.conn.alpn_protocol(), Some(&b"http/1.1"[..]));
+    drop(stream);
+    assert!(server.stop().success());
+}
+
+/// Starts serve in `dir` with a pid file and `args`, shuts it down once it is serving, and
+/// returns what it printed.
+#[cfg(unix)]
+fn start_and_stop(dir: &Path, args: &[&str]) -> Output {
+    let child = Command::new(env!("CARGO_BIN_EXE_serve"))
+        .current_dir(dir)
+        .args(["--port", "0", "--pid-file", "serve.pid"])
+        .args(args)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    eventually(|| fs::metadata(dir.join("serve.pid")).ok());
+    Command::new("kill")
+        .args(["-INT", &child.id().to_string()])
+        .status()
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+#[cfg(unix)]
+#[test]
+fn quiet_leaves_out_the_startup_output_but_not_requests() {
+    let dir = TempDir::new("quiet");
+    fs::write(dir.path().join("a.txt"), "a").unwrap();
+    let addr = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let port = addr.port().to_string();
+    let child = Command::new(env!("CARGO_BIN_EXE_serve"))
+        .current_dir(dir.path())
+        .env("SERVE_LOG_LEVEL", "info")
+        .args([".", "--port", &port, "--pid-file", "serve.pid", "--quiet"])
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    eventually(|| fs::metadata(dir.path().join("serve.pid")).ok());
+    assert!(http_get(addr, "/a.txt").starts_with("HTTP/1.1 200 OK\r\n"));
+    Command::new("kill")
+        .args(["-INT", &child.id().to_string()])
+        .status()
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("finished sending response"), "{}", stdout);
+    assert!(!stdout.contains("listening on"), "{}", stdout);
+    assert!(!stdout.contains("shutdown complete"), "{}", stdout);
+}
+
+#[cfg(unix)]
+#[test]
+fn silent_prints_nothing() {
+    let dir = TempDir::new("silent");
+    let output = start_and_stop(dir.path(), &["--log-level", "info"]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("shutdown complete"), "{}", stdout);
+
+    let output = start_and_stop(dir.path(), &["--log-level", "info", "--silent"]);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "");
+    assert_eq!(String::from_utf8(output.stderr).unwrap(), "");
+}